@@ -23,6 +23,20 @@ android {
             isMinifyEnabled = false
         }
     }
+
+    sourceSets {
+        getByName("main") {
+            jniLibs.srcDir(rootProject.file("build/stagedExecutables"))
+        }
+    }
+
+    packaging {
+        jniLibs {
+            // Staged programs are run from the native library dir, so they
+            // have to be unpacked there rather than mapped from the APK.
+            useLegacyPackaging = true
+        }
+    }
 }
 
 dependencies { }
@@ -35,6 +49,11 @@ tasks.matching { it.name == "mergeReleaseJniLibFolders" }.configureEach {
     dependsOn(":buildRust")
 }
 
+tasks.matching { it.name == "mergeDebugJniLibFolders" || it.name == "mergeReleaseJniLibFolders" }.configureEach {
+    dependsOn(":stageNativeExecutables")
+    dependsOn(":buildControlClient")
+}
+
 tasks.matching { it.name == "mergeDebugAssets" || it.name == "mergeReleaseAssets" }.configureEach {
     dependsOn(":buildCustomBootstrap")
     dependsOn(":buildTermuxExecCompat")
@@ -0,0 +1,18 @@
+package com.mynk8.gui_engine;
+
+import android.app.Activity;
+import android.widget.Toast;
+
+/**
+ * The parts of the control socket's commands that have to run on the UI
+ * thread, which the native side can't post to itself.
+ */
+final class ControlBridge {
+    private ControlBridge() {}
+
+    /** Show {@code text} in a toast, for a short while if {@code brief}. */
+    static void toast(Activity activity, String text, boolean brief) {
+        int duration = brief ? Toast.LENGTH_SHORT : Toast.LENGTH_LONG;
+        activity.runOnUiThread(() -> Toast.makeText(activity, text, duration).show());
+    }
+}
@@ -0,0 +1,88 @@
+package com.mynk8.gui_engine;
+
+import android.content.ContentProvider;
+import android.content.ContentValues;
+import android.database.Cursor;
+import android.database.MatrixCursor;
+import android.net.Uri;
+import android.os.ParcelFileDescriptor;
+import android.provider.OpenableColumns;
+import android.webkit.MimeTypeMap;
+
+import java.io.File;
+import java.io.FileNotFoundException;
+import java.util.Locale;
+
+/**
+ * Lends files to other apps as {@code content://<package>.files/<path>},
+ * read-only. It isn't exported: an app only gets at the files the native
+ * side grants it a URI for, when {@code open} hands one to a viewer.
+ */
+public class FileShareProvider extends ContentProvider {
+    private static final String[] COLUMNS = {OpenableColumns.DISPLAY_NAME, OpenableColumns.SIZE};
+
+    @Override
+    public boolean onCreate() {
+        return true;
+    }
+
+    @Override
+    public Cursor query(Uri uri, String[] projection, String selection, String[] selectionArgs,
+            String sortOrder) {
+        File file = file(uri);
+        String[] columns = projection != null ? projection : COLUMNS;
+        MatrixCursor cursor = new MatrixCursor(columns, 1);
+        Object[] row = new Object[columns.length];
+        for (int i = 0; i < columns.length; i++) {
+            if (OpenableColumns.DISPLAY_NAME.equals(columns[i])) {
+                row[i] = file.getName();
+            } else if (OpenableColumns.SIZE.equals(columns[i])) {
+                row[i] = file.length();
+            }
+        }
+        cursor.addRow(row);
+        return cursor;
+    }
+
+    @Override
+    public String getType(Uri uri) {
+        return mimeType(file(uri).getName());
+    }
+
+    /** The MIME type for a file called {@code name}, going by its extension. */
+    static String mimeType(String name) {
+        int dot = name.lastIndexOf('.');
+        if (dot >= 0) {
+            String extension = name.substring(dot + 1).toLowerCase(Locale.ROOT);
+            String type = MimeTypeMap.getSingleton().getMimeTypeFromExtension(extension);
+            if (type != null) {
+                return type;
+            }
+        }
+        return "application/octet-stream";
+    }
+
+    @Override
+    public ParcelFileDescriptor openFile(Uri uri, String mode) throws FileNotFoundException {
+        return ParcelFileDescriptor.open(file(uri), ParcelFileDescriptor.MODE_READ_ONLY);
+    }
+
+    @Override
+    public Uri insert(Uri uri, ContentValues values) {
+        throw new UnsupportedOperationException("Read-only");
+    }
+
+    @Override
+    public int delete(Uri uri, String selection, String[] selectionArgs) {
+        throw new UnsupportedOperationException("Read-only");
+    }
+
+    @Override
+    public int update(Uri uri, ContentValues values, String selection, String[] selectionArgs) {
+        throw new UnsupportedOperationException("Read-only");
+    }
+
+    private static File file(Uri uri) {
+        return new File(uri.getPath());
+    }
+}
@@ -0,0 +1,217 @@
+package com.mynk8.gui_engine;
+
+import android.content.Context;
+import android.content.pm.ProviderInfo;
+import android.database.Cursor;
+import android.database.MatrixCursor;
+import android.os.CancellationSignal;
+import android.os.ParcelFileDescriptor;
+import android.provider.DocumentsContract;
+import android.provider.DocumentsContract.Document;
+import android.provider.DocumentsContract.Root;
+import android.provider.DocumentsProvider;
+
+import java.io.FileNotFoundException;
+
+/**
+ * Shows $HOME and the prefix in the system's file picker and Files app, so
+ * other apps can open and save files there. The file work is the native
+ * side's (documents.rs); this turns its entries into cursors.
+ *
+ * <p>A document id is a root's id, {@code home} or {@code prefix}, then the
+ * path under it, such as {@code home/notes/todo.txt}. A named environment's
+ * roots add its name, as in {@code home@work}.
+ */
+public class HomeDocumentsProvider extends DocumentsProvider {
+    static {
+        System.loadLibrary("gui_engine");
+    }
+
+    private static final String[] ROOT_COLUMNS = {
+        Root.COLUMN_ROOT_ID, Root.COLUMN_FLAGS, Root.COLUMN_ICON, Root.COLUMN_TITLE,
+        Root.COLUMN_SUMMARY, Root.COLUMN_DOCUMENT_ID,
+    };
+    private static final String[] DOCUMENT_COLUMNS = {
+        Document.COLUMN_DOCUMENT_ID, Document.COLUMN_MIME_TYPE, Document.COLUMN_DISPLAY_NAME,
+        Document.COLUMN_LAST_MODIFIED, Document.COLUMN_FLAGS, Document.COLUMN_SIZE,
+    };
+    /**
+     * Root ids, which are also their top documents' ids, and their summaries,
+     * for each environment.
+     */
+    private static final String[][] ROOTS = {
+        {"home", "Home"},
+        {"prefix", "Packages (prefix)"},
+    };
+
+    /** A file or directory, as the native side describes it. */
+    static final class Entry {
+        final String id;
+        final String name;
+        final boolean directory;
+        final boolean writable;
+        final long size;
+        final long lastModified;
+
+        Entry(String id, String name, boolean directory, boolean writable, long size,
+                long lastModified) {
+            this.id = id;
+            this.name = name;
+            this.directory = directory;
+            this.writable = writable;
+            this.size = size;
+            this.lastModified = lastModified;
+        }
+    }
+
+    private String authority;
+
+    @Override
+    public void attachInfo(Context context, ProviderInfo info) {
+        authority = info.authority;
+        super.attachInfo(context, info);
+    }
+
+    @Override
+    public boolean onCreate() {
+        return true;
+    }
+
+    @Override
+    public Cursor queryRoots(String[] projection) throws FileNotFoundException {
+        MatrixCursor cursor = new MatrixCursor(projection != null ? projection : ROOT_COLUMNS);
+        String title = getContext().getString(R.string.app_name);
+        for (String[] root : ROOTS) {
+            addRoot(cursor, title, root[0], root[1]);
+        }
+        for (String environment : nativeEnvironments(base())) {
+            for (String[] root : ROOTS) {
+                addRoot(cursor, title, root[0] + "@" + environment,
+                        environment + ": " + root[1]);
+            }
+        }
+        return cursor;
+    }
+
+    @Override
+    public Cursor queryDocument(String documentId, String[] projection)
+            throws FileNotFoundException {
+        MatrixCursor cursor = new MatrixCursor(projection != null ? projection : DOCUMENT_COLUMNS);
+        addRow(cursor, nativeQueryDocument(base(), documentId));
+        return cursor;
+    }
+
+    @Override
+    public Cursor queryChildDocuments(String parentDocumentId, String[] projection,
+            String sortOrder) throws FileNotFoundException {
+        MatrixCursor cursor = new MatrixCursor(projection != null ? projection : DOCUMENT_COLUMNS);
+        for (Entry entry : nativeQueryChildren(base(), parentDocumentId)) {
+            addRow(cursor, entry);
+        }
+        cursor.setNotificationUri(getContext().getContentResolver(),
+                DocumentsContract.buildChildDocumentsUri(authority, parentDocumentId));
+        return cursor;
+    }
+
+    @Override
+    public ParcelFileDescriptor openDocument(String documentId, String mode,
+            CancellationSignal signal) throws FileNotFoundException {
+        return ParcelFileDescriptor.adoptFd(nativeOpenDocument(base(), documentId, mode));
+    }
+
+    @Override
+    public String createDocument(String parentDocumentId, String mimeType, String displayName)
+            throws FileNotFoundException {
+        boolean directory = Document.MIME_TYPE_DIR.equals(mimeType);
+        String id = nativeCreateDocument(base(), parentDocumentId, displayName, directory);
+        childrenChanged(parentDocumentId);
+        return id;
+    }
+
+    @Override
+    public void deleteDocument(String documentId) throws FileNotFoundException {
+        nativeDeleteDocument(base(), documentId);
+        childrenChanged(parent(documentId));
+    }
+
+    @Override
+    public String renameDocument(String documentId, String displayName)
+            throws FileNotFoundException {
+        String id = nativeRenameDocument(base(), documentId, displayName);
+        childrenChanged(parent(documentId));
+        return id;
+    }
+
+    @Override
+    public boolean isChildDocument(String parentDocumentId, String documentId) {
+        return documentId.startsWith(parentDocumentId + "/");
+    }
+
+    /** The app's files dir, which the bootstrap is installed under. */
+    private String base() {
+        return getContext().getFilesDir().getPath();
+    }
+
+    private static String parent(String documentId) {
+        int slash = documentId.lastIndexOf('/');
+        return slash >= 0 ? documentId.substring(0, slash) : documentId;
+    }
+
+    private void childrenChanged(String parentDocumentId) {
+        getContext().getContentResolver().notifyChange(
+                DocumentsContract.buildChildDocumentsUri(authority, parentDocumentId), null);
+    }
+
+    private static void addRoot(MatrixCursor cursor, String title, String id, String summary) {
+        cursor.newRow()
+                .add(Root.COLUMN_ROOT_ID, id)
+                .add(Root.COLUMN_FLAGS, Root.FLAG_SUPPORTS_CREATE | Root.FLAG_SUPPORTS_IS_CHILD
+                        | Root.FLAG_LOCAL_ONLY)
+                .add(Root.COLUMN_ICON, R.mipmap.ic_launcher)
+                .add(Root.COLUMN_TITLE, title)
+                .add(Root.COLUMN_SUMMARY, summary)
+                .add(Root.COLUMN_DOCUMENT_ID, id);
+    }
+
+    private static void addRow(MatrixCursor cursor, Entry entry) {
+        int flags = 0;
+        boolean root = entry.id.indexOf('/') < 0;
+        if (entry.writable) {
+            flags |= entry.directory ? Document.FLAG_DIR_SUPPORTS_CREATE
+                    : Document.FLAG_SUPPORTS_WRITE;
+            if (!root) {
+                flags |= Document.FLAG_SUPPORTS_DELETE | Document.FLAG_SUPPORTS_RENAME;
+            }
+        }
+        String mimeType = entry.directory ? Document.MIME_TYPE_DIR
+                : FileShareProvider.mimeType(entry.name);
+        cursor.newRow()
+                .add(Document.COLUMN_DOCUMENT_ID, entry.id)
+                .add(Document.COLUMN_MIME_TYPE, mimeType)
+                .add(Document.COLUMN_DISPLAY_NAME, entry.name)
+                .add(Document.COLUMN_LAST_MODIFIED, entry.lastModified)
+                .add(Document.COLUMN_FLAGS, flags)
+                .add(Document.COLUMN_SIZE, entry.size);
+    }
+
+    private static native String[] nativeEnvironments(String base) throws FileNotFoundException;
+
+    private static native Entry nativeQueryDocument(String base, String documentId)
+            throws FileNotFoundException;
+
+    private static native Entry[] nativeQueryChildren(String base, String parentDocumentId)
+            throws FileNotFoundException;
+
+    /** Returns a file descriptor to adopt. */
+    private static native int nativeOpenDocument(String base, String documentId, String mode)
+            throws FileNotFoundException;
+
+    private static native String nativeCreateDocument(String base, String parentDocumentId,
+            String name, boolean directory) throws FileNotFoundException;
+
+    private static native void nativeDeleteDocument(String base, String documentId)
+            throws FileNotFoundException;
+
+    private static native String nativeRenameDocument(String base, String documentId,
+            String name) throws FileNotFoundException;
+}
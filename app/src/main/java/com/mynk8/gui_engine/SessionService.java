@@ -0,0 +1,67 @@
+package com.mynk8.gui_engine;
+
+import android.app.Notification;
+import android.app.NotificationChannel;
+import android.app.NotificationManager;
+import android.app.PendingIntent;
+import android.app.Service;
+import android.content.Intent;
+import android.content.pm.ServiceInfo;
+import android.os.Build;
+import android.os.IBinder;
+
+/**
+ * Keeps the process in the foreground while a command runs, so Android
+ * doesn't kill the terminal and the shell's children with it once the app
+ * is in the background. The native side starts it with the command's name
+ * in {@link #EXTRA_TEXT} and stops it when the command finishes.
+ */
+public class SessionService extends Service {
+    static final String EXTRA_TEXT = "text";
+
+    private static final String CHANNEL = "keep_alive";
+    private static final String CHANNEL_NAME = "Running commands";
+    /** Clear of the ids the native side posts under. */
+    private static final int NOTIFICATION_ID = 3;
+
+    @Override
+    public int onStartCommand(Intent intent, int flags, int startId) {
+        String text = intent != null ? intent.getStringExtra(EXTRA_TEXT) : null;
+        Notification notification = buildNotification(text != null ? text : "A command is running");
+        if (Build.VERSION.SDK_INT >= Build.VERSION_CODES.UPSIDE_DOWN_CAKE) {
+            startForeground(NOTIFICATION_ID, notification,
+                    ServiceInfo.FOREGROUND_SERVICE_TYPE_SPECIAL_USE);
+        } else {
+            startForeground(NOTIFICATION_ID, notification);
+        }
+        // A restarted service would have no session to keep alive.
+        return START_NOT_STICKY;
+    }
+
+    @Override
+    public IBinder onBind(Intent intent) {
+        return null;
+    }
+
+    private Notification buildNotification(String text) {
+        Notification.Builder builder;
+        if (Build.VERSION.SDK_INT >= Build.VERSION_CODES.O) {
+            NotificationChannel channel = new NotificationChannel(
+                    CHANNEL, CHANNEL_NAME, NotificationManager.IMPORTANCE_LOW);
+            getSystemService(NotificationManager.class).createNotificationChannel(channel);
+            builder = new Notification.Builder(this, CHANNEL);
+        } else {
+            builder = new Notification.Builder(this);
+        }
+        Intent launch = getPackageManager().getLaunchIntentForPackage(getPackageName());
+        PendingIntent pending = PendingIntent.getActivity(this, 0, launch,
+                PendingIntent.FLAG_IMMUTABLE | PendingIntent.FLAG_UPDATE_CURRENT);
+        return builder
+                .setSmallIcon(android.R.drawable.ic_dialog_info)
+                .setContentTitle("Terminal session running")
+                .setContentText(text)
+                .setContentIntent(pending)
+                .setOngoing(true)
+                .build();
+    }
+}
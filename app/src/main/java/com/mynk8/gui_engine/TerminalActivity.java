@@ -0,0 +1,24 @@
+package com.mynk8.gui_engine;
+
+import android.app.NativeActivity;
+import android.content.Intent;
+
+/**
+ * The terminal's activity. NativeActivity leaves the native side only the
+ * intent that started it; this makes later ones, such as a RUN_COMMAND sent
+ * while the terminal is open, the activity's intent too and says one came in.
+ */
+public class TerminalActivity extends NativeActivity {
+    static {
+        System.loadLibrary("gui_engine");
+    }
+
+    @Override
+    protected void onNewIntent(Intent intent) {
+        super.onNewIntent(intent);
+        setIntent(intent);
+        nativeNewIntent();
+    }
+
+    private static native void nativeNewIntent();
+}
@@ -0,0 +1,146 @@
+package com.mynk8.gui_engine;
+
+import java.io.Closeable;
+import java.io.IOException;
+
+/**
+ * A program running on a terminal, for apps that draw the screen in their
+ * own views rather than the terminal activity. The native library parses
+ * the program's output into a screen of cells; read it back with
+ * {@link #readRow} or {@link #rowText} after {@link Listener#onScreenUpdate}.
+ *
+ * <p>Listener methods are called on the session's reader thread; post to the
+ * UI thread before touching views. The other methods may be called from any
+ * thread; each holds the session's lock for its native call, so {@link #close}
+ * can't free the session under it.
+ */
+public final class TerminalSession implements Closeable {
+    static {
+        System.loadLibrary("gui_engine");
+    }
+
+    /** Ints per cell in {@link #readRow}'s output. */
+    public static final int CELL_INTS = 4;
+    /** Set in a colour for direct RGB in the low 24 bits, rather than a palette index. */
+    public static final int TRUECOLOR = 1 << 24;
+    /** The theme's default foreground colour. */
+    public static final int DEFAULT_FG = 256;
+    /** The theme's default background colour. */
+    public static final int DEFAULT_BG = 257;
+
+    public static final int ATTR_BOLD = 1;
+    public static final int ATTR_FAINT = 1 << 1;
+    public static final int ATTR_ITALIC = 1 << 2;
+    public static final int ATTR_UNDERLINE = 1 << 3;
+    public static final int ATTR_BLINK = 1 << 4;
+    public static final int ATTR_REVERSE = 1 << 5;
+    public static final int ATTR_INVISIBLE = 1 << 6;
+    public static final int ATTR_STRUCK = 1 << 7;
+    /** First cell of a double-width character. */
+    public static final int ATTR_WIDE = 1 << 8;
+    /** Second cell of a double-width character, with nothing of its own to draw. */
+    public static final int ATTR_WIDE_SPACER = 1 << 9;
+
+    public interface Listener {
+        /** The screen changed. */
+        void onScreenUpdate();
+
+        /** The program exited with {@code status}, 128 + the signal if killed by one. */
+        void onExit(int status);
+    }
+
+    private long handle;
+
+    /**
+     * Start {@code program} with {@code args} on a {@code cols}x{@code rows}
+     * terminal. {@code env} holds extra {@code NAME=value} variables; {@code cwd}
+     * may be null.
+     */
+    public TerminalSession(String program, String[] args, String[] env, String cwd,
+            int cols, int rows, Listener listener) throws IOException {
+        handle = nativeCreate(program, args, env, cwd, cols, rows, listener);
+    }
+
+    /** Send {@code data} to the program, as if typed. */
+    public synchronized void write(byte[] data) {
+        nativeWrite(checkOpen(), data);
+    }
+
+    public synchronized void resize(int cols, int rows) {
+        nativeResize(checkOpen(), cols, rows);
+    }
+
+    public synchronized int columns() {
+        return nativeColumns(checkOpen());
+    }
+
+    public synchronized int rows() {
+        return nativeRows(checkOpen());
+    }
+
+    public synchronized int cursorRow() {
+        return nativeCursor(checkOpen()) >>> 16;
+    }
+
+    public synchronized int cursorColumn() {
+        return nativeCursor(checkOpen()) & 0xffff;
+    }
+
+    /**
+     * Fill {@code cells} with screen row {@code row}, {@link #CELL_INTS} ints a
+     * cell: the code point, the foreground and background colours (a palette
+     * index, {@link #DEFAULT_FG}/{@link #DEFAULT_BG}, or {@link #TRUECOLOR} | RGB)
+     * and the {@code ATTR_} bits. Returns how many cells fit.
+     */
+    public synchronized int readRow(int row, int[] cells) {
+        return nativeReadRow(checkOpen(), row, cells);
+    }
+
+    /** Text of screen row {@code row}, without trailing blanks. */
+    public synchronized String rowText(int row) {
+        return nativeRowText(checkOpen(), row);
+    }
+
+    /** The title the program last set. */
+    public synchronized String title() {
+        return nativeTitle(checkOpen());
+    }
+
+    /** Hang up the program and free the session. */
+    @Override
+    public synchronized void close() {
+        if (handle != 0) {
+            nativeDestroy(handle);
+            handle = 0;
+        }
+    }
+
+    /** The handle, with the lock held by the caller. */
+    private long checkOpen() {
+        if (handle == 0) {
+            throw new IllegalStateException("TerminalSession is closed");
+        }
+        return handle;
+    }
+
+    private static native long nativeCreate(String program, String[] args, String[] env,
+            String cwd, int cols, int rows, Listener listener) throws IOException;
+
+    private static native void nativeWrite(long handle, byte[] data);
+
+    private static native void nativeResize(long handle, int cols, int rows);
+
+    private static native int nativeColumns(long handle);
+
+    private static native int nativeRows(long handle);
+
+    private static native int nativeCursor(long handle);
+
+    private static native int nativeReadRow(long handle, int row, int[] cells);
+
+    private static native String nativeRowText(long handle, int row);
+
+    private static native String nativeTitle(long handle);
+
+    private static native void nativeDestroy(long handle);
+}
@@ -6,6 +6,7 @@ val rustDir = file("rust")
 val jniLibsDir = file("app/src/main/jniLibs")
 val bootstrapAsset = file("app/src/main/assets/bootstrap-aarch64.zip")
 val bootstrapUpstream = file("build/bootstrap/bootstrap-aarch64-upstream.zip")
+val stagedExecutablesDir = file("build/stagedExecutables")
 
 val rustTargets = mapOf(
     "aarch64-linux-android" to "arm64-v8a"
@@ -115,6 +116,32 @@ tasks.register<Exec>("buildTermuxExecCompat") {
     )
 }
 
+tasks.register<Exec>("buildControlClient") {
+    description = "Build the control socket client the prefix's helpers run"
+    group = "bootstrap"
+
+    // A native library, as programs in app data may not be executable.
+    val src = file("control-client/gui_engine_ctl.c")
+    val out = file("${jniLibsDir.absolutePath}/arm64-v8a/libgui-engine-ctl.so")
+    inputs.file(src)
+    outputs.file(out)
+
+    doFirst {
+        out.parentFile.mkdirs()
+    }
+
+    commandLine(
+        "${getNdkToolchainBinDir()}/aarch64-linux-android28-clang",
+        "-O2",
+        "-Wall",
+        "-Wextra",
+        "-Werror",
+        src.absolutePath,
+        "-o",
+        out.absolutePath
+    )
+}
+
 tasks.register<DefaultTask>("downloadBootstrap") {
     description = "Download Termux bootstrap (apt-android-7)"
     group = "bootstrap"
@@ -249,6 +276,51 @@ tasks.register<DefaultTask>("buildCustomBootstrap") {
     }
 }
 
+tasks.register<DefaultTask>("stageNativeExecutables") {
+    description = "Stage essential bootstrap programs as native libraries, for devices that won't run them from app data"
+    group = "bootstrap"
+    dependsOn("buildCustomBootstrap")
+    inputs.file(bootstrapAsset)
+    outputs.dir(stagedExecutablesDir)
+
+    doLast {
+        // Keep in step with STAGED_EXECUTABLES in rust/src/bootstrap.rs.
+        val names = listOf(
+            "bin/bash",
+            "bin/dash",
+            "bin/apt",
+            "bin/apt-get",
+            "bin/apt-cache",
+            "bin/apt-config",
+            "bin/apt-mark",
+            "bin/dpkg",
+            "bin/dpkg-deb",
+            "bin/dpkg-query",
+            "bin/gpgv",
+            "lib/apt/apt-helper",
+            "lib/apt/methods/copy",
+            "lib/apt/methods/file",
+            "lib/apt/methods/gpgv",
+            "lib/apt/methods/http",
+            "lib/apt/methods/https",
+            "lib/apt/methods/store",
+        )
+        val abiDir = File(stagedExecutablesDir, "arm64-v8a")
+        abiDir.deleteRecursively()
+        abiDir.mkdirs()
+        java.util.zip.ZipFile(bootstrapAsset).use { zip ->
+            for (name in names) {
+                // Symlinks aren't entries of their own; those are left out.
+                val entry = zip.getEntry(name) ?: continue
+                val out = File(abiDir, "lib${name.replace('/', '-')}.so")
+                zip.getInputStream(entry).use { input ->
+                    out.outputStream().use { output -> input.copyTo(output) }
+                }
+            }
+        }
+    }
+}
+
 tasks.register<Exec>("buildRustDebug") {
     description = "Build Rust library for Android arm64 (debug)"
     group = "rust"
@@ -0,0 +1,154 @@
+/*
+ * Client for the app's control socket, which does for programs in the
+ * session what only the app can, such as opening a file in another app.
+ * The prefix's helper scripts run it as
+ *
+ *     gui-engine-ctl [--stdin] <command> [args...]
+ *
+ * It sends the working directory and the arguments, each NUL-terminated,
+ * and closes its side; the app replies with an exit status byte and then
+ * text, which goes to stdout on success and stderr otherwise. With
+ * --stdin, what it reads from stdin follows as one more argument, after a
+ * "--" so it isn't taken for an option.
+ */
+
+#include <errno.h>
+#include <limits.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <sys/un.h>
+#include <unistd.h>
+
+/* Set in the session's environment to the socket's path. */
+#define SOCKET_ENV "GUI_ENGINE_CONTROL"
+
+static int write_all(int fd, const char *data, size_t len) {
+    while (len > 0) {
+        ssize_t written = write(fd, data, len);
+        if (written < 0) {
+            if (errno == EINTR) {
+                continue;
+            }
+            return -1;
+        }
+        data += written;
+        len -= (size_t)written;
+    }
+    return 0;
+}
+
+static int send_field(int fd, const char *field) {
+    return write_all(fd, field, strlen(field) + 1);
+}
+
+/* Send all of stdin as one field. */
+static int send_stdin(int fd) {
+    char buf[4096];
+    for (;;) {
+        ssize_t got = read(STDIN_FILENO, buf, sizeof(buf));
+        if (got < 0 && errno == EINTR) {
+            continue;
+        }
+        if (got < 0) {
+            return -1;
+        }
+        if (got == 0) {
+            return write_all(fd, "", 1);
+        }
+        if (write_all(fd, buf, (size_t)got) < 0) {
+            return -1;
+        }
+    }
+}
+
+static int connect_socket(const char *path) {
+    struct sockaddr_un addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sun_family = AF_UNIX;
+    if (strlen(path) >= sizeof(addr.sun_path)) {
+        errno = ENAMETOOLONG;
+        return -1;
+    }
+    strcpy(addr.sun_path, path);
+
+    int fd = socket(AF_UNIX, SOCK_STREAM | SOCK_CLOEXEC, 0);
+    if (fd < 0) {
+        return -1;
+    }
+    if (connect(fd, (struct sockaddr *)&addr, sizeof(addr)) < 0) {
+        int saved = errno;
+        close(fd);
+        errno = saved;
+        return -1;
+    }
+    return fd;
+}
+
+int main(int argc, char **argv) {
+    int from_stdin = argc > 1 && strcmp(argv[1], "--stdin") == 0;
+    if (from_stdin) {
+        argv[1] = argv[0];
+        argv++;
+        argc--;
+    }
+    if (argc < 2) {
+        fprintf(stderr, "usage: %s [--stdin] <command> [args...]\n", argv[0]);
+        return 2;
+    }
+    const char *path = getenv(SOCKET_ENV);
+    if (!path || !*path) {
+        fprintf(stderr, "%s: %s is not set; run this from the terminal app\n", argv[1], SOCKET_ENV);
+        return 1;
+    }
+    int fd = connect_socket(path);
+    if (fd < 0) {
+        fprintf(stderr, "%s: cannot reach the app: %s\n", argv[1], strerror(errno));
+        return 1;
+    }
+
+    char cwd[PATH_MAX];
+    if (!getcwd(cwd, sizeof(cwd))) {
+        cwd[0] = '\0';
+    }
+    int failed = send_field(fd, cwd);
+    for (int i = 1; i < argc && !failed; i++) {
+        failed = send_field(fd, argv[i]);
+    }
+    if (from_stdin && !failed) {
+        if (strcmp(argv[argc - 1], "--") != 0) {
+            failed = send_field(fd, "--");
+        }
+        failed = failed || send_stdin(fd);
+    }
+    if (failed || shutdown(fd, SHUT_WR) < 0) {
+        fprintf(stderr, "%s: sending the request failed: %s\n", argv[1], strerror(errno));
+        close(fd);
+        return 1;
+    }
+
+    unsigned char status;
+    ssize_t got;
+    do {
+        got = read(fd, &status, 1);
+    } while (got < 0 && errno == EINTR);
+    if (got != 1) {
+        fprintf(stderr, "%s: the app did not reply\n", argv[1]);
+        close(fd);
+        return 1;
+    }
+    int out = status == 0 ? STDOUT_FILENO : STDERR_FILENO;
+    char buf[4096];
+    for (;;) {
+        got = read(fd, buf, sizeof(buf));
+        if (got < 0 && errno == EINTR) {
+            continue;
+        }
+        if (got <= 0 || write_all(out, buf, (size_t)got) < 0) {
+            break;
+        }
+    }
+    close(fd);
+    return status;
+}
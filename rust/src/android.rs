@@ -0,0 +1,1591 @@
+//! Calls into the Java side of the activity for what the NDK doesn't cover.
+
+use android_activity::{AndroidApp, WindowManagerFlags};
+use jni::objects::{GlobalRef, JClass, JObject, JObjectArray, JString, JValue};
+use jni::{JNIEnv, JavaVM};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Intent action automation apps send to run a command in a new session.
+pub const RUN_COMMAND_ACTION: &str = "com.mynk8.gui_engine.RUN_COMMAND";
+/// The activity alias that needs the RUN_COMMAND permission. The action
+/// alone can be sent straight to the activity by anyone.
+const RUN_COMMAND_ALIAS: &str = "com.mynk8.gui_engine.RunCommand";
+/// Intent.ACTION_SEND, for the share sheet.
+const SEND_ACTION: &str = "android.intent.action.SEND";
+/// Java side of [`toast`].
+const CONTROL_BRIDGE: &str = "com.mynk8.gui_engine.ControlBridge";
+/// Authority of FileShareProvider, after the package name.
+const FILE_PROVIDER: &str = ".files";
+/// Intent.FLAG_GRANT_READ_URI_PERMISSION.
+const FLAG_GRANT_READ_URI_PERMISSION: i32 = 1;
+/// Most of a selection or command output [`share_text`] sends.
+const SHARE_LIMIT: usize = 256 * 1024;
+
+/// First SDK with `VibrationEffect`, and so a settable strength.
+const VIBRATION_EFFECT_SDK: i32 = 26;
+
+/// PowerManager.PARTIAL_WAKE_LOCK: the CPU stays on, the screen needn't.
+const PARTIAL_WAKE_LOCK: i32 = 1;
+/// Names our wake lock in `dumpsys power` and battery stats.
+const WAKE_LOCK_TAG: &str = "gui_engine:job";
+
+/// Display.DEFAULT_DISPLAY: the device's own screen.
+pub const DEFAULT_DISPLAY: i32 = 0;
+/// Display.FLAG_PRIVATE: a virtual display only its owner can show on.
+const DISPLAY_FLAG_PRIVATE: i32 = 4;
+/// First SDK with ActivityOptions.setLaunchDisplayId.
+const LAUNCH_DISPLAY_SDK: i32 = 26;
+/// Intent.FLAG_ACTIVITY_NEW_TASK | FLAG_ACTIVITY_REORDER_TO_FRONT: bring
+/// the running activity's task along rather than start another.
+const MOVE_TASK_FLAGS: i32 = 0x1000_0000 | 0x0002_0000;
+
+/// First SDK with notification channels.
+const NOTIFICATION_CHANNEL_SDK: i32 = 26;
+/// First SDK where the storage permissions stop covering shared storage,
+/// leaving the all files access setting.
+const ALL_FILES_ACCESS_SDK: i32 = 30;
+const ALL_FILES_ACCESS_SETTINGS: &str = "android.settings.MANAGE_APP_ALL_FILES_ACCESS_PERMISSION";
+/// First SDK with startForegroundService.
+const FOREGROUND_SERVICE_SDK: i32 = 26;
+/// Java side of [`start_keep_alive`], in the app's package.
+const KEEP_ALIVE_SERVICE: &str = ".SessionService";
+/// First SDK where posting notifications needs a runtime permission.
+const POST_NOTIFICATIONS_SDK: i32 = 33;
+const NOTIFICATION_CHANNEL: &str = "session";
+/// Shown in the app's notification settings.
+const NOTIFICATION_CHANNEL_NAME: &str = "Session activity";
+/// NotificationManager.IMPORTANCE_DEFAULT: makes a sound, no heads-up.
+const IMPORTANCE_DEFAULT: i32 = 3;
+/// PendingIntent.FLAG_IMMUTABLE | PendingIntent.FLAG_UPDATE_CURRENT.
+const PENDING_INTENT_FLAGS: i32 = 0x0400_0000 | 0x0800_0000;
+
+/// First SDK that reports display cutouts.
+const DISPLAY_CUTOUT_SDK: i32 = 28;
+/// WindowManager.LayoutParams.FLAG_TRANSLUCENT_STATUS and
+/// FLAG_TRANSLUCENT_NAVIGATION, which android-activity has no names for.
+const TRANSLUCENT_BARS: u32 = 0x0400_0000 | 0x0800_0000;
+/// PixelFormat.TRANSLUCENT.
+const PIXEL_FORMAT_TRANSLUCENT: i32 = -3;
+
+/// Label shown by clipboard UIs for text we copy.
+const CLIP_LABEL: &str = "Terminal";
+
+/// Run `f` with a JNI environment attached to this thread and the activity.
+/// Java exceptions are logged and cleared, and come back as None.
+fn with_activity<T>(
+    app: &AndroidApp,
+    what: &str,
+    f: impl FnOnce(&mut JNIEnv, &JObject) -> jni::errors::Result<T>,
+) -> Option<T> {
+    // SAFETY: android-activity keeps the VM and activity alive for as long
+    // as the AndroidApp exists.
+    let vm = match unsafe { JavaVM::from_raw(app.vm_as_ptr().cast()) } {
+        Ok(vm) => vm,
+        Err(e) => {
+            log::warn!("{}: no Java VM: {:?}", what, e);
+            return None;
+        }
+    };
+    let mut env = match vm.attach_current_thread() {
+        Ok(env) => env,
+        Err(e) => {
+            log::warn!("{}: failed to attach to the VM: {:?}", what, e);
+            return None;
+        }
+    };
+    let activity = unsafe { JObject::from_raw(app.activity_as_ptr().cast()) };
+    match f(&mut env, &activity) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            log::warn!("{} failed: {:?}", what, e);
+            if env.exception_check().unwrap_or(false) {
+                let _ = env.exception_describe();
+                let _ = env.exception_clear();
+            }
+            None
+        }
+    }
+}
+
+/// A command handed over by a [`RUN_COMMAND_ACTION`] intent.
+#[derive(Clone, Debug)]
+pub struct RunCommand {
+    pub command: String,
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    /// Keep the terminal out of sight while the command runs.
+    pub background: bool,
+    /// Named environment to run it in, rather than the configured one.
+    pub environment: Option<String>,
+}
+
+/// What another app handed us through the share sheet.
+#[derive(Clone, Debug)]
+pub enum Shared {
+    Text(String),
+    /// A shared file, copied to this path.
+    File(PathBuf),
+}
+
+fn system_service<'local>(
+    env: &mut JNIEnv<'local>,
+    activity: &JObject,
+    name: &str,
+) -> jni::errors::Result<JObject<'local>> {
+    let name = env.new_string(name)?;
+    env.call_method(
+        activity,
+        "getSystemService",
+        "(Ljava/lang/String;)Ljava/lang/Object;",
+        &[(&name).into()],
+    )?
+    .l()
+}
+
+/// Put `text` on the system clipboard.
+pub fn set_clipboard_text(app: &AndroidApp, text: &str) -> bool {
+    with_activity(app, "Clipboard copy", |env, activity| {
+        let clipboard = system_service(env, activity, "clipboard")?;
+        let label = env.new_string(CLIP_LABEL)?;
+        let text = env.new_string(text)?;
+        let clip = env
+            .call_static_method(
+                "android/content/ClipData",
+                "newPlainText",
+                "(Ljava/lang/CharSequence;Ljava/lang/CharSequence;)Landroid/content/ClipData;",
+                &[(&label).into(), (&text).into()],
+            )?
+            .l()?;
+        env.call_method(
+            &clipboard,
+            "setPrimaryClip",
+            "(Landroid/content/ClipData;)V",
+            &[(&clip).into()],
+        )?;
+        Ok(())
+    })
+    .is_some()
+}
+
+/// The clipboard's first item as text, if there is one. Non-text items
+/// (URIs, intents) are converted the way other apps would paste them.
+pub fn clipboard_text(app: &AndroidApp) -> Option<String> {
+    with_activity(app, "Clipboard paste", |env, activity| {
+        let clipboard = system_service(env, activity, "clipboard")?;
+        let clip = env
+            .call_method(
+                &clipboard,
+                "getPrimaryClip",
+                "()Landroid/content/ClipData;",
+                &[],
+            )?
+            .l()?;
+        if clip.is_null() || env.call_method(&clip, "getItemCount", "()I", &[])?.i()? == 0 {
+            return Ok(None);
+        }
+        let item = env
+            .call_method(
+                &clip,
+                "getItemAt",
+                "(I)Landroid/content/ClipData$Item;",
+                &[0.into()],
+            )?
+            .l()?;
+        let text = env
+            .call_method(
+                &item,
+                "coerceToText",
+                "(Landroid/content/Context;)Ljava/lang/CharSequence;",
+                &[activity.into()],
+            )?
+            .l()?;
+        if text.is_null() {
+            return Ok(None);
+        }
+        let text = JString::from(
+            env.call_method(&text, "toString", "()Ljava/lang/String;", &[])?
+                .l()?,
+        );
+        let text: String = env.get_string(&text)?.into();
+        Ok(Some(text))
+    })
+    .flatten()
+}
+
+/// Hand `url` to whichever app views it, usually the browser.
+pub fn open_url(app: &AndroidApp, url: &str) -> bool {
+    with_activity(app, "Opening URL", |env, activity| {
+        let url = env.new_string(url)?;
+        let uri = env
+            .call_static_method(
+                "android/net/Uri",
+                "parse",
+                "(Ljava/lang/String;)Landroid/net/Uri;",
+                &[(&url).into()],
+            )?
+            .l()?;
+        let action = env.new_string("android.intent.action.VIEW")?;
+        let intent = env.new_object(
+            "android/content/Intent",
+            "(Ljava/lang/String;Landroid/net/Uri;)V",
+            &[(&action).into(), (&uri).into()],
+        )?;
+        env.call_method(
+            activity,
+            "startActivity",
+            "(Landroid/content/Intent;)V",
+            &[(&intent).into()],
+        )?;
+        Ok(())
+    })
+    .is_some()
+}
+
+/// Offer `text` to other apps through the share sheet. Only the first
+/// [`SHARE_LIMIT`] bytes go, as an intent too big for Binder fails to start.
+pub fn share_text(app: &AndroidApp, text: &str) -> bool {
+    let mut end = text.len().min(SHARE_LIMIT);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    with_activity(app, "Sharing text", |env, activity| {
+        let action = env.new_string(SEND_ACTION)?;
+        let intent = env.new_object(
+            "android/content/Intent",
+            "(Ljava/lang/String;)V",
+            &[(&action).into()],
+        )?;
+        let mime = env.new_string("text/plain")?;
+        env.call_method(
+            &intent,
+            "setType",
+            "(Ljava/lang/String;)Landroid/content/Intent;",
+            &[(&mime).into()],
+        )?;
+        let name = env.new_string("android.intent.extra.TEXT")?;
+        let text = env.new_string(&text[..end])?;
+        env.call_method(
+            &intent,
+            "putExtra",
+            "(Ljava/lang/String;Ljava/lang/String;)Landroid/content/Intent;",
+            &[(&name).into(), (&text).into()],
+        )?;
+        let chooser = env
+            .call_static_method(
+                "android/content/Intent",
+                "createChooser",
+                "(Landroid/content/Intent;Ljava/lang/CharSequence;)Landroid/content/Intent;",
+                &[(&intent).into(), (&JObject::null()).into()],
+            )?
+            .l()?;
+        env.call_method(
+            activity,
+            "startActivity",
+            "(Landroid/content/Intent;)V",
+            &[(&chooser).into()],
+        )?;
+        Ok(())
+    })
+    .is_some()
+}
+
+/// Hand the file at `path` to whichever app views its type, lent through
+/// the app's file provider. Returns false if none takes it.
+pub fn view_file(app: &AndroidApp, path: &Path) -> bool {
+    with_activity(app, "Opening file", |env, activity| {
+        let package = package_name(env, activity)?;
+        let authority = env.new_string(package + FILE_PROVIDER)?;
+        let path_string = env.new_string(path.to_string_lossy())?;
+        let builder = env.new_object("android/net/Uri$Builder", "()V", &[])?;
+        let scheme = env.new_string("content")?;
+        env.call_method(
+            &builder,
+            "scheme",
+            "(Ljava/lang/String;)Landroid/net/Uri$Builder;",
+            &[(&scheme).into()],
+        )?;
+        env.call_method(
+            &builder,
+            "authority",
+            "(Ljava/lang/String;)Landroid/net/Uri$Builder;",
+            &[(&authority).into()],
+        )?;
+        env.call_method(
+            &builder,
+            "path",
+            "(Ljava/lang/String;)Landroid/net/Uri$Builder;",
+            &[(&path_string).into()],
+        )?;
+        let uri = env
+            .call_method(&builder, "build", "()Landroid/net/Uri;", &[])?
+            .l()?;
+        let mime = match mime_type(env, path)? {
+            Some(mime) => mime,
+            None => env.new_string("*/*")?,
+        };
+        let action = env.new_string("android.intent.action.VIEW")?;
+        let intent = env.new_object(
+            "android/content/Intent",
+            "(Ljava/lang/String;)V",
+            &[(&action).into()],
+        )?;
+        env.call_method(
+            &intent,
+            "setDataAndType",
+            "(Landroid/net/Uri;Ljava/lang/String;)Landroid/content/Intent;",
+            &[(&uri).into(), (&mime).into()],
+        )?;
+        env.call_method(
+            &intent,
+            "addFlags",
+            "(I)Landroid/content/Intent;",
+            &[FLAG_GRANT_READ_URI_PERMISSION.into()],
+        )?;
+        env.call_method(
+            activity,
+            "startActivity",
+            "(Landroid/content/Intent;)V",
+            &[(&intent).into()],
+        )?;
+        Ok(())
+    })
+    .is_some()
+}
+
+/// The MIME type Android knows `path`'s extension by.
+fn mime_type<'local>(
+    env: &mut JNIEnv<'local>,
+    path: &Path,
+) -> jni::errors::Result<Option<JString<'local>>> {
+    let Some(extension) = path.extension() else {
+        return Ok(None);
+    };
+    let extension = env.new_string(extension.to_string_lossy().to_lowercase())?;
+    let map = env
+        .call_static_method(
+            "android/webkit/MimeTypeMap",
+            "getSingleton",
+            "()Landroid/webkit/MimeTypeMap;",
+            &[],
+        )?
+        .l()?;
+    let mime = env
+        .call_method(
+            &map,
+            "getMimeTypeFromExtension",
+            "(Ljava/lang/String;)Ljava/lang/String;",
+            &[(&extension).into()],
+        )?
+        .l()?;
+    Ok((!mime.is_null()).then(|| JString::from(mime)))
+}
+
+fn package_name(env: &mut JNIEnv, activity: &JObject) -> jni::errors::Result<String> {
+    let package = env
+        .call_method(activity, "getPackageName", "()Ljava/lang/String;", &[])?
+        .l()?;
+    Ok(env.get_string(&JString::from(package))?.into())
+}
+
+/// Send the activity to the background without finishing it, as Home does.
+pub fn move_to_background(app: &AndroidApp) -> bool {
+    with_activity(app, "Moving to background", |env, activity| {
+        env.call_method(activity, "moveTaskToBack", "(Z)Z", &[true.into()])?
+            .z()
+    })
+    .unwrap_or(false)
+}
+
+/// Name the task in the recent apps list; empty goes back to the app name.
+pub fn set_task_label(app: &AndroidApp, label: &str) -> bool {
+    with_activity(app, "Setting task label", |env, activity| {
+        let label = match label {
+            "" => JObject::null(),
+            label => env.new_string(label)?.into(),
+        };
+        let description = env.new_object(
+            "android/app/ActivityManager$TaskDescription",
+            "(Ljava/lang/String;)V",
+            &[(&label).into()],
+        )?;
+        env.call_method(
+            activity,
+            "setTaskDescription",
+            "(Landroid/app/ActivityManager$TaskDescription;)V",
+            &[(&description).into()],
+        )?;
+        Ok(())
+    })
+    .is_some()
+}
+
+/// A display the activity can show on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Display {
+    pub id: i32,
+    pub name: String,
+    /// Frames per second.
+    pub refresh_rate: f32,
+}
+
+fn display_from(env: &mut JNIEnv, display: &JObject) -> jni::errors::Result<Display> {
+    let id = env.call_method(display, "getDisplayId", "()I", &[])?.i()?;
+    let name = env
+        .call_method(display, "getName", "()Ljava/lang/String;", &[])?
+        .l()?;
+    let refresh_rate = env
+        .call_method(display, "getRefreshRate", "()F", &[])?
+        .f()?;
+    Ok(Display {
+        id,
+        name: env.get_string(&JString::from(name))?.into(),
+        refresh_rate,
+    })
+}
+
+/// The display the activity is on.
+pub fn current_display(app: &AndroidApp) -> Option<Display> {
+    with_activity(app, "Reading display", |env, activity| {
+        // An activity's window manager is the one for its own display.
+        let window_manager = env
+            .call_method(
+                activity,
+                "getWindowManager",
+                "()Landroid/view/WindowManager;",
+                &[],
+            )?
+            .l()?;
+        let display = env
+            .call_method(
+                &window_manager,
+                "getDefaultDisplay",
+                "()Landroid/view/Display;",
+                &[],
+            )?
+            .l()?;
+        display_from(env, &display)
+    })
+}
+
+/// Displays the activity could move to: the device's own and those
+/// plugged in or cast to, but not other apps' private virtual displays.
+pub fn displays(app: &AndroidApp) -> Vec<Display> {
+    with_activity(app, "Listing displays", |env, activity| {
+        let manager = system_service(env, activity, "display")?;
+        let array = env
+            .call_method(&manager, "getDisplays", "()[Landroid/view/Display;", &[])?
+            .l()?;
+        let array = JObjectArray::from(array);
+        let len = env.get_array_length(&array)?;
+        let mut displays = Vec::with_capacity(len as usize);
+        for i in 0..len {
+            let display = env.get_object_array_element(&array, i)?;
+            let flags = env.call_method(&display, "getFlags", "()I", &[])?.i()?;
+            if flags & DISPLAY_FLAG_PRIVATE == 0 {
+                displays.push(display_from(env, &display)?);
+            }
+        }
+        Ok(displays)
+    })
+    .unwrap_or_default()
+}
+
+/// Move the activity's task to display `id`. The window comes back as a
+/// resize and, if the density differs, a scale factor change.
+pub fn move_to_display(app: &AndroidApp, id: i32) -> bool {
+    if app.config().sdk_version() < LAUNCH_DISPLAY_SDK {
+        return false;
+    }
+    with_activity(app, "Moving to display", |env, activity| {
+        let class = env.get_object_class(activity)?;
+        let intent = env.new_object(
+            "android/content/Intent",
+            "(Landroid/content/Context;Ljava/lang/Class;)V",
+            &[activity.into(), (&class).into()],
+        )?;
+        env.call_method(
+            &intent,
+            "addFlags",
+            "(I)Landroid/content/Intent;",
+            &[MOVE_TASK_FLAGS.into()],
+        )?;
+        let options = env
+            .call_static_method(
+                "android/app/ActivityOptions",
+                "makeBasic",
+                "()Landroid/app/ActivityOptions;",
+                &[],
+            )?
+            .l()?;
+        env.call_method(
+            &options,
+            "setLaunchDisplayId",
+            "(I)Landroid/app/ActivityOptions;",
+            &[id.into()],
+        )?;
+        let bundle = env
+            .call_method(&options, "toBundle", "()Landroid/os/Bundle;", &[])?
+            .l()?;
+        env.call_method(
+            activity,
+            "startActivity",
+            "(Landroid/content/Intent;Landroid/os/Bundle;)V",
+            &[(&intent).into(), (&bundle).into()],
+        )?;
+        Ok(())
+    })
+    .is_some()
+}
+
+/// Ask for the notification permission Android 13 and later withhold until
+/// granted, unless we have it. The system stops asking once it's refused
+/// twice.
+pub fn request_notification_permission(app: &AndroidApp) {
+    if app.config().sdk_version() < POST_NOTIFICATIONS_SDK {
+        return;
+    }
+    with_activity(
+        app,
+        "Requesting notification permission",
+        |env, activity| {
+            request_permissions(env, activity, &["android.permission.POST_NOTIFICATIONS"])
+        },
+    );
+}
+
+/// Ask the user for those of runtime `permissions` we don't hold yet. The
+/// answer comes back to the activity, which has no code to hear it; check
+/// again later instead.
+fn request_permissions(
+    env: &mut JNIEnv,
+    activity: &JObject,
+    permissions: &[&str],
+) -> jni::errors::Result<()> {
+    let mut missing = Vec::new();
+    for &name in permissions {
+        let permission = env.new_string(name)?;
+        let granted = env
+            .call_method(
+                activity,
+                "checkSelfPermission",
+                "(Ljava/lang/String;)I",
+                &[(&permission).into()],
+            )?
+            .i()?
+            == 0;
+        if !granted {
+            missing.push(permission);
+        }
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let array = env.new_object_array(missing.len() as i32, "java/lang/String", JObject::null())?;
+    for (i, permission) in missing.iter().enumerate() {
+        env.set_object_array_element(&array, i as i32, permission)?;
+    }
+    env.call_method(
+        activity,
+        "requestPermissions",
+        "([Ljava/lang/String;I)V",
+        &[(&array).into(), 0.into()],
+    )?;
+    Ok(())
+}
+
+/// Shared storage directories to link under ~/storage: the whole of it,
+/// and the downloads and camera folders.
+pub fn shared_storage_dirs(app: &AndroidApp) -> Vec<(&'static str, PathBuf)> {
+    with_activity(app, "Looking up shared storage", |env, _| {
+        let mut dirs = Vec::new();
+        let root = env
+            .call_static_method(
+                "android/os/Environment",
+                "getExternalStorageDirectory",
+                "()Ljava/io/File;",
+                &[],
+            )?
+            .l()?;
+        dirs.push(("shared", file_path(env, &root)?));
+        for (name, field) in [
+            ("downloads", "DIRECTORY_DOWNLOADS"),
+            ("dcim", "DIRECTORY_DCIM"),
+        ] {
+            let kind = env
+                .get_static_field("android/os/Environment", field, "Ljava/lang/String;")?
+                .l()?;
+            let dir = env
+                .call_static_method(
+                    "android/os/Environment",
+                    "getExternalStoragePublicDirectory",
+                    "(Ljava/lang/String;)Ljava/io/File;",
+                    &[(&kind).into()],
+                )?
+                .l()?;
+            dirs.push((name, file_path(env, &dir)?));
+        }
+        Ok(dirs)
+    })
+    .unwrap_or_default()
+}
+
+fn file_path(env: &mut JNIEnv, file: &JObject) -> jni::errors::Result<PathBuf> {
+    let path = env
+        .call_method(file, "getAbsolutePath", "()Ljava/lang/String;", &[])?
+        .l()?;
+    let path: String = env.get_string(&JString::from(path))?.into();
+    Ok(PathBuf::from(path))
+}
+
+/// Ask for access to all of shared storage, unless we have it: the storage
+/// permissions up to Android 10, and after that the all files access
+/// setting, which the user grants on a settings screen this opens.
+pub fn request_storage_access(app: &AndroidApp) {
+    let sdk = app.config().sdk_version();
+    with_activity(app, "Requesting storage access", |env, activity| {
+        if sdk < ALL_FILES_ACCESS_SDK {
+            return request_permissions(
+                env,
+                activity,
+                &[
+                    "android.permission.READ_EXTERNAL_STORAGE",
+                    "android.permission.WRITE_EXTERNAL_STORAGE",
+                ],
+            );
+        }
+        let granted = env
+            .call_static_method(
+                "android/os/Environment",
+                "isExternalStorageManager",
+                "()Z",
+                &[],
+            )?
+            .z()?;
+        if granted {
+            return Ok(());
+        }
+        let package = package_name(env, activity)?;
+        let uri = env.new_string(format!("package:{}", package))?;
+        let uri = env
+            .call_static_method(
+                "android/net/Uri",
+                "parse",
+                "(Ljava/lang/String;)Landroid/net/Uri;",
+                &[(&uri).into()],
+            )?
+            .l()?;
+        let action = env.new_string(ALL_FILES_ACCESS_SETTINGS)?;
+        let intent = env.new_object(
+            "android/content/Intent",
+            "(Ljava/lang/String;Landroid/net/Uri;)V",
+            &[(&action).into(), (&uri).into()],
+        )?;
+        env.call_method(
+            activity,
+            "startActivity",
+            "(Landroid/content/Intent;)V",
+            &[(&intent).into()],
+        )?;
+        Ok(())
+    });
+}
+
+/// Post notification `id`, replacing any earlier one with that id without
+/// alerting again. Tapping it brings the terminal back.
+pub fn notify(app: &AndroidApp, id: i32, title: &str, text: &str) -> bool {
+    let sdk = app.config().sdk_version();
+    with_activity(app, "Posting notification", |env, activity| {
+        let manager = system_service(env, activity, "notification")?;
+        let builder = if sdk >= NOTIFICATION_CHANNEL_SDK {
+            let channel_id = env.new_string(NOTIFICATION_CHANNEL)?;
+            let name = env.new_string(NOTIFICATION_CHANNEL_NAME)?;
+            let channel = env.new_object(
+                "android/app/NotificationChannel",
+                "(Ljava/lang/String;Ljava/lang/CharSequence;I)V",
+                &[
+                    (&channel_id).into(),
+                    (&name).into(),
+                    IMPORTANCE_DEFAULT.into(),
+                ],
+            )?;
+            env.call_method(
+                &manager,
+                "createNotificationChannel",
+                "(Landroid/app/NotificationChannel;)V",
+                &[(&channel).into()],
+            )?;
+            env.new_object(
+                "android/app/Notification$Builder",
+                "(Landroid/content/Context;Ljava/lang/String;)V",
+                &[activity.into(), (&channel_id).into()],
+            )?
+        } else {
+            env.new_object(
+                "android/app/Notification$Builder",
+                "(Landroid/content/Context;)V",
+                &[activity.into()],
+            )?
+        };
+
+        let package = env
+            .call_method(activity, "getPackageName", "()Ljava/lang/String;", &[])?
+            .l()?;
+        let package_manager = env
+            .call_method(
+                activity,
+                "getPackageManager",
+                "()Landroid/content/pm/PackageManager;",
+                &[],
+            )?
+            .l()?;
+        let intent = env
+            .call_method(
+                &package_manager,
+                "getLaunchIntentForPackage",
+                "(Ljava/lang/String;)Landroid/content/Intent;",
+                &[(&package).into()],
+            )?
+            .l()?;
+        let pending = env
+            .call_static_method(
+                "android/app/PendingIntent",
+                "getActivity",
+                "(Landroid/content/Context;ILandroid/content/Intent;I)Landroid/app/PendingIntent;",
+                &[
+                    activity.into(),
+                    0.into(),
+                    (&intent).into(),
+                    PENDING_INTENT_FLAGS.into(),
+                ],
+            )?
+            .l()?;
+
+        let icon = env
+            .get_static_field("android/R$drawable", "ic_dialog_info", "I")?
+            .i()?;
+        let title = env.new_string(title)?;
+        let text = env.new_string(text)?;
+        let builder_calls: [(&str, &str, JValue); 6] = [
+            ("setSmallIcon", "(I)", icon.into()),
+            (
+                "setContentTitle",
+                "(Ljava/lang/CharSequence;)",
+                (&title).into(),
+            ),
+            (
+                "setContentText",
+                "(Ljava/lang/CharSequence;)",
+                (&text).into(),
+            ),
+            (
+                "setContentIntent",
+                "(Landroid/app/PendingIntent;)",
+                (&pending).into(),
+            ),
+            ("setAutoCancel", "(Z)", true.into()),
+            ("setOnlyAlertOnce", "(Z)", true.into()),
+        ];
+        for (method, args, value) in builder_calls {
+            let sig = format!("{}Landroid/app/Notification$Builder;", args);
+            env.call_method(&builder, method, sig, &[value])?;
+        }
+        let notification = env
+            .call_method(&builder, "build", "()Landroid/app/Notification;", &[])?
+            .l()?;
+        env.call_method(
+            &manager,
+            "notify",
+            "(ILandroid/app/Notification;)V",
+            &[id.into(), (&notification).into()],
+        )?;
+        Ok(())
+    })
+    .is_some()
+}
+
+/// Take down everything [`notify`] posted.
+pub fn cancel_notifications(app: &AndroidApp) {
+    with_activity(app, "Cancelling notifications", |env, activity| {
+        let manager = system_service(env, activity, "notification")?;
+        env.call_method(&manager, "cancelAll", "()V", &[])?;
+        Ok(())
+    });
+}
+
+/// A partial wake lock we hold; give it back with [`WakeLock::release`].
+pub struct WakeLock(GlobalRef);
+
+impl WakeLock {
+    pub fn acquire(app: &AndroidApp) -> Option<Self> {
+        with_activity(app, "Acquiring wake lock", |env, activity| {
+            let power = system_service(env, activity, "power")?;
+            let tag = env.new_string(WAKE_LOCK_TAG)?;
+            let lock = env
+                .call_method(
+                    &power,
+                    "newWakeLock",
+                    "(ILjava/lang/String;)Landroid/os/PowerManager$WakeLock;",
+                    &[PARTIAL_WAKE_LOCK.into(), (&tag).into()],
+                )?
+                .l()?;
+            env.call_method(&lock, "acquire", "()V", &[])?;
+            Ok(WakeLock(env.new_global_ref(lock)?))
+        })
+    }
+
+    pub fn release(self, app: &AndroidApp) {
+        with_activity(app, "Releasing wake lock", |env, _| {
+            env.call_method(&self.0, "release", "()V", &[])?;
+            // Drop the reference while the thread is attached.
+            drop(self);
+            Ok(())
+        });
+    }
+}
+
+/// Intent for the keep-alive service.
+fn keep_alive_intent<'local>(
+    env: &mut JNIEnv<'local>,
+    activity: &JObject,
+) -> jni::errors::Result<JObject<'local>> {
+    let package = package_name(env, activity)?;
+    let class = env.new_string(package + KEEP_ALIVE_SERVICE)?;
+    let intent = env.new_object("android/content/Intent", "()V", &[])?;
+    env.call_method(
+        &intent,
+        "setClassName",
+        "(Landroid/content/Context;Ljava/lang/String;)Landroid/content/Intent;",
+        &[activity.into(), (&class).into()],
+    )?;
+    Ok(intent)
+}
+
+/// Start the foreground service that keeps the process alive in the
+/// background, its notification saying `text`. Android 12 and later only
+/// allow this while the app is in sight. Returns whether it started.
+pub fn start_keep_alive(app: &AndroidApp, text: &str) -> bool {
+    let sdk = app.config().sdk_version();
+    with_activity(app, "Starting keep-alive service", |env, activity| {
+        let intent = keep_alive_intent(env, activity)?;
+        let key = env.new_string("text")?;
+        let text = env.new_string(text)?;
+        env.call_method(
+            &intent,
+            "putExtra",
+            "(Ljava/lang/String;Ljava/lang/String;)Landroid/content/Intent;",
+            &[(&key).into(), (&text).into()],
+        )?;
+        let method = if sdk >= FOREGROUND_SERVICE_SDK {
+            "startForegroundService"
+        } else {
+            "startService"
+        };
+        env.call_method(
+            activity,
+            method,
+            "(Landroid/content/Intent;)Landroid/content/ComponentName;",
+            &[(&intent).into()],
+        )?;
+        Ok(())
+    })
+    .is_some()
+}
+
+/// Stop the service [`start_keep_alive`] started, taking its notification
+/// with it.
+pub fn stop_keep_alive(app: &AndroidApp) {
+    with_activity(app, "Stopping keep-alive service", |env, activity| {
+        let intent = keep_alive_intent(env, activity)?;
+        env.call_method(
+            activity,
+            "stopService",
+            "(Landroid/content/Intent;)Z",
+            &[(&intent).into()],
+        )?;
+        Ok(())
+    });
+}
+
+/// Pixels in from each edge of the window that something of the system's
+/// covers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Insets {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+/// What covers the window's edges: the system bars, or the cutout around
+/// a camera, whichever reaches further in. The soft keyboard counts as a
+/// bar.
+pub fn safe_insets(app: &AndroidApp) -> Insets {
+    let sdk = app.config().sdk_version();
+    with_activity(app, "Reading window insets", |env, activity| {
+        let window = env
+            .call_method(activity, "getWindow", "()Landroid/view/Window;", &[])?
+            .l()?;
+        let decor = env
+            .call_method(&window, "getDecorView", "()Landroid/view/View;", &[])?
+            .l()?;
+        let insets = env
+            .call_method(
+                &decor,
+                "getRootWindowInsets",
+                "()Landroid/view/WindowInsets;",
+                &[],
+            )?
+            .l()?;
+        if insets.is_null() {
+            return Ok(Insets::default());
+        }
+        let bars = insets_from(
+            env,
+            &insets,
+            [
+                "getSystemWindowInsetLeft",
+                "getSystemWindowInsetTop",
+                "getSystemWindowInsetRight",
+                "getSystemWindowInsetBottom",
+            ],
+        )?;
+        if sdk < DISPLAY_CUTOUT_SDK {
+            return Ok(bars);
+        }
+        let cutout = env
+            .call_method(
+                &insets,
+                "getDisplayCutout",
+                "()Landroid/view/DisplayCutout;",
+                &[],
+            )?
+            .l()?;
+        if cutout.is_null() {
+            return Ok(bars);
+        }
+        let cutout = insets_from(
+            env,
+            &cutout,
+            [
+                "getSafeInsetLeft",
+                "getSafeInsetTop",
+                "getSafeInsetRight",
+                "getSafeInsetBottom",
+            ],
+        )?;
+        Ok(Insets {
+            left: bars.left.max(cutout.left),
+            top: bars.top.max(cutout.top),
+            right: bars.right.max(cutout.right),
+            bottom: bars.bottom.max(cutout.bottom),
+        })
+    })
+    .unwrap_or_default()
+}
+
+/// Insets from the four `()I` getters of `object` named, left first.
+fn insets_from(
+    env: &mut JNIEnv,
+    object: &JObject,
+    getters: [&str; 4],
+) -> jni::errors::Result<Insets> {
+    let mut values = [0; 4];
+    for (value, getter) in values.iter_mut().zip(getters) {
+        *value = env.call_method(object, getter, "()I", &[])?.i()?;
+    }
+    let [left, top, right, bottom] = values;
+    Ok(Insets {
+        left,
+        top,
+        right,
+        bottom,
+    })
+}
+
+/// Lay the window out behind translucent system bars, or back between
+/// opaque ones.
+pub fn set_edge_to_edge(app: &AndroidApp, edge_to_edge: bool) {
+    let flags = WindowManagerFlags::LAYOUT_IN_SCREEN
+        | WindowManagerFlags::LAYOUT_NO_LIMITS
+        | WindowManagerFlags::from_bits_retain(TRANSLUCENT_BARS);
+    if edge_to_edge {
+        app.set_window_flags(flags, WindowManagerFlags::empty());
+    } else {
+        app.set_window_flags(WindowManagerFlags::empty(), flags);
+    }
+}
+
+/// Show the wallpaper through the window, for a background that isn't
+/// opaque. The window is only made translucent then: compositing it over
+/// the wallpaper costs every frame.
+pub fn show_wallpaper(app: &AndroidApp) {
+    app.set_window_flags(
+        WindowManagerFlags::SHOW_WALLPAPER,
+        WindowManagerFlags::empty(),
+    );
+    // SAFETY: the activity outlives the app's main thread.
+    unsafe {
+        ndk_sys::ANativeActivity_setWindowFormat(
+            app.activity_as_ptr().cast(),
+            PIXEL_FORMAT_TRANSLUCENT,
+        );
+    }
+}
+
+/// Where the APK's native libraries are unpacked: the one place in the
+/// app's reach the system always lets it execute files from.
+pub fn native_library_dir(app: &AndroidApp) -> Option<PathBuf> {
+    with_activity(app, "Reading native library dir", |env, activity| {
+        let info = env
+            .call_method(
+                activity,
+                "getApplicationInfo",
+                "()Landroid/content/pm/ApplicationInfo;",
+                &[],
+            )?
+            .l()?;
+        let dir = env
+            .get_field(&info, "nativeLibraryDir", "Ljava/lang/String;")?
+            .l()?;
+        if dir.is_null() {
+            return Ok(None);
+        }
+        let dir: String = env.get_string(&JString::from(dir))?.into();
+        Ok(Some(PathBuf::from(dir)))
+    })
+    .flatten()
+}
+
+/// The active network's DNS servers, preferred first; empty when offline
+/// or the system won't say.
+pub fn dns_servers(app: &AndroidApp) -> Vec<String> {
+    with_activity(app, "Reading DNS servers", |env, activity| {
+        let mut servers = Vec::new();
+        let connectivity = system_service(env, activity, "connectivity")?;
+        if connectivity.is_null() {
+            return Ok(servers);
+        }
+        let network = env
+            .call_method(
+                &connectivity,
+                "getActiveNetwork",
+                "()Landroid/net/Network;",
+                &[],
+            )?
+            .l()?;
+        if network.is_null() {
+            return Ok(servers);
+        }
+        let link = env
+            .call_method(
+                &connectivity,
+                "getLinkProperties",
+                "(Landroid/net/Network;)Landroid/net/LinkProperties;",
+                &[(&network).into()],
+            )?
+            .l()?;
+        if link.is_null() {
+            return Ok(servers);
+        }
+        let list = env
+            .call_method(&link, "getDnsServers", "()Ljava/util/List;", &[])?
+            .l()?;
+        let count = env.call_method(&list, "size", "()I", &[])?.i()?;
+        for i in 0..count {
+            let address = env
+                .call_method(&list, "get", "(I)Ljava/lang/Object;", &[i.into()])?
+                .l()?;
+            let host = JString::from(
+                env.call_method(&address, "getHostAddress", "()Ljava/lang/String;", &[])?
+                    .l()?,
+            );
+            servers.push(env.get_string(&host)?.into());
+        }
+        Ok(servers)
+    })
+    .unwrap_or_default()
+}
+
+/// Buzz for `duration` at `amplitude`, from 1 to 255. Devices before
+/// Android 8 can't set the strength and use their default.
+pub fn vibrate(app: &AndroidApp, duration: Duration, amplitude: u8) -> bool {
+    let sdk = app.config().sdk_version();
+    with_activity(app, "Vibrating", |env, activity| {
+        let vibrator = system_service(env, activity, "vibrator")?;
+        if vibrator.is_null() {
+            return Ok(());
+        }
+        let ms = duration.as_millis() as i64;
+        if sdk >= VIBRATION_EFFECT_SDK {
+            let effect = env
+                .call_static_method(
+                    "android/os/VibrationEffect",
+                    "createOneShot",
+                    "(JI)Landroid/os/VibrationEffect;",
+                    &[ms.into(), i32::from(amplitude).into()],
+                )?
+                .l()?;
+            env.call_method(
+                &vibrator,
+                "vibrate",
+                "(Landroid/os/VibrationEffect;)V",
+                &[(&effect).into()],
+            )?;
+        } else {
+            env.call_method(&vibrator, "vibrate", "(J)V", &[ms.into()])?;
+        }
+        Ok(())
+    })
+    .is_some()
+}
+
+/// Show `text` in a toast, briefly if `brief`.
+pub fn toast(app: &AndroidApp, text: &str, brief: bool) -> bool {
+    with_activity(app, "Showing toast", |env, activity| {
+        let bridge = app_class(env, activity, CONTROL_BRIDGE)?;
+        let text = env.new_string(text)?;
+        env.call_static_method(
+            &bridge,
+            "toast",
+            "(Landroid/app/Activity;Ljava/lang/String;Z)V",
+            &[activity.into(), (&text).into(), brief.into()],
+        )?;
+        Ok(())
+    })
+    .is_some()
+}
+
+/// The battery, as its last ACTION_BATTERY_CHANGED broadcast has it. The
+/// codes are BatteryManager's.
+#[derive(Clone, Copy, Debug)]
+pub struct BatteryStatus {
+    pub percentage: i32,
+    /// BATTERY_STATUS_*: charging, full and so on.
+    pub status: i32,
+    /// BATTERY_PLUGGED_* bits; none when on battery.
+    pub plugged: i32,
+    /// BATTERY_HEALTH_*.
+    pub health: i32,
+    /// In degrees Celsius.
+    pub temperature: f32,
+}
+
+pub fn battery_status(app: &AndroidApp) -> Option<BatteryStatus> {
+    with_activity(app, "Reading battery status", |env, activity| {
+        let action = env.new_string("android.intent.action.BATTERY_CHANGED")?;
+        let filter = env.new_object(
+            "android/content/IntentFilter",
+            "(Ljava/lang/String;)V",
+            &[(&action).into()],
+        )?;
+        // With no receiver this only returns the sticky broadcast.
+        let intent = env
+            .call_method(
+                activity,
+                "registerReceiver",
+                "(Landroid/content/BroadcastReceiver;Landroid/content/IntentFilter;)Landroid/content/Intent;",
+                &[(&JObject::null()).into(), (&filter).into()],
+            )?
+            .l()?;
+        if intent.is_null() {
+            return Ok(None);
+        }
+        let mut extra = |name: &str| -> jni::errors::Result<i32> {
+            let name = env.new_string(name)?;
+            env.call_method(
+                &intent,
+                "getIntExtra",
+                "(Ljava/lang/String;I)I",
+                &[(&name).into(), (-1).into()],
+            )?
+            .i()
+        };
+        let level = extra("level")?;
+        let scale = extra("scale")?;
+        Ok(Some(BatteryStatus {
+            percentage: if scale > 0 { level * 100 / scale } else { level },
+            status: extra("status")?,
+            plugged: extra("plugged")?,
+            health: extra("health")?,
+            // Tenths of a degree.
+            temperature: extra("temperature")? as f32 / 10.0,
+        }))
+    })
+    .flatten()
+}
+
+/// The app's own class `name`. FindClass on threads the native side
+/// started only sees the system's classes, so it goes through the
+/// activity's class loader.
+fn app_class<'local>(
+    env: &mut JNIEnv<'local>,
+    activity: &JObject,
+    name: &str,
+) -> jni::errors::Result<JClass<'local>> {
+    let loader = env
+        .call_method(activity, "getClassLoader", "()Ljava/lang/ClassLoader;", &[])?
+        .l()?;
+    let name = env.new_string(name)?;
+    let class = env
+        .call_method(
+            &loader,
+            "loadClass",
+            "(Ljava/lang/String;)Ljava/lang/Class;",
+            &[(&name).into()],
+        )?
+        .l()?;
+    Ok(JClass::from(class))
+}
+
+fn string_extra(
+    env: &mut JNIEnv,
+    intent: &JObject,
+    name: &str,
+) -> jni::errors::Result<Option<String>> {
+    let name = env.new_string(name)?;
+    let value = env
+        .call_method(
+            intent,
+            "getStringExtra",
+            "(Ljava/lang/String;)Ljava/lang/String;",
+            &[(&name).into()],
+        )?
+        .l()?;
+    if value.is_null() {
+        return Ok(None);
+    }
+    let value: String = env.get_string(&JString::from(value))?.into();
+    Ok(Some(value))
+}
+
+fn string_array_extra(
+    env: &mut JNIEnv,
+    intent: &JObject,
+    name: &str,
+) -> jni::errors::Result<Vec<String>> {
+    let name = env.new_string(name)?;
+    let array = env
+        .call_method(
+            intent,
+            "getStringArrayExtra",
+            "(Ljava/lang/String;)[Ljava/lang/String;",
+            &[(&name).into()],
+        )?
+        .l()?;
+    if array.is_null() {
+        return Ok(Vec::new());
+    }
+    let array = JObjectArray::from(array);
+    let len = env.get_array_length(&array)?;
+    let mut values = Vec::with_capacity(len as usize);
+    for i in 0..len {
+        let value = env.get_object_array_element(&array, i)?;
+        if value.is_null() {
+            values.push(String::new());
+        } else {
+            values.push(env.get_string(&JString::from(value))?.into());
+        }
+    }
+    Ok(values)
+}
+
+/// Set when the running activity is handed a new intent.
+static NEW_INTENT: AtomicBool = AtomicBool::new(false);
+
+/// Called by `TerminalActivity.onNewIntent`, which has already made the
+/// intent the activity's own. The resume that follows wakes the event loop.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_TerminalActivity_nativeNewIntent(
+    _env: JNIEnv,
+    _class: JClass,
+) {
+    NEW_INTENT.store(true, Ordering::Release);
+}
+
+/// Whether a new intent came in since the last call; read it with
+/// [`run_command`] or [`shared_content`].
+pub fn take_new_intent() -> bool {
+    NEW_INTENT.swap(false, Ordering::Acquire)
+}
+
+/// The action of the activity's intent: the one that started it, or the
+/// latest one handed to it since.
+fn launch_action<'local>(
+    env: &mut JNIEnv<'local>,
+    activity: &JObject,
+) -> jni::errors::Result<Option<(JObject<'local>, String)>> {
+    let intent = env
+        .call_method(activity, "getIntent", "()Landroid/content/Intent;", &[])?
+        .l()?;
+    if intent.is_null() {
+        return Ok(None);
+    }
+    let action = env
+        .call_method(&intent, "getAction", "()Ljava/lang/String;", &[])?
+        .l()?;
+    if action.is_null() {
+        return Ok(None);
+    }
+    let action: String = env.get_string(&JString::from(action))?.into();
+    Ok(Some((intent, action)))
+}
+
+/// The command in the activity's intent, if it is a
+/// [`RUN_COMMAND_ACTION`] one sent through [`RUN_COMMAND_ALIAS`].
+/// Extras: `command` (required), `args` (a string array), `cwd`,
+/// `background` and `environment`.
+pub fn run_command(app: &AndroidApp) -> Option<RunCommand> {
+    with_activity(app, "Reading launch intent", |env, activity| {
+        let Some((intent, action)) = launch_action(env, activity)? else {
+            return Ok(None);
+        };
+        if action != RUN_COMMAND_ACTION {
+            return Ok(None);
+        }
+        let component = env
+            .call_method(
+                &intent,
+                "getComponent",
+                "()Landroid/content/ComponentName;",
+                &[],
+            )?
+            .l()?;
+        let class = if component.is_null() {
+            None
+        } else {
+            let class = env
+                .call_method(&component, "getClassName", "()Ljava/lang/String;", &[])?
+                .l()?;
+            Some(String::from(env.get_string(&JString::from(class))?))
+        };
+        if class.as_deref() != Some(RUN_COMMAND_ALIAS) {
+            log::warn!(
+                "Refusing {} sent to {:?} rather than {}",
+                RUN_COMMAND_ACTION,
+                class,
+                RUN_COMMAND_ALIAS
+            );
+            return Ok(None);
+        }
+        let Some(command) = string_extra(env, &intent, "command")? else {
+            log::warn!("{} intent without a command", RUN_COMMAND_ACTION);
+            return Ok(None);
+        };
+        let args = string_array_extra(env, &intent, "args")?;
+        let cwd = string_extra(env, &intent, "cwd")?.map(PathBuf::from);
+        let name = env.new_string("background")?;
+        let background = env
+            .call_method(
+                &intent,
+                "getBooleanExtra",
+                "(Ljava/lang/String;Z)Z",
+                &[(&name).into(), false.into()],
+            )?
+            .z()?;
+        let environment = string_extra(env, &intent, "environment")?;
+        Ok(Some(RunCommand {
+            command,
+            args,
+            cwd,
+            background,
+            environment,
+        }))
+    })
+    .flatten()
+}
+
+/// How long a download may take to connect, or go without data.
+const DOWNLOAD_TIMEOUT_MS: i32 = 30_000;
+/// Bytes copied out of Java per read.
+const DOWNLOAD_CHUNK: i32 = 64 * 1024;
+
+/// The text or file shared with us, if the activity's intent came from
+/// the share sheet. Programs in the shell can't open content URIs, so a file
+/// is copied into `dir` first.
+pub fn shared_content(app: &AndroidApp, dir: &Path) -> Option<Shared> {
+    with_activity(app, "Reading shared content", |env, activity| {
+        let Some((intent, action)) = launch_action(env, activity)? else {
+            return Ok(None);
+        };
+        if action != SEND_ACTION {
+            return Ok(None);
+        }
+        let name = env.new_string("android.intent.extra.STREAM")?;
+        let uri = env
+            .call_method(
+                &intent,
+                "getParcelableExtra",
+                "(Ljava/lang/String;)Landroid/os/Parcelable;",
+                &[(&name).into()],
+            )?
+            .l()?;
+        if uri.is_null() {
+            let text = string_extra(env, &intent, "android.intent.extra.TEXT")?;
+            return Ok(text.map(Shared::Text));
+        }
+
+        let resolver = env
+            .call_method(
+                activity,
+                "getContentResolver",
+                "()Landroid/content/ContentResolver;",
+                &[],
+            )?
+            .l()?;
+        let mode = env.new_string("r")?;
+        let descriptor = env
+            .call_method(
+                &resolver,
+                "openFileDescriptor",
+                "(Landroid/net/Uri;Ljava/lang/String;)Landroid/os/ParcelFileDescriptor;",
+                &[(&uri).into(), (&mode).into()],
+            )?
+            .l()?;
+        if descriptor.is_null() {
+            return Ok(None);
+        }
+        let fd = env.call_method(&descriptor, "detachFd", "()I", &[])?.i()?;
+        // SAFETY: detachFd hands the descriptor over to us.
+        let mut source = unsafe { File::from_raw_fd(fd) };
+
+        let segment = env
+            .call_method(&uri, "getLastPathSegment", "()Ljava/lang/String;", &[])?
+            .l()?;
+        let segment: String = if segment.is_null() {
+            String::new()
+        } else {
+            env.get_string(&JString::from(segment))?.into()
+        };
+        let path = match copy_shared_file(&mut source, &segment, dir) {
+            Ok(path) => path,
+            Err(e) => {
+                log::warn!("Failed to copy shared file into {:?}: {:?}", dir, e);
+                return Ok(None);
+            }
+        };
+        log::info!("Shared file copied to {:?}", path);
+        Ok(Some(Shared::File(path)))
+    })
+    .flatten()
+}
+
+/// Copy `source` into `dir` under the last part of `name`, numbered if a
+/// file of that name is already there.
+fn copy_shared_file(source: &mut File, name: &str, dir: &Path) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let name = match name.rsplit('/').next() {
+        Some(name) if !name.is_empty() && name != "." && name != ".." => name,
+        _ => "shared",
+    };
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+        _ => (name, None),
+    };
+    let mut path = dir.join(name);
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = dir.join(match extension {
+            Some(extension) => format!("{}-{}.{}", stem, n, extension),
+            None => format!("{}-{}", stem, n),
+        });
+    }
+    let mut target = File::create(&path)?;
+    std::io::copy(source, &mut target)?;
+    Ok(path)
+}
+
+/// Fetch `url` into `dest`, carrying on from the end of whatever is already
+/// there if the server allows. `progress` is told the bytes fetched so far
+/// and, if the server says, the total.
+pub fn download(
+    app: &AndroidApp,
+    url: &str,
+    dest: &Path,
+    progress: &mut dyn FnMut(u64, Option<u64>),
+) -> io::Result<()> {
+    let offset = std::fs::metadata(dest).map_or(0, |m| m.len());
+    let result = with_activity(app, "Downloading", |env, _activity| {
+        let spec = env.new_string(url)?;
+        let target = env.new_object("java/net/URL", "(Ljava/lang/String;)V", &[(&spec).into()])?;
+        let connection = env
+            .call_method(&target, "openConnection", "()Ljava/net/URLConnection;", &[])?
+            .l()?;
+        for method in ["setConnectTimeout", "setReadTimeout"] {
+            env.call_method(&connection, method, "(I)V", &[DOWNLOAD_TIMEOUT_MS.into()])?;
+        }
+        if offset > 0 {
+            let key = env.new_string("Range")?;
+            let value = env.new_string(format!("bytes={}-", offset))?;
+            env.call_method(
+                &connection,
+                "setRequestProperty",
+                "(Ljava/lang/String;Ljava/lang/String;)V",
+                &[(&key).into(), (&value).into()],
+            )?;
+        }
+        let status = env
+            .call_method(&connection, "getResponseCode", "()I", &[])?
+            .i()?;
+        let (file, mut done) = match status {
+            206 => (OpenOptions::new().append(true).open(dest), offset),
+            // The server ignored the range, so start over.
+            200 => (File::create(dest), 0),
+            // Nothing left past the end: the file is already whole.
+            416 if offset > 0 => {
+                env.call_method(&connection, "disconnect", "()V", &[])?;
+                return Ok(Ok(()));
+            }
+            _ => {
+                env.call_method(&connection, "disconnect", "()V", &[])?;
+                let e = io::Error::other(format!("HTTP status {}", status));
+                return Ok(Err(e));
+            }
+        };
+        let mut file = match file {
+            Ok(file) => file,
+            Err(e) => return Ok(Err(e)),
+        };
+        let length = env
+            .call_method(&connection, "getContentLengthLong", "()J", &[])?
+            .j()?;
+        let total = u64::try_from(length).ok().map(|length| done + length);
+        log::info!("Downloading {} from byte {} of {:?}", url, done, total);
+
+        let stream = env
+            .call_method(
+                &connection,
+                "getInputStream",
+                "()Ljava/io/InputStream;",
+                &[],
+            )?
+            .l()?;
+        let buffer = env.new_byte_array(DOWNLOAD_CHUNK)?;
+        let mut chunk = vec![0i8; DOWNLOAD_CHUNK as usize];
+        let written = loop {
+            let n = env
+                .call_method(&stream, "read", "([B)I", &[(&buffer).into()])?
+                .i()?;
+            let Ok(n) = usize::try_from(n) else {
+                break Ok(());
+            };
+            env.get_byte_array_region(&buffer, 0, &mut chunk[..n])?;
+            let bytes: Vec<u8> = chunk[..n].iter().map(|&b| b as u8).collect();
+            if let Err(e) = file.write_all(&bytes) {
+                break Err(e);
+            }
+            done += n as u64;
+            progress(done, total);
+        };
+        env.call_method(&stream, "close", "()V", &[])?;
+        env.call_method(&connection, "disconnect", "()V", &[])?;
+        Ok(written)
+    });
+    // Java exceptions, such as a dropped connection, are already logged.
+    result.unwrap_or_else(|| Err(io::Error::other("download failed")))
+}
@@ -0,0 +1,186 @@
+use std::ffi::CString;
+use std::num::NonZeroU32;
+
+use glutin::config::Config;
+use glutin::{
+    config::ConfigTemplateBuilder,
+    context::{
+        ContextApi, ContextAttributesBuilder, NotCurrentGlContext, PossiblyCurrentContext, Version,
+    },
+    display::{Display, DisplayApiPreference, GlDisplay},
+    prelude::GlSurface,
+    surface::{Rect as GlRect, Surface as GlutinSurface, SurfaceAttributesBuilder, WindowSurface},
+};
+use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+use skia_safe::{
+    gpu::{
+        backend_render_targets, direct_contexts, gl::FramebufferInfo, surfaces, DirectContext,
+        Protected, SurfaceOrigin,
+    },
+    ColorType, IRect, Surface,
+};
+use winit::{event_loop::ActiveEventLoop, window::Window};
+
+/// GLES 2 through glutin/EGL, drawing straight into the default framebuffer.
+///
+/// Fields drop in order: Skia has to free its GL objects while the context
+/// they belong to is still current.
+pub struct GlBackend {
+    skia_surface: Surface,
+    gr_context: DirectContext,
+    gl_surface: GlutinSurface<WindowSurface>,
+    gl_context: PossiblyCurrentContext,
+    #[allow(dead_code)]
+    gl_config: Config,
+    /// Cleared after the first failed damage swap so we stop retrying.
+    damage_supported: bool,
+}
+
+impl GlBackend {
+    pub fn new(
+        event_loop: &ActiveEventLoop,
+        window: &Window,
+        translucent: bool,
+    ) -> Result<Self, String> {
+        let template = ConfigTemplateBuilder::new()
+            .with_alpha_size(8)
+            .with_depth_size(0)
+            .with_stencil_size(8)
+            .with_transparency(translucent)
+            .build();
+
+        let raw_display_handle = event_loop
+            .display_handle()
+            .map_err(|e| e.to_string())?
+            .as_raw();
+        let gl_display = unsafe { Display::new(raw_display_handle, DisplayApiPreference::Egl) }
+            .map_err(|e| format!("EGL display: {}", e))?;
+
+        // The window already exists, we only need a config that can render
+        // to it.
+        let gl_config = unsafe { gl_display.find_configs(template) }
+            .map_err(|e| format!("EGL config: {}", e))?
+            .next()
+            .ok_or("EGL returned an empty config list")?;
+
+        let raw_window_handle = window.window_handle().map_err(|e| e.to_string())?.as_raw();
+
+        let context_attrs = ContextAttributesBuilder::new()
+            .with_context_api(ContextApi::Gles(Some(Version::new(2, 0))))
+            .build(Some(raw_window_handle));
+
+        let not_current = unsafe { gl_display.create_context(&gl_config, &context_attrs) }
+            .map_err(|e| format!("create_context: {}", e))?;
+
+        let size = window.inner_size();
+
+        let surface_attrs = SurfaceAttributesBuilder::<WindowSurface>::new().build(
+            raw_window_handle,
+            NonZeroU32::new(size.width.max(1)).unwrap(),
+            NonZeroU32::new(size.height.max(1)).unwrap(),
+        );
+
+        let gl_surface = unsafe { gl_display.create_window_surface(&gl_config, &surface_attrs) }
+            .map_err(|e| format!("create_window_surface: {}", e))?;
+
+        let gl_context = not_current
+            .make_current(&gl_surface)
+            .map_err(|e| format!("make_current: {}", e))?;
+
+        gl_surface
+            .set_swap_interval(&gl_context, glutin::surface::SwapInterval::DontWait)
+            .unwrap_or_else(|e| log::warn!("Failed to disable VSync: {:?}", e));
+
+        gl::load_with(|s| gl_display.get_proc_address(&CString::new(s).unwrap()));
+
+        let interface = skia_safe::gpu::gl::Interface::new_load_with(|s| {
+            gl_display.get_proc_address(&CString::new(s).unwrap())
+        })
+        .ok_or("Failed to create Skia GL interface")?;
+
+        let mut gr_context = direct_contexts::make_gl(interface, None)
+            .ok_or("Failed to create Skia DirectContext")?;
+
+        let fb_info = FramebufferInfo {
+            fboid: 0,
+            format: skia_safe::gpu::gl::Format::RGBA8.into(),
+            protected: Protected::No,
+        };
+
+        let backend_rt =
+            backend_render_targets::make_gl((size.width as i32, size.height as i32), 0, 8, fb_info);
+
+        let skia_surface = surfaces::wrap_backend_render_target(
+            &mut gr_context,
+            &backend_rt,
+            SurfaceOrigin::BottomLeft,
+            ColorType::RGBA8888,
+            None,
+            None,
+        )
+        .ok_or("Failed to create Skia surface")?;
+
+        Ok(Self {
+            gl_config,
+            gl_context,
+            gl_surface,
+            gr_context,
+            skia_surface,
+            damage_supported: true,
+        })
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), String> {
+        let fb_info = FramebufferInfo {
+            fboid: 0,
+            format: skia_safe::gpu::gl::Format::RGBA8.into(),
+            protected: Protected::No,
+        };
+
+        let backend_rt =
+            backend_render_targets::make_gl((width as i32, height as i32), 0, 0, fb_info);
+
+        self.skia_surface = surfaces::wrap_backend_render_target(
+            &mut self.gr_context,
+            &backend_rt,
+            SurfaceOrigin::BottomLeft,
+            ColorType::RGBA8888,
+            None,
+            None,
+        )
+        .ok_or_else(|| format!("Failed to create {}x{} Skia surface", width, height))?;
+        Ok(())
+    }
+
+    pub fn frame(&mut self) -> Option<&mut Surface> {
+        Some(&mut self.skia_surface)
+    }
+
+    /// Swap buffers, telling the compositor which parts changed when the
+    /// driver has EGL_KHR_swap_buffers_with_damage. The whole frame is
+    /// always redrawn, so there is no buffer age to track.
+    pub fn present(&mut self, damage: Option<&[IRect]>) -> Result<(), String> {
+        self.gr_context.flush_and_submit();
+        if let Some(damage) = damage.filter(|_| self.damage_supported) {
+            // EGL counts rows from the bottom of the surface.
+            let height = self.skia_surface.height();
+            let rects: Vec<GlRect> = damage
+                .iter()
+                .map(|r| GlRect::new(r.left, height - r.bottom, r.width(), r.height()))
+                .collect();
+            match self
+                .gl_surface
+                .swap_buffers_with_damage(&self.gl_context, &rects)
+            {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    log::info!("Damage swaps unavailable, swapping whole frames: {}", e);
+                    self.damage_supported = false;
+                }
+            }
+        }
+        self.gl_surface
+            .swap_buffers(&self.gl_context)
+            .map_err(|e| format!("swap_buffers: {}", e))
+    }
+}
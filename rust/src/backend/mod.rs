@@ -0,0 +1,114 @@
+//! Rendering backends. Each one owns the window's surface and hands the
+//! [`Renderer`](crate::core::Renderer) a Skia surface to draw a frame into.
+
+pub mod gl;
+pub mod raster;
+#[cfg(feature = "vulkan")]
+pub mod vulkan;
+
+use skia_safe::{IRect, Surface};
+use winit::{event_loop::ActiveEventLoop, window::Window};
+
+use crate::config::AppConfig;
+use gl::GlBackend;
+use raster::RasterBackend;
+#[cfg(feature = "vulkan")]
+use vulkan::VulkanBackend;
+
+/// Backend requested by `[render] backend` in the config.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BackendKind {
+    #[default]
+    Gles,
+    Vulkan,
+    /// CPU rendering only; also the last resort when GPU init fails.
+    Raster,
+}
+
+pub enum Backend {
+    Gl(GlBackend),
+    Raster(RasterBackend),
+    #[cfg(feature = "vulkan")]
+    Vulkan(VulkanBackend),
+}
+
+impl Backend {
+    /// Create the window and the configured backend, falling back from
+    /// Vulkan to GLES to CPU raster as each fails to initialize. None if
+    /// even raster fails; there is nothing to draw with until the next
+    /// window.
+    pub fn create(event_loop: &ActiveEventLoop, config: &AppConfig) -> (Window, Option<Self>) {
+        let translucent = config.background_opacity < 1.0;
+        let attrs = Window::default_attributes().with_transparent(translucent);
+        let window = event_loop
+            .create_window(attrs)
+            .expect("Failed to create window");
+
+        if config.backend == BackendKind::Vulkan {
+            #[cfg(feature = "vulkan")]
+            match VulkanBackend::new(&window) {
+                Ok(backend) => {
+                    log::info!("Using Vulkan backend");
+                    return (window, Some(Backend::Vulkan(backend)));
+                }
+                Err(e) => log::warn!("Vulkan init failed, using GLES: {}", e),
+            }
+            #[cfg(not(feature = "vulkan"))]
+            log::warn!("Built without the vulkan feature, using GLES");
+        }
+
+        if config.backend != BackendKind::Raster {
+            match GlBackend::new(event_loop, &window, translucent) {
+                Ok(backend) => return (window, Some(Backend::Gl(backend))),
+                Err(e) => log::error!("GLES init failed, falling back to CPU: {}", e),
+            }
+        }
+
+        match RasterBackend::new(&window) {
+            Ok(backend) => (window, Some(Backend::Raster(backend))),
+            Err(e) => {
+                log::error!("No usable rendering backend: {}", e);
+                (window, None)
+            }
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        match self {
+            Backend::Gl(b) => {
+                if let Err(e) = b.resize(width, height) {
+                    log::error!("Failed to resize GL surface: {}", e);
+                }
+            }
+            Backend::Raster(b) => b.resize(width, height),
+            #[cfg(feature = "vulkan")]
+            Backend::Vulkan(b) => b.resize(width, height),
+        }
+    }
+
+    /// Surface for the next frame, or None if there is nothing to draw on
+    /// right now (e.g. an out-of-date swapchain).
+    pub fn frame(&mut self) -> Option<&mut Surface> {
+        match self {
+            Backend::Gl(b) => b.frame(),
+            Backend::Raster(b) => b.frame(),
+            #[cfg(feature = "vulkan")]
+            Backend::Vulkan(b) => b.frame(),
+        }
+    }
+
+    /// Submit the frame returned by [`Backend::frame`] and show it.
+    /// `damage` lists the changed areas, or None if everything changed.
+    pub fn present(&mut self, damage: Option<&[IRect]>) {
+        match self {
+            Backend::Gl(b) => {
+                if let Err(e) = b.present(damage) {
+                    log::warn!("{}", e);
+                }
+            }
+            Backend::Raster(b) => b.present(),
+            #[cfg(feature = "vulkan")]
+            Backend::Vulkan(b) => b.present(),
+        }
+    }
+}
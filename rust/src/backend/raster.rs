@@ -0,0 +1,82 @@
+use std::ptr::NonNull;
+
+use ndk::hardware_buffer_format::HardwareBufferFormat;
+use ndk::native_window::NativeWindow;
+use raw_window_handle::{HasWindowHandle, RawWindowHandle};
+use skia_safe::{surfaces, AlphaType, ColorType, ImageInfo, Surface};
+use winit::window::Window;
+
+/// Software fallback: Skia draws into a CPU raster surface whose pixels are
+/// copied into the locked ANativeWindow buffer on present.
+pub struct RasterBackend {
+    native_window: NativeWindow,
+    surface: Surface,
+}
+
+impl RasterBackend {
+    pub fn new(window: &Window) -> Result<Self, String> {
+        let handle = window.window_handle().map_err(|e| e.to_string())?;
+        let RawWindowHandle::AndroidNdk(handle) = handle.as_raw() else {
+            return Err("not an Android window".into());
+        };
+        let ptr =
+            NonNull::new(handle.a_native_window.as_ptr().cast()).ok_or("null ANativeWindow")?;
+        let native_window = unsafe { NativeWindow::clone_from_ptr(ptr) };
+
+        // 0x0 keeps the buffers at the window's own size.
+        native_window
+            .set_buffers_geometry(0, 0, Some(HardwareBufferFormat::R8G8B8A8_UNORM))
+            .map_err(|e| format!("set_buffers_geometry: {}", e))?;
+
+        let size = window.inner_size();
+        let surface = raster_surface(size.width, size.height)?;
+        log::warn!("Using CPU raster rendering");
+        Ok(Self {
+            native_window,
+            surface,
+        })
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        match raster_surface(width, height) {
+            Ok(surface) => self.surface = surface,
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    pub fn frame(&mut self) -> Option<&mut Surface> {
+        Some(&mut self.surface)
+    }
+
+    pub fn present(&mut self) {
+        let mut buffer = match self.native_window.lock(None) {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                log::warn!("ANativeWindow_lock failed: {:?}", e);
+                return;
+            }
+        };
+        // The window may have resized under us; copy what overlaps.
+        let width = buffer.width().min(self.surface.width() as usize);
+        let height = buffer.height().min(self.surface.height() as usize);
+        let row_bytes = buffer.stride() * 4;
+        let info = ImageInfo::new(
+            (width as i32, height as i32),
+            ColorType::RGBA8888,
+            AlphaType::Premul,
+            None,
+        );
+        let dst = unsafe {
+            std::slice::from_raw_parts_mut(buffer.bits().cast::<u8>(), row_bytes * height)
+        };
+        if !self.surface.read_pixels(&info, dst, row_bytes, (0, 0)) {
+            log::warn!("Failed to read back raster surface");
+        }
+        // Dropping the guard unlocks and posts the buffer.
+    }
+}
+
+fn raster_surface(width: u32, height: u32) -> Result<Surface, String> {
+    surfaces::raster_n32_premul((width.max(1) as i32, height.max(1) as i32))
+        .ok_or_else(|| format!("Failed to create {}x{} raster surface", width, height))
+}
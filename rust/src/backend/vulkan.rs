@@ -0,0 +1,405 @@
+use std::ops::Deref;
+use std::ptr;
+
+use ash::vk::{self, Handle};
+use ash::{khr, Device, Entry, Instance};
+use raw_window_handle::{HasWindowHandle, RawWindowHandle};
+use skia_safe::{
+    gpu::{
+        backend_render_targets, direct_contexts, surfaces, vk as skvk, DirectContext, FlushInfo,
+        SurfaceOrigin, SyncCpu,
+    },
+    ColorType, Surface,
+};
+use winit::window::Window;
+
+/// Vulkan through ash with a Skia surface wrapped around each swapchain image.
+///
+/// Frames are paced with a fence on image acquisition and a CPU sync on
+/// submit, which keeps the code free of semaphore plumbing at the cost of
+/// a little latency; the terminal renders far below the display rate anyway.
+pub struct VulkanBackend {
+    _entry: Entry,
+    instance: Instance,
+    surface_loader: khr::surface::Instance,
+    surface: vk::SurfaceKHR,
+    physical_device: vk::PhysicalDevice,
+    device: Device,
+    queue: vk::Queue,
+    swapchain_loader: khr::swapchain::Device,
+    swapchain: vk::SwapchainKHR,
+    format: vk::SurfaceFormatKHR,
+    acquire_fence: vk::Fence,
+    gr_context: DirectContext,
+    /// One Skia surface per swapchain image, by image index.
+    surfaces: Vec<Surface>,
+    image_index: Option<u32>,
+}
+
+impl VulkanBackend {
+    pub fn new(window: &Window) -> Result<Self, String> {
+        let handle = window.window_handle().map_err(|e| e.to_string())?;
+        let RawWindowHandle::AndroidNdk(handle) = handle.as_raw() else {
+            return Err("not an Android window".into());
+        };
+
+        let entry = unsafe { Entry::load() }.map_err(|e| e.to_string())?;
+
+        let app_info = vk::ApplicationInfo::default()
+            .application_name(c"gui-engine")
+            .api_version(vk::API_VERSION_1_1);
+        let extensions = [
+            khr::surface::NAME.as_ptr(),
+            khr::android_surface::NAME.as_ptr(),
+        ];
+        let instance_info = vk::InstanceCreateInfo::default()
+            .application_info(&app_info)
+            .enabled_extension_names(&extensions);
+        let instance = unsafe { entry.create_instance(&instance_info, None) }
+            .map_err(|e| format!("create_instance: {}", e))?;
+        let instance = Guard::new(instance, |instance: &Instance| unsafe {
+            instance.destroy_instance(None)
+        });
+
+        let surface_loader = khr::surface::Instance::new(&entry, &instance);
+        let android_surface = khr::android_surface::Instance::new(&entry, &instance);
+        let surface_info = vk::AndroidSurfaceCreateInfoKHR::default()
+            .window(handle.a_native_window.as_ptr() as *mut vk::ANativeWindow);
+        let surface = unsafe { android_surface.create_android_surface(&surface_info, None) }
+            .map_err(|e| format!("create_android_surface: {}", e))?;
+        let surface = Guard::new(surface, |surface: &vk::SurfaceKHR| unsafe {
+            surface_loader.destroy_surface(*surface, None)
+        });
+
+        let (physical_device, queue_family) =
+            pick_device(&instance, &surface_loader, *surface).ok_or("no usable Vulkan device")?;
+
+        let priorities = [1.0];
+        let queue_info = [vk::DeviceQueueCreateInfo::default()
+            .queue_family_index(queue_family)
+            .queue_priorities(&priorities)];
+        let device_extensions = [khr::swapchain::NAME.as_ptr()];
+        let device_info = vk::DeviceCreateInfo::default()
+            .queue_create_infos(&queue_info)
+            .enabled_extension_names(&device_extensions);
+        let device = unsafe { instance.create_device(physical_device, &device_info, None) }
+            .map_err(|e| format!("create_device: {}", e))?;
+        let device = Guard::new(device, |device: &Device| unsafe {
+            device.destroy_device(None)
+        });
+        let queue = unsafe { device.get_device_queue(queue_family, 0) };
+
+        let gr_context = {
+            let get_proc = |of: skvk::GetProcOf| unsafe {
+                let proc = match of {
+                    skvk::GetProcOf::Instance(raw, name) => {
+                        entry.get_instance_proc_addr(vk::Instance::from_raw(raw as _), name)
+                    }
+                    skvk::GetProcOf::Device(raw, name) => instance
+                        .fp_v1_0()
+                        .get_device_proc_addr(vk::Device::from_raw(raw as _), name),
+                };
+                proc.map_or(ptr::null(), |f| f as _)
+            };
+            let backend_context = unsafe {
+                skvk::BackendContext::new(
+                    instance.handle().as_raw() as _,
+                    physical_device.as_raw() as _,
+                    device.handle().as_raw() as _,
+                    (queue.as_raw() as _, queue_family as usize),
+                    &get_proc,
+                )
+            };
+            direct_contexts::make_vulkan(&backend_context, None)
+                .ok_or("failed to create Skia Vulkan context")?
+        };
+
+        let formats = unsafe {
+            surface_loader.get_physical_device_surface_formats(physical_device, *surface)
+        }
+        .map_err(|e| format!("surface formats: {}", e))?;
+        let format = formats
+            .into_iter()
+            .find(|f| skia_format(f.format).is_some())
+            .ok_or("no RGBA8/BGRA8 surface format")?;
+
+        let acquire_fence = unsafe { device.create_fence(&vk::FenceCreateInfo::default(), None) }
+            .map_err(|e| format!("create_fence: {}", e))?;
+        let swapchain_loader = khr::swapchain::Device::new(&instance, &device);
+
+        // Nothing left to fail before the backend's own Drop takes over.
+        let device = device.keep();
+        let surface = surface.keep();
+        let instance = instance.keep();
+        let mut backend = Self {
+            _entry: entry,
+            instance,
+            surface_loader,
+            surface,
+            physical_device,
+            device,
+            queue,
+            swapchain_loader,
+            swapchain: vk::SwapchainKHR::null(),
+            format,
+            acquire_fence,
+            gr_context,
+            surfaces: Vec::new(),
+            image_index: None,
+        };
+        let size = window.inner_size();
+        backend.recreate_swapchain(size.width, size.height)?;
+        Ok(backend)
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if let Err(e) = self.recreate_swapchain(width, height) {
+            log::error!("Failed to recreate swapchain: {}", e);
+        }
+    }
+
+    pub fn frame(&mut self) -> Option<&mut Surface> {
+        if self.image_index.is_none() {
+            let acquired = unsafe {
+                self.swapchain_loader.acquire_next_image(
+                    self.swapchain,
+                    u64::MAX,
+                    vk::Semaphore::null(),
+                    self.acquire_fence,
+                )
+            };
+            match acquired {
+                Ok((index, _suboptimal)) => unsafe {
+                    let fences = [self.acquire_fence];
+                    let _ = self.device.wait_for_fences(&fences, true, u64::MAX);
+                    let _ = self.device.reset_fences(&fences);
+                    self.image_index = Some(index);
+                },
+                Err(e) => {
+                    log::warn!("acquire_next_image: {}", e);
+                    return None;
+                }
+            }
+        }
+        self.surfaces.get_mut(self.image_index? as usize)
+    }
+
+    pub fn present(&mut self) {
+        let Some(index) = self.image_index.take() else {
+            return;
+        };
+        let Some(surface) = self.surfaces.get_mut(index as usize) else {
+            return;
+        };
+        self.gr_context.flush_surface_with_access(
+            surface,
+            surfaces::BackendSurfaceAccess::Present,
+            &FlushInfo::default(),
+        );
+        self.gr_context.submit(SyncCpu::Yes);
+
+        let swapchains = [self.swapchain];
+        let indices = [index];
+        let present_info = vk::PresentInfoKHR::default()
+            .swapchains(&swapchains)
+            .image_indices(&indices);
+        match unsafe {
+            self.swapchain_loader
+                .queue_present(self.queue, &present_info)
+        } {
+            Ok(false) => {}
+            // Suboptimal or out of date: rebuild at the surface's new size.
+            Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.resize(0, 0),
+            Err(e) => log::warn!("queue_present: {}", e),
+        }
+    }
+
+    /// Build a swapchain for the surface, `width`x`height` being used only
+    /// when the surface doesn't dictate its own extent.
+    fn recreate_swapchain(&mut self, width: u32, height: u32) -> Result<(), String> {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+        }
+        self.surfaces.clear();
+        self.image_index = None;
+
+        let caps = unsafe {
+            self.surface_loader
+                .get_physical_device_surface_capabilities(self.physical_device, self.surface)
+        }
+        .map_err(|e| format!("surface capabilities: {}", e))?;
+        let extent = if caps.current_extent.width != u32::MAX {
+            caps.current_extent
+        } else {
+            vk::Extent2D {
+                width: width.max(1),
+                height: height.max(1),
+            }
+        };
+        let mut image_count = caps.min_image_count + 1;
+        if caps.max_image_count > 0 {
+            image_count = image_count.min(caps.max_image_count);
+        }
+        let composite_alpha = [
+            vk::CompositeAlphaFlagsKHR::INHERIT,
+            vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+            vk::CompositeAlphaFlagsKHR::OPAQUE,
+        ]
+        .into_iter()
+        .find(|a| caps.supported_composite_alpha.contains(*a))
+        .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE);
+
+        let old_swapchain = self.swapchain;
+        let swapchain_info = vk::SwapchainCreateInfoKHR::default()
+            .surface(self.surface)
+            .min_image_count(image_count)
+            .image_format(self.format.format)
+            .image_color_space(self.format.color_space)
+            .image_extent(extent)
+            .image_array_layers(1)
+            .image_usage(
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+            )
+            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .pre_transform(caps.current_transform)
+            .composite_alpha(composite_alpha)
+            .present_mode(vk::PresentModeKHR::FIFO)
+            .clipped(true)
+            .old_swapchain(old_swapchain);
+        self.swapchain = unsafe {
+            self.swapchain_loader
+                .create_swapchain(&swapchain_info, None)
+        }
+        .map_err(|e| format!("create_swapchain: {}", e))?;
+        if old_swapchain != vk::SwapchainKHR::null() {
+            unsafe { self.swapchain_loader.destroy_swapchain(old_swapchain, None) };
+        }
+
+        let images = unsafe { self.swapchain_loader.get_swapchain_images(self.swapchain) }
+            .map_err(|e| format!("get_swapchain_images: {}", e))?;
+        let (sk_format, color_type) =
+            skia_format(self.format.format).ok_or("unsupported swapchain format")?;
+        for image in images {
+            let image_info = unsafe {
+                skvk::ImageInfo::new(
+                    image.as_raw() as _,
+                    skvk::Alloc::default(),
+                    skvk::ImageTiling::OPTIMAL,
+                    skvk::ImageLayout::UNDEFINED,
+                    sk_format,
+                    1,
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+            };
+            let render_target = backend_render_targets::make_vk(
+                (extent.width as i32, extent.height as i32),
+                &image_info,
+            );
+            let surface = surfaces::wrap_backend_render_target(
+                &mut self.gr_context,
+                &render_target,
+                SurfaceOrigin::TopLeft,
+                color_type,
+                None,
+                None,
+            )
+            .ok_or("failed to wrap swapchain image")?;
+            self.surfaces.push(surface);
+        }
+        log::info!(
+            "Vulkan swapchain: {} images, {}x{}",
+            self.surfaces.len(),
+            extent.width,
+            extent.height
+        );
+        Ok(())
+    }
+}
+
+impl Drop for VulkanBackend {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            self.surfaces.clear();
+            self.gr_context.abandon();
+            self.device.destroy_fence(self.acquire_fence, None);
+            self.swapchain_loader
+                .destroy_swapchain(self.swapchain, None);
+            self.device.destroy_device(None);
+            self.surface_loader.destroy_surface(self.surface, None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+/// Destroys a Vulkan object when dropped, so `new` can bail out at any
+/// step without leaking what it made before.
+struct Guard<T: Clone, F: FnOnce(&T)> {
+    value: T,
+    destroy: Option<F>,
+}
+
+impl<T: Clone, F: FnOnce(&T)> Guard<T, F> {
+    fn new(value: T, destroy: F) -> Self {
+        Self {
+            value,
+            destroy: Some(destroy),
+        }
+    }
+
+    /// Hand the object over to an owner that destroys it itself.
+    fn keep(mut self) -> T {
+        self.destroy = None;
+        self.value.clone()
+    }
+}
+
+impl<T: Clone, F: FnOnce(&T)> Deref for Guard<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Clone, F: FnOnce(&T)> Drop for Guard<T, F> {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.destroy.take() {
+            destroy(&self.value);
+        }
+    }
+}
+
+/// First device with a graphics queue that can present to `surface`.
+fn pick_device(
+    instance: &Instance,
+    surface_loader: &khr::surface::Instance,
+    surface: vk::SurfaceKHR,
+) -> Option<(vk::PhysicalDevice, u32)> {
+    let devices = unsafe { instance.enumerate_physical_devices() }.ok()?;
+    devices.into_iter().find_map(|device| {
+        let families = unsafe { instance.get_physical_device_queue_family_properties(device) };
+        families.iter().enumerate().find_map(|(i, family)| {
+            let i = i as u32;
+            let graphics = family.queue_flags.contains(vk::QueueFlags::GRAPHICS);
+            let present =
+                unsafe { surface_loader.get_physical_device_surface_support(device, i, surface) }
+                    .unwrap_or(false);
+            (graphics && present).then_some((device, i))
+        })
+    })
+}
+
+/// Skia's view of a swapchain format we can render into.
+fn skia_format(format: vk::Format) -> Option<(skvk::Format, ColorType)> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM => Some((skvk::Format::R8G8B8A8_UNORM, ColorType::RGBA8888)),
+        vk::Format::B8G8R8A8_UNORM => Some((skvk::Format::B8G8R8A8_UNORM, ColorType::BGRA8888)),
+        _ => None,
+    }
+}
@@ -1,20 +1,119 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::io::{self, Read};
-use std::os::unix::fs::PermissionsExt;
-use std::path::{Path, PathBuf};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use android_activity::AndroidApp;
 use ndk::asset::AssetManager;
+use sha2::{Digest, Sha256};
 use std::ffi::CString;
+use zip::read::ZipFile;
 use zip::ZipArchive;
 
+use crate::android;
+use crate::control;
+use crate::core::children;
+
 const BOOTSTRAP_ASSET: &str = "bootstrap-aarch64.zip";
+/// Bump whenever the bundled bootstrap changes, so existing installs are
+/// upgraded in place on the next launch.
+const BOOTSTRAP_VERSION: u32 = 1;
+/// Where a [`Manifest`] of the installed bootstrap is kept in the prefix.
+const MANIFEST_FILE: &str = ".gui-engine-bootstrap";
+const DPKG_STATUS_REL_PATH: &str = "var/lib/dpkg/status";
+/// dpkg's database, which a repair leaves as it is.
+const DPKG_STATE_REL_PATH: &str = "var/lib/dpkg/";
+/// Where a downloaded bootstrap is kept until it is installed, so an
+/// interrupted download can be resumed.
+const DOWNLOAD_FILE: &str = "bootstrap-download.zip";
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+/// Wait before the next attempt, times the attempts so far.
+const DOWNLOAD_RETRY_DELAY: Duration = Duration::from_secs(2);
 const PREFIX_DIR: &str = "prefix";
+const HOME_DIR: &str = "home";
 const STAGING_DIR: &str = "prefix-staging";
+/// Beside the app's own data dir, holding the named environments, each
+/// laid out like that dir so everything below works on it unchanged.
+const ENVIRONMENTS_DIR: &str = "environments";
+/// What a removed environment is renamed to, by a dot, while it is
+/// deleted in the background.
+const REMOVED_ENVIRONMENT_PREFIX: &str = ".removed-";
+/// Optional zips a custom build can bundle to preseed tools, extracted
+/// in this order over the installed bootstrap, into the prefix or $HOME.
+const ASSET_PACKS: [(&str, &str); 3] = [
+    ("extra-packages.zip", PREFIX_DIR),
+    ("fonts.zip", PREFIX_DIR),
+    ("user-overlay.zip", HOME_DIR),
+];
+/// SHA-256 of each asset pack as last extracted, kept in the prefix so a
+/// pack is extracted again once an update changes it.
+const ASSET_PACKS_STAMP: &str = ".gui-engine-asset-packs";
 const SYMLINKS_FILE: &str = "SYMLINKS.txt";
 const SHELL_REL_PATH: &str = "bin/sh";
+const SYSTEM_LINKER: &str = "/system/bin/linker64";
+/// Programs the build stages in the APK's native library dir, as
+/// `lib<path with / as ->.so`, for devices where the linker won't run the
+/// prefix's own. Keep in step with stageNativeExecutables in
+/// build.gradle.kts.
+const STAGED_EXECUTABLES: [&str; 18] = [
+    "bin/bash",
+    "bin/dash",
+    "bin/apt",
+    "bin/apt-get",
+    "bin/apt-cache",
+    "bin/apt-config",
+    "bin/apt-mark",
+    "bin/dpkg",
+    "bin/dpkg-deb",
+    "bin/dpkg-query",
+    "bin/gpgv",
+    "lib/apt/apt-helper",
+    "lib/apt/methods/copy",
+    "lib/apt/methods/file",
+    "lib/apt/methods/gpgv",
+    "lib/apt/methods/http",
+    "lib/apt/methods/https",
+    "lib/apt/methods/store",
+];
+/// Start of the scripts standing in for [`STAGED_EXECUTABLES`].
+const STAGED_WRAPPER_HEADER: &str = "#!/system/bin/sh\n# Runs the copy staged with the app\n";
+/// Present once the prefix runs staged programs, holding the native
+/// library dir the wrappers point into.
+const STAGED_STAMP: &str = ".gui-engine-staged-exec";
+/// Scripts in the prefix running a command of the app's control socket,
+/// with the command each runs. termux-api's own would start Termux.
+const CONTROL_HELPERS: [(&str, &str); 7] = [
+    ("bin/open", "open"),
+    ("bin/termux-open", "open"),
+    ("bin/termux-toast", "toast"),
+    ("bin/termux-vibrate", "vibrate"),
+    ("bin/termux-clipboard-get", "clipboard-get"),
+    ("bin/termux-clipboard-set", "clipboard-set"),
+    ("bin/termux-battery-status", "battery-status"),
+];
+/// Commands whose helpers pass stdin along when no text is left after
+/// their options, as termux-api's do, with the options for `getopts`.
+const STDIN_COMMANDS: [(&str, &str); 2] = [("toast", "sb:c:g:"), ("clipboard-set", "")];
+const CONTROL_HELPER_HEADER: &str = "#!/system/bin/sh\n# Asks the app through its control socket\n";
 const TERMUX_EXEC_REL_PATH: &str = "lib/libtermux-exec.so";
 const TERMUX_EXEC_COMPAT_ASSET: &str = "libtermux-exec.so";
-const PATH_PATCH_STAMP: &str = ".gui-engine-termux-paths-v3";
+/// Bump with each change to termux-exec-compat, so existing installs get
+/// the bundled build.
+const TERMUX_EXEC_COMPAT_VERSION: u32 = 1;
+/// Version and SHA-256 of the termux-exec library this installed, kept in
+/// the prefix.
+const TERMUX_EXEC_STAMP: &str = ".gui-engine-termux-exec";
+const TERMUX_EXEC_DOWNLOAD_FILE: &str = "termux-exec-download.so";
+/// Optional script run with the prefix's sh once a bootstrap is freshly
+/// installed, to set up dotfiles and the like.
+const POST_INSTALL_ASSET: &str = "post-install.sh";
+const PATH_PATCH_STAMP: &str = ".gui-engine-termux-paths-v4";
+/// Interpreter directories of a standard system that script shebangs are
+/// moved from into the prefix's bin, as termux-exec does when it runs them.
+const SHEBANG_DIRS: [&str; 2] = ["/usr/bin/", "/bin/"];
 const LEGACY_TERMUX_PREFIX: &str = "/data/data/com.termux/files/usr";
 const LEGACY_TERMUX_PREFIX_USER: &str = "/data/user/0/com.termux/files/usr";
 const LEGACY_TERMUX_HOME: &str = "/data/data/com.termux/files/home";
@@ -26,25 +125,239 @@ const LEGACY_TERMUX_REPO_HOST: &str = "packages.termux.org";
 const CURRENT_TERMUX_REPO_CF_HOST: &str = "packages-cf.termux.dev";
 const CURRENT_TERMUX_REPO_HOST: &str = "packages.termux.dev";
 const APT_CONFIG_REL_PATH: &str = "etc/apt/apt.conf";
+const SOURCES_LIST_REL_PATH: &str = "etc/apt/sources.list";
+/// First line of a sources.list written by [`set_mirror`], so that
+/// clearing the mirror can tell it from one the user wrote.
+const SOURCES_LIST_MARKER: &str = "# Written from [bootstrap] mirror in the app's config";
+const MAIN_REPO_PATH: &str = "apt/termux-main";
+const RESOLV_CONF_REL_PATH: &str = "etc/resolv.conf";
+const HOSTS_REL_PATH: &str = "etc/hosts";
+/// First line of a resolv.conf written by [`update_network_config`].
+const RESOLV_CONF_MARKER: &str = "# Written from the system's DNS servers by the app";
+/// For when the system won't say which servers it uses.
+const FALLBACK_DNS: [&str; 2] = ["1.1.1.1", "8.8.8.8"];
+/// The most nameservers the resolver reads.
+const MAX_NAMESERVERS: usize = 3;
+const HOSTS: &str = "127.0.0.1 localhost\n::1 localhost ip6-localhost ip6-loopback\n";
+/// Under $HOME, holding [`link_storage`]'s links.
+const STORAGE_DIR: &str = "storage";
 
 pub struct BootstrapPaths {
     pub prefix: PathBuf,
     pub home: PathBuf,
     pub tmp: PathBuf,
+    /// Whether it was installed just now rather than found ready.
+    pub installed: bool,
+}
+
+/// What the installer is busy with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Downloading,
+    Extracting,
+    /// Looking for missing or damaged files to repair.
+    Checking,
+    Linking,
+    /// Rewriting Termux's paths in scripts and configuration.
+    Patching,
+    /// Running the post-install script.
+    Finishing,
+}
+
+/// How far installation has got, for showing on screen. `done` and
+/// `total` count bytes while downloading and files otherwise; a `total`
+/// of 0 means there is no telling.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub phase: Phase,
+    pub done: u64,
+    pub total: u64,
+}
+
+impl Progress {
+    fn new(phase: Phase, done: u64, total: u64) -> Self {
+        Self { phase, done, total }
+    }
+
+    /// How much of the phase is done, from 0 to 1.
+    pub fn fraction(&self) -> Option<f32> {
+        (self.total > 0).then(|| self.done as f32 / self.total as f32)
+    }
+
+    /// A line of status text, such as "Extracting 120 of 4316 files".
+    pub fn status(&self) -> String {
+        const MIB: f64 = (1 << 20) as f64;
+        match (self.phase, self.total) {
+            (Phase::Downloading, 0) => {
+                format!("Downloading {:.1} MiB", self.done as f64 / MIB)
+            }
+            (Phase::Downloading, total) => format!(
+                "Downloading {:.1} of {:.1} MiB",
+                self.done as f64 / MIB,
+                total as f64 / MIB
+            ),
+            (Phase::Extracting, total) => {
+                format!("Extracting {} of {} files", self.done, total)
+            }
+            (Phase::Checking, total) => format!("Checking {} of {} files", self.done, total),
+            (Phase::Linking, total) => format!("Linking {} of {} files", self.done, total),
+            (Phase::Patching, _) => "Updating paths".to_string(),
+            (Phase::Finishing, _) => "Finishing setup".to_string(),
+        }
+    }
+}
+
+/// Where the bootstrap zip comes from.
+pub enum BootstrapSource<'a> {
+    /// Bundled in the APK.
+    Asset,
+    /// Fetched on first run, and only installed if it matches `sha256`.
+    Url { url: &'a str, sha256: &'a str },
 }
 
-pub fn setup_bootstrap_if_needed(base: &Path, assets: &AssetManager) -> io::Result<BootstrapPaths> {
+/// $HOME for a bootstrap installed under `base`, known before it is.
+pub fn home_dir(base: &Path) -> PathBuf {
+    base.join(HOME_DIR)
+}
+
+/// $PREFIX for a bootstrap installed under `base`.
+pub fn prefix_dir(base: &Path) -> PathBuf {
+    base.join(PREFIX_DIR)
+}
+
+/// Whether `name` can name an environment: a short run of letters,
+/// digits, `-`, `_` and `.` that doesn't start with a dot.
+pub fn is_environment_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 32
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// The base of environment `name`, to pass for `base` below, given the
+/// default environment's; an empty name is the default. The default
+/// keeps the app's data dir, as before there were others.
+pub fn environment_base(base: &Path, name: &str) -> PathBuf {
+    if name.is_empty() {
+        return base.to_path_buf();
+    }
+    let app_data_dir = base.parent().unwrap_or(base);
+    let files = base.file_name().unwrap_or_default();
+    app_data_dir.join(ENVIRONMENTS_DIR).join(name).join(files)
+}
+
+/// The named environments there are, by name.
+pub fn environments(base: &Path) -> Vec<String> {
+    let app_data_dir = base.parent().unwrap_or(base);
+    let Ok(entries) = fs::read_dir(app_data_dir.join(ENVIRONMENTS_DIR)) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| is_environment_name(name))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Delete environment `name`, its $HOME too. It is out of the way once
+/// this returns; the files go on a thread of their own.
+pub fn remove_environment(base: &Path, name: &str) -> io::Result<()> {
+    if !is_environment_name(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("not an environment: {:?}", name),
+        ));
+    }
+    let app_data_dir = base.parent().unwrap_or(base);
+    let dir = app_data_dir.join(ENVIRONMENTS_DIR);
+    let removed = dir.join(format!("{}{}", REMOVED_ENVIRONMENT_PREFIX, name));
+    // Left over if the app was stopped partway through last time.
+    let _ = fs::remove_dir_all(&removed);
+    fs::rename(dir.join(name), &removed)?;
+    log::info!("Removing environment {}", name);
+    std::thread::spawn(move || {
+        if let Err(e) = fs::remove_dir_all(&removed) {
+            log::warn!("Failed to remove {:?}: {:?}", removed, e);
+        }
+    });
+    Ok(())
+}
+
+pub fn setup_bootstrap_if_needed(
+    base: &Path,
+    app: &AndroidApp,
+    source: &BootstrapSource,
+    progress: &mut dyn FnMut(Progress),
+) -> io::Result<BootstrapPaths> {
+    let assets = &app.asset_manager();
     let prefix = base.join(PREFIX_DIR);
-    let home = base.join("home");
+    let home = home_dir(base);
     let tmp = base.join("tmp");
 
     log::info!("Bootstrap base dir: {:?}", base);
     if is_prefix_ready(&prefix)? {
+        // Only the bundled bootstrap has a version to compare; one fetched
+        // from a URL stays as installed.
+        let installed = match (source, Manifest::load(&prefix)) {
+            (BootstrapSource::Asset, Some(manifest)) if manifest.version < BOOTSTRAP_VERSION => {
+                log::info!(
+                    "Upgrading bootstrap from version {} to {}",
+                    manifest.version,
+                    BOOTSTRAP_VERSION
+                );
+                let mut archive = open_archive(load_asset(assets, BOOTSTRAP_ASSET)?)?;
+                let files = extract(&mut archive, &prefix, Some(&manifest), progress)?;
+                // Everything new still has Termux's paths in it.
+                let _ = fs::remove_file(prefix.join(PATH_PATCH_STAMP));
+                Some(files)
+            }
+            (BootstrapSource::Asset, None) => {
+                // Installed before versions were kept: take it to be the
+                // bundled one, as it was until then.
+                match load_asset(assets, BOOTSTRAP_ASSET) {
+                    Ok(bytes) => {
+                        log::info!("Recording manifest for existing bootstrap");
+                        Some(archive_files(&open_archive(bytes)?))
+                    }
+                    Err(e) => {
+                        log::warn!("No bootstrap asset to record a manifest from: {:?}", e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        if extract_asset_packs(assets, base, progress)? {
+            let _ = fs::remove_file(prefix.join(PATH_PATCH_STAMP));
+        }
+        if installed.is_some() {
+            progress(Progress::new(Phase::Patching, 0, 0));
+        }
         apply_termux_path_rewrites_if_needed(base, &prefix, &home)?;
         ensure_apt_runtime_config(base, &prefix)?;
         install_termux_exec_compat_if_available(assets, &prefix)?;
+        if let Err(e) = stage_native_executables(app, &prefix) {
+            log::warn!("Failed to stage executables: {:?}", e);
+        }
+        if let Err(e) = install_control_helpers(app, &prefix) {
+            log::warn!("Failed to install control helpers: {:?}", e);
+        }
+        if let Some(files) = installed {
+            Manifest::record(&prefix, files).save(&prefix)?;
+        }
+        if let Err(e) = update_network_config(base, &android::dns_servers(app)) {
+            log::warn!("Failed to write network configuration: {:?}", e);
+        }
         log::info!("Bootstrap prefix already initialized: {:?}", prefix);
-        return Ok(BootstrapPaths { prefix, home, tmp });
+        return Ok(BootstrapPaths {
+            prefix,
+            home,
+            tmp,
+            installed: false,
+        });
     }
     if prefix.exists() {
         log::warn!("Existing prefix is incomplete; reinstalling bootstrap");
@@ -62,15 +375,535 @@ pub fn setup_bootstrap_if_needed(base: &Path, assets: &AssetManager) -> io::Resu
     set_permissions_best_effort(&home, 0o700);
     set_permissions_best_effort(&tmp, 0o700);
 
-    log::info!("Extracting bootstrap asset: {}", BOOTSTRAP_ASSET);
-    let zip_bytes = load_asset(assets, BOOTSTRAP_ASSET)?;
-    let reader = std::io::Cursor::new(zip_bytes);
-    let mut archive =
-        ZipArchive::new(reader).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let mut archive = open_archive(bootstrap_zip(app, base, source, progress)?)?;
+    let files = extract(&mut archive, &staging, None, progress)?;
+
+    if prefix.exists() {
+        let _ = fs::remove_dir_all(&prefix);
+    }
+    fs::rename(&staging, &prefix)?;
+    set_permissions_best_effort(&prefix, 0o700);
+    extract_asset_packs(assets, base, progress)?;
+    progress(Progress::new(Phase::Patching, 0, 0));
+    apply_termux_path_rewrites_if_needed(base, &prefix, &home)?;
+    ensure_apt_runtime_config(base, &prefix)?;
+    install_termux_exec_compat_if_available(assets, &prefix)?;
+    if let Err(e) = stage_native_executables(app, &prefix) {
+        log::warn!("Failed to stage executables: {:?}", e);
+    }
+    if let Err(e) = install_control_helpers(app, &prefix) {
+        log::warn!("Failed to install control helpers: {:?}", e);
+    }
+    let paths = BootstrapPaths {
+        prefix,
+        home,
+        tmp,
+        installed: true,
+    };
+    // Before the manifest, so what the script sets up counts as installed
+    // rather than as the user's changes.
+    progress(Progress::new(Phase::Finishing, 0, 0));
+    if let Err(e) = run_post_install_script(assets, &paths) {
+        log::warn!("Failed to run post-install script: {:?}", e);
+    }
+    Manifest::record(&paths.prefix, files).save(&paths.prefix)?;
+    // After the manifest, which tells the bootstrap's resolv.conf from one
+    // the user wrote.
+    if let Err(e) = update_network_config(base, &android::dns_servers(app)) {
+        log::warn!("Failed to write network configuration: {:?}", e);
+    }
+    let _ = fs::remove_file(base.join(DOWNLOAD_FILE));
+
+    log::info!("Bootstrap installed at {:?}", paths.prefix);
+
+    Ok(paths)
+}
+
+/// Run [`POST_INSTALL_ASSET`], if the APK has one, with the prefix's sh in
+/// $HOME, logging what it prints. Its failing doesn't fail the install.
+fn run_post_install_script(assets: &AssetManager, paths: &BootstrapPaths) -> io::Result<()> {
+    let Ok(script) = load_asset(assets, POST_INSTALL_ASSET) else {
+        return Ok(());
+    };
+    log::info!("Running {}", POST_INSTALL_ASSET);
+    let script_path = paths.tmp.join(POST_INSTALL_ASSET);
+    fs::write(&script_path, script)?;
+    let bin = paths.prefix.join("bin");
+    let mut command = Command::new(paths.prefix.join(SHELL_REL_PATH));
+    command
+        .arg(&script_path)
+        .current_dir(&paths.home)
+        .env_clear()
+        .env("HOME", &paths.home)
+        .env("PREFIX", &paths.prefix)
+        .env("TMPDIR", &paths.tmp)
+        .env("PATH", format!("{}:/system/bin", bin.display()))
+        .env("LD_LIBRARY_PATH", paths.prefix.join("lib"))
+        .stdin(Stdio::null());
+    let termux_exec = paths.prefix.join(TERMUX_EXEC_REL_PATH);
+    if termux_exec.is_file() {
+        command.env("LD_PRELOAD", termux_exec);
+    }
+    let output = children::output(&mut command);
+    let _ = fs::remove_file(&script_path);
+    let output = output?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for line in stdout.lines().chain(stderr.lines()) {
+        log::info!("{}: {}", POST_INSTALL_ASSET, line);
+    }
+    if !output.status.success() {
+        log::warn!("{} exited with {}", POST_INSTALL_ASSET, output.status);
+    }
+    Ok(())
+}
+
+/// Put back files of the bootstrap that have gone missing or been
+/// truncated, their permissions and its symlinks, and redo the path
+/// rewrites. Configuration the user may have edited is only restored if
+/// missing, and dpkg's database and $HOME are left alone. Returns how many
+/// files were restored.
+pub fn repair_prefix(
+    base: &Path,
+    app: &AndroidApp,
+    source: &BootstrapSource,
+    progress: &mut dyn FnMut(Progress),
+) -> io::Result<usize> {
+    let assets = &app.asset_manager();
+    let prefix = base.join(PREFIX_DIR);
+    if !prefix.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no bootstrap installed to repair",
+        ));
+    }
+    let mut manifest = Manifest::load(&prefix);
+    let mut archive = open_archive(bootstrap_zip(app, base, source, progress)?)?;
+
+    let mut restored = Vec::new();
+    let mut symlinks = Vec::new();
+    let files = archive.len() as u64;
+    for i in 0..archive.len() {
+        progress(Progress::new(Phase::Checking, i as u64, files));
+        let mut file = archive.by_index(i).map_err(io::Error::other)?;
+        let name = file.name().to_string();
+        if name == SYMLINKS_FILE {
+            let mut buf = String::new();
+            file.read_to_string(&mut buf)?;
+            symlinks = parse_symlinks(&buf, &prefix);
+            continue;
+        }
+        if !is_enclosed(&name) {
+            log::warn!("Skipping {:?}, outside the prefix", name);
+            continue;
+        }
+        let path = prefix.join(&name);
+        if file.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if name.starts_with(DPKG_STATE_REL_PATH) {
+            continue;
+        }
+        let mode = file_mode(&name, file.unix_mode());
+        let damaged = match fs::symlink_metadata(&path) {
+            Err(_) => true,
+            // Replaced with something else on purpose, presumably.
+            Ok(metadata) if !metadata.is_file() => false,
+            // Sizes are as recorded after path rewriting, so the zip's own
+            // won't do; without a manifest only missing files are found.
+            Ok(metadata) => {
+                !is_conffile(&name)
+                    && manifest
+                        .as_ref()
+                        .and_then(|manifest| manifest.files.get(&name))
+                        .is_some_and(|entry| entry.size != metadata.len())
+            }
+        };
+        if !damaged {
+            set_permissions_best_effort(&path, mode);
+            continue;
+        }
+        log::info!("Restoring {}", name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let _ = fs::remove_file(&path);
+        let mut out = fs::File::create(&path)?;
+        io::copy(&mut file, &mut out)?;
+        set_mtime(&out, entry_mtime(&file));
+        set_permissions_best_effort(&path, mode);
+        restored.push(name);
+    }
+
+    let links = symlinks.len() as u64;
+    for (i, (target, link)) in symlinks.into_iter().enumerate() {
+        progress(Progress::new(Phase::Linking, i as u64, links));
+        // Anything but a symlink at the link's path was put there since.
+        if fs::symlink_metadata(&link).is_ok_and(|m| !m.file_type().is_symlink()) {
+            continue;
+        }
+        let _ = fs::remove_file(&link);
+        let _ = std::os::unix::fs::symlink(target, link);
+    }
+
+    progress(Progress::new(Phase::Patching, 0, 0));
+    let _ = fs::remove_file(prefix.join(PATH_PATCH_STAMP));
+    apply_termux_path_rewrites_if_needed(base, &prefix, &home_dir(base))?;
+    ensure_apt_runtime_config(base, &prefix)?;
+    install_termux_exec_compat_if_available(assets, &prefix)?;
+    // Restoring undid the wrappers.
+    if let Err(e) = stage_native_executables(app, &prefix) {
+        log::warn!("Failed to stage executables: {:?}", e);
+    }
+    if let Err(e) = install_control_helpers(app, &prefix) {
+        log::warn!("Failed to install control helpers: {:?}", e);
+    }
+    if let Some(manifest) = &mut manifest {
+        for name in &restored {
+            if let Some(entry) = ManifestEntry::of(&prefix, name) {
+                manifest.files.insert(name.clone(), entry);
+            }
+        }
+        manifest.save(&prefix)?;
+    }
+    let _ = fs::remove_file(base.join(DOWNLOAD_FILE));
+
+    log::info!("Bootstrap repaired: {} files restored", restored.len());
+    Ok(restored.len())
+}
+
+/// Delete the bootstrap outright: the prefix, a half-finished install or
+/// download of it, and the caches apt and the path rewrites filled.
+/// $HOME is kept. The next [`setup_bootstrap_if_needed`] starts from
+/// scratch.
+pub fn reset_prefix(base: &Path) -> io::Result<()> {
+    let app_data_dir = base.parent().unwrap_or(base);
+    for dir in [
+        base.join(PREFIX_DIR),
+        base.join(STAGING_DIR),
+        app_data_dir.join("cache"),
+    ] {
+        match fs::remove_dir_all(&dir) {
+            Ok(()) => log::info!("Removed {:?}", dir),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    match fs::remove_file(base.join(DOWNLOAD_FILE)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Space the bootstrap and $HOME take up on disk, in bytes.
+#[derive(Clone, Debug, Default)]
+pub struct DiskUsage {
+    pub prefix: u64,
+    pub apt_cache: u64,
+    pub home: u64,
+    /// $HOME's top-level entries, largest first.
+    pub home_entries: Vec<(String, u64)>,
+}
+
+/// $HOME's largest entries listed by [`DiskUsage::report`]; the rest are
+/// summed up.
+const REPORTED_HOME_ENTRIES: usize = 8;
+
+impl DiskUsage {
+    /// Measure the bootstrap under `base`, which can take a while.
+    pub fn measure(base: &Path) -> Self {
+        let home = home_dir(base);
+        let mut home_entries: Vec<(String, u64)> = fs::read_dir(&home)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                (name, disk_size(&entry.path()))
+            })
+            .collect();
+        home_entries.sort_by_key(|&(_, size)| std::cmp::Reverse(size));
+        Self {
+            prefix: disk_size(&base.join(PREFIX_DIR)),
+            apt_cache: disk_size(&apt_cache_dir(base)),
+            home: disk_size(&home),
+            home_entries,
+        }
+    }
+
+    /// One line per figure, for showing to the user.
+    pub fn report(&self) -> Vec<String> {
+        let line = |size: u64, what: &str| format!("{:>10}  {}", format_size(size), what);
+        let mut lines = vec![
+            line(self.prefix, "prefix"),
+            line(self.apt_cache, "apt cache"),
+            line(self.home, "~"),
+        ];
+        let shown = self.home_entries.len().min(REPORTED_HOME_ENTRIES);
+        for (name, size) in &self.home_entries[..shown] {
+            lines.push(line(*size, &format!("~/{}", name)));
+        }
+        let others = &self.home_entries[shown..];
+        if !others.is_empty() {
+            let size = others.iter().map(|(_, size)| size).sum();
+            lines.push(line(size, &format!("~/... {} more", others.len())));
+        }
+        lines
+    }
+}
+
+/// Blocks allocated to `path` and everything under it, symlinks not
+/// followed. Whatever can't be read counts as empty.
+fn disk_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    let mut size = metadata.blocks() * 512;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+            size += disk_size(&entry.path());
+        }
+    }
+    size
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Delete the packages and indexes apt has cached, like `apt clean`,
+/// leaving its directories. Returns the bytes freed.
+pub fn clean_apt_cache(base: &Path) -> io::Result<u64> {
+    fn clean(dir: &Path) -> io::Result<u64> {
+        let mut freed = 0;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let metadata = fs::symlink_metadata(&path)?;
+            if metadata.is_dir() {
+                freed += clean(&path)?;
+            } else {
+                fs::remove_file(&path)?;
+                freed += metadata.blocks() * 512;
+            }
+        }
+        Ok(freed)
+    }
+    match clean(&apt_cache_dir(base)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        result => result,
+    }
+}
+
+fn apt_cache_dir(base: &Path) -> PathBuf {
+    let app_data_dir = base.parent().unwrap_or(base);
+    app_data_dir.join("cache").join("apt")
+}
+
+/// Point apt at `mirror` for packages, or back at the default repository
+/// once it is empty. A sources.list the user edited is only replaced
+/// when a mirror is set.
+pub fn set_mirror(base: &Path, mirror: &str) -> io::Result<()> {
+    let prefix = base.join(PREFIX_DIR);
+    if !prefix.is_dir() {
+        return Ok(());
+    }
+    let path = prefix.join(SOURCES_LIST_REL_PATH);
+    let current = match fs::read_to_string(&path) {
+        Ok(current) => current,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let contents = if !mirror.is_empty() {
+        format!(
+            "{}\ndeb {} stable main\n",
+            SOURCES_LIST_MARKER,
+            mirror.trim_end_matches('/')
+        )
+    } else if current.starts_with(SOURCES_LIST_MARKER) {
+        format!(
+            "deb https://{}/{} stable main\n",
+            CURRENT_TERMUX_REPO_CF_HOST, MAIN_REPO_PATH
+        )
+    } else {
+        return Ok(());
+    };
+    if contents == current {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, contents)?;
+    log::info!("Package repository set to {:?}", mirror);
+    Ok(())
+}
+
+/// Point the prefix's resolv.conf at `dns`, the system's DNS servers, or
+/// public ones if that is empty, and give it a hosts file if it has none.
+/// A resolv.conf the user has edited is left alone.
+pub fn update_network_config(base: &Path, dns: &[String]) -> io::Result<()> {
+    let prefix = base.join(PREFIX_DIR);
+    if !prefix.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(prefix.join("etc"))?;
+    let hosts = prefix.join(HOSTS_REL_PATH);
+    if !hosts.exists() {
+        fs::write(&hosts, HOSTS)?;
+    }
+
+    let path = prefix.join(RESOLV_CONF_REL_PATH);
+    let current = fs::read_to_string(&path).ok();
+    let ours = match &current {
+        None => true,
+        Some(current) if current.starts_with(RESOLV_CONF_MARKER) => true,
+        Some(_) => Manifest::load(&prefix)
+            .is_some_and(|manifest| !manifest.changed(&prefix, RESOLV_CONF_REL_PATH)),
+    };
+    if !ours {
+        return Ok(());
+    }
+    let servers: Vec<&str> = match dns {
+        [] => FALLBACK_DNS.to_vec(),
+        dns => dns.iter().map(String::as_str).collect(),
+    };
+    let mut contents = format!("{}\n", RESOLV_CONF_MARKER);
+    for server in servers.iter().take(MAX_NAMESERVERS) {
+        contents.push_str(&format!("nameserver {}\n", server));
+    }
+    if current.as_deref() == Some(contents.as_str()) {
+        return Ok(());
+    }
+    fs::write(&path, contents)?;
+    log::info!("DNS servers set to {:?}", servers);
+    Ok(())
+}
+
+/// Point `$HOME/storage/<name>` at each shared storage directory in
+/// `dirs`, as termux-setup-storage does. Links from an earlier run are
+/// replaced; anything else in the way is left alone.
+pub fn link_storage(home: &Path, dirs: &[(&str, PathBuf)]) -> io::Result<()> {
+    let storage = home.join(STORAGE_DIR);
+    fs::create_dir_all(&storage)?;
+    for (name, target) in dirs {
+        let link = storage.join(name);
+        match fs::symlink_metadata(&link) {
+            Ok(meta) if meta.file_type().is_symlink() => fs::remove_file(&link)?,
+            Ok(_) => {
+                log::warn!("Not linking {:?}: something else is there", link);
+                continue;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        std::os::unix::fs::symlink(target, &link)?;
+    }
+    Ok(())
+}
+
+/// The bootstrap zip, from the APK or downloaded.
+fn bootstrap_zip(
+    app: &AndroidApp,
+    base: &Path,
+    source: &BootstrapSource,
+    progress: &mut dyn FnMut(Progress),
+) -> io::Result<Vec<u8>> {
+    match source {
+        BootstrapSource::Asset => {
+            log::info!("Extracting bootstrap asset: {}", BOOTSTRAP_ASSET);
+            load_asset(&app.asset_manager(), BOOTSTRAP_ASSET)
+        }
+        BootstrapSource::Url { url, sha256 } => {
+            let bytes = download(app, url, sha256, &base.join(DOWNLOAD_FILE), progress)?;
+            log::info!("Extracting downloaded bootstrap");
+            Ok(bytes)
+        }
+    }
+}
+
+/// Extract those of the [`ASSET_PACKS`] in the APK that are new or have
+/// changed since last time. Returns whether there were any.
+fn extract_asset_packs(
+    assets: &AssetManager,
+    base: &Path,
+    progress: &mut dyn FnMut(Progress),
+) -> io::Result<bool> {
+    let stamp_path = base.join(PREFIX_DIR).join(ASSET_PACKS_STAMP);
+    let stamp = fs::read_to_string(&stamp_path).unwrap_or_default();
+    let mut packs = String::new();
+    let mut extracted = false;
+    for (name, dir) in ASSET_PACKS {
+        let Ok(bytes) = load_asset(assets, name) else {
+            continue;
+        };
+        let line = format!("{} {}", sha256_hex(&bytes), name);
+        packs.push_str(&line);
+        packs.push('\n');
+        if stamp.lines().any(|l| l == line) {
+            continue;
+        }
+        log::info!("Extracting asset pack {}", name);
+        let dest = base.join(dir);
+        fs::create_dir_all(&dest)?;
+        set_permissions_best_effort(&dest, 0o700);
+        extract(&mut open_archive(bytes)?, &dest, None, progress)?;
+        extracted = true;
+    }
+    if packs != stamp {
+        fs::write(&stamp_path, packs)?;
+        set_permissions_best_effort(&stamp_path, 0o600);
+    }
+    Ok(extracted)
+}
+
+type Archive = ZipArchive<io::Cursor<Vec<u8>>>;
+
+fn open_archive(bytes: Vec<u8>) -> io::Result<Archive> {
+    ZipArchive::new(io::Cursor::new(bytes)).map_err(io::Error::other)
+}
 
+/// Names of the regular files in `archive`.
+fn archive_files(archive: &Archive) -> Vec<String> {
+    archive
+        .file_names()
+        .filter(|name| !name.ends_with('/') && *name != SYMLINKS_FILE && is_enclosed(name))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Extract `archive` into `dest` and create its symlinks, returning the
+/// regular files written. When upgrading over an install described by
+/// `installed`, configuration the user has changed is left alone, with
+/// the new version beside it as `<name>.new`, and the dpkg database is
+/// merged rather than replaced.
+fn extract(
+    archive: &mut Archive,
+    dest: &Path,
+    installed: Option<&Manifest>,
+    progress: &mut dyn FnMut(Progress),
+) -> io::Result<Vec<String>> {
     let mut symlinks: Vec<(String, String)> = Vec::new();
+    let mut written = Vec::new();
+    // Zip has no hard links, so files that were come out as copies: the
+    // first of each, by checksum, size, mode and mtime, to link others to.
+    let mut originals: HashMap<(u32, u64, u32, Option<SystemTime>), String> = HashMap::new();
+    let mut linked = 0;
 
+    let files = archive.len() as u64;
     for i in 0..archive.len() {
+        progress(Progress::new(Phase::Extracting, i as u64, files));
         let mut file = archive
             .by_index(i)
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
@@ -78,54 +911,355 @@ pub fn setup_bootstrap_if_needed(base: &Path, assets: &AssetManager) -> io::Resu
         if name == SYMLINKS_FILE {
             let mut buf = String::new();
             file.read_to_string(&mut buf)?;
-            for line in buf.lines() {
-                let parts: Vec<&str> = line.split('←').collect();
-                if parts.len() != 2 {
-                    continue;
-                }
-                let old_path = parts[0].to_string();
-                let new_path = staging.join(parts[1]).to_string_lossy().to_string();
-                if let Some(parent) = Path::new(&new_path).parent() {
-                    let _ = fs::create_dir_all(parent);
-                }
-                symlinks.push((old_path, new_path));
-            }
+            symlinks = parse_symlinks(&buf, dest);
+            continue;
+        }
+        if !is_enclosed(&name) {
+            log::warn!("Skipping {:?}, outside the prefix", name);
             continue;
         }
 
-        let out_path = staging.join(&name);
+        let out_path = dest.join(&name);
         if file.is_dir() {
             fs::create_dir_all(&out_path)?;
             set_permissions_best_effort(&out_path, dir_mode(file.unix_mode()));
-        } else {
-            if let Some(parent) = out_path.parent() {
-                fs::create_dir_all(parent)?;
-                set_permissions_best_effort(parent, 0o700);
+            continue;
+        }
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+            set_permissions_best_effort(parent, 0o700);
+        }
+        written.push(name.clone());
+        let mtime = entry_mtime(&file);
+        if let Some(installed) = installed {
+            if name == DPKG_STATUS_REL_PATH {
+                let mut bootstrap = String::new();
+                file.read_to_string(&mut bootstrap)?;
+                let current = fs::read_to_string(&out_path).unwrap_or_default();
+                fs::write(&out_path, merge_dpkg_status(&current, &bootstrap))?;
+                continue;
             }
-            let mut out = fs::File::create(&out_path)?;
-            io::copy(&mut file, &mut out)?;
-            set_permissions_best_effort(&out_path, file_mode(&name, file.unix_mode()));
+            if is_conffile(&name) && installed.changed(dest, &name) {
+                log::info!("Keeping changed {}; new version in {}.new", name, name);
+                let mut out = fs::File::create(dest.join(format!("{}.new", name)))?;
+                io::copy(&mut file, &mut out)?;
+                set_mtime(&out, mtime);
+                continue;
+            }
+            // Unlink first: running programs keep the old file, and the
+            // new one doesn't inherit a hard link's other names.
+            let _ = fs::remove_file(&out_path);
+        }
+        let mode = file_mode(&name, file.unix_mode());
+        let key = (file.crc32(), file.size(), mode, mtime);
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)?;
+        // Edits to one configuration file mustn't show up in another.
+        let original = (file.size() > 0 && !is_conffile(&name))
+            .then(|| originals.get(&key))
+            .flatten();
+        if original
+            .is_some_and(|original| hard_link_if_same(&dest.join(original), &out_path, &data))
+        {
+            linked += 1;
+            continue;
         }
+        let mut out = fs::File::create(&out_path)?;
+        out.write_all(&data)?;
+        set_mtime(&out, mtime);
+        set_permissions_best_effort(&out_path, mode);
+        originals.entry(key).or_insert(name);
+    }
+    if linked > 0 {
+        log::info!("Hard linked {} duplicate files", linked);
     }
 
     log::info!("Applying {} symlinks", symlinks.len());
-    for (old_path, new_path) in symlinks {
+    let links = symlinks.len() as u64;
+    for (i, (old_path, new_path)) in symlinks.into_iter().enumerate() {
+        progress(Progress::new(Phase::Linking, i as u64, links));
         let _ = fs::remove_file(&new_path);
         let _ = std::os::unix::fs::symlink(old_path, new_path);
     }
+    Ok(written)
+}
 
-    if prefix.exists() {
-        let _ = fs::remove_dir_all(&prefix);
+/// When an archive entry was last modified. Zip keeps local time without
+/// a zone; the bootstrap's is taken to be UTC.
+fn entry_mtime(file: &ZipFile) -> Option<SystemTime> {
+    let time = file.last_modified();
+    // Days since the epoch, by Howard Hinnant's days_from_civil.
+    let (month, day) = (u64::from(time.month()), u64::from(time.day()));
+    let year = u64::from(time.year()) - u64::from(month <= 2);
+    let (era, year_of_era) = (year / 400, year % 400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day.checked_sub(1)?;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+    let seconds = days * 86_400
+        + u64::from(time.hour()) * 3_600
+        + u64::from(time.minute()) * 60
+        + u64::from(time.second());
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+fn set_mtime(file: &fs::File, mtime: Option<SystemTime>) {
+    if let Some(mtime) = mtime {
+        if let Err(e) = file.set_modified(mtime) {
+            log::warn!("Failed to set modification time: {:?}", e);
+        }
     }
-    fs::rename(&staging, &prefix)?;
-    set_permissions_best_effort(&prefix, 0o700);
-    apply_termux_path_rewrites_if_needed(base, &prefix, &home)?;
-    ensure_apt_runtime_config(base, &prefix)?;
-    install_termux_exec_compat_if_available(assets, &prefix)?;
+}
+
+/// Hard link `path` to `original` if it holds `data`, returning whether it
+/// did.
+fn hard_link_if_same(original: &Path, path: &Path, data: &[u8]) -> bool {
+    fs::read(original).is_ok_and(|existing| existing == data)
+        && fs::hard_link(original, path).is_ok()
+}
+
+/// SYMLINKS.txt's `target←link` lines, with each link under `dest` and its
+/// directory created. Links that would be made outside `dest`, or point
+/// outside the app's data directory, are left out.
+fn parse_symlinks(text: &str, dest: &Path) -> Vec<(String, String)> {
+    // `dest` is always a directory in the bootstrap base, which is in turn
+    // in the app's data directory.
+    let root = dest.parent().and_then(Path::parent).unwrap_or(dest);
+    let mut symlinks = Vec::new();
+    for line in text.lines() {
+        let parts: Vec<&str> = line.split('←').collect();
+        if parts.len() != 2 {
+            continue;
+        }
+        let link = dest.join(parts[1]);
+        let target = link.parent().and_then(|dir| normalize(&dir.join(parts[0])));
+        if !is_enclosed(parts[1]) || !target.is_some_and(|target| target.starts_with(root)) {
+            log::warn!(
+                "Skipping symlink {} -> {} leading outside",
+                parts[1],
+                parts[0]
+            );
+            continue;
+        }
+        let old_path = parts[0].to_string();
+        let new_path = link.to_string_lossy().to_string();
+        if let Some(parent) = Path::new(&new_path).parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        symlinks.push((old_path, new_path));
+    }
+    symlinks
+}
+
+/// Whether an archive entry's `name` stays in the directory it is
+/// extracted to: relative, and never climbing out with `..`.
+fn is_enclosed(name: &str) -> bool {
+    let mut named = false;
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(_) => named = true,
+            Component::CurDir => {}
+            _ => return false,
+        }
+    }
+    named
+}
+
+/// `path` with `.` and `..` worked out without looking at the filesystem,
+/// or None if it climbs above the root.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normal.pop() {
+                    return None;
+                }
+            }
+            component => normal.push(component),
+        }
+    }
+    Some(normal)
+}
+
+/// Configuration files, which an upgrade mustn't overwrite if edited.
+fn is_conffile(name: &str) -> bool {
+    name.starts_with("etc/")
+}
 
-    log::info!("Bootstrap installed at {:?}", prefix);
+/// Take a new bootstrap's dpkg status over the installed one: its packages
+/// replace their old entries, and packages the user installed since stay.
+fn merge_dpkg_status(installed: &str, bootstrap: &str) -> String {
+    fn stanzas(status: &str) -> impl Iterator<Item = &str> {
+        status
+            .split("\n\n")
+            .map(|stanza| stanza.trim_matches('\n'))
+            .filter(|stanza| !stanza.is_empty())
+    }
+    fn package(stanza: &str) -> Option<&str> {
+        stanza
+            .lines()
+            .find_map(|line| line.strip_prefix("Package:"))
+            .map(str::trim)
+    }
+
+    let replaced: HashSet<&str> = stanzas(bootstrap).filter_map(package).collect();
+    let mut merged = String::new();
+    let kept =
+        stanzas(installed).filter(|stanza| package(stanza).is_none_or(|p| !replaced.contains(p)));
+    for stanza in kept.chain(stanzas(bootstrap)) {
+        merged.push_str(stanza);
+        merged.push_str("\n\n");
+    }
+    merged
+}
+
+/// What a bootstrap installed, kept in the prefix as [`MANIFEST_FILE`]:
+/// the [`BOOTSTRAP_VERSION`] and each of its files as left after path
+/// rewriting, with a SHA-256 for configuration files to tell whether the
+/// user has edited them since.
+struct Manifest {
+    version: u32,
+    files: BTreeMap<String, ManifestEntry>,
+}
 
-    Ok(BootstrapPaths { prefix, home, tmp })
+#[derive(PartialEq, Eq)]
+struct ManifestEntry {
+    size: u64,
+    sha256: Option<String>,
+}
+
+impl Manifest {
+    fn load(prefix: &Path) -> Option<Self> {
+        let text = fs::read_to_string(prefix.join(MANIFEST_FILE)).ok()?;
+        let mut lines = text.lines();
+        let version = lines.next()?.strip_prefix("version ")?.parse().ok()?;
+        let files = lines
+            .filter_map(|line| {
+                let mut parts = line.splitn(3, ' ');
+                let size = parts.next()?.parse().ok()?;
+                let sha256 = match parts.next()? {
+                    "-" => None,
+                    hash => Some(hash.to_string()),
+                };
+                let name = parts.next()?.to_string();
+                Some((name, ManifestEntry { size, sha256 }))
+            })
+            .collect();
+        Some(Self { version, files })
+    }
+
+    /// Describe `files` as they are now under `prefix`.
+    fn record(prefix: &Path, files: Vec<String>) -> Self {
+        let files = files
+            .into_iter()
+            .filter_map(|name| {
+                let entry = ManifestEntry::of(prefix, &name)?;
+                Some((name, entry))
+            })
+            .collect();
+        Self {
+            version: BOOTSTRAP_VERSION,
+            files,
+        }
+    }
+
+    fn save(&self, prefix: &Path) -> io::Result<()> {
+        let mut text = format!("version {}\n", self.version);
+        for (name, entry) in &self.files {
+            let sha256 = entry.sha256.as_deref().unwrap_or("-");
+            text.push_str(&format!("{} {} {}\n", entry.size, sha256, name));
+        }
+        let path = prefix.join(MANIFEST_FILE);
+        fs::write(&path, text)?;
+        set_permissions_best_effort(&path, 0o600);
+        Ok(())
+    }
+
+    /// Whether `name` exists under `prefix` and isn't as installed.
+    fn changed(&self, prefix: &Path, name: &str) -> bool {
+        match ManifestEntry::of(prefix, name) {
+            Some(now) => self.files.get(name) != Some(&now),
+            None => false,
+        }
+    }
+}
+
+impl ManifestEntry {
+    fn of(prefix: &Path, name: &str) -> Option<Self> {
+        let path = prefix.join(name);
+        let metadata = fs::symlink_metadata(&path).ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        let sha256 = match is_conffile(name) {
+            true => Some(sha256_hex(&fs::read(&path).ok()?)),
+            false => None,
+        };
+        Some(Self {
+            size: metadata.len(),
+            sha256,
+        })
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Fetch `url` into `path`, retrying a few times and resuming where the
+/// last attempt, or the last launch, left off. Without a `sha256` to check
+/// it against nothing is fetched, as anything could be served there.
+fn download(
+    app: &AndroidApp,
+    url: &str,
+    sha256: &str,
+    path: &Path,
+    progress: &mut dyn FnMut(Progress),
+) -> io::Result<Vec<u8>> {
+    // As pasted from sha256sum, which may be in upper case.
+    let sha256 = sha256.trim();
+    if sha256.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no sha256 given for {}", url),
+        ));
+    }
+    let mut logged = 0;
+    let mut report = |done: u64, total: Option<u64>| {
+        // Every tenth of the way, or every 4 MiB if the size is unknown.
+        let step = total.map_or(4 << 20, |total| (total / 10).max(1));
+        if done / step != logged {
+            logged = done / step;
+            log::info!("Downloaded {} of {:?} bytes", done, total);
+        }
+        progress(Progress::new(Phase::Downloading, done, total.unwrap_or(0)));
+    };
+    let mut attempt = 1;
+    while let Err(e) = android::download(app, url, path, &mut report) {
+        if attempt == DOWNLOAD_ATTEMPTS {
+            return Err(e);
+        }
+        log::warn!("Download attempt {} of {} failed: {:?}", attempt, url, e);
+        std::thread::sleep(DOWNLOAD_RETRY_DELAY * attempt);
+        attempt += 1;
+    }
+
+    let bytes = fs::read(path)?;
+    let actual = sha256_hex(&bytes);
+    if !actual.eq_ignore_ascii_case(sha256) {
+        // Resuming on top of a different file can end up here too, so
+        // start from scratch next time.
+        let _ = fs::remove_file(path);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("checksum of {} is {}, expected {}", url, actual, sha256),
+        ));
+    }
+    Ok(bytes)
 }
 
 fn load_asset(assets: &AssetManager, name: &str) -> io::Result<Vec<u8>> {
@@ -213,6 +1347,7 @@ fn apply_termux_path_rewrites_if_needed(base: &Path, prefix: &Path, home: &Path)
         prefix_str, home_str, cache_str
     );
     let stamp_path = prefix.join(PATH_PATCH_STAMP);
+    let bin = prefix.join("bin").to_string_lossy().into_owned();
     let replacements = vec![
         (
             LEGACY_TERMUX_REPO_CF_HOST.to_string(),
@@ -232,21 +1367,22 @@ fn apply_termux_path_rewrites_if_needed(base: &Path, prefix: &Path, home: &Path)
 
     if let Ok(existing) = fs::read_to_string(&stamp_path) {
         if existing == stamp_payload {
-            rewrite_dynamic_termux_paths(prefix, &replacements)?;
+            rewrite_dynamic_termux_paths(prefix, &replacements, &bin)?;
             return Ok(());
         }
     }
 
     let mut stats = RewriteStats::default();
-    rewrite_legacy_termux_paths(prefix, &replacements, &mut stats)?;
-    rewrite_dynamic_termux_paths(prefix, &replacements)?;
+    rewrite_legacy_termux_paths(prefix, &replacements, &bin, &mut stats)?;
+    rewrite_dynamic_termux_paths(prefix, &replacements, &bin)?;
     fs::write(&stamp_path, stamp_payload)?;
     set_permissions_best_effort(&stamp_path, 0o600);
 
     log::info!(
-        "Patched legacy Termux paths: files_changed={}, replacements={}",
+        "Patched legacy Termux paths: files_changed={}, replacements={}, shebangs={}",
         stats.files_changed,
-        stats.replacements
+        stats.replacements,
+        stats.shebangs
     );
     Ok(())
 }
@@ -255,11 +1391,15 @@ fn apply_termux_path_rewrites_if_needed(base: &Path, prefix: &Path, home: &Path)
 struct RewriteStats {
     files_changed: usize,
     replacements: usize,
+    shebangs: usize,
 }
 
+/// Apply `replacements` to the text files under `path`, and point their
+/// shebangs at interpreters in `bin`.
 fn rewrite_legacy_termux_paths(
     path: &Path,
     replacements: &[(String, String)],
+    bin: &str,
     stats: &mut RewriteStats,
 ) -> io::Result<()> {
     let metadata = fs::symlink_metadata(path)?;
@@ -271,7 +1411,7 @@ fn rewrite_legacy_termux_paths(
     if file_type.is_dir() {
         for entry in fs::read_dir(path)? {
             let entry = entry?;
-            rewrite_legacy_termux_paths(&entry.path(), replacements, stats)?;
+            rewrite_legacy_termux_paths(&entry.path(), replacements, bin, stats)?;
         }
         return Ok(());
     }
@@ -297,16 +1437,28 @@ fn rewrite_legacy_termux_paths(
             replaced_any = true;
         }
     }
+    if let Some(next) = rewrite_shebang(&data, bin) {
+        data = next;
+        stats.shebangs += 1;
+        replaced_any = true;
+    }
 
     if replaced_any {
-        fs::write(path, &data)?;
+        let mut file = fs::File::create(path)?;
+        file.write_all(&data)?;
+        // Keep the mtime dpkg and build tools go by.
+        set_mtime(&file, metadata.modified().ok());
         stats.files_changed += 1;
     }
 
     Ok(())
 }
 
-fn rewrite_dynamic_termux_paths(prefix: &Path, replacements: &[(String, String)]) -> io::Result<()> {
+fn rewrite_dynamic_termux_paths(
+    prefix: &Path,
+    replacements: &[(String, String)],
+    bin: &str,
+) -> io::Result<()> {
     let mut stats = RewriteStats::default();
     let dynamic_dirs = [
         prefix.join("var/lib/dpkg/info"),
@@ -316,7 +1468,7 @@ fn rewrite_dynamic_termux_paths(prefix: &Path, replacements: &[(String, String)]
 
     for dir in dynamic_dirs {
         if dir.exists() {
-            rewrite_legacy_termux_paths(&dir, replacements, &mut stats)?;
+            rewrite_legacy_termux_paths(&dir, replacements, bin, &mut stats)?;
         }
     }
 
@@ -330,6 +1482,25 @@ fn rewrite_dynamic_termux_paths(prefix: &Path, replacements: &[(String, String)]
     Ok(())
 }
 
+/// `script` with a `#!/bin/...` or `#!/usr/bin/...` interpreter moved
+/// into `bin`, or None if it has neither.
+fn rewrite_shebang(script: &[u8], bin: &str) -> Option<Vec<u8>> {
+    let line = script.strip_prefix(b"#!")?;
+    let space = line
+        .iter()
+        .take_while(|&&b| b == b' ' || b == b'\t')
+        .count();
+    let interpreter = SHEBANG_DIRS
+        .iter()
+        .find_map(|dir| line[space..].strip_prefix(dir.as_bytes()))?;
+    let mut out = Vec::with_capacity(script.len() + bin.len());
+    out.extend_from_slice(&script[..2 + space]);
+    out.extend_from_slice(bin.as_bytes());
+    out.push(b'/');
+    out.extend_from_slice(interpreter);
+    Some(out)
+}
+
 fn replace_all_bytes(haystack: &[u8], needle: &[u8], replacement: &[u8]) -> (Vec<u8>, usize) {
     if needle.is_empty() {
         return (haystack.to_vec(), 0);
@@ -354,8 +1525,7 @@ fn replace_all_bytes(haystack: &[u8], needle: &[u8], replacement: &[u8]) -> (Vec
 }
 
 fn ensure_apt_runtime_config(base: &Path, prefix: &Path) -> io::Result<()> {
-    let app_data_dir = base.parent().unwrap_or(base);
-    let cache_dir = app_data_dir.join("cache").join("apt");
+    let cache_dir = apt_cache_dir(base);
     fs::create_dir_all(&cache_dir)?;
     set_permissions_best_effort(&cache_dir, 0o700);
 
@@ -396,21 +1566,153 @@ Acquire::https::CaInfo \"{prefix}/etc/tls/cert.pem\";\n",
     Ok(())
 }
 
-fn install_termux_exec_compat_if_available(assets: &AssetManager, prefix: &Path) -> io::Result<()> {
-    match load_asset(assets, TERMUX_EXEC_COMPAT_ASSET) {
-        Ok(bytes) => {
-            let target = prefix.join(TERMUX_EXEC_REL_PATH);
-            if let Some(parent) = target.parent() {
-                fs::create_dir_all(parent)?;
+/// Whether the system linker will run the prefix's programs, as the
+/// shell is started. Some hardened builds refuse it for app data files.
+fn linker_exec_works(prefix: &Path) -> bool {
+    let shell = prefix.join(SHELL_REL_PATH);
+    if !shell.exists() {
+        return true;
+    }
+    let mut command = Command::new(SYSTEM_LINKER);
+    command
+        .arg(&shell)
+        .args(["-c", ":"])
+        .env_clear()
+        .env("LD_LIBRARY_PATH", prefix.join("lib"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let status = children::status(&mut command);
+    match status {
+        Ok(status) => status.success(),
+        Err(e) => {
+            log::warn!("Failed to run {:?} through the linker: {:?}", shell, e);
+            false
+        }
+    }
+}
+
+/// Where `name`, one of [`STAGED_EXECUTABLES`], is staged in `native_dir`.
+fn staged_path(native_dir: &Path, name: &str) -> PathBuf {
+    native_dir.join(format!("lib{}.so", name.replace('/', "-")))
+}
+
+/// If the linker won't run the prefix's programs, replace the essential
+/// ones with scripts running the copies the build staged in the native
+/// library dir, which stays executable, so the shell and apt still work.
+/// Once staged the prefix stays so, and the wrappers are rewritten
+/// whenever an update moves the dir or a package puts a binary back.
+fn stage_native_executables(app: &AndroidApp, prefix: &Path) -> io::Result<()> {
+    let stamp_path = prefix.join(STAGED_STAMP);
+    let staged = stamp_path.exists();
+    if !staged && linker_exec_works(prefix) {
+        return Ok(());
+    }
+    let Some(native_dir) = android::native_library_dir(app) else {
+        log::warn!("The linker won't run the prefix's programs and there is nothing staged");
+        return Ok(());
+    };
+    let mut wrapped = 0;
+    for name in STAGED_EXECUTABLES {
+        let target = staged_path(&native_dir, name);
+        if !target.is_file() {
+            continue;
+        }
+        let wrapper = format!(
+            "{}exec '{}' \"$@\"\n",
+            STAGED_WRAPPER_HEADER,
+            target.display()
+        );
+        let path = prefix.join(name);
+        if read_wrapper(&path).is_some_and(|current| current == wrapper) {
+            continue;
+        }
+        // Not through a symlink, which would point at something else.
+        let _ = fs::remove_file(&path);
+        fs::write(&path, wrapper)?;
+        set_permissions_best_effort(&path, 0o700);
+        wrapped += 1;
+    }
+    if !staged {
+        log::warn!(
+            "The linker won't run the prefix's programs; using those staged in {:?}",
+            native_dir
+        );
+    }
+    if wrapped > 0 {
+        log::info!("Wrote {} wrappers for staged executables", wrapped);
+    }
+    fs::write(&stamp_path, format!("{}\n", native_dir.display()))
+}
+
+/// Write [`CONTROL_HELPERS`], pointing at the control client in the
+/// native library dir, where they aren't already.
+fn install_control_helpers(app: &AndroidApp, prefix: &Path) -> io::Result<()> {
+    let Some(native_dir) = android::native_library_dir(app) else {
+        return Ok(());
+    };
+    let client = native_dir.join(control::CLIENT);
+    if !client.is_file() {
+        log::warn!("No control client at {:?}", client);
+        return Ok(());
+    }
+    for (name, command) in CONTROL_HELPERS {
+        let exec = format!("exec '{}'", client.display());
+        let helper = match STDIN_COMMANDS.iter().find(|(name, _)| *name == command) {
+            // The client streams stdin itself; $(cat) would drop its
+            // trailing newlines.
+            Some((_, options)) => {
+                let skip_options = match *options {
+                    "" => String::new(),
+                    options => format!("while getopts ':{}' option; do :; done\n", options),
+                };
+                format!(
+                    "{}{}stdin=\n[ $OPTIND -gt $# ] && stdin=--stdin\n{} $stdin {} \"$@\"\n",
+                    CONTROL_HELPER_HEADER, skip_options, exec, command
+                )
             }
-            fs::write(&target, bytes)?;
-            set_permissions_best_effort(&target, 0o700);
-            log::info!(
-                "Installed termux-exec compatibility library at {:?}",
-                target
-            );
-            Ok(())
+            None => format!("{}{} {} \"$@\"\n", CONTROL_HELPER_HEADER, exec, command),
+        };
+        let path = prefix.join(name);
+        if read_wrapper(&path).is_some_and(|current| current == helper) {
+            continue;
         }
+        let _ = fs::remove_file(&path);
+        fs::write(&path, helper)?;
+        set_permissions_best_effort(&path, 0o700);
+    }
+    Ok(())
+}
+
+/// The program a wrapper from [`stage_native_executables`] runs, or
+/// `path` itself for anything else. The wrapper's shell would start as
+/// /system/bin/sh's login shell, so sessions start the program directly.
+pub fn resolve_staged(path: &Path) -> PathBuf {
+    let target = read_wrapper(path).and_then(|text| {
+        let exec = text.strip_prefix(STAGED_WRAPPER_HEADER)?;
+        let target = exec.strip_prefix("exec '")?.split('\'').next()?;
+        Some(PathBuf::from(target))
+    });
+    target.unwrap_or_else(|| path.to_path_buf())
+}
+
+/// `path` as text, if it is small enough to be a wrapper script rather
+/// than the program itself.
+fn read_wrapper(path: &Path) -> Option<String> {
+    if fs::metadata(path).ok()?.len() > 4096 {
+        return None;
+    }
+    fs::read_to_string(path).ok()
+}
+
+/// Install the bundled termux-exec library, unless the one in the prefix
+/// is as new and unchanged since.
+fn install_termux_exec_compat_if_available(assets: &AssetManager, prefix: &Path) -> io::Result<()> {
+    if installed_termux_exec_version(prefix).is_some_and(|v| v >= TERMUX_EXEC_COMPAT_VERSION) {
+        return Ok(());
+    }
+    match load_asset(assets, TERMUX_EXEC_COMPAT_ASSET) {
+        Ok(bytes) => install_termux_exec(prefix, &bytes, TERMUX_EXEC_COMPAT_VERSION),
         Err(e) if e.kind() == io::ErrorKind::NotFound => {
             log::warn!(
                 "No '{}' asset found; keeping bootstrap termux-exec library",
@@ -421,3 +1723,61 @@ fn install_termux_exec_compat_if_available(assets: &AssetManager, prefix: &Path)
         Err(e) => Err(e),
     }
 }
+
+/// Fetch termux-exec `version` from `url` and install it, if it is newer
+/// than the library in the bootstrap under `base`. Programs already
+/// running keep the old one.
+pub fn update_termux_exec(
+    app: &AndroidApp,
+    base: &Path,
+    url: &str,
+    version: u32,
+    sha256: &str,
+) -> io::Result<()> {
+    let prefix = base.join(PREFIX_DIR);
+    if !prefix.is_dir() || installed_termux_exec_version(&prefix).is_some_and(|v| v >= version) {
+        return Ok(());
+    }
+    log::info!(
+        "Fetching termux-exec compatibility library version {}",
+        version
+    );
+    let path = base.join(TERMUX_EXEC_DOWNLOAD_FILE);
+    let bytes = download(app, url, sha256, &path, &mut |_| {})?;
+    let _ = fs::remove_file(&path);
+    install_termux_exec(&prefix, &bytes, version)
+}
+
+/// Version of the termux-exec library in `prefix`, if this installed it
+/// and it hasn't changed since.
+fn installed_termux_exec_version(prefix: &Path) -> Option<u32> {
+    let stamp = fs::read_to_string(prefix.join(TERMUX_EXEC_STAMP)).ok()?;
+    let (version, sha256) = stamp.trim().split_once(' ')?;
+    let library = fs::read(prefix.join(TERMUX_EXEC_REL_PATH)).ok()?;
+    if sha256_hex(&library) != sha256 {
+        return None;
+    }
+    version.parse().ok()
+}
+
+fn install_termux_exec(prefix: &Path, library: &[u8], version: u32) -> io::Result<()> {
+    let target = prefix.join(TERMUX_EXEC_REL_PATH);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Renamed into place rather than overwritten, which would pull the
+    // library out from under processes that have it loaded.
+    let staged = target.with_extension("so.new");
+    fs::write(&staged, library)?;
+    set_permissions_best_effort(&staged, 0o700);
+    fs::rename(&staged, &target)?;
+    let stamp = prefix.join(TERMUX_EXEC_STAMP);
+    fs::write(&stamp, format!("{} {}\n", version, sha256_hex(library)))?;
+    set_permissions_best_effort(&stamp, 0o600);
+    log::info!(
+        "Installed termux-exec compatibility library version {} at {:?}",
+        version,
+        target
+    );
+    Ok(())
+}
@@ -0,0 +1,63 @@
+/// Base letters each accent combines with, and the results, in the same
+/// order. Accents are keyed by their combining form, which is what Android
+/// reports for dead keys.
+const COMPOSITIONS: [(char, &str, &str); 7] = [
+    ('\u{300}', "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+    ('\u{301}', "aeiouycnszAEIOUYCNSZ", "áéíóúýćńśźÁÉÍÓÚÝĆŃŚŹ"),
+    ('\u{302}', "aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+    ('\u{303}', "anoANO", "ãñõÃÑÕ"),
+    ('\u{308}', "aeiouyAEIOU", "äëïöüÿÄËÏÖÜ"),
+    ('\u{30a}', "auAU", "åůÅŮ"),
+    ('\u{327}', "csCS", "çşÇŞ"),
+];
+
+/// Spacing accents some layouts report instead of the combining ones.
+const SPACING_ACCENTS: [(char, char); 7] = [
+    ('`', '\u{300}'),
+    ('´', '\u{301}'),
+    ('^', '\u{302}'),
+    ('~', '\u{303}'),
+    ('¨', '\u{308}'),
+    ('˚', '\u{30a}'),
+    ('¸', '\u{327}'),
+];
+
+/// What a dead key `accent` followed by a key typing `text` produces: the
+/// accented letter if there is one, the accent alone after a space, and
+/// otherwise both, as other systems do. Text that is already composed
+/// passes through.
+pub fn combine(accent: char, text: &str) -> String {
+    let accent = combining(accent);
+    let mut chars = text.chars();
+    let (Some(c), None) = (chars.next(), chars.next()) else {
+        return text.to_string();
+    };
+    let composed =
+        COMPOSITIONS
+            .iter()
+            .find(|(a, _, _)| *a == accent)
+            .and_then(|(_, bases, results)| {
+                let i = bases.chars().position(|b| b == c)?;
+                results.chars().nth(i)
+            });
+    match composed {
+        Some(composed) => composed.to_string(),
+        None if c == ' ' => spacing(accent).to_string(),
+        None if c.is_ascii() => format!("{}{}", spacing(accent), c),
+        None => text.to_string(),
+    }
+}
+
+fn combining(accent: char) -> char {
+    SPACING_ACCENTS
+        .iter()
+        .find(|(s, _)| *s == accent)
+        .map_or(accent, |&(_, c)| c)
+}
+
+fn spacing(accent: char) -> char {
+    SPACING_ACCENTS
+        .iter()
+        .find(|(_, c)| *c == accent)
+        .map_or(accent, |&(s, _)| s)
+}
@@ -1,34 +1,220 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use crate::core::glyph::DEFAULT_COLORS;
+use crate::backend::BackendKind;
+use crate::bootstrap::is_environment_name;
+use crate::core::theme::{Theme, ThemeMode};
+use crate::core::types::{CursorShape, CursorStyle, WORD_SEPARATORS};
+use crate::extra_keys::{parse_extra_keys, ExtraKey, DEFAULT_EXTRA_KEYS};
+
+/// Which program `[shell] program` starts.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ShellProgram {
+    /// The bootstrap's bash or sh once installed, else the system shell.
+    #[default]
+    Auto,
+    /// /system/bin/sh, even with a bootstrap installed.
+    System,
+    /// A path, or a bare name looked up in the bootstrap's bin directory.
+    Path(String),
+}
+
+/// What the Android back button or gesture does.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BackAction {
+    /// Send ESC, for vi and friends.
+    Escape,
+    /// Hide the soft keyboard if it is up, and otherwise do nothing.
+    #[default]
+    Keyboard,
+    /// Leave the app running in the background, like Home.
+    Background,
+}
 
 #[derive(Clone, Debug)]
 pub struct AppConfig {
+    /// Font size in density-independent points; scaled by the display
+    /// density so it looks the same size on every screen.
     pub font_size: f32,
+    /// `[font] size` from a config written when sizes were in pixels,
+    /// until [`Self::resolve_font_size`] turns it into points.
+    pub legacy_font_px: Option<f32>,
+    /// Font size on external displays, such as a monitor when docked;
+    /// None keeps `font_size` there too.
+    pub external_font_size: Option<f32>,
+    /// User font file; relative paths are resolved against the app data dir.
+    pub font_path: Option<PathBuf>,
+    /// Shape text runs with HarfBuzz so ligatures and complex scripts join.
+    pub ligatures: bool,
+    /// Extra points around each cell (letter and line spacing).
+    pub cell_padding: (f32, f32),
+    /// Points between the grid and the screen edges.
+    pub margin: (f32, f32),
     pub grid_cols: Option<usize>,
     pub grid_rows: Option<usize>,
-    pub palette: [u32; 16],
+    pub theme: ThemeMode,
+    /// `[colors]`, used in dark mode.
+    pub dark_theme: Theme,
+    /// `[colors.light]`, used in light mode.
+    pub light_theme: Theme,
+    /// Alpha of the default background, 0.0 (clear) to 1.0 (opaque).
+    pub background_opacity: f32,
+    pub ambiguous_wide: bool,
+    /// Reorder right-to-left text (Unicode bidi) when drawing.
+    pub bidi: bool,
+    pub answerback: String,
+    /// Ctrl+S pauses output until Ctrl+Q, as on a serial terminal. Off by
+    /// default, since a stray Ctrl+S otherwise looks like a hang.
+    pub flow_control: bool,
+    pub shell: ShellProgram,
+    pub shell_args: Vec<String>,
+    /// Start the shell as a login shell, reading /etc/profile and
+    /// ~/.profile.
+    pub login_shell: bool,
+    /// `[env]`: variables exported into every session, as written; they
+    /// override the built-in ones.
+    pub env: Vec<(String, String)>,
+    /// Run on text or files shared with the terminal, with the text or the
+    /// file's path as its last argument. Empty pastes them at the prompt.
+    pub share_command: String,
+    /// Keep the CPU awake while a command runs in the foreground, so
+    /// builds and downloads carry on with the screen off.
+    pub wake_lock: bool,
+    /// Run a foreground service with a notification while a command runs,
+    /// so Android doesn't kill the app and the command with it once it's
+    /// in the background.
+    pub keep_alive: bool,
+    /// Start the shell in the directory the last session was in, rather
+    /// than in $HOME, as desktop terminals do for a new tab.
+    pub restore_cwd: bool,
+    /// Run the commands other apps send with a RUN_COMMAND intent. Off by
+    /// default: any app the user grants the permission to could run
+    /// anything in the terminal.
+    pub allow_external_apps: bool,
+    /// Characters besides whitespace that end a word when selecting by
+    /// word; drop `:` to select URLs whole, or add `-` or `/` to stop at
+    /// hyphens or path components.
+    pub word_separators: String,
+    /// Cursor style used until an application sends DECSCUSR.
+    pub cursor_style: CursorStyle,
+    /// Cursor colour as 0xRRGGBB; None draws the cell in reverse video.
+    pub cursor_color: Option<u32>,
+    pub backend: BackendKind,
+    /// Lay the window out behind translucent system bars and around the
+    /// display cutout, rather than between them. The grid keeps clear of
+    /// them either way.
+    pub edge_to_edge: bool,
+    /// Soft keys shown along the bottom; empty hides the row.
+    pub extra_keys: Vec<ExtraKey>,
+    /// Volume Down acts as Ctrl and Volume Up as a layer of special keys,
+    /// instead of changing the volume.
+    pub volume_keys: bool,
+    pub back_key: BackAction,
+    /// Vibration strength for soft key presses and long presses, 1 to
+    /// 255; 0 turns it off.
+    pub haptics: u8,
+    /// Post a notification when the bell rings while the app is out of
+    /// sight.
+    pub notify_bell: bool,
+    /// Post a notification when a command finishes while the app is out
+    /// of sight.
+    pub notify_done: bool,
+    /// Record the session to `transcript_dir` from the start; it can also
+    /// be switched on and off from the menu.
+    pub transcript: bool,
+    /// Record what is typed and pasted too, in separate files.
+    pub transcript_input: bool,
+    /// Where transcripts go, relative to $HOME unless absolute.
+    pub transcript_dir: PathBuf,
+    /// Bytes after which a transcript continues in a new file; 0 never.
+    pub transcript_max_size: u64,
+    /// Transcript files of each kind kept, oldest deleted first; 0 keeps
+    /// them all.
+    pub transcript_keep: usize,
+    /// Fetch the bootstrap zip from here on first run instead of using the
+    /// one bundled in the APK; empty uses the bundled one.
+    pub bootstrap_url: String,
+    /// SHA-256 of the zip at `bootstrap_url`, in hex; required with it.
+    pub bootstrap_sha256: String,
+    /// URL of the package repository apt uses, such as a regional Termux
+    /// mirror; empty keeps the bootstrap's. Applied on config change too.
+    pub bootstrap_mirror: String,
+    /// Named environment, a prefix and $HOME of its own, new sessions
+    /// start in; it is installed the first time. Empty is the default
+    /// one. Switched to on config change too.
+    pub bootstrap_environment: String,
+    /// Fetch this build of the termux-exec library in the background and
+    /// use it if `termux_exec_version` is newer than the one installed,
+    /// for when a new Android release changes how exec behaves.
+    pub termux_exec_url: String,
+    pub termux_exec_version: u32,
+    /// SHA-256 of the library at `termux_exec_url`; required with it.
+    pub termux_exec_sha256: String,
+    /// Show frame/parse statistics over the terminal.
+    pub debug_overlay: bool,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
-            font_size: 32.0,
+            font_size: 12.0,
+            legacy_font_px: None,
+            external_font_size: None,
+            font_path: None,
+            ligatures: true,
+            cell_padding: (0.0, 0.0),
+            margin: (0.0, 0.0),
             grid_cols: None,
             grid_rows: None,
-            palette: DEFAULT_COLORS,
+            theme: ThemeMode::default(),
+            dark_theme: Theme::DARK,
+            light_theme: Theme::LIGHT,
+            background_opacity: 1.0,
+            ambiguous_wide: false,
+            bidi: true,
+            answerback: String::new(),
+            flow_control: false,
+            shell: ShellProgram::default(),
+            shell_args: Vec::new(),
+            login_shell: true,
+            env: Vec::new(),
+            share_command: String::new(),
+            wake_lock: true,
+            keep_alive: false,
+            restore_cwd: false,
+            allow_external_apps: false,
+            word_separators: WORD_SEPARATORS.to_string(),
+            cursor_style: CursorStyle::default(),
+            cursor_color: None,
+            backend: BackendKind::default(),
+            edge_to_edge: false,
+            extra_keys: parse_extra_keys(DEFAULT_EXTRA_KEYS),
+            volume_keys: true,
+            back_key: BackAction::default(),
+            haptics: 64,
+            notify_bell: true,
+            notify_done: true,
+            transcript: false,
+            transcript_input: false,
+            transcript_dir: PathBuf::from("transcripts"),
+            transcript_max_size: 8 << 20,
+            transcript_keep: 10,
+            bootstrap_url: String::new(),
+            bootstrap_sha256: String::new(),
+            bootstrap_mirror: String::new(),
+            bootstrap_environment: String::new(),
+            termux_exec_url: String::new(),
+            termux_exec_version: 0,
+            termux_exec_sha256: String::new(),
+            debug_overlay: false,
         }
     }
 }
 
 impl AppConfig {
     pub fn load_or_create(path: &Path) -> Self {
-        if let Ok(contents) = fs::read_to_string(path) {
-            let cfg = Self::from_ini(&contents);
-            if cfg.is_some() {
-                return cfg.unwrap();
-            }
+        if let Some(cfg) = Self::load(path) {
+            return cfg;
         }
 
         let cfg = Self::default();
@@ -39,8 +225,48 @@ impl AppConfig {
         cfg
     }
 
+    /// Make relative file paths in the config absolute under `base`.
+    pub fn resolve_paths(&mut self, base: &Path) {
+        if let Some(path) = self.font_path.as_ref() {
+            if path.is_relative() {
+                self.font_path = Some(base.join(path));
+            }
+        }
+    }
+
+    /// Turn a font size left in pixels by an old config into points at
+    /// the display's `scale`, so it keeps the size it had.
+    pub fn resolve_font_size(&mut self, scale: f32) {
+        if let Some(px) = self.legacy_font_px.take() {
+            self.font_size = (px / scale.max(1.0)).clamp(4.0, 48.0);
+        }
+    }
+
+    /// Read an existing config file without creating one.
+    pub fn load(path: &Path) -> Option<Self> {
+        Self::from_ini(&fs::read_to_string(path).ok()?)
+    }
+
+    /// The theme to show given the system's dark mode setting.
+    pub fn active_theme(&self, system_dark: bool) -> Theme {
+        let dark = match self.theme {
+            ThemeMode::System => system_dark,
+            ThemeMode::Dark => true,
+            ThemeMode::Light => false,
+        };
+        if dark {
+            self.dark_theme
+        } else {
+            self.light_theme
+        }
+    }
+
     fn from_ini(contents: &str) -> Option<Self> {
         let mut cfg = Self::default();
+        let mut size_in_points = false;
+        // Whether `[colors]` sets its defaults, or they follow its palette.
+        let mut foreground = false;
+        let mut background = false;
         let mut section = String::new();
 
         for raw_line in contents.lines() {
@@ -58,17 +284,80 @@ impl AppConfig {
                 continue;
             };
 
+            // Variable names are case-sensitive, unlike the keys below.
+            if section == "env" {
+                let name = key.trim();
+                let valid = !name.is_empty()
+                    && !name.starts_with(|c: char| c.is_ascii_digit())
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if valid {
+                    cfg.env.push((name.to_string(), value.trim().to_string()));
+                }
+                continue;
+            }
+
             let key = key.trim().to_ascii_lowercase();
             let value = value.trim();
 
             match (section.as_str(), key.as_str()) {
-                ("font", "size") => {
+                ("font", "size_pt") => {
                     if let Ok(v) = value.parse::<f32>() {
-                        if v >= 8.0 && v <= 96.0 {
+                        if (4.0..=48.0).contains(&v) {
                             cfg.font_size = v;
+                            cfg.legacy_font_px = None;
+                            size_in_points = true;
+                        }
+                    }
+                }
+                // Pixels, from before sizes were in points; `size_pt` wins.
+                ("font", "size") => {
+                    if let Ok(v) = value.parse::<f32>() {
+                        if (8.0..=96.0).contains(&v) && !size_in_points {
+                            cfg.legacy_font_px = Some(v);
+                        }
+                    }
+                }
+                ("font", "external_size") => {
+                    if value.is_empty() {
+                        cfg.external_font_size = None;
+                    } else if let Ok(v) = value.parse::<f32>() {
+                        if (4.0..=48.0).contains(&v) {
+                            cfg.external_font_size = Some(v);
                         }
                     }
                 }
+                ("font", "path") => {
+                    cfg.font_path = if value.is_empty() {
+                        None
+                    } else {
+                        Some(PathBuf::from(value))
+                    };
+                }
+                ("font", "ligatures") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.ligatures = v;
+                    }
+                }
+                ("padding", "cell_x") => {
+                    if let Some(v) = parse_padding(value) {
+                        cfg.cell_padding.0 = v;
+                    }
+                }
+                ("padding", "cell_y") => {
+                    if let Some(v) = parse_padding(value) {
+                        cfg.cell_padding.1 = v;
+                    }
+                }
+                ("padding", "margin_x") => {
+                    if let Some(v) = parse_padding(value) {
+                        cfg.margin.0 = v;
+                    }
+                }
+                ("padding", "margin_y") => {
+                    if let Some(v) = parse_padding(value) {
+                        cfg.margin.1 = v;
+                    }
+                }
                 ("grid", "cols") => {
                     if let Ok(v) = value.parse::<usize>() {
                         cfg.grid_cols = if v > 0 { Some(v) } else { None };
@@ -79,15 +368,233 @@ impl AppConfig {
                         cfg.grid_rows = if v > 0 { Some(v) } else { None };
                     }
                 }
-                ("colors", "palette") => {
-                    if let Some(palette) = parse_palette(value) {
-                        cfg.palette = palette;
+                ("colors", "theme") => {
+                    cfg.theme = match value.to_ascii_lowercase().as_str() {
+                        "system" | "auto" => ThemeMode::System,
+                        "dark" => ThemeMode::Dark,
+                        "light" => ThemeMode::Light,
+                        _ => cfg.theme,
+                    };
+                }
+                ("colors", "opacity") => {
+                    if let Ok(v) = value.parse::<f32>() {
+                        cfg.background_opacity = v.clamp(0.0, 1.0);
+                    }
+                }
+                ("colors" | "colors.light", _) => {
+                    let theme = if section == "colors" {
+                        &mut cfg.dark_theme
+                    } else {
+                        &mut cfg.light_theme
+                    };
+                    match key.as_str() {
+                        "palette" => {
+                            if let Some(palette) = parse_palette(value) {
+                                theme.palette = palette;
+                            }
+                        }
+                        "foreground" => {
+                            if let Some(v) = parse_color(value) {
+                                theme.foreground = v;
+                                foreground |= section == "colors";
+                            }
+                        }
+                        "background" => {
+                            if let Some(v) = parse_color(value) {
+                                theme.background = v;
+                                background |= section == "colors";
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                ("unicode", "ambiguous_width") => {
+                    if let Ok(v) = value.parse::<u8>() {
+                        cfg.ambiguous_wide = v == 2;
+                    }
+                }
+                ("unicode", "bidi") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.bidi = v;
+                    }
+                }
+                ("terminal", "answerback") => {
+                    cfg.answerback = value.to_string();
+                }
+                ("terminal", "flow_control") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.flow_control = v;
+                    }
+                }
+                ("cursor", "style") => {
+                    cfg.cursor_style.shape = match value.to_ascii_lowercase().as_str() {
+                        "block" => CursorShape::Block,
+                        "underline" => CursorShape::Underline,
+                        "bar" | "beam" => CursorShape::Bar,
+                        _ => cfg.cursor_style.shape,
+                    };
+                }
+                ("cursor", "blink") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.cursor_style.blinking = v;
+                    }
+                }
+                ("cursor", "color") => {
+                    if value.is_empty() || value.eq_ignore_ascii_case("reverse") {
+                        cfg.cursor_color = None;
+                    } else if let Some(v) = parse_color(value) {
+                        cfg.cursor_color = Some(v);
+                    }
+                }
+                ("render", "backend") => {
+                    cfg.backend = match value.to_ascii_lowercase().as_str() {
+                        "vulkan" => BackendKind::Vulkan,
+                        "gles" | "gl" => BackendKind::Gles,
+                        "raster" | "cpu" => BackendKind::Raster,
+                        _ => cfg.backend,
+                    };
+                }
+                ("render", "edge_to_edge") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.edge_to_edge = v;
+                    }
+                }
+                ("extra_keys", "keys") => {
+                    cfg.extra_keys = parse_extra_keys(value);
+                }
+                ("keyboard", "volume_keys") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.volume_keys = v;
+                    }
+                }
+                ("keyboard", "back") => {
+                    cfg.back_key = match value.to_ascii_lowercase().as_str() {
+                        "escape" | "esc" => BackAction::Escape,
+                        "keyboard" => BackAction::Keyboard,
+                        "background" => BackAction::Background,
+                        _ => cfg.back_key,
+                    };
+                }
+                ("keyboard", "haptics") => {
+                    if let Ok(v) = value.parse::<u32>() {
+                        cfg.haptics = v.min(255) as u8;
+                    } else if parse_bool(value) == Some(false) {
+                        cfg.haptics = 0;
+                    }
+                }
+                ("shell", "program") => {
+                    cfg.shell = match value.to_ascii_lowercase().as_str() {
+                        "" | "auto" => ShellProgram::Auto,
+                        "system" => ShellProgram::System,
+                        _ => ShellProgram::Path(value.to_string()),
+                    };
+                }
+                ("shell", "args") => {
+                    cfg.shell_args = split_args(value);
+                }
+                ("shell", "login") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.login_shell = v;
+                    }
+                }
+                ("shell", "wake_lock") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.wake_lock = v;
+                    }
+                }
+                ("shell", "keep_alive") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.keep_alive = v;
+                    }
+                }
+                ("shell", "restore_cwd") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.restore_cwd = v;
+                    }
+                }
+                ("shell", "allow_external_apps") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.allow_external_apps = v;
+                    }
+                }
+                ("selection", "word_separators") => {
+                    cfg.word_separators = value.to_string();
+                }
+                ("share", "command") => {
+                    cfg.share_command = value.to_string();
+                }
+                ("notifications", "bell") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.notify_bell = v;
+                    }
+                }
+                ("notifications", "command_done") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.notify_done = v;
+                    }
+                }
+                ("transcript", "enabled") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.transcript = v;
+                    }
+                }
+                ("transcript", "input") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.transcript_input = v;
+                    }
+                }
+                ("transcript", "dir") if !value.is_empty() => {
+                    cfg.transcript_dir = PathBuf::from(value);
+                }
+                ("transcript", "max_size_mb") => {
+                    if let Ok(v) = value.parse::<u64>() {
+                        cfg.transcript_max_size = v << 20;
+                    }
+                }
+                ("transcript", "keep") => {
+                    if let Ok(v) = value.parse::<usize>() {
+                        cfg.transcript_keep = v;
+                    }
+                }
+                ("bootstrap", "url") => {
+                    cfg.bootstrap_url = value.to_string();
+                }
+                ("bootstrap", "sha256") => {
+                    cfg.bootstrap_sha256 = value.to_ascii_lowercase();
+                }
+                ("bootstrap", "mirror") => {
+                    cfg.bootstrap_mirror = value.to_string();
+                }
+                ("bootstrap", "environment") if value.is_empty() || is_environment_name(value) => {
+                    cfg.bootstrap_environment = value.to_string();
+                }
+                ("bootstrap", "termux_exec_url") => {
+                    cfg.termux_exec_url = value.to_string();
+                }
+                ("bootstrap", "termux_exec_version") => {
+                    if let Ok(v) = value.parse::<u32>() {
+                        cfg.termux_exec_version = v;
+                    }
+                }
+                ("bootstrap", "termux_exec_sha256") => {
+                    cfg.termux_exec_sha256 = value.to_ascii_lowercase();
+                }
+                ("debug", "overlay") => {
+                    if let Some(v) = parse_bool(value) {
+                        cfg.debug_overlay = v;
                     }
                 }
                 _ => {}
             }
         }
 
+        let dark = Theme::on_black(cfg.dark_theme.palette);
+        if !foreground {
+            cfg.dark_theme.foreground = dark.foreground;
+        }
+        if !background {
+            cfg.dark_theme.background = dark.background;
+        }
         Some(cfg)
     }
 
@@ -95,7 +602,26 @@ impl AppConfig {
         let mut out = String::new();
         out.push_str("# gui-engine config\n\n");
         out.push_str("[font]\n");
-        out.push_str(&format!("size = {}\n\n", self.font_size));
+        out.push_str(&format!("size_pt = {}\n", self.font_size));
+        out.push_str(&format!(
+            "external_size = {}\n",
+            self.external_font_size
+                .map(|size| size.to_string())
+                .unwrap_or_default()
+        ));
+        out.push_str(&format!(
+            "path = {}\n",
+            self.font_path
+                .as_ref()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default()
+        ));
+        out.push_str(&format!("ligatures = {}\n\n", self.ligatures));
+        out.push_str("[padding]\n");
+        out.push_str(&format!(
+            "cell_x = {}\ncell_y = {}\nmargin_x = {}\nmargin_y = {}\n\n",
+            self.cell_padding.0, self.cell_padding.1, self.margin.0, self.margin.1
+        ));
         out.push_str("[grid]\n");
         out.push_str(&format!(
             "cols = {}\nrows = {}\n\n",
@@ -103,18 +629,200 @@ impl AppConfig {
             self.grid_rows.unwrap_or(0)
         ));
         out.push_str("[colors]\n");
-        out.push_str("palette = ");
-        for (i, c) in self.palette.iter().enumerate() {
-            if i > 0 {
-                out.push(',');
-            }
-            out.push_str(&format!("#{:06x}", c));
-        }
+        let mode = match self.theme {
+            ThemeMode::System => "system",
+            ThemeMode::Dark => "dark",
+            ThemeMode::Light => "light",
+        };
+        out.push_str(&format!("theme = {}\n", mode));
+        write_theme(
+            &mut out,
+            &self.dark_theme,
+            Some(Theme::on_black(self.dark_theme.palette)),
+        );
+        out.push_str(&format!("opacity = {}\n\n", self.background_opacity));
+        out.push_str("[colors.light]\n");
+        write_theme(&mut out, &self.light_theme, None);
         out.push('\n');
+        out.push_str("[unicode]\n");
+        out.push_str(&format!(
+            "ambiguous_width = {}\n",
+            if self.ambiguous_wide { 2 } else { 1 }
+        ));
+        out.push_str(&format!("bidi = {}\n", self.bidi));
+        out.push_str("\n[terminal]\n");
+        out.push_str(&format!("answerback = {}\n", self.answerback));
+        out.push_str(&format!("flow_control = {}\n", self.flow_control));
+        out.push_str("\n[shell]\n");
+        let program = match &self.shell {
+            ShellProgram::Auto => "auto",
+            ShellProgram::System => "system",
+            ShellProgram::Path(path) => path,
+        };
+        out.push_str(&format!("program = {}\n", program));
+        out.push_str(&format!("args = {}\n", join_args(&self.shell_args)));
+        out.push_str(&format!("login = {}\n", self.login_shell));
+        out.push_str(&format!("wake_lock = {}\n", self.wake_lock));
+        out.push_str(&format!("keep_alive = {}\n", self.keep_alive));
+        out.push_str(&format!("restore_cwd = {}\n", self.restore_cwd));
+        out.push_str(&format!(
+            "allow_external_apps = {}\n",
+            self.allow_external_apps
+        ));
+        out.push_str("\n[env]\n");
+        for (name, value) in &self.env {
+            out.push_str(&format!("{} = {}\n", name, value));
+        }
+        out.push_str("\n[cursor]\n");
+        let shape = match self.cursor_style.shape {
+            CursorShape::Block => "block",
+            CursorShape::Underline => "underline",
+            CursorShape::Bar => "bar",
+        };
+        out.push_str(&format!("style = {}\n", shape));
+        out.push_str(&format!("blink = {}\n", self.cursor_style.blinking));
+        match self.cursor_color {
+            Some(c) => out.push_str(&format!("color = #{:06x}\n", c)),
+            None => out.push_str("color = reverse\n"),
+        }
+        out.push_str("\n[render]\n");
+        let backend = match self.backend {
+            BackendKind::Gles => "gles",
+            BackendKind::Vulkan => "vulkan",
+            BackendKind::Raster => "raster",
+        };
+        out.push_str(&format!("backend = {}\n", backend));
+        out.push_str(&format!("edge_to_edge = {}\n", self.edge_to_edge));
+        out.push_str("\n[extra_keys]\n");
+        let keys: Vec<&str> = self.extra_keys.iter().map(ExtraKey::name).collect();
+        out.push_str(&format!("keys = {}\n", keys.join(" ")));
+        out.push_str("\n[keyboard]\n");
+        out.push_str(&format!("volume_keys = {}\n", self.volume_keys));
+        let back = match self.back_key {
+            BackAction::Escape => "escape",
+            BackAction::Keyboard => "keyboard",
+            BackAction::Background => "background",
+        };
+        out.push_str(&format!("back = {}\n", back));
+        out.push_str(&format!("haptics = {}\n", self.haptics));
+        out.push_str("\n[selection]\n");
+        out.push_str(&format!("word_separators = {}\n", self.word_separators));
+        out.push_str("\n[share]\n");
+        out.push_str(&format!("command = {}\n", self.share_command));
+        out.push_str("\n[notifications]\n");
+        out.push_str(&format!("bell = {}\n", self.notify_bell));
+        out.push_str(&format!("command_done = {}\n", self.notify_done));
+        out.push_str("\n[transcript]\n");
+        out.push_str(&format!("enabled = {}\n", self.transcript));
+        out.push_str(&format!("input = {}\n", self.transcript_input));
+        out.push_str(&format!("dir = {}\n", self.transcript_dir.display()));
+        out.push_str(&format!(
+            "max_size_mb = {}\n",
+            self.transcript_max_size >> 20
+        ));
+        out.push_str(&format!("keep = {}\n", self.transcript_keep));
+        out.push_str("\n[bootstrap]\n");
+        out.push_str(&format!("url = {}\n", self.bootstrap_url));
+        out.push_str(&format!("sha256 = {}\n", self.bootstrap_sha256));
+        out.push_str(&format!("mirror = {}\n", self.bootstrap_mirror));
+        out.push_str(&format!("environment = {}\n", self.bootstrap_environment));
+        out.push_str(&format!("termux_exec_url = {}\n", self.termux_exec_url));
+        out.push_str(&format!(
+            "termux_exec_version = {}\n",
+            self.termux_exec_version
+        ));
+        out.push_str(&format!(
+            "termux_exec_sha256 = {}\n",
+            self.termux_exec_sha256
+        ));
+        out.push_str("\n[debug]\n");
+        out.push_str(&format!("overlay = {}\n", self.debug_overlay));
         out
     }
 }
 
+/// Write `theme`'s colours, leaving out defaults that are `follows`'s, so
+/// they go on following the palette.
+fn write_theme(out: &mut String, theme: &Theme, follows: Option<Theme>) {
+    out.push_str("palette = ");
+    for (i, c) in theme.palette.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str(&format!("#{:06x}", c));
+    }
+    out.push('\n');
+    if follows.is_none_or(|f| f.foreground != theme.foreground) {
+        out.push_str(&format!("foreground = #{:06x}\n", theme.foreground));
+    }
+    if follows.is_none_or(|f| f.background != theme.background) {
+        out.push_str(&format!("background = #{:06x}\n", theme.background));
+    }
+}
+
+fn parse_padding(value: &str) -> Option<f32> {
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|v| (0.0..=256.0).contains(v))
+}
+
+/// Split a command line into arguments the way a shell would, minus
+/// expansions: whitespace separates, quotes group and backslash escapes
+/// outside single quotes.
+fn split_args(value: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut quote = None;
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"') | None, '\\') => {
+                if let Some(next) = chars.next() {
+                    arg.get_or_insert_with(String::new).push(next);
+                }
+            }
+            (Some(_), c) => arg.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                arg.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => args.extend(arg.take()),
+            (None, c) => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    args
+}
+
+/// The reverse of [`split_args`], single-quoting where needed.
+fn join_args(args: &[String]) -> String {
+    let quoted: Vec<String> = args
+        .iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && !arg
+                    .chars()
+                    .any(|c| c.is_whitespace() || matches!(c, '\'' | '"' | '\\'));
+            if plain {
+                arg.clone()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect();
+    quoted.join(" ")
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
 fn parse_palette(value: &str) -> Option<[u32; 16]> {
     let parts: Vec<&str> = value.split(',').map(|s| s.trim()).collect();
     if parts.len() != 16 {
@@ -123,20 +831,26 @@ fn parse_palette(value: &str) -> Option<[u32; 16]> {
 
     let mut palette = [0u32; 16];
     for (i, part) in parts.iter().enumerate() {
-        let p = part.trim_start_matches('#').trim_start_matches("0x");
-        if p.len() != 6 {
-            return None;
-        }
-        if let Ok(v) = u32::from_str_radix(p, 16) {
-            palette[i] = v;
-        } else {
-            return None;
-        }
+        palette[i] = parse_color(part)?;
     }
 
     Some(palette)
 }
 
+/// Parse `#rrggbb` (or `0xrrggbb`, or bare hex) into 0xRRGGBB.
+fn parse_color(value: &str) -> Option<u32> {
+    let p = value.trim_start_matches('#').trim_start_matches("0x");
+    if p.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(p, 16).ok()
+}
+
 pub fn config_path(base: &Path) -> PathBuf {
     base.join("gui-engine.ini")
 }
+
+/// Where the shell's last directory is kept for `[shell] restore_cwd`.
+pub fn last_cwd_path(base: &Path) -> PathBuf {
+    base.join("last-cwd")
+}
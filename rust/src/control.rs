@@ -0,0 +1,282 @@
+//! The control socket, through which programs in the session ask the app
+//! for what only an app can do, like opening a file in another app. The
+//! prefix's helper scripts run the client the build stages as
+//! [`CLIENT`], whose source is control-client/gui_engine_ctl.c. Besides
+//! `open`, the commands cover the most used of termux-api's, taking the
+//! same options, so scripts written for it work.
+//!
+//! A request is the client's working directory and then the command and
+//! its arguments, each NUL-terminated, up to the client closing its side.
+//! The reply is an exit status byte and then text for the client to print,
+//! to stdout on success and stderr otherwise.
+//!
+//! The socket sits in the app's private data dir, so only the app's own
+//! processes can reach it.
+
+use android_activity::AndroidApp;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::android;
+
+/// Names the socket's path in the session's environment.
+pub const SOCKET_ENV: &str = "GUI_ENGINE_CONTROL";
+/// The client, as the build stages it in the native library dir.
+pub const CLIENT: &str = "libgui-engine-ctl.so";
+const SOCKET_FILE: &str = "control.sock";
+/// Longest request read; a client sending more is cut off.
+const MAX_REQUEST: u64 = 1024 * 1024;
+/// Schemes `open` hands to [`android::open_url`] rather than taking for
+/// a file name.
+const URL_SCHEMES: [&str; 6] = ["http:", "https:", "mailto:", "tel:", "geo:", "market:"];
+/// termux-vibrate's default.
+const VIBRATE_DEFAULT_MS: u64 = 1000;
+/// Longest vibration asked for that is honoured.
+const VIBRATE_MAX_MS: u64 = 5000;
+
+/// Where the socket goes for the app's data dir `data_dir`.
+pub fn socket_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(SOCKET_FILE)
+}
+
+/// Listen at `path`, serving each connection on a thread of its own.
+pub fn serve(app: AndroidApp, path: &Path) -> io::Result<()> {
+    // Left by the last process; nobody listens on it now.
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    log::info!("Control socket listening at {:?}", path);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let app = app.clone();
+                    std::thread::spawn(move || handle(&app, stream));
+                }
+                Err(e) => log::warn!("Control socket accept failed: {:?}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+struct Request {
+    cwd: PathBuf,
+    command: String,
+    args: Vec<String>,
+}
+
+impl Request {
+    fn read(stream: &mut UnixStream) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        stream.take(MAX_REQUEST).read_to_end(&mut bytes)?;
+        let mut fields = bytes
+            .split(|&b| b == 0)
+            .map(|field| String::from_utf8_lossy(field).into_owned());
+        let cwd = PathBuf::from(fields.next().unwrap_or_default());
+        let command = fields.next().unwrap_or_default();
+        let mut args: Vec<String> = fields.collect();
+        // What follows the last NUL.
+        args.pop();
+        Ok(Self { cwd, command, args })
+    }
+
+    /// `arg` as a path, relative to the client's working directory.
+    fn path(&self, arg: &str) -> PathBuf {
+        self.cwd.join(arg)
+    }
+}
+
+/// What the client prints, and the status it exits with.
+struct Reply {
+    status: u8,
+    text: String,
+}
+
+impl Reply {
+    fn ok() -> Self {
+        Self::text(String::new())
+    }
+
+    fn text(text: String) -> Self {
+        Self { status: 0, text }
+    }
+
+    fn error(text: impl Into<String>) -> Self {
+        let mut text = text.into();
+        text.push('\n');
+        Self { status: 1, text }
+    }
+}
+
+fn handle(app: &AndroidApp, mut stream: UnixStream) {
+    let reply = match Request::read(&mut stream) {
+        Ok(request) => run(app, &request),
+        Err(e) => {
+            log::warn!("Failed to read control request: {:?}", e);
+            return;
+        }
+    };
+    let result = stream
+        .write_all(&[reply.status])
+        .and_then(|()| stream.write_all(reply.text.as_bytes()));
+    if let Err(e) = result {
+        log::warn!("Failed to reply to control request: {:?}", e);
+    }
+}
+
+fn run(app: &AndroidApp, request: &Request) -> Reply {
+    // Not the arguments: they can be a clipboard's or a toast's text,
+    // passwords included.
+    log::info!("Control request: {}", request.command);
+    match request.command.as_str() {
+        "open" => open(app, request),
+        "toast" => toast(app, request),
+        "vibrate" => vibrate(app, request),
+        "clipboard-get" => clipboard_get(app),
+        "clipboard-set" => clipboard_set(app, request),
+        "battery-status" => battery_status(app),
+        command => Reply::error(format!("unknown command: {}", command)),
+    }
+}
+
+/// `open <file or URL>`: view it in whichever app handles it.
+fn open(app: &AndroidApp, request: &Request) -> Reply {
+    let [target] = request.args.as_slice() else {
+        return Reply::error("usage: open <file or URL>");
+    };
+    if is_url(target) {
+        if !android::open_url(app, target) {
+            return Reply::error(format!("open: no app opens {}", target));
+        }
+        return Reply::ok();
+    }
+    let path = request.path(target);
+    if !path.exists() {
+        return Reply::error(format!("open: {}: No such file or directory", target));
+    }
+    if !android::view_file(app, &path) {
+        return Reply::error(format!("open: no app opens {}", target));
+    }
+    Reply::ok()
+}
+
+fn is_url(target: &str) -> bool {
+    let lower = target.to_ascii_lowercase();
+    URL_SCHEMES.iter().any(|scheme| lower.starts_with(scheme))
+}
+
+/// `toast [-s] [text...]`: show the text in a toast, briefly with `-s`.
+/// termux-toast's colour and position options are taken and ignored.
+fn toast(app: &AndroidApp, request: &Request) -> Reply {
+    let mut brief = false;
+    let mut args = request.args.iter();
+    let mut text = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-s" => brief = true,
+            "-b" | "-c" | "-g" => {
+                args.next();
+            }
+            "--" => {
+                text.extend(args.by_ref().map(String::as_str));
+                break;
+            }
+            option if option.starts_with('-') && text.is_empty() => {
+                return Reply::error(format!("toast: unknown option {}", option));
+            }
+            _ => text.push(arg.as_str()),
+        }
+    }
+    if !android::toast(app, &text.join(" "), brief) {
+        return Reply::error("toast: failed to show it");
+    }
+    Reply::ok()
+}
+
+/// `vibrate [-d ms] [-f]`: buzz for the duration, a second unless told.
+/// `-f`, to buzz in silent mode, is taken and ignored.
+fn vibrate(app: &AndroidApp, request: &Request) -> Reply {
+    let mut ms = VIBRATE_DEFAULT_MS;
+    let mut args = request.args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-d" => match args.next().and_then(|d| d.parse().ok()) {
+                Some(d) => ms = d,
+                None => return Reply::error("vibrate: -d takes milliseconds"),
+            },
+            "-f" => {}
+            other => return Reply::error(format!("vibrate: unknown option {}", other)),
+        }
+    }
+    let duration = Duration::from_millis(ms.min(VIBRATE_MAX_MS));
+    if !android::vibrate(app, duration, u8::MAX) {
+        return Reply::error("vibrate: failed");
+    }
+    Reply::ok()
+}
+
+fn clipboard_get(app: &AndroidApp) -> Reply {
+    Reply::text(android::clipboard_text(app).unwrap_or_default())
+}
+
+/// `clipboard-set [--] text...`: the arguments, joined by spaces. The
+/// helper passes stdin along when there are none.
+fn clipboard_set(app: &AndroidApp, request: &Request) -> Reply {
+    let text = match request.args.split_first() {
+        Some((first, rest)) if first == "--" => rest,
+        _ => &request.args[..],
+    };
+    if !android::set_clipboard_text(app, &text.join(" ")) {
+        return Reply::error("clipboard-set: failed");
+    }
+    Reply::ok()
+}
+
+/// `battery-status`: the battery as termux-battery-status prints it, in
+/// JSON.
+fn battery_status(app: &AndroidApp) -> Reply {
+    let Some(battery) = android::battery_status(app) else {
+        return Reply::error("battery-status: no battery information");
+    };
+    let status = match battery.status {
+        2 => "CHARGING",
+        3 => "DISCHARGING",
+        4 => "NOT_CHARGING",
+        5 => "FULL",
+        _ => "UNKNOWN",
+    };
+    let plugged = match battery.plugged {
+        0 => "UNPLUGGED",
+        1 => "PLUGGED_AC",
+        2 => "PLUGGED_USB",
+        4 => "PLUGGED_WIRELESS",
+        8 => "PLUGGED_DOCK",
+        _ => "PLUGGED_UNKNOWN",
+    };
+    let health = match battery.health {
+        2 => "GOOD",
+        3 => "OVERHEAT",
+        4 => "DEAD",
+        5 => "OVER_VOLTAGE",
+        6 => "UNSPECIFIED_FAILURE",
+        7 => "COLD",
+        _ => "UNKNOWN",
+    };
+    Reply::text(format!(
+        concat!(
+            "{{\n",
+            "  \"health\": \"{}\",\n",
+            "  \"percentage\": {},\n",
+            "  \"plugged\": \"{}\",\n",
+            "  \"status\": \"{}\",\n",
+            "  \"temperature\": {:.1}\n",
+            "}}\n",
+        ),
+        health, battery.percentage, plugged, status, battery.temperature
+    ))
+}
@@ -0,0 +1,48 @@
+use unicode_bidi::{bidi_class, BidiClass, BidiInfo, Level};
+
+use crate::core::glyph::{Glyph, GlyphFlags};
+
+/// Display order of a row's cells under the Unicode bidi algorithm, as
+/// (logical column, right-to-left) for each visual column. Rows are laid out
+/// with a left-to-right base direction, like the rest of the terminal.
+///
+/// Returns None when the row has no right-to-left text and shows as stored,
+/// which is almost always.
+pub fn visual_order(row: &[Glyph]) -> Option<Vec<(usize, bool)>> {
+    if !row.iter().any(|g| is_rtl(g.char())) {
+        return None;
+    }
+
+    // One char per cell; a wide char's spacer travels with it.
+    let mut text = String::with_capacity(row.len());
+    let mut starts = Vec::with_capacity(row.len());
+    for (x, g) in row.iter().enumerate() {
+        if !g.flags().contains(GlyphFlags::WIDE_SPACER) {
+            text.push(g.char());
+            starts.push(x);
+        }
+    }
+
+    let info = BidiInfo::new(&text, Some(Level::ltr()));
+    let para = info.paragraphs.first()?;
+    let levels = info.reordered_levels_per_char(para, para.range.clone());
+    if levels.len() != starts.len() {
+        // A paragraph separator split the row; leave it alone.
+        return None;
+    }
+
+    let mut order = Vec::with_capacity(row.len());
+    for i in BidiInfo::reorder_visual(&levels) {
+        let end = starts.get(i + 1).copied().unwrap_or(row.len());
+        let rtl = levels[i].is_rtl();
+        order.extend((starts[i]..end).map(|x| (x, rtl)));
+    }
+    Some(order)
+}
+
+fn is_rtl(c: char) -> bool {
+    matches!(
+        bidi_class(c),
+        BidiClass::R | BidiClass::AL | BidiClass::RLE | BidiClass::RLO | BidiClass::RLI
+    )
+}
@@ -0,0 +1,83 @@
+//! Who waits for which child. The session's reaper collects the shell and
+//! the jobs it orphans onto us, but children started elsewhere (the
+//! bootstrap's commands, embedded sessions) are waited for by whoever
+//! started them, and must keep their exit statuses. Those are claimed
+//! while they are spawned and released once their owner has reaped them.
+
+use nix::sys::wait::{waitid, waitpid, Id, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use std::io;
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::{Condvar, Mutex};
+
+/// Pids whose owners reap them.
+static CLAIMED: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+/// Signalled when a pid leaves [`CLAIMED`].
+static RELEASED: Condvar = Condvar::new();
+
+/// Start a child with `spawn`, keeping the reaper off it until [`release`]
+/// is called with `pid(&child)`. The claim is taken before the child can
+/// exit, so the reaper can't get to it first.
+pub fn claim<T>(
+    spawn: impl FnOnce() -> io::Result<T>,
+    pid: impl FnOnce(&T) -> Pid,
+) -> io::Result<T> {
+    let mut claimed = CLAIMED.lock().unwrap();
+    let child = spawn()?;
+    claimed.push(pid(&child).as_raw());
+    Ok(child)
+}
+
+/// Hand `pid` back to the reaper once its owner has waited for it.
+pub fn release(pid: Pid) {
+    CLAIMED
+        .lock()
+        .unwrap()
+        .retain(|&claimed| claimed != pid.as_raw());
+    RELEASED.notify_all();
+}
+
+/// [`Command::status`], for a child the reaper leaves alone.
+pub fn status(command: &mut Command) -> io::Result<ExitStatus> {
+    let mut child = claim(|| command.spawn(), |child| Pid::from_raw(child.id() as i32))?;
+    let status = child.wait();
+    release(Pid::from_raw(child.id() as i32));
+    status
+}
+
+/// [`Command::output`], for a child the reaper leaves alone.
+pub fn output(command: &mut Command) -> io::Result<Output> {
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let child = claim(|| command.spawn(), |child| Pid::from_raw(child.id() as i32))?;
+    let pid = Pid::from_raw(child.id() as i32);
+    let output = child.wait_with_output();
+    release(pid);
+    output
+}
+
+/// Block until a child nobody has claimed exits, and reap it. Fails with
+/// ECHILD once there are no children left.
+pub fn wait_unclaimed() -> nix::Result<WaitStatus> {
+    loop {
+        // Look without reaping; the child may not be ours to reap.
+        let peeked = waitid(Id::All, WaitPidFlag::WEXITED | WaitPidFlag::WNOWAIT)?;
+        let Some(pid) = peeked.pid() else {
+            continue;
+        };
+        let mut claimed = CLAIMED.lock().unwrap();
+        if claimed.contains(&pid.as_raw()) {
+            // Its owner reaps it; wait for that rather than spin on it.
+            while claimed.contains(&pid.as_raw()) {
+                claimed = RELEASED.wait(claimed).unwrap();
+            }
+            continue;
+        }
+        drop(claimed);
+        // The owner of a child claimed and released since the peek has
+        // reaped it already, and the pid may have been reused since.
+        match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) | Err(nix::errno::Errno::ECHILD) => continue,
+            result => return result,
+        }
+    }
+}
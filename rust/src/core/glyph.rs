@@ -1,4 +1,7 @@
 use bitflags::bitflags;
+use std::collections::HashMap;
+
+use crate::core::theme::Theme;
 
 bitflags! {
     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -14,26 +17,54 @@ bitflags! {
     }
 }
 
-/// Layout: [rune: 4 bytes][fg: 1 byte][bg: 1 byte][attrs: 1 byte][pad: 1 byte]
+bitflags! {
+    /// Cell layout flags for double-width characters and wrapped lines.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct GlyphFlags: u8 {
+        /// First cell of a double-width character.
+        const WIDE = 1 << 0;
+        /// Second cell of a double-width character; holds no rune of its own.
+        const WIDE_SPACER = 1 << 1;
+        /// Last cell of a line that autowrapped onto the next one.
+        const WRAPLINE = 1 << 2;
+    }
+}
+
+/// Set on a colour to mark it as direct RGB (`0x00RRGGBB`) instead of a
+/// palette index, as in st.
+pub const TRUECOLOR: u32 = 1 << 24;
+
+/// The theme's default foreground and background, as in st.
+pub const DEFAULT_FG: u32 = 256;
+pub const DEFAULT_BG: u32 = 257;
+
+#[inline]
+pub const fn truecolor(r: u8, g: u8, b: u8) -> u32 {
+    TRUECOLOR | (r as u32) << 16 | (g as u32) << 8 | b as u32
+}
+
+/// Layout: [rune: 4 bytes][fg: 4 bytes][bg: 4 bytes][attrs: 1 byte][flags: 1 byte][marks: 2]
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct Glyph {
-    pub rune: u32, // char as u32 (4 bytes)
-    pub fg: u8,    // foreground color index (1 byte)
-    pub bg: u8,    // background color index (1 byte)
-    pub attrs: u8, // GlyphAttrs bits (1 byte)
-    _pad: u8,      // alignment padding (1 byte)
+    pub rune: u32,  // char as u32 (4 bytes)
+    pub fg: u32,    // palette index 0-255, DEFAULT_FG, or TRUECOLOR | rgb (4 bytes)
+    pub bg: u32,    // palette index 0-255, DEFAULT_BG, or TRUECOLOR | rgb (4 bytes)
+    pub attrs: u8,  // GlyphAttrs bits (1 byte)
+    pub flags: u8,  // GlyphFlags bits (1 byte)
+    pub marks: u16, // Marks id of the zero-width chars after rune, 0 for none (2 bytes)
 }
 
 impl Glyph {
     #[inline]
-    pub fn new(c: char, fg: u8, bg: u8) -> Self {
+    pub fn new(c: char, fg: u32, bg: u32) -> Self {
         Self {
             rune: c as u32,
             fg,
             bg,
             attrs: 0,
-            _pad: 0,
+            flags: 0,
+            marks: 0,
         }
     }
 
@@ -41,16 +72,74 @@ impl Glyph {
     pub fn char(&self) -> char {
         char::from_u32(self.rune).unwrap_or(' ')
     }
+
+    #[inline]
+    pub fn flags(&self) -> GlyphFlags {
+        GlyphFlags::from_bits_truncate(self.flags)
+    }
+}
+
+/// Most zero-width characters kept on one cell; a longer pile of
+/// combining marks is cut short here.
+const MAX_MARKS: usize = 8;
+
+/// Zero-width characters that follow a cell's own, such as combining
+/// accents, ZWJ and viramas. They have no cell of their own, so each
+/// distinct sequence is stored once here and cells hold its id in
+/// [`Glyph::marks`]. Id 0 is the empty sequence.
+pub struct Marks {
+    sequences: Vec<String>,
+    ids: HashMap<String, u16>,
+}
+
+impl Marks {
+    pub fn new() -> Self {
+        Self {
+            sequences: vec![String::new()],
+            ids: HashMap::new(),
+        }
+    }
+
+    /// The characters of sequence `id`.
+    pub fn get(&self, id: u16) -> &str {
+        self.sequences.get(id as usize).map_or("", String::as_str)
+    }
+
+    /// The id of sequence `id` with `c` added, or `id` itself once the
+    /// sequence or the table is full.
+    pub fn push(&mut self, id: u16, c: char) -> u16 {
+        let mut sequence = self.get(id).to_string();
+        if sequence.chars().count() >= MAX_MARKS {
+            return id;
+        }
+        sequence.push(c);
+        if let Some(&id) = self.ids.get(&sequence) {
+            return id;
+        }
+        let Ok(new_id) = u16::try_from(self.sequences.len()) else {
+            return id;
+        };
+        self.sequences.push(sequence.clone());
+        self.ids.insert(sequence, new_id);
+        new_id
+    }
+}
+
+impl Default for Marks {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Default for Glyph {
     fn default() -> Self {
         Self {
             rune: ' ' as u32,
-            fg: 7, // white
-            bg: 0, // black
+            fg: DEFAULT_FG,
+            bg: DEFAULT_BG,
             attrs: 0,
-            _pad: 0,
+            flags: 0,
+            marks: 0,
         }
     }
 }
@@ -75,12 +164,21 @@ pub const DEFAULT_COLORS: [u32; 16] = [
     0xffffff, // 15: bright white
 ];
 
+/// Resolve a cell colour: the theme's defaults and 16-entry palette, the
+/// xterm 6x6x6 cube and grayscale ramp for 16-255, or direct RGB for
+/// [`TRUECOLOR`] values.
 #[inline]
-pub fn color_from_index(palette: &[u32; 16], idx: u8) -> skia_safe::Color {
-    let rgb = if idx < 16 {
-        palette[idx as usize]
+pub fn color_from_index(theme: &Theme, color: u32) -> skia_safe::Color {
+    let rgb = if color & TRUECOLOR != 0 {
+        color & 0xFF_FFFF
+    } else if color == DEFAULT_FG {
+        theme.foreground
+    } else if color == DEFAULT_BG {
+        theme.background
+    } else if color < 16 {
+        theme.palette[color as usize]
     } else {
-        xterm_256_rgb(idx)
+        xterm_256_rgb(color.min(255) as u8)
     };
     skia_safe::Color::from_rgb(
         ((rgb >> 16) & 0xFF) as u8,
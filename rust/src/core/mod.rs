@@ -1,12 +1,17 @@
+pub mod bidi;
+pub mod children;
 pub mod glyph;
 pub mod parser;
 pub mod pty;
 pub mod screen;
 pub mod terminal;
+pub mod theme;
+pub mod tmux;
 pub mod types;
 
 pub use parser::Parser;
 pub use pty::Pty;
 pub use pty::PtyEnv;
-pub use screen::Renderer;
+pub use pty::ShellCommand;
+pub use screen::{DebugStats, Renderer};
 pub use types::Term;
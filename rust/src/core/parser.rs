@@ -1,22 +1,126 @@
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+use unicode_width::UnicodeWidthChar;
 use vte::{Params, Parser as VteParserInner};
 
-use crate::core::glyph::Glyph;
-use crate::core::types::{Cursor, Term, TermMode};
+use crate::core::glyph::{truecolor, Glyph, GlyphFlags, DEFAULT_BG, DEFAULT_FG};
+use crate::core::tmux::TmuxControl;
+use crate::core::types::{Charset, Cursor, CursorStyle, LineSize, PromptKind, Term, TermMode};
+
+/// `DCS tmux; <payload> ST`: tmux passthrough with every ESC doubled.
+const TMUX_PASSTHROUGH: &[u8] = b"\x1bPtmux;";
+/// `DCS 1000 p`: start of tmux control mode (`tmux -CC`).
+const TMUX_CONTROL: &[u8] = b"\x1bP1000p";
+
+/// DCS strings handled before vte sees them. vte drops bytes >= 0x80 and
+/// ends the string on the first ESC, which breaks both tmux forms.
+enum DcsIntercept {
+    None,
+    Passthrough { payload: Vec<u8>, esc: bool },
+    Control { esc: bool },
+}
 
 pub struct VteParser {
     parser: VteParserInner,
+    held: Vec<u8>,
+    intercept: DcsIntercept,
+    pub tmux: Option<Box<TmuxControl>>,
 }
 
 impl VteParser {
     pub fn new() -> Self {
         Self {
             parser: VteParserInner::new(),
+            held: Vec::new(),
+            intercept: DcsIntercept::None,
+            tmux: None,
         }
     }
 
     pub fn process(&mut self, term: &mut Term, c: u8) {
+        match &mut self.intercept {
+            DcsIntercept::None => {}
+            DcsIntercept::Passthrough { payload, esc } => {
+                if !*esc {
+                    if c == 0x1b {
+                        *esc = true;
+                    } else {
+                        payload.push(c);
+                    }
+                    return;
+                }
+                *esc = false;
+                match c {
+                    b'\\' => {
+                        let payload = std::mem::take(payload);
+                        self.intercept = DcsIntercept::None;
+                        for byte in payload {
+                            self.process(term, byte);
+                        }
+                    }
+                    0x1b => payload.push(0x1b),
+                    _ => payload.extend_from_slice(&[0x1b, c]),
+                }
+                return;
+            }
+            DcsIntercept::Control { esc } => {
+                if *esc && c == b'\\' {
+                    self.intercept = DcsIntercept::None;
+                    self.tmux = None;
+                    term.mark_dirty();
+                    log::info!("tmux control mode ended");
+                    return;
+                }
+                *esc = c == 0x1b;
+                if !*esc {
+                    if let Some(tmux) = self.tmux.as_mut() {
+                        tmux.feed(c);
+                    }
+                }
+                return;
+            }
+        }
+
+        if self.held.is_empty() && c != 0x1b {
+            self.advance(term, &[c]);
+            return;
+        }
+
+        self.held.push(c);
+        if self.held == TMUX_PASSTHROUGH {
+            self.held.clear();
+            self.intercept = DcsIntercept::Passthrough {
+                payload: Vec::new(),
+                esc: false,
+            };
+            return;
+        }
+        if self.held == TMUX_CONTROL {
+            self.held.clear();
+            self.intercept = DcsIntercept::Control { esc: false };
+            self.tmux = Some(Box::new(TmuxControl::new(term.cols, term.rows)));
+            log::info!("tmux control mode started");
+            return;
+        }
+        if TMUX_PASSTHROUGH.starts_with(&self.held) || TMUX_CONTROL.starts_with(&self.held) {
+            return;
+        }
+
+        // Not a tmux DCS after all; replay the held bytes, keeping a
+        // trailing ESC in case it starts the next candidate.
+        let mut held = std::mem::take(&mut self.held);
+        if held.len() > 1 && held.last() == Some(&0x1b) {
+            held.pop();
+            self.held.push(0x1b);
+        }
+        self.advance(term, &held);
+    }
+
+    fn advance(&mut self, term: &mut Term, bytes: &[u8]) {
         let mut performer = Performer(term);
-        self.parser.advance(&mut performer, &[c]);
+        self.parser.advance(&mut performer, bytes);
     }
 }
 
@@ -32,15 +136,58 @@ impl<'a> vte::Perform for Performer<'a> {
     fn print(&mut self, c: char) {
         let term = &mut *self.0;
         clamp_cursor(term);
+
+        let set = term.single_shift.take().unwrap_or(term.charset);
+        let c = term.trantbl[set].map(c);
+
+        let width = if term.ambiguous_wide {
+            c.width_cjk()
+        } else {
+            c.width()
+        }
+        .unwrap_or(1);
+        // Combining marks, ZWJ and the like have no cell of their own and
+        // don't move the cursor; they join the character before them.
+        if width == 0 {
+            attach_mark(term, c);
+            return;
+        }
+
+        let line_cols = term.line_cols(term.cursor.y);
+        if width == 2 && line_cols < 2 {
+            return;
+        }
+        if width == 2 && term.cursor.x + 1 >= line_cols {
+            // Wide character doesn't fit in the last column: wrap first.
+            mark_wrapped(term);
+            term.cursor.x = 0;
+            if term.cursor.y + 1 >= term.rows {
+                scroll_up(term);
+            } else {
+                term.cursor.y += 1;
+            }
+        }
+
         let idx = term.cursor.y * term.cols + term.cursor.x;
         if idx < term.grid.len() {
-            let attrs = term.cursor.attr.attrs;
-            term.grid[idx] = Glyph::new(c, term.cursor.attr.fg, term.cursor.attr.bg);
-            term.grid[idx].attrs = attrs;
+            clear_wide_pair(term, term.cursor.x, term.cursor.y);
+            let mut glyph = Glyph::new(c, term.cursor.attr.fg, term.cursor.attr.bg);
+            glyph.attrs = term.cursor.attr.attrs;
+            if width == 2 {
+                clear_wide_pair(term, term.cursor.x + 1, term.cursor.y);
+                glyph.flags = GlyphFlags::WIDE.bits();
+                let mut spacer = Glyph::new(' ', glyph.fg, glyph.bg);
+                spacer.attrs = glyph.attrs;
+                spacer.flags = GlyphFlags::WIDE_SPACER.bits();
+                term.grid[idx + 1] = spacer;
+            }
+            term.grid[idx] = glyph;
             mark_dirty(term);
         }
+        term.cursor.x += width - 1;
 
-        if term.cursor.x + 1 >= term.cols {
+        if term.cursor.x + 1 >= term.line_cols(term.cursor.y) {
+            mark_wrapped(term);
             term.cursor.x = 0;
             if term.cursor.y + 1 >= term.rows {
                 term.cursor.y = term.rows - 1;
@@ -59,7 +206,11 @@ impl<'a> vte::Perform for Performer<'a> {
         clamp_cursor(term);
         match c {
             0x00 => {}
-            0x07 => {}
+            0x05 if !term.answerback.is_empty() => {
+                let answerback = term.answerback.clone();
+                term.respond(answerback.as_bytes());
+            }
+            0x07 => term.bell = true,
             0x08 => {
                 if term.cursor.x > 0 {
                     term.cursor.x -= 1;
@@ -90,6 +241,12 @@ impl<'a> vte::Perform for Performer<'a> {
                 term.cursor.x = 0;
                 mark_dirty(term);
             }
+            0x0e => {
+                term.charset = 1;
+            }
+            0x0f => {
+                term.charset = 0;
+            }
             0x84 => {
                 term.cursor.y += 1;
                 if term.cursor.y >= term.rows {
@@ -108,6 +265,12 @@ impl<'a> vte::Perform for Performer<'a> {
                 mark_dirty(term);
             }
             0x88 => {}
+            0x8e => {
+                term.single_shift = Some(2);
+            }
+            0x8f => {
+                term.single_shift = Some(3);
+            }
             0x8d => {
                 if term.cursor.y == 0 {
                     scroll_down(term);
@@ -120,7 +283,7 @@ impl<'a> vte::Perform for Performer<'a> {
         }
     }
 
-    fn csi_dispatch(&mut self, params: &Params, _intermediates: &[u8], _ignore: bool, c: char) {
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, c: char) {
         let term = &mut *self.0;
         clamp_cursor(term);
 
@@ -135,6 +298,34 @@ impl<'a> vte::Perform for Performer<'a> {
         }
 
         match c as u8 {
+            b'v' if intermediates == b"$" => {
+                copy_rect(term, params);
+            }
+            b'x' if intermediates == b"$" => {
+                let code = get_param!(0, 0);
+                // DECFRA only accepts printable GL/GR characters.
+                if matches!(code, 32..=126 | 160..=255) {
+                    let ch = char::from(code as u8);
+                    let mut glyph = Glyph::new(ch, term.cursor.attr.fg, term.cursor.attr.bg);
+                    glyph.attrs = term.cursor.attr.attrs;
+                    if let Some(rect) = rect_params(term, params, 1) {
+                        fill_rect(term, rect, glyph);
+                    }
+                }
+            }
+            b'q' if intermediates == b" " => match get_param!(0, 0) {
+                0 => term.cursor_style = term.default_cursor_style,
+                ps => {
+                    if let Some(style) = CursorStyle::from_decscusr(ps) {
+                        term.cursor_style = style;
+                    }
+                }
+            },
+            b'z' if intermediates == b"$" => {
+                if let Some(rect) = rect_params(term, params, 0) {
+                    fill_rect(term, rect, Glyph::default());
+                }
+            }
             b'@' => {
                 let n = get_param!(0, 1);
                 insert_blank(term, n);
@@ -194,7 +385,13 @@ impl<'a> vte::Perform for Performer<'a> {
                         term.rows - 1,
                     ),
                     1 => clear_region(term, 0, 0, term.cursor.x, term.cursor.y),
-                    2 | 3 => clear_region(term, 0, 0, term.cols - 1, term.rows - 1),
+                    2 => {
+                        clear_region(term, 0, 0, term.cols - 1, term.rows - 1);
+                        term.line_size.fill(LineSize::Single);
+                    }
+                    // xterm extension: erase the saved lines only. `clear` sends it
+                    // (its E3 capability); `clear -x` leaves scrollback alone.
+                    3 => term.clear_history(),
                     _ => {}
                 }
             }
@@ -262,15 +459,40 @@ impl<'a> vte::Perform for Performer<'a> {
                 term.dirty.iter_mut().for_each(|d| *d = true);
             }
             b's' => {}
+            b't' => {
+                window_ops(term, params);
+            }
             b'u' => {}
             _ => {}
         }
     }
 
-    fn esc_dispatch(&mut self, _intermediates: &[u8], _ignore: bool, c: u8) {
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, c: u8) {
         let term = &mut *self.0;
         clamp_cursor(term);
         match c {
+            _ if matches!(intermediates, [b'(' | b')' | b'*' | b'+']) => {
+                let slot = match intermediates[0] {
+                    b'(' => 0,
+                    b')' => 1,
+                    b'*' => 2,
+                    _ => 3,
+                };
+                if let Some(set) = Charset::from_designator(c) {
+                    term.trantbl[slot] = set;
+                }
+            }
+            b'3'..=b'6' if intermediates == b"#" => {
+                let y = term.cursor.y;
+                term.line_size[y] = match c {
+                    b'3' => LineSize::DoubleHeightTop,
+                    b'4' => LineSize::DoubleHeightBottom,
+                    b'6' => LineSize::DoubleWidth,
+                    _ => LineSize::Single,
+                };
+                term.cursor.x = term.cursor.x.min(term.line_cols(y) - 1);
+                term.dirty[y] = true;
+            }
             b'D' => {
                 term.cursor.y += 1;
                 if term.cursor.y >= term.rows {
@@ -289,6 +511,18 @@ impl<'a> vte::Perform for Performer<'a> {
                 mark_dirty(term);
             }
             b'H' => {}
+            b'N' => {
+                term.single_shift = Some(2);
+            }
+            b'O' => {
+                term.single_shift = Some(3);
+            }
+            b'n' => {
+                term.charset = 2;
+            }
+            b'o' => {
+                term.charset = 3;
+            }
             b'M' => {
                 if term.cursor.y == 0 {
                     scroll_down(term);
@@ -299,6 +533,12 @@ impl<'a> vte::Perform for Performer<'a> {
             }
             b'7' => {}
             b'8' => {}
+            b'=' => {
+                term.mode.insert(TermMode::APPKEYPAD);
+            }
+            b'>' => {
+                term.mode.remove(TermMode::APPKEYPAD);
+            }
             b'c' => {
                 term.reset();
             }
@@ -306,10 +546,139 @@ impl<'a> vte::Perform for Performer<'a> {
         }
     }
 
-    fn osc_dispatch(&mut self, _params: &[&[u8]], _ignore: bool) {}
+    fn osc_dispatch(&mut self, params: &[&[u8]], _ignore: bool) {
+        let term = &mut *self.0;
+        match params.first().copied() {
+            // Icon name (1) is not shown anywhere, so only 0 and 2 set the title.
+            Some(b"0") | Some(b"2") => {
+                // vte splits on ';', which may legitimately appear in a title.
+                let parts: Vec<_> = params[1..]
+                    .iter()
+                    .map(|p| String::from_utf8_lossy(p))
+                    .collect();
+                term.title = parts.join(";");
+            }
+            Some(b"7") => {
+                let url: Vec<_> = params[1..]
+                    .iter()
+                    .map(|p| String::from_utf8_lossy(p))
+                    .collect();
+                term.cwd = file_url_path(&url.join(";"));
+            }
+            Some(b"133") => semantic_prompt(term, &params[1..]),
+            _ => {}
+        }
+    }
+}
+
+/// The path in a `file://host/path` URL, as OSC 7 reports the working
+/// directory, with %XX escapes decoded.
+fn file_url_path(url: &str) -> Option<PathBuf> {
+    let rest = url.strip_prefix("file://")?;
+    let path = &rest[rest.find('/')?..];
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(b) = iter.next() {
+        if b != b'%' {
+            bytes.push(b);
+            continue;
+        }
+        let hex = [iter.next()?, iter.next()?];
+        let hex = std::str::from_utf8(&hex).ok()?;
+        bytes.push(u8::from_str_radix(hex, 16).ok()?);
+    }
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+/// OSC 133 ; A|B|C|D [; exit-code] (FinalTerm / iTerm2 shell integration).
+fn semantic_prompt(term: &mut Term, params: &[&[u8]]) {
+    let Some(kind) = params.first().and_then(|p| p.first()) else {
+        return;
+    };
+    let kind = match kind {
+        b'A' => PromptKind::PromptStart,
+        b'B' => PromptKind::CommandStart,
+        b'C' => PromptKind::OutputStart,
+        b'D' => {
+            let code = params
+                .get(1)
+                .and_then(|p| std::str::from_utf8(p).ok())
+                .and_then(|s| s.parse().ok());
+            PromptKind::CommandEnd(code)
+        }
+        _ => return,
+    };
+    clamp_cursor(term);
+    term.add_prompt_mark(kind);
+}
+
+/// XTWINOPS. Only the title stack applies to a fullscreen Android window:
+/// `22;0|2 t` pushes and `23;0|2 t` pops the window title.
+fn window_ops(term: &mut Term, params: &Params) {
+    let mut iter = params.iter().map(|p| p.first().copied().unwrap_or(0));
+    let op = iter.next().unwrap_or(0);
+    let which = iter.next().unwrap_or(0);
+    if which == 1 {
+        return;
+    }
+    match op {
+        22 => term.push_title(),
+        23 => term.pop_title(),
+        _ => {}
+    }
+}
+
+/// Flag the cursor's line as continuing on the next, so copied text joins.
+fn mark_wrapped(term: &mut Term) {
+    let idx = term.cursor.y * term.cols + term.line_cols(term.cursor.y) - 1;
+    if let Some(glyph) = term.grid.get_mut(idx) {
+        glyph.flags |= GlyphFlags::WRAPLINE.bits();
+    }
+}
+
+/// Add zero-width `c` to the cell before the cursor: the one to its left,
+/// or the last of the line above if that wrapped onto this one. A wide
+/// character takes it on its first cell.
+fn attach_mark(term: &mut Term, c: char) {
+    let (mut x, y) = if term.cursor.x > 0 {
+        (term.cursor.x - 1, term.cursor.y)
+    } else if term.cursor.y > 0 {
+        let y = term.cursor.y - 1;
+        (term.line_cols(y) - 1, y)
+    } else {
+        return;
+    };
+    let wrapped = term.grid[y * term.cols + x]
+        .flags()
+        .contains(GlyphFlags::WRAPLINE);
+    if y != term.cursor.y && !wrapped {
+        return;
+    }
+    if x > 0
+        && term.grid[y * term.cols + x]
+            .flags()
+            .contains(GlyphFlags::WIDE_SPACER)
+    {
+        x -= 1;
+    }
+    let idx = y * term.cols + x;
+    term.grid[idx].marks = term.marks.push(term.grid[idx].marks, c);
+    term.dirty[y] = true;
+}
+
+/// Overwriting either half of a wide character blanks the other half.
+fn clear_wide_pair(term: &mut Term, x: usize, y: usize) {
+    let idx = y * term.cols + x;
+    let flags = term.grid[idx].flags();
+    if flags.contains(GlyphFlags::WIDE) && x + 1 < term.cols {
+        term.grid[idx + 1] = Glyph::default();
+    } else if flags.contains(GlyphFlags::WIDE_SPACER) && x > 0 {
+        term.grid[idx - 1] = Glyph::default();
+    }
 }
 
 fn scroll_up(term: &mut Term) {
+    term.save_to_history(0);
     for y in 1..term.rows {
         let src_start = y * term.cols;
         let dst_start = (y - 1) * term.cols;
@@ -322,6 +691,8 @@ fn scroll_up(term: &mut Term) {
     for x in 0..term.cols {
         term.grid[bottom_start + x] = Glyph::default();
     }
+    term.line_size.rotate_left(1);
+    term.line_size[term.rows - 1] = LineSize::Single;
     term.dirty[term.rows - 1] = true;
 }
 
@@ -337,6 +708,8 @@ fn scroll_down(term: &mut Term) {
     for x in 0..term.cols {
         term.grid[x] = Glyph::default();
     }
+    term.line_size.rotate_right(1);
+    term.line_size[0] = LineSize::Single;
     term.dirty[0] = true;
 }
 
@@ -358,6 +731,72 @@ fn clear_region(term: &mut Term, x1: usize, y1: usize, x2: usize, y2: usize) {
     }
 }
 
+/// Rectangle `Pt;Pl;Pb;Pr` starting at parameter `first`. Coordinates are
+/// 1-based and 0 or missing means the screen edge. Returns inclusive,
+/// 0-based `(top, left, bottom, right)`, or None for an empty rectangle.
+fn rect_params(term: &Term, params: &Params, first: usize) -> Option<(usize, usize, usize, usize)> {
+    let p: Vec<usize> = params
+        .iter()
+        .skip(first)
+        .take(4)
+        .map(|p| p.first().copied().unwrap_or(0) as usize)
+        .collect();
+    let get = |i: usize, default: usize| p.get(i).copied().filter(|v| *v != 0).unwrap_or(default);
+
+    let top = get(0, 1) - 1;
+    let left = get(1, 1) - 1;
+    let bottom = get(2, term.rows).min(term.rows) - 1;
+    let right = get(3, term.cols).min(term.cols) - 1;
+    if top > bottom || left > right {
+        return None;
+    }
+    Some((top, left, bottom, right))
+}
+
+fn fill_rect(term: &mut Term, rect: (usize, usize, usize, usize), glyph: Glyph) {
+    let (top, left, bottom, right) = rect;
+    for y in top..=bottom {
+        let start = y * term.cols;
+        term.grid[start + left..=start + right].fill(glyph);
+        term.dirty[y] = true;
+    }
+}
+
+/// DECCRA: `Pts;Pls;Pbs;Prs;Pps;Ptd;Pld;Ppd $ v`. Pages are ignored since
+/// there is only one. The source is buffered so overlapping copies work.
+fn copy_rect(term: &mut Term, params: &Params) {
+    let Some((top, left, bottom, right)) = rect_params(term, params, 0) else {
+        return;
+    };
+    let dest = |i: usize| {
+        params
+            .iter()
+            .nth(i)
+            .and_then(|p| p.first().copied())
+            .filter(|v| *v != 0)
+            .unwrap_or(1) as usize
+            - 1
+    };
+    let (dst_top, dst_left) = (dest(5), dest(6));
+    if dst_top >= term.rows || dst_left >= term.cols {
+        return;
+    }
+
+    let height = (bottom - top + 1).min(term.rows - dst_top);
+    let width = (right - left + 1).min(term.cols - dst_left);
+    let mut block = Vec::with_capacity(width * height);
+    for y in top..top + height {
+        let start = y * term.cols + left;
+        block.extend_from_slice(&term.grid[start..start + width]);
+    }
+    for (i, row) in block.chunks(width).enumerate() {
+        let y = dst_top + i;
+        let start = y * term.cols + dst_left;
+        term.grid[start..start + width].copy_from_slice(row);
+        term.dirty[y] = true;
+    }
+}
+
 fn insert_blank(term: &mut Term, n: usize) {
     let y = term.cursor.y;
     let x = term.cursor.x;
@@ -411,6 +850,8 @@ fn insert_lines(term: &mut Term, n: usize) {
         }
         term.dirty[i] = true;
     }
+    term.line_size[y..].rotate_right(n);
+    term.line_size[y..y + n].fill(LineSize::Single);
 }
 
 fn delete_lines(term: &mut Term, n: usize) {
@@ -432,12 +873,22 @@ fn delete_lines(term: &mut Term, n: usize) {
         }
         term.dirty[i] = true;
     }
+    term.line_size[y..].rotate_left(n);
+    let rows = term.rows;
+    term.line_size[rows - n..].fill(LineSize::Single);
 }
 
 fn set_mode(term: &mut Term, params: &Params, set: bool) {
     for param in params.iter() {
         let val = param.first().copied().unwrap_or(0) as usize;
         match val {
+            1 => {
+                if set {
+                    term.mode.insert(TermMode::APPCURSOR);
+                } else {
+                    term.mode.remove(TermMode::APPCURSOR);
+                }
+            }
             7 => {
                 if set {
                     term.mode.insert(TermMode::WRAP);
@@ -445,6 +896,38 @@ fn set_mode(term: &mut Term, params: &Params, set: bool) {
                     term.mode.remove(TermMode::WRAP);
                 }
             }
+            1004 => {
+                if set {
+                    term.mode.insert(TermMode::FOCUS);
+                } else {
+                    term.mode.remove(TermMode::FOCUS);
+                }
+            }
+            1000 | 1002 | 1003 => {
+                // The tracking modes replace each other.
+                term.mode.remove(TermMode::MOUSE);
+                if set {
+                    term.mode.insert(match val {
+                        1000 => TermMode::MOUSE_BUTTON,
+                        1002 => TermMode::MOUSE_DRAG,
+                        _ => TermMode::MOUSE_MOTION,
+                    });
+                }
+            }
+            1006 => {
+                if set {
+                    term.mode.insert(TermMode::MOUSE_SGR);
+                } else {
+                    term.mode.remove(TermMode::MOUSE_SGR);
+                }
+            }
+            2004 => {
+                if set {
+                    term.mode.insert(TermMode::BRACKETED_PASTE);
+                } else {
+                    term.mode.remove(TermMode::BRACKETED_PASTE);
+                }
+            }
             1049 => {
                 if set {
                     term.mode.insert(TermMode::ALTSCREEN);
@@ -514,52 +997,32 @@ fn sgr(term: &mut Term, params: &Params) {
                 term.cursor.attr.attrs &= !(1 << 7);
             }
             30..=37 => {
-                term.cursor.attr.fg = (val - 30) as u8;
+                term.cursor.attr.fg = val - 30;
             }
             38 => {
-                if let Some(next_param) = iter.next() {
-                    let next_val = next_param.first().copied().unwrap_or(0) as u32;
-                    if next_val == 5 {
-                        if let Some(color_param) = iter.next() {
-                            term.cursor.attr.fg = color_param.first().copied().unwrap_or(0) as u8;
-                        }
-                    } else if next_val == 2 {
-                        let r = iter.next().and_then(|p| p.first().copied()).unwrap_or(0) as u8;
-                        let g = iter.next().and_then(|p| p.first().copied()).unwrap_or(0) as u8;
-                        let b = iter.next().and_then(|p| p.first().copied()).unwrap_or(0) as u8;
-                        term.cursor.attr.fg = rgb_to_ansi256(r, g, b);
-                    }
+                if let Some(color) = sgr_color(param, &mut iter) {
+                    term.cursor.attr.fg = color;
                 }
             }
             39 => {
-                term.cursor.attr.fg = 7;
+                term.cursor.attr.fg = DEFAULT_FG;
             }
             40..=47 => {
-                term.cursor.attr.bg = (val - 40) as u8;
+                term.cursor.attr.bg = val - 40;
             }
             48 => {
-                if let Some(next_param) = iter.next() {
-                    let next_val = next_param.first().copied().unwrap_or(0) as u32;
-                    if next_val == 5 {
-                        if let Some(color_param) = iter.next() {
-                            term.cursor.attr.bg = color_param.first().copied().unwrap_or(0) as u8;
-                        }
-                    } else if next_val == 2 {
-                        let r = iter.next().and_then(|p| p.first().copied()).unwrap_or(0) as u8;
-                        let g = iter.next().and_then(|p| p.first().copied()).unwrap_or(0) as u8;
-                        let b = iter.next().and_then(|p| p.first().copied()).unwrap_or(0) as u8;
-                        term.cursor.attr.bg = rgb_to_ansi256(r, g, b);
-                    }
+                if let Some(color) = sgr_color(param, &mut iter) {
+                    term.cursor.attr.bg = color;
                 }
             }
             49 => {
-                term.cursor.attr.bg = 0;
+                term.cursor.attr.bg = DEFAULT_BG;
             }
             90..=97 => {
-                term.cursor.attr.fg = (val - 90 + 8) as u8;
+                term.cursor.attr.fg = val - 90 + 8;
             }
             100..=107 => {
-                term.cursor.attr.bg = (val - 100 + 8) as u8;
+                term.cursor.attr.bg = val - 100 + 8;
             }
             _ => {}
         }
@@ -593,19 +1056,34 @@ fn mark_dirty(term: &mut Term) {
     term.dirty[row] = true;
 }
 
-fn rgb_to_ansi256(r: u8, g: u8, b: u8) -> u8 {
-    if r == g && g == b {
-        if r < 8 {
-            return 16;
-        }
-        if r > 248 {
-            return 231;
+/// Extended colour after SGR 38/48, either as colon subparameters within
+/// `param` (`38:5:n`, `38:2::r:g:b`) or as the following `;` parameters.
+fn sgr_color<'a>(param: &[u16], iter: &mut impl Iterator<Item = &'a [u16]>) -> Option<u32> {
+    let args: Vec<u16> = if param.len() > 1 {
+        param[1..].to_vec()
+    } else {
+        let kind = iter.next()?.first().copied()?;
+        let count = match kind {
+            5 => 1,
+            2 => 3,
+            _ => 0,
+        };
+        std::iter::once(kind)
+            .chain(
+                iter.by_ref()
+                    .take(count)
+                    .map(|p| p.first().copied().unwrap_or(0)),
+            )
+            .collect()
+    };
+
+    match args.as_slice() {
+        [5, idx, ..] => u8::try_from(*idx).ok().map(u32::from),
+        // The colon form may carry a colour-space id before r:g:b.
+        [2, _, r, g, b] | [2, r, g, b] => {
+            let channel = |v: &u16| u8::try_from(*v).ok();
+            Some(truecolor(channel(r)?, channel(g)?, channel(b)?))
         }
-        return 232 + ((r as u16 - 8) / 10) as u8;
+        _ => None,
     }
-
-    let r6 = ((r as u16 * 5 + 127) / 255) as u8;
-    let g6 = ((g as u16 * 5 + 127) / 255) as u8;
-    let b6 = ((b as u16 * 5 + 127) / 255) as u8;
-    16 + 36 * r6 + 6 * g6 + b6
 }
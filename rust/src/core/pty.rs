@@ -1,21 +1,36 @@
 use nix::fcntl::{fcntl, FcntlArg, OFlag};
 use nix::libc::{self, winsize, TIOCSCTTY, TIOCSWINSZ};
 use nix::pty::{openpty, OpenptyResult};
-use nix::sys::signal::{kill, Signal};
-use nix::unistd::{execv, fork, setsid, ForkResult, Pid};
+use nix::sys::eventfd::{EfdFlags, EventFd};
+use nix::sys::signal::{kill, killpg, Signal};
+use nix::sys::termios::{tcgetattr, tcsetattr, InputFlags, SetArg, SpecialCharacterIndices};
+use nix::unistd::{execv, fork, setsid, tcgetpgrp, ForkResult, Pid};
 use std::ffi::CString;
 use std::io;
-use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
 use std::os::unix::ffi::OsStrExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
 
 pub struct Pty {
     master: OwnedFd,
     child_pid: Pid,
+    /// Input the shell wasn't ready to take yet, oldest first.
+    queued: Mutex<Vec<u8>>,
+    /// Signalled whenever input is queued; see [`Pty::queue_event`].
+    queue_event: EventFd,
+    /// Rows and columns last set, packed by [`pack_size`].
+    size: AtomicU32,
+}
+
+fn pack_size(rows: u16, cols: u16) -> u32 {
+    u32::from(rows) << 16 | u32::from(cols)
 }
 
 impl Pty {
-    pub fn spawn(shell: &str, rows: u16, cols: u16, env: &PtyEnv) -> io::Result<Self> {
+    pub fn spawn(command: &ShellCommand, rows: u16, cols: u16, env: &PtyEnv) -> io::Result<Self> {
+        let shell = command.program.as_str();
         let OpenptyResult { master, slave } =
             openpty(None, None).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
@@ -28,6 +43,9 @@ impl Pty {
         unsafe {
             libc::ioctl(master.as_raw_fd(), TIOCSWINSZ, &ws);
         }
+        if let Err(e) = configure_line(&slave, env.flow_control) {
+            log::warn!("Failed to set PTY line settings: {:?}", e);
+        }
 
         match unsafe { fork() } {
             Ok(ForkResult::Parent { child }) => {
@@ -37,6 +55,9 @@ impl Pty {
                 let flags = OFlag::from_bits_truncate(flags);
                 fcntl(&master, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))
                     .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                let queue_event =
+                    EventFd::from_flags(EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK)
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
                 log::info!(
                     "PTY spawned: child={}, master_fd={}",
@@ -46,6 +67,9 @@ impl Pty {
                 Ok(Pty {
                     master,
                     child_pid: child,
+                    queued: Mutex::new(Vec::new()),
+                    queue_event,
+                    size: AtomicU32::new(pack_size(rows, cols)),
                 })
             }
             Ok(ForkResult::Child) => {
@@ -143,6 +167,17 @@ impl Pty {
                     } else {
                         std::env::remove_var("LD_PRELOAD");
                     }
+                    for (name, value) in &env.vars {
+                        // set_var panics on these rather than failing.
+                        let valid = !name.is_empty()
+                            && !name.contains(['=', '\0'])
+                            && !value.contains('\0');
+                        if valid {
+                            std::env::set_var(name, value);
+                        } else {
+                            log::warn!("Skipping invalid environment variable {:?}", name);
+                        }
+                    }
                 }
 
                 let shell_cstr = match CString::new(shell) {
@@ -152,6 +187,15 @@ impl Pty {
                         std::process::exit(127);
                     }
                 };
+                let Ok(args) = command
+                    .args
+                    .iter()
+                    .map(|arg| CString::new(arg.as_str()))
+                    .collect::<Result<Vec<_>, _>>()
+                else {
+                    log::error!("Shell arguments contain a NUL byte: {:?}", command.args);
+                    std::process::exit(127);
+                };
 
                 let exec_result = if should_use_system_linker_exec(shell) {
                     if env.ld_preload.is_none() {
@@ -174,11 +218,22 @@ impl Pty {
                         linker,
                         shell
                     );
-                    let args = [linker_cstr.as_c_str(), shell_cstr.as_c_str()];
-                    execv(linker_cstr.as_c_str(), &args)
+                    // The linker hands the program its own path as argv[0],
+                    // so a login shell has to be asked for with -l.
+                    let mut argv = vec![linker_cstr.as_c_str(), shell_cstr.as_c_str()];
+                    if command.login {
+                        argv.push(c"-l");
+                    }
+                    argv.extend(args.iter().map(CString::as_c_str));
+                    execv(linker_cstr.as_c_str(), &argv)
                 } else {
-                    let args = [shell_cstr.as_c_str()];
-                    execv(shell_cstr.as_c_str(), &args)
+                    let argv0 = match CString::new(command.argv0()) {
+                        Ok(s) => s,
+                        Err(_) => shell_cstr.clone(),
+                    };
+                    let mut argv = vec![argv0.as_c_str()];
+                    argv.extend(args.iter().map(CString::as_c_str));
+                    execv(shell_cstr.as_c_str(), &argv)
                 };
 
                 let e = exec_result.expect_err("execv unexpectedly returned success");
@@ -211,7 +266,54 @@ impl Pty {
         }
     }
 
+    /// Send `data` to the shell. Whatever the terminal can't take right
+    /// now is queued behind anything already waiting, so the whole of
+    /// `data` is always accepted; [`Pty::flush`] writes out the rest.
     pub fn write(&self, data: &[u8]) -> io::Result<usize> {
+        let mut queued = self.queued.lock().unwrap();
+        if queued.is_empty() {
+            let n = match self.write_master(data) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => 0,
+                Err(e) => return Err(e),
+            };
+            if n == data.len() {
+                return Ok(n);
+            }
+            queued.extend_from_slice(&data[n..]);
+        } else {
+            queued.extend_from_slice(data);
+        }
+        let _ = self.queue_event.write(1);
+        Ok(data.len())
+    }
+
+    /// Write out as much queued input as the terminal takes. True once the
+    /// queue is empty; a closed terminal drops what was left.
+    pub fn flush(&self) -> io::Result<bool> {
+        let _ = self.queue_event.read();
+        let mut queued = self.queued.lock().unwrap();
+        while !queued.is_empty() {
+            match self.write_master(&queued) {
+                Ok(n) => {
+                    queued.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => {
+                    queued.clear();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Readable after [`Pty::write`] queues input, until [`Pty::flush`].
+    pub fn queue_event(&self) -> BorrowedFd<'_> {
+        self.queue_event.as_fd()
+    }
+
+    fn write_master(&self, data: &[u8]) -> io::Result<usize> {
         let n = unsafe {
             libc::write(
                 self.master.as_raw_fd(),
@@ -227,7 +329,14 @@ impl Pty {
         }
     }
 
+    /// Tell the program the terminal is now `rows`x`cols`. Nothing is sent
+    /// if that is the size it has, so a full-screen program only redraws
+    /// for a real change.
     pub fn resize(&self, rows: u16, cols: u16) {
+        let size = pack_size(rows, cols);
+        if self.size.swap(size, Ordering::Relaxed) == size {
+            return;
+        }
         let ws = winsize {
             ws_row: rows,
             ws_col: cols,
@@ -240,6 +349,40 @@ impl Pty {
         let _ = kill(self.child_pid, Signal::SIGWINCH);
     }
 
+    /// The process group in the terminal's foreground: the running job, or
+    /// the shell itself at its prompt.
+    pub fn foreground_pgrp(&self) -> Option<Pid> {
+        tcgetpgrp(&self.master).ok()
+    }
+
+    /// Send `signal` to the foreground job, as the key for it would if the
+    /// job hadn't changed or ignored it.
+    pub fn signal_foreground(&self, signal: Signal) -> io::Result<()> {
+        // The shell leads its own process group, which is all there is
+        // to fall back on once the terminal has no foreground.
+        let pgrp = self.foreground_pgrp().unwrap_or(self.child_pid);
+        log::info!("Sending {} to process group {}", signal, pgrp);
+        killpg(pgrp, signal).map_err(io::Error::from)
+    }
+
+    /// Name of the foreground job's leader, as `ps` shows it.
+    pub fn foreground_command(&self) -> Option<String> {
+        let pgrp = self.foreground_pgrp()?;
+        let comm = std::fs::read_to_string(format!("/proc/{}/comm", pgrp)).ok()?;
+        Some(comm.trim_end().to_string())
+    }
+
+    /// Working directory of the shell itself.
+    pub fn cwd(&self) -> Option<PathBuf> {
+        std::fs::read_link(format!("/proc/{}/cwd", self.child_pid)).ok()
+    }
+
+    /// Working directory of the foreground job's leader.
+    pub fn foreground_cwd(&self) -> Option<PathBuf> {
+        let pgrp = self.foreground_pgrp()?;
+        std::fs::read_link(format!("/proc/{}/cwd", pgrp)).ok()
+    }
+
     pub fn master_fd(&self) -> RawFd {
         self.master.as_raw_fd()
     }
@@ -249,7 +392,48 @@ impl Pty {
     }
 }
 
-#[derive(Clone)]
+/// Line discipline settings the kernel defaults get wrong for us: erase
+/// whole UTF-8 characters, erase with DEL as our backspace key sends, and
+/// only let Ctrl+S pause output when asked to.
+fn configure_line(slave: &OwnedFd, flow_control: bool) -> nix::Result<()> {
+    let mut termios = tcgetattr(slave)?;
+    termios.input_flags.insert(InputFlags::IUTF8);
+    termios.input_flags.set(InputFlags::IXON, flow_control);
+    termios.control_chars[SpecialCharacterIndices::VERASE as usize] = 0x7f;
+    tcsetattr(slave, SetArg::TCSANOW, &termios)
+}
+
+impl AsFd for Pty {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.master.as_fd()
+    }
+}
+
+/// The program a PTY runs, with its arguments.
+#[derive(Clone, Debug)]
+pub struct ShellCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Run as a login shell, which reads /etc/profile and ~/.profile.
+    pub login: bool,
+}
+
+impl ShellCommand {
+    /// What the program sees as its name: the file name, with a leading
+    /// `-` for a login shell, as login(1) does.
+    fn argv0(&self) -> String {
+        if !self.login {
+            return self.program.clone();
+        }
+        let name = Path::new(&self.program)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(&self.program);
+        format!("-{}", name)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct PtyEnv {
     pub term: String,
     pub home: std::path::PathBuf,
@@ -259,6 +443,11 @@ pub struct PtyEnv {
     pub prefix: Option<std::path::PathBuf>,
     pub ld_library_path: Option<String>,
     pub ld_preload: Option<String>,
+    /// Let Ctrl+S stop output and Ctrl+Q resume it (IXON).
+    pub flow_control: bool,
+    /// Extra variables, set after all of the above so they can override
+    /// them.
+    pub vars: Vec<(String, String)>,
 }
 
 impl PtyEnv {
@@ -272,8 +461,84 @@ impl PtyEnv {
             prefix: None,
             ld_library_path: None,
             ld_preload: None,
+            flow_control: false,
+            vars: Vec::new(),
         }
     }
+
+    /// Start from [`PtyEnv::system_default`] and change what differs.
+    pub fn builder() -> PtyEnvBuilder {
+        PtyEnvBuilder(Self::system_default())
+    }
+}
+
+/// Builds a [`PtyEnv`]; see [`PtyEnv::builder`].
+pub struct PtyEnvBuilder(PtyEnv);
+
+impl PtyEnvBuilder {
+    pub fn term(mut self, term: impl Into<String>) -> Self {
+        self.0.term = term.into();
+        self
+    }
+
+    /// $HOME, which is also where the shell starts unless [`Self::cwd`]
+    /// says otherwise.
+    pub fn home(mut self, home: impl Into<PathBuf>) -> Self {
+        let home = home.into();
+        self.0.cwd = Some(home.clone());
+        self.0.home = home;
+        self
+    }
+
+    pub fn cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.0.cwd = Some(cwd.into());
+        self
+    }
+
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.0.path = path.into();
+        self
+    }
+
+    pub fn tmp(mut self, tmp: impl Into<PathBuf>) -> Self {
+        self.0.tmp = Some(tmp.into());
+        self
+    }
+
+    pub fn prefix(mut self, prefix: impl Into<PathBuf>) -> Self {
+        self.0.prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn ld_library_path(mut self, path: impl Into<String>) -> Self {
+        self.0.ld_library_path = Some(path.into());
+        self
+    }
+
+    pub fn ld_preload(mut self, library: impl Into<String>) -> Self {
+        self.0.ld_preload = Some(library.into());
+        self
+    }
+
+    pub fn flow_control(mut self, enabled: bool) -> Self {
+        self.0.flow_control = enabled;
+        self
+    }
+
+    /// Set `name` to `value`, after the built-in variables.
+    pub fn var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.0.vars.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn vars(mut self, vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.0.vars.extend(vars);
+        self
+    }
+
+    pub fn build(self) -> PtyEnv {
+        self.0
+    }
 }
 
 impl Drop for Pty {
@@ -282,8 +547,21 @@ impl Drop for Pty {
     }
 }
 
+/// Have jobs the shell leaves behind reparented to this process instead of
+/// init, so they can be reaped alongside the shell.
+pub fn adopt_orphans() -> io::Result<()> {
+    let res = unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Whether `target` is app data the kernel won't exec. The APK's own
+/// files under /data/app, staged programs among them, run directly.
 fn should_use_system_linker_exec(target: &str) -> bool {
-    target.starts_with("/data/") || target.starts_with("/mnt/expand/")
+    (target.starts_with("/data/") && !target.starts_with("/data/app/"))
+        || target.starts_with("/mnt/expand/")
 }
 
 fn select_system_linker() -> &'static str {
@@ -1,115 +1,1144 @@
-use skia_safe::{Canvas, Color, Data, Font, FontMgr, Paint, Point, Rect};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-use crate::core::glyph::{color_from_index, GlyphAttrs};
-use crate::core::types::Term;
+use skia_safe::font_style::{Slant, Weight};
+use skia_safe::{
+    BlendMode, Canvas, Color, Data, Font, FontMgr, FontStyle, IRect, Paint, PaintStyle, Point,
+    Rect, SamplingOptions, Shaper, Surface, TextBlob, Typeface,
+};
+
+use crate::core::bidi::visual_order;
+use crate::core::glyph::{color_from_index, Glyph, GlyphAttrs, GlyphFlags, DEFAULT_BG, DEFAULT_FG};
+use crate::core::theme::Theme;
+use crate::core::types::{CursorShape, LineSize, Term};
 
 const FONT_DATA: &[u8] = include_bytes!("../../assets/font.ttf");
+const MAX_FONT_FILE_BYTES: u64 = 32 * 1024 * 1024;
+const OVERLAY_BG: Color = Color::new(0xb000_0000);
+/// Translucent tint laid over selected cells.
+const SELECTION_TINT: Color = Color::new(0x6066_99ff);
+const SELECTION_HANDLE: Color = Color::new(0xff66_99ff);
+const MENU_DIVIDER: Color = Color::new(0x40ff_ffff);
+/// Overscroll glow at full pull: depth in rows, and opacity.
+const OVERSCROLL_DEPTH: f32 = 3.0;
+const OVERSCROLL_ALPHA: f32 = 96.0;
+/// Horizontal skew for synthesized italics (about 14 degrees).
+const FAUX_ITALIC_SKEW: f32 = -0.25;
 
 pub struct Renderer {
     pub font: Font,
+    /// Bold, italic and bold-italic faces, indexed by [`style_index`] - 1.
+    /// Synthesized from `font` when the family has no such face.
+    styled: [Font; 3],
     pub painter: Paint,
     pub cell_w: f32,
     pub cell_h: f32,
     pub descent: f32,
-    palette: [u32; 16],
+    theme: Theme,
+    /// Alpha of the default background, 0.0-1.0.
+    pub background_opacity: f32,
+    /// Extra pixels added to every cell, split evenly around the glyph.
+    cell_padding: (f32, f32),
+    /// Gap between the grid and the surface edges.
+    pub margin: (f32, f32),
+    /// Pixels at the left and top edges that the system draws over, which
+    /// the grid starts beyond.
+    insets: (f32, f32),
+    /// Fixed cursor colour (0xRRGGBB); None reverses the cell under it.
+    pub cursor_color: Option<u32>,
+    /// Reorder right-to-left text for display. Off for applications that
+    /// already send it in visual order.
+    pub bidi: bool,
+    /// Offscreen copy of the grid; only dirty rows are repainted into it.
+    cache: Option<Surface>,
+    /// Areas changed since the last [`Renderer::take_damage`].
+    damage: Vec<IRect>,
+    /// Set when the whole surface changed.
+    full_damage: bool,
+    /// What was drawn over the grid last frame (cursor, handles, buttons),
+    /// which must be repaired when it moves or goes away.
+    last_overlays: Vec<Rect>,
+    last_scroll_px: f32,
+    /// HarfBuzz shaper for ligatures; None draws each cell's glyph alone.
+    shaper: Option<Shaper>,
 }
 
 impl Renderer {
-    pub fn new(font_size: f32, palette: [u32; 16]) -> Self {
+    pub fn new(font_size: f32, theme: Theme, font_path: Option<&Path>, ligatures: bool) -> Self {
         let font_mgr = FontMgr::new();
+        let shaper = ligatures.then(|| Shaper::new(font_mgr.clone()));
 
-        let font_data = Data::new_copy(FONT_DATA);
-        let typeface = font_mgr.new_from_data(&font_data, None).unwrap_or_else(|| {
-            log::warn!("Failed to load embedded font, using system fallback");
-            font_mgr
-                .match_family_style("monospace", skia_safe::FontStyle::default())
-                .or_else(|| font_mgr.match_family_style("", skia_safe::FontStyle::default()))
-                .expect("No fonts available")
-        });
+        let typeface = font_path
+            .and_then(|path| load_font_file(&font_mgr, path))
+            .or_else(|| {
+                let font_data = Data::new_copy(FONT_DATA);
+                font_mgr.new_from_data(&font_data, None)
+            })
+            .unwrap_or_else(|| {
+                log::warn!("Failed to load embedded font, using system fallback");
+                font_mgr
+                    .match_family_style("monospace", skia_safe::FontStyle::default())
+                    .or_else(|| font_mgr.match_family_style("", skia_safe::FontStyle::default()))
+                    .expect("No fonts available")
+            });
 
+        let styled = [
+            FontStyle::bold(),
+            FontStyle::italic(),
+            FontStyle::bold_italic(),
+        ]
+        .map(|style| styled_font(&font_mgr, &typeface, style, font_size));
         let font = Font::from_typeface(typeface, font_size);
-        let (_, metrics) = font.metrics();
-        let cell_w = font.measure_str("M", None).1.width().max(16.0);
-        let cell_h = (metrics.descent - metrics.ascent + metrics.leading).max(20.0);
-        let descent = metrics.descent;
+        let (cell_w, cell_h, descent) = cell_metrics(&font);
 
         log::info!("Font loaded: cell={}x{}", cell_w, cell_h);
 
         Self {
             font,
+            styled,
             painter: Paint::default(),
             cell_w,
             cell_h,
             descent,
-            palette,
+            theme,
+            background_opacity: 1.0,
+            cell_padding: (0.0, 0.0),
+            margin: (0.0, 0.0),
+            insets: (0.0, 0.0),
+            cursor_color: None,
+            bidi: true,
+            cache: None,
+            damage: Vec::new(),
+            full_damage: true,
+            last_overlays: Vec::new(),
+            last_scroll_px: 0.0,
+            shaper,
+        }
+    }
+
+    fn default_bg(&self) -> Color {
+        let alpha = (self.background_opacity.clamp(0.0, 1.0) * 255.0).round() as u8;
+        color_from_index(&self.theme, DEFAULT_BG).with_a(alpha)
+    }
+
+    /// Switch colours, repainting everything on the next frame.
+    pub fn set_theme(&mut self, theme: Theme) {
+        if self.theme != theme {
+            self.theme = theme;
+            self.cache = None;
+        }
+    }
+
+    pub fn font_size(&self) -> f32 {
+        self.font.size()
+    }
+
+    /// Change the font size and recompute the cell metrics. The caller is
+    /// responsible for re-gridding the terminal to the new cell size.
+    pub fn set_font_size(&mut self, font_size: f32) {
+        self.font.set_size(font_size);
+        for font in self.styled.iter_mut() {
+            font.set_size(font_size);
+        }
+        self.update_metrics();
+        log::info!(
+            "Font size {}: cell={}x{}",
+            font_size,
+            self.cell_w,
+            self.cell_h
+        );
+    }
+
+    /// Set the per-cell padding and the outer margins, both in pixels.
+    /// Like [`Renderer::set_font_size`], this changes the grid geometry.
+    pub fn set_padding(&mut self, cell_padding: (f32, f32), margin: (f32, f32)) {
+        self.cell_padding = cell_padding;
+        self.margin = margin;
+        self.update_metrics();
+    }
+
+    /// Move the grid clear of `insets` pixels of system UI at the left and
+    /// top edges.
+    pub fn set_insets(&mut self, insets: (f32, f32)) {
+        if insets != self.insets {
+            self.insets = insets;
+            self.cache = None;
+        }
+    }
+
+    /// Top-left corner of the grid in surface coordinates.
+    fn origin(&self) -> (f32, f32) {
+        (self.margin.0 + self.insets.0, self.margin.1 + self.insets.1)
+    }
+
+    fn update_metrics(&mut self) {
+        let (cell_w, cell_h, descent) = cell_metrics(&self.font);
+        self.cell_w = cell_w + self.cell_padding.0;
+        self.cell_h = cell_h + self.cell_padding.1;
+        self.descent = descent + self.cell_padding.1 / 2.0;
+        self.cache = None;
+    }
+
+    /// The regular font or one of the bold/italic variants.
+    fn font_for(&self, style: usize) -> &Font {
+        match style {
+            0 => &self.font,
+            n => &self.styled[n - 1],
         }
     }
 
     #[inline]
-    fn draw_char(&self, canvas: &Canvas, c: char, x: f32, y: f32, paint: &Paint) {
+    fn draw_char(&self, canvas: &Canvas, c: char, style: usize, x: f32, y: f32, paint: &Paint) {
         let mut buf = [0u8; 4];
-        let s = c.encode_utf8(&mut buf);
-        canvas.draw_str(s, Point::new(x, y), &self.font, paint);
+        self.draw_cell(canvas, c.encode_utf8(&mut buf), style, x, y, paint);
     }
 
-    pub fn draw_cells(&mut self, term: &Term, canvas: &Canvas) {
+    /// Draw one cell's text, a character and any zero-width ones after it.
+    #[inline]
+    fn draw_cell(&self, canvas: &Canvas, s: &str, style: usize, x: f32, y: f32, paint: &Paint) {
+        let x = x + self.cell_padding.0 / 2.0;
+        canvas.draw_str(s, Point::new(x, y), self.font_for(style), paint);
+    }
+
+    /// Draw a shaped run with its first glyph at cell origin `x` and its
+    /// baseline at `y`, falling back to per-char drawing if shaping fails.
+    /// `text` is in logical order, so right-to-left scripts like Arabic
+    /// still join and combining marks follow their base characters.
+    fn draw_run(&self, canvas: &Canvas, text: &str, style: usize, rtl: bool, x: f32, y: f32) {
+        let font = self.font_for(style);
+        let shaped = self
+            .shaper
+            .as_ref()
+            .and_then(|shaper| shaper.shape_text_blob(text, font, !rtl, f32::MAX, (0.0, 0.0)));
+        match shaped {
+            // The shaper lays out from the line top; shift to our baseline.
+            Some((blob, _)) => {
+                let (_, metrics) = font.metrics();
+                let x = x + self.cell_padding.0 / 2.0;
+                canvas.draw_text_blob(&blob, (x, y + metrics.ascent), &self.painter);
+            }
+            None => {
+                let chars: Vec<char> = if rtl {
+                    text.chars().rev().collect()
+                } else {
+                    text.chars().collect()
+                };
+                for (i, c) in chars.into_iter().enumerate() {
+                    let x = x + i as f32 * self.cell_w;
+                    self.draw_char(canvas, c, style, x, y, &self.painter);
+                }
+            }
+        }
+    }
+
+    /// Push the DECDWL/DECDHL transform for the row whose top edge is
+    /// `base_y`, clipped to that row. Returns false (nothing pushed) for
+    /// single-size rows.
+    fn push_line_transform(
+        &self,
+        canvas: &Canvas,
+        size: LineSize,
+        base_y: f32,
+        width: f32,
+    ) -> bool {
+        let (scale_y, shift) = match size {
+            LineSize::Single => return false,
+            LineSize::DoubleWidth => (1.0, 0.0),
+            LineSize::DoubleHeightTop => (2.0, 0.0),
+            // Draw the line twice as tall, shifted up so its lower half shows.
+            LineSize::DoubleHeightBottom => (2.0, self.cell_h),
+        };
+        canvas.save();
+        canvas.clip_rect(Rect::from_xywh(0.0, base_y, width, self.cell_h), None, None);
+        canvas.translate((0.0, base_y - shift));
+        canvas.scale((2.0, scale_y));
+        canvas.translate((0.0, -base_y));
+        true
+    }
+
+    /// Repaint dirty rows (background first) and clear their dirty flags.
+    /// Returns how many rows were repainted.
+    pub fn draw_cells(&mut self, term: &mut Term, canvas: &Canvas) -> usize {
+        let mut repainted = 0;
         for y in 0..term.rows {
-            let base_y = y as f32 * self.cell_h;
-            let text_y = (y + 1) as f32 * self.cell_h - self.descent;
+            if !term.dirty[y] {
+                continue;
+            }
+            term.dirty[y] = false;
+            repainted += 1;
+            self.draw_line(term, canvas, term.view_line(y), y as f32 * self.cell_h);
+            self.add_damage(self.grid_row_rect(term, y));
+        }
+        repainted
+    }
+
+    /// Row `y` of the grid in surface coordinates.
+    fn grid_row_rect(&self, term: &Term, y: usize) -> Rect {
+        let (x, top) = self.origin();
+        Rect::from_xywh(
+            x,
+            top + y as f32 * self.cell_h,
+            term.cols as f32 * self.cell_w,
+            self.cell_h,
+        )
+    }
+
+    fn add_damage(&mut self, rect: Rect) {
+        let rect = rect.round_out();
+        // Consecutive rows usually coalesce into one band.
+        match self.damage.last_mut() {
+            Some(last)
+                if last.left == rect.left
+                    && last.right == rect.right
+                    && last.bottom == rect.top =>
+            {
+                last.bottom = rect.bottom;
+            }
+            _ => self.damage.push(rect),
+        }
+    }
+
+    /// Damage `rect` now and again next frame, after which whatever was
+    /// drawn there may be gone.
+    fn add_overlay(&mut self, rect: Rect) {
+        self.add_damage(rect);
+        self.last_overlays.push(rect);
+    }
+
+    /// Areas drawn since the last call, or None if the whole surface changed.
+    pub fn take_damage(&mut self) -> Option<Vec<IRect>> {
+        let damage = std::mem::take(&mut self.damage);
+        if std::mem::take(&mut self.full_damage) {
+            None
+        } else {
+            Some(damage)
+        }
+    }
+
+    /// Paint absolute line `line` as a row whose top edge is at `base_y`.
+    fn draw_line(&mut self, term: &Term, canvas: &Canvas, line: usize, base_y: f32) {
+        let Some(row) = term.line_at(line) else {
+            return;
+        };
+        let row = &row[..row.len().min(term.cols)];
+        let width = term.cols as f32 * self.cell_w;
+        // (first visual column, cells, colour, style, right-to-left, text)
+        let mut runs: Vec<(usize, usize, u32, usize, bool, String)> = Vec::new();
+        // Unshaped text is batched into one blob per colour and style.
+        let mut batches: Vec<(u32, usize, String, Vec<f32>)> = Vec::new();
+
+        // Decoration lines as (offset from baseline, thickness).
+        let (_, metrics) = self.font.metrics();
+        let thickness = metrics.underline_thickness().unwrap_or(1.0).max(1.0);
+        let underline = (
+            metrics
+                .underline_position()
+                .unwrap_or(metrics.descent / 2.0),
+            thickness,
+        );
+        let strike = (
+            metrics
+                .strikeout_position()
+                .unwrap_or(-metrics.x_height / 2.0),
+            metrics.strikeout_thickness().unwrap_or(thickness).max(1.0),
+        );
+
+        // Clear rather than paint so a translucent background replaces
+        // the old row instead of blending over it.
+        canvas.save();
+        canvas.clip_rect(
+            Rect::from_xywh(0.0, base_y, canvas.image_info().width() as f32, self.cell_h),
+            None,
+            None,
+        );
+        canvas.clear(self.default_bg());
+        canvas.restore();
+
+        let text_y = base_y + self.cell_h - self.descent;
+        let size = term.line_size_at(line);
+        let transformed = self.push_line_transform(canvas, size, base_y, width);
+        let visible = if transformed {
+            row.len().div_ceil(2)
+        } else {
+            row.len()
+        };
+
+        let order = if self.bidi {
+            visual_order(&row[..visible])
+        } else {
+            None
+        };
+
+        // Backgrounds go first, one rect per run of same-coloured cells.
+        // The row is already cleared to the default background.
+        let mut bg_run: Option<(usize, u32)> = None;
+        for vx in 0..=visible {
+            let bg = (vx < visible).then(|| {
+                let x = order.as_ref().map_or(vx, |order| order[vx].0);
+                cell_colors(&row[x]).1
+            });
+            if matches!(bg_run, Some((_, run_bg)) if Some(run_bg) == bg) {
+                continue;
+            }
+            if let Some((start, run_bg)) = bg_run.take() {
+                self.painter
+                    .set_color(color_from_index(&self.theme, run_bg));
+                let rect = Rect::from_xywh(
+                    start as f32 * self.cell_w,
+                    base_y,
+                    (vx - start) as f32 * self.cell_w,
+                    self.cell_h,
+                );
+                canvas.draw_rect(rect, &self.painter);
+            }
+            bg_run = bg.filter(|&bg| bg != DEFAULT_BG).map(|bg| (vx, bg));
+        }
+
+        for vx in 0..visible {
+            let (x, rtl) = order.as_ref().map_or((vx, false), |order| order[vx]);
+            let g = &row[x];
+            let base_x = vx as f32 * self.cell_w;
+            let attrs = GlyphAttrs::from_bits_truncate(g.attrs);
+            let (fg_idx, bg_idx) = cell_colors(g);
 
-            for x in 0..term.cols {
-                let g = term.get(x, y);
-                let base_x = x as f32 * self.cell_w;
-                let attrs = GlyphAttrs::from_bits_truncate(g.attrs);
-                let (mut fg_idx, mut bg_idx) = (g.fg, g.bg);
+            let fg = color_from_index(&self.theme, fg_idx);
+            for (flag, (offset, thick)) in [
+                (GlyphAttrs::UNDERLINE, underline),
+                (GlyphAttrs::STRUCK, strike),
+            ] {
+                if attrs.contains(flag) && fg_idx != bg_idx {
+                    self.painter.set_color(fg);
+                    let rect = Rect::from_xywh(base_x, text_y + offset, self.cell_w, thick);
+                    canvas.draw_rect(rect, &self.painter);
+                }
+            }
 
-                if attrs.contains(GlyphAttrs::REVERSE) {
-                    (fg_idx, bg_idx) = (bg_idx, fg_idx);
+            let c = g.char();
+            let marks = term.marks.get(g.marks);
+            let style = style_index(attrs);
+            if self.shaper.is_some() {
+                // Spaces and colour or style changes end a run so each run
+                // can be placed at its own cell and never drifts off the grid.
+                let continues = matches!(runs.last(), Some((start, cells, fg, st, r, _))
+                    if *fg == fg_idx && *st == style && *r == rtl && start + cells == vx);
+                if (c == ' ' && marks.is_empty()) || g.flags().contains(GlyphFlags::WIDE_SPACER) {
+                    continue;
                 }
-                if attrs.contains(GlyphAttrs::BOLD) && fg_idx < 8 {
-                    fg_idx += 8;
+                let mut cell = String::new();
+                term.push_cell_text(&mut cell, g);
+                if continues {
+                    let (_, cells, .., text) = runs.last_mut().unwrap();
+                    *cells += 1;
+                    // Right-to-left cells come in visual order; keep the
+                    // text logical.
+                    if rtl {
+                        text.insert_str(0, &cell);
+                    } else {
+                        text.push_str(&cell);
+                    }
+                } else {
+                    runs.push((vx, 1, fg_idx, style, rtl, cell));
                 }
-                if attrs.contains(GlyphAttrs::INVISIBLE) {
-                    fg_idx = bg_idx;
+            } else if c != ' ' || !marks.is_empty() {
+                // Marks go at their base character's position and overlay it.
+                let x = base_x + self.cell_padding.0 / 2.0;
+                let batch = match batches
+                    .iter()
+                    .position(|(fg, st, ..)| *fg == fg_idx && *st == style)
+                {
+                    Some(i) => &mut batches[i],
+                    None => {
+                        batches.push((fg_idx, style, String::new(), Vec::new()));
+                        batches.last_mut().unwrap()
+                    }
+                };
+                for c in std::iter::once(c).chain(marks.chars()) {
+                    batch.2.push(c);
+                    batch.3.push(x);
                 }
+            }
+        }
 
-                self.painter
-                    .set_color(color_from_index(&self.palette, bg_idx));
-                let rect = Rect::from_xywh(base_x, base_y, self.cell_w, self.cell_h);
+        for (fg_idx, style, text, xs) in batches {
+            self.painter
+                .set_color(color_from_index(&self.theme, fg_idx));
+            let font = self.font_for(style);
+            if let Some(blob) = TextBlob::from_pos_text_h(text.as_str(), &xs, text_y, font) {
+                canvas.draw_text_blob(&blob, (0.0, 0.0), &self.painter);
+            }
+        }
+
+        for (start, _, fg_idx, style, rtl, text) in runs {
+            self.painter
+                .set_color(color_from_index(&self.theme, fg_idx));
+            let x = start as f32 * self.cell_w;
+            self.draw_run(canvas, &text, style, rtl, x, text_y);
+        }
+
+        if let Some((from, to)) = term.selected_cols(line) {
+            self.painter.set_color(SELECTION_TINT);
+            match order {
+                // Reordered cells may be selected in several pieces.
+                Some(order) => {
+                    for (vx, &(x, _)) in order.iter().enumerate() {
+                        if (from..to).contains(&x) {
+                            let rect = Rect::from_xywh(
+                                vx as f32 * self.cell_w,
+                                base_y,
+                                self.cell_w,
+                                self.cell_h,
+                            );
+                            canvas.draw_rect(rect, &self.painter);
+                        }
+                    }
+                }
+                None => {
+                    let rect = Rect::from_xywh(
+                        from as f32 * self.cell_w,
+                        base_y,
+                        (to - from) as f32 * self.cell_w,
+                        self.cell_h,
+                    );
+                    canvas.draw_rect(rect, &self.painter);
+                }
+            }
+        }
+
+        if transformed {
+            canvas.restore();
+        }
+    }
+
+    /// Visual column of the cursor once its row is reordered for bidi.
+    fn cursor_col(&self, term: &Term) -> usize {
+        let (x, y) = (term.cursor.x, term.cursor.y);
+        if !self.bidi {
+            return x;
+        }
+        let Some(row) = term.line_at(term.abs_line(y)) else {
+            return x;
+        };
+        let visible = term.line_cols(y).min(row.len());
+        visual_order(&row[..visible])
+            .and_then(|order| order.iter().position(|&(col, _)| col == x))
+            .unwrap_or(x)
+    }
+
+    /// Draw the cursor in its DECSCUSR style, or as an outline when the
+    /// window doesn't have focus.
+    pub fn draw_cursor(&mut self, term: &Term, canvas: &Canvas, focused: bool) {
+        let x = self.cursor_col(term) as f32 * self.cell_w;
+        let y = term.cursor.y as f32 * self.cell_h;
+        let width = term.cols as f32 * self.cell_w;
+        let size = term.line_size[term.cursor.y];
+        let transformed = self.push_line_transform(canvas, size, y, width);
+
+        let g = term.get(term.cursor.x, term.cursor.y);
+        let cells = if g.flags().contains(GlyphFlags::WIDE) {
+            2.0
+        } else {
+            1.0
+        };
+
+        let text_y = (term.cursor.y + 1) as f32 * self.cell_h - self.descent;
+        let (_, metrics) = self.font.metrics();
+        let thickness = metrics
+            .underline_thickness()
+            .unwrap_or(1.0)
+            .max(self.cell_w / 8.0);
+
+        // Reverse video by default, so the cursor takes the cell's own text
+        // colour and the character under it is drawn in the cell's background.
+        let (fg_idx, bg_idx) = cell_colors(g);
+        let cursor = match self.cursor_color {
+            Some(rgb) => Color::new(0xff00_0000 | rgb),
+            // Hidden text would leave nothing to reverse.
+            None if fg_idx == bg_idx => Color::WHITE,
+            None => color_from_index(&self.theme, fg_idx),
+        };
+
+        self.painter.set_color(cursor);
+        match term.cursor_style.shape {
+            _ if !focused => {
+                let rect = Rect::from_xywh(x, y, self.cell_w * cells, self.cell_h)
+                    .with_inset((thickness / 2.0, thickness / 2.0));
+                self.painter.set_style(PaintStyle::Stroke);
+                self.painter.set_stroke_width(thickness);
+                canvas.draw_rect(rect, &self.painter);
+                self.painter.set_style(PaintStyle::Fill);
+            }
+            CursorShape::Block => {
+                let rect = Rect::from_xywh(x, y, self.cell_w * cells, self.cell_h);
                 canvas.draw_rect(rect, &self.painter);
 
-                let c = g.char();
-                if c != ' ' {
+                let mut text = String::new();
+                term.push_cell_text(&mut text, g);
+                if text != " " {
+                    let style = style_index(GlyphAttrs::from_bits_truncate(g.attrs));
                     self.painter
-                        .set_color(color_from_index(&self.palette, fg_idx));
-                    self.draw_char(canvas, c, base_x, text_y, &self.painter);
+                        .set_color(color_from_index(&self.theme, bg_idx));
+                    self.draw_cell(canvas, &text, style, x, text_y, &self.painter);
                 }
             }
+            CursorShape::Underline => {
+                // Sit on the font's underline, but never below the cell.
+                let top = (text_y + metrics.underline_position().unwrap_or(0.0))
+                    .min(y + self.cell_h - thickness);
+                let rect = Rect::from_xywh(x, top, self.cell_w * cells, thickness);
+                canvas.draw_rect(rect, &self.painter);
+            }
+            CursorShape::Bar => {
+                let rect = Rect::from_xywh(x, y, thickness, self.cell_h);
+                canvas.draw_rect(rect, &self.painter);
+            }
+        }
+
+        if transformed {
+            canvas.restore();
         }
     }
 
-    pub fn draw_cursor(&mut self, term: &Term, canvas: &Canvas) {
-        let x = term.cursor.x as f32 * self.cell_w;
-        let y = term.cursor.y as f32 * self.cell_h;
+    /// Forget the cached grid so the next frame is drawn from scratch,
+    /// e.g. when the GPU context it lives in goes away.
+    pub fn drop_cache(&mut self) {
+        self.cache = None;
+    }
 
-        self.painter.set_color(Color::WHITE);
-        let rect = Rect::from_xywh(x, y, self.cell_w, self.cell_h);
+    /// Draw the terminal. `scroll_px` (0 up to the cell height) shifts the
+    /// grid down to reveal part of the line above the viewport, for
+    /// smooth scrolling.
+    pub fn render(
+        &mut self,
+        canvas: &Canvas,
+        term: &mut Term,
+        cursor_visible: bool,
+        focused: bool,
+        scroll_px: f32,
+    ) -> usize {
+        let info = canvas.image_info();
+        let cache_size = self.cache.as_ref().map(|s| (s.width(), s.height()));
+        if cache_size != Some((info.width(), info.height())) {
+            self.cache = canvas.new_surface(&info, None);
+            self.full_damage = true;
+            term.mark_dirty();
+            if let Some(cache) = self.cache.as_mut() {
+                cache.canvas().clear(self.default_bg());
+            }
+        }
+
+        // The cursor is drawn over the blitted cache so it never leaves trails.
+        let repainted = match self.cache.take() {
+            Some(mut cache) => {
+                let cache_canvas = cache.canvas();
+                cache_canvas.save();
+                cache_canvas.translate(self.origin());
+                let repainted = self.draw_cells(term, cache_canvas);
+                cache_canvas.restore();
+                if scroll_px > 0.0 {
+                    // Copy rather than blend so a translucent background
+                    // isn't applied twice over the cleared strip.
+                    canvas.clear(self.default_bg());
+                    let mut paint = Paint::default();
+                    paint.set_blend_mode(BlendMode::Src);
+                    let sampling = SamplingOptions::default();
+                    cache.draw(canvas, (0.0, scroll_px), sampling, Some(&paint));
+                } else {
+                    if self.background_opacity < 1.0 {
+                        canvas.clear(Color::TRANSPARENT);
+                    }
+                    cache.draw(canvas, (0.0, 0.0), SamplingOptions::default(), None);
+                }
+                self.cache = Some(cache);
+                repainted
+            }
+            None => {
+                canvas.clear(self.default_bg());
+                self.full_damage = true;
+                term.mark_dirty();
+                canvas.save();
+                let (x, y) = self.origin();
+                canvas.translate((x, y + scroll_px));
+                let repainted = self.draw_cells(term, canvas);
+                canvas.restore();
+                repainted
+            }
+        };
+
+        let above = term.view_line(0).checked_sub(1);
+        if let Some(line) = above.filter(|_| scroll_px > 0.0) {
+            canvas.save();
+            let (x, y) = self.origin();
+            canvas.clip_rect(
+                Rect::from_xywh(x, y, term.cols as f32 * self.cell_w, scroll_px),
+                None,
+                None,
+            );
+            canvas.translate((x, y));
+            self.draw_line(term, canvas, line, scroll_px - self.cell_h);
+            canvas.restore();
+        }
+
+        // Everything moves while scrolling smoothly, and once more as it stops.
+        if scroll_px > 0.0 || self.last_scroll_px > 0.0 {
+            self.full_damage = true;
+        }
+        self.last_scroll_px = scroll_px;
+
+        for rect in std::mem::take(&mut self.last_overlays) {
+            self.add_damage(rect);
+        }
+        if cursor_visible && term.display_offset == 0 {
+            canvas.save();
+            let (x, y) = self.origin();
+            canvas.translate((x, y + scroll_px));
+            self.draw_cursor(term, canvas, focused);
+            canvas.restore();
+            // The whole row, since DECDWL and bidi move the cell around.
+            self.add_overlay(self.grid_row_rect(term, term.cursor.y));
+        }
+        repainted
+    }
+
+    /// Viewport cell under surface position `pos`, clamped to the grid.
+    pub fn cell_at(&self, term: &Term, pos: Point) -> (usize, usize) {
+        let (left, top) = self.origin();
+        let x = ((pos.x - left) / self.cell_w).max(0.0) as usize;
+        let y = ((pos.y - top) / self.cell_h).max(0.0) as usize;
+        (x.min(term.cols - 1), y.min(term.rows - 1))
+    }
+
+    /// Where the selection handles point: the bottom-left corner of the
+    /// first selected cell and the bottom-right corner of the last, in
+    /// surface coordinates. Ends scrolled out of view are None.
+    pub fn selection_handles(&self, term: &Term) -> [Option<Point>; 2] {
+        let Some((start, end)) = term.selection.map(|s| s.ordered()) else {
+            return [None, None];
+        };
+        let top = term.view_line(0);
+        let (left, y0) = self.origin();
+        let point = |(line, col): (usize, usize), right: bool| {
+            let y = line.checked_sub(top).filter(|&y| y < term.rows)?;
+            Some(Point::new(
+                left + (col + right as usize) as f32 * self.cell_w,
+                y0 + (y + 1) as f32 * self.cell_h,
+            ))
+        };
+        [point(start, false), point(end, true)]
+    }
+
+    /// Draw teardrop handles of radius `radius` hanging off the selection
+    /// ends, leaning outwards.
+    pub fn draw_selection_handles(&mut self, canvas: &Canvas, term: &Term, radius: f32) {
+        let [start, end] = self.selection_handles(term);
+        self.painter.set_anti_alias(true);
+        self.painter.set_color(SELECTION_HANDLE);
+        for (tip, dir) in [(start, -1.0), (end, 1.0)] {
+            let Some(tip) = tip else {
+                continue;
+            };
+            let center = Point::new(tip.x + dir * radius, tip.y + radius);
+            canvas.draw_circle(center, radius, &self.painter);
+            // Square off the quarter facing the tip to make the point.
+            let corner = Rect::new(tip.x.min(center.x), tip.y, tip.x.max(center.x), center.y);
+            canvas.draw_rect(corner, &self.painter);
+            self.add_overlay(Rect::from_xywh(
+                center.x - radius,
+                tip.y,
+                2.0 * radius,
+                2.0 * radius,
+            ));
+        }
+        self.painter.set_anti_alias(false);
+    }
+
+    /// Draw the soft keyboard toggle in `rect`, highlighted while the
+    /// keyboard is shown.
+    pub fn draw_keyboard_button(&mut self, canvas: &Canvas, rect: Rect, active: bool) {
+        self.add_overlay(rect);
+        let radius = rect.width() / 5.0;
+        self.painter.set_anti_alias(true);
+        self.painter.set_color(OVERLAY_BG);
+        canvas.draw_round_rect(rect, radius, radius, &self.painter);
+
+        // A keyboard outline with two rows of keys and a space bar.
+        let icon = rect.with_inset((rect.width() / 5.0, rect.height() / 3.0));
+        let stroke = (icon.height() / 10.0).max(1.0);
+        let fg = if active {
+            color_from_index(&self.theme, 4)
+        } else {
+            Color::WHITE
+        };
+        self.painter.set_color(fg);
+        self.painter.set_style(PaintStyle::Stroke);
+        self.painter.set_stroke_width(stroke);
+        canvas.draw_round_rect(icon, stroke, stroke, &self.painter);
+        self.painter.set_style(PaintStyle::Fill);
+        let key = icon.width() / 9.0;
+        for row in 0..2 {
+            let y = icon.top + key * (0.8 + 1.5 * row as f32);
+            for col in 0..4 {
+                let x = icon.left + key * (1.0 + 2.0 * col as f32);
+                canvas.draw_rect(Rect::from_xywh(x, y, key, key), &self.painter);
+            }
+        }
+        let bar_y = icon.bottom - key * 1.6;
+        let bar = Rect::from_xywh(icon.left + key * 2.5, bar_y, key * 4.0, key * 0.8);
+        canvas.draw_rect(bar, &self.painter);
+        self.painter.set_anti_alias(false);
+    }
+
+    /// Glow along the top (`amount` > 0) or bottom edge when the view is
+    /// pulled past the end of history, growing with `amount` up to 1.0.
+    pub fn draw_overscroll(&mut self, canvas: &Canvas, amount: f32) {
+        if amount == 0.0 {
+            return;
+        }
+        let info = canvas.image_info();
+        let (width, height) = (info.width() as f32, info.height() as f32);
+        let amount = amount.clamp(-1.0, 1.0);
+        // An arc bulging in from the edge, wider than the screen so only
+        // its flatter middle shows.
+        let depth = amount.abs() * OVERSCROLL_DEPTH * self.cell_h;
+        let center_y = if amount > 0.0 { 0.0 } else { height };
+        let oval = Rect::from_ltrb(
+            -width / 4.0,
+            center_y - depth,
+            width * 1.25,
+            center_y + depth,
+        );
+        let visible = Rect::from_ltrb(0.0, center_y - depth, width, center_y + depth);
+        self.add_overlay(visible);
+
+        let alpha = (amount.abs() * OVERSCROLL_ALPHA) as u8;
+        self.painter
+            .set_color(color_from_index(&self.theme, DEFAULT_FG).with_a(alpha));
+        self.painter.set_anti_alias(true);
+        canvas.draw_oval(oval, &self.painter);
+        self.painter.set_anti_alias(false);
+    }
+
+    /// Mouse pointer: an I-beam one cell tall centred on `pos`, outlined in
+    /// the background colour so it shows over text of either colour.
+    pub fn draw_pointer(&mut self, canvas: &Canvas, pos: Point) {
+        let half_h = self.cell_h / 2.0;
+        let half_w = (self.cell_w / 3.0).max(2.0);
+        let stroke = (self.cell_h / 16.0).max(1.0);
+        let bars = [
+            Rect::from_ltrb(
+                pos.x - stroke / 2.0,
+                pos.y - half_h,
+                pos.x + stroke / 2.0,
+                pos.y + half_h,
+            ),
+            Rect::from_ltrb(
+                pos.x - half_w,
+                pos.y - half_h,
+                pos.x + half_w,
+                pos.y - half_h + stroke,
+            ),
+            Rect::from_ltrb(
+                pos.x - half_w,
+                pos.y + half_h - stroke,
+                pos.x + half_w,
+                pos.y + half_h,
+            ),
+        ];
+        let outline = Rect::from_ltrb(
+            pos.x - half_w,
+            pos.y - half_h,
+            pos.x + half_w,
+            pos.y + half_h,
+        )
+        .with_outset((stroke, stroke));
+        self.add_overlay(outline);
+
+        self.painter
+            .set_color(color_from_index(&self.theme, DEFAULT_BG));
+        for bar in bars {
+            canvas.draw_rect(bar.with_outset((stroke, stroke)), &self.painter);
+        }
+        self.painter
+            .set_color(color_from_index(&self.theme, DEFAULT_FG));
+        for bar in bars {
+            canvas.draw_rect(bar, &self.painter);
+        }
+    }
+
+    /// Size of a context menu entry labelled `label`.
+    pub fn menu_item_size(&self, label: &str) -> (f32, f32) {
+        let (line_h, _) = self.font.metrics();
+        let text_w = self.font.measure_str(label, None).0;
+        (text_w + 2.0 * line_h, 2.0 * line_h)
+    }
+
+    /// Draw a context menu filling `rect`, with each label centred in its
+    /// own rect and thin dividers between them.
+    pub fn draw_menu<'a>(
+        &mut self,
+        canvas: &Canvas,
+        rect: Rect,
+        items: impl IntoIterator<Item = (&'a str, Rect)>,
+    ) {
+        self.add_overlay(rect);
+        let (line_h, _) = self.font.metrics();
+        let radius = line_h / 2.0;
+        self.painter.set_anti_alias(true);
+        self.painter.set_color(OVERLAY_BG);
+        canvas.draw_round_rect(rect, radius, radius, &self.painter);
+        self.painter.set_anti_alias(false);
+
+        for (i, (label, item)) in items.into_iter().enumerate() {
+            if i > 0 {
+                self.painter.set_color(MENU_DIVIDER);
+                let divider =
+                    Rect::from_xywh(item.left, item.top + radius, 1.0, item.height() - line_h);
+                canvas.draw_rect(divider, &self.painter);
+            }
+            self.draw_label(canvas, label, item, Color::WHITE);
+        }
+    }
+
+    /// Draw the extra keys row filling `rect`. Latched modifiers are
+    /// highlighted.
+    pub fn draw_extra_keys<'a>(
+        &mut self,
+        canvas: &Canvas,
+        rect: Rect,
+        keys: impl IntoIterator<Item = (&'a str, Rect, bool)>,
+    ) {
+        self.add_overlay(rect);
+        self.painter.set_color(OVERLAY_BG);
         canvas.draw_rect(rect, &self.painter);
+        let accent = color_from_index(&self.theme, 4);
+        for (label, key, active) in keys {
+            if active {
+                let pill = key.with_inset((key.width() / 10.0, key.height() / 8.0));
+                let radius = pill.height() / 4.0;
+                self.painter.set_anti_alias(true);
+                self.painter.set_color(accent);
+                canvas.draw_round_rect(pill, radius, radius, &self.painter);
+                self.painter.set_anti_alias(false);
+            }
+            self.draw_label(canvas, label, key, Color::WHITE);
+        }
+    }
 
-        let g = term.get(term.cursor.x, term.cursor.y);
-        let c = g.char();
-        if c != ' ' {
-            self.painter.set_color(Color::BLACK);
-            let text_y = (term.cursor.y + 1) as f32 * self.cell_h - self.descent;
-            self.draw_char(canvas, c, x, text_y, &self.painter);
+    /// Draw `label` centred in `rect`.
+    fn draw_label(&mut self, canvas: &Canvas, label: &str, rect: Rect, color: Color) {
+        let (line_h, metrics) = self.font.metrics();
+        let text_w = self.font.measure_str(label, None).0;
+        let x = rect.center_x() - text_w / 2.0;
+        let y = rect.center_y() - line_h / 2.0 - metrics.ascent;
+        self.painter.set_color(color);
+        canvas.draw_str(label, Point::new(x, y), &self.font, &self.painter);
+    }
+
+    /// Draw the debug overlay in the top-right corner.
+    pub fn draw_overlay(&mut self, canvas: &Canvas, stats: &DebugStats) {
+        let lines = [
+            format!(
+                "{:.2} ms  {:.0} fps",
+                stats.frame_time.as_secs_f64() * 1000.0,
+                stats.fps
+            ),
+            format!("parse {:.1} KiB/s", stats.parse_rate / 1024.0),
+            format!("dirty {} rows", stats.dirty_rows),
+            format!("backlog {} B", stats.pty_backlog),
+        ];
+        let font = self
+            .font
+            .with_size(self.font.size() * 0.6)
+            .unwrap_or_else(|| self.font.clone());
+        let width = canvas.image_info().width() as f32;
+        self.draw_text_box(canvas, &lines, &font, |w| Point::new(width - w, 0.0));
+    }
+
+    /// Draw a report of several lines centred across the top part of the
+    /// screen, such as the bootstrap's disk usage.
+    pub fn draw_report(&mut self, canvas: &Canvas, lines: &[String]) {
+        let font = self.font.clone();
+        let info = canvas.image_info();
+        let (width, height) = (info.width() as f32, info.height() as f32);
+        self.draw_text_box(canvas, lines, &font, |w| {
+            Point::new((width - w) / 2.0, height / 8.0)
+        });
+    }
+
+    /// Draw `lines` in a box sized to fit them, with its top-left corner
+    /// where `place` puts it given the box's width.
+    fn draw_text_box(
+        &mut self,
+        canvas: &Canvas,
+        lines: &[String],
+        font: &Font,
+        place: impl FnOnce(f32) -> Point,
+    ) {
+        let (line_h, metrics) = font.metrics();
+        let pad = line_h / 2.0;
+        let text_w = lines
+            .iter()
+            .map(|l| font.measure_str(l, None).0)
+            .fold(0.0, f32::max);
+        let (w, h) = (text_w + 2.0 * pad, line_h * lines.len() as f32 + 2.0 * pad);
+        let rect = Rect::from_point_and_size(place(w), (w, h));
+
+        self.add_overlay(rect);
+        self.painter.set_color(OVERLAY_BG);
+        canvas.draw_rect(rect, &self.painter);
+        self.painter.set_color(Color::WHITE);
+        for (i, line) in lines.iter().enumerate() {
+            let y = rect.top + pad + i as f32 * line_h - metrics.ascent;
+            canvas.draw_str(line, Point::new(rect.left + pad, y), font, &self.painter);
+        }
+    }
+
+    /// Draw `status` over a progress bar in the middle of an otherwise
+    /// empty screen, for while the bootstrap installs. The bar is left
+    /// empty when `fraction` is None. The terminal is repainted in full
+    /// on the next frame.
+    pub fn draw_progress(&mut self, canvas: &Canvas, status: &str, fraction: Option<f32>) {
+        canvas.clear(self.default_bg());
+        self.full_damage = true;
+        self.cache = None;
+        let info = canvas.image_info();
+        let (width, height) = (info.width() as f32, info.height() as f32);
+        let (line_h, _) = self.font.metrics();
+        let fg = color_from_index(&self.theme, DEFAULT_FG);
+        let text = Rect::from_xywh(0.0, height / 2.0 - 2.0 * line_h, width, line_h);
+        self.draw_label(canvas, status, text, fg);
+
+        let bar = Rect::from_xywh(width / 6.0, height / 2.0, width * 2.0 / 3.0, line_h / 3.0);
+        self.painter.set_color(fg.with_a(0x40));
+        canvas.draw_rect(bar, &self.painter);
+        if let Some(fraction) = fraction {
+            let mut filled = bar;
+            filled.right = bar.left + bar.width() * fraction.clamp(0.0, 1.0);
+            self.painter.set_color(color_from_index(&self.theme, 4));
+            canvas.draw_rect(filled, &self.painter);
+        }
+    }
+}
+
+/// Numbers shown by the debug overlay (`[debug] overlay` or Ctrl+Shift+D).
+#[derive(Default)]
+pub struct DebugStats {
+    /// Time spent rendering the last frame.
+    pub frame_time: Duration,
+    pub fps: f32,
+    /// PTY bytes parsed per second over the last sampling window.
+    pub parse_rate: f64,
+    pub dirty_rows: usize,
+    /// PTY bytes read but not yet parsed.
+    pub pty_backlog: usize,
+    window_start: Option<Instant>,
+    frames: u32,
+    parsed: usize,
+}
+
+impl DebugStats {
+    pub fn record_frame(&mut self, frame_time: Duration, dirty_rows: usize) {
+        self.frame_time = frame_time;
+        self.dirty_rows = dirty_rows;
+        self.frames += 1;
+
+        let now = Instant::now();
+        let start = *self.window_start.get_or_insert(now);
+        let elapsed = now.duration_since(start).as_secs_f64();
+        if elapsed >= 1.0 {
+            self.fps = (self.frames as f64 / elapsed) as f32;
+            self.parse_rate = self.parsed as f64 / elapsed;
+            self.frames = 0;
+            self.parsed = 0;
+            self.window_start = Some(now);
         }
     }
 
-    pub fn render(&mut self, canvas: &Canvas, term: &Term, cursor_visible: bool) {
-        canvas.clear(color_from_index(&self.palette, 0));
-        self.draw_cells(term, canvas);
-        if cursor_visible {
-            self.draw_cursor(term, canvas);
+    pub fn record_parse(&mut self, bytes: usize) {
+        self.parsed += bytes;
+    }
+}
+
+/// Cell width, height and descent for a monospace font.
+fn cell_metrics(font: &Font) -> (f32, f32, f32) {
+    let (_, metrics) = font.metrics();
+    let cell_w = font.measure_str("M", None).1.width().max(16.0);
+    let cell_h = (metrics.descent - metrics.ascent + metrics.leading).max(20.0);
+    (cell_w, cell_h, metrics.descent)
+}
+
+/// Effective (fg, bg) colour indices of a cell after REVERSE and INVISIBLE.
+fn cell_colors(g: &Glyph) -> (u32, u32) {
+    let attrs = GlyphAttrs::from_bits_truncate(g.attrs);
+    let (mut fg, mut bg) = (g.fg, g.bg);
+    if attrs.contains(GlyphAttrs::REVERSE) {
+        (fg, bg) = (bg, fg);
+    }
+    if attrs.contains(GlyphAttrs::INVISIBLE) {
+        fg = bg;
+    }
+    (fg, bg)
+}
+
+/// 0 for regular text, then 1 bold, 2 italic, 3 bold italic.
+fn style_index(attrs: GlyphAttrs) -> usize {
+    attrs.contains(GlyphAttrs::BOLD) as usize | (attrs.contains(GlyphAttrs::ITALIC) as usize) << 1
+}
+
+/// Find the `style` face of `regular`'s family, synthesizing whatever weight
+/// or slant it lacks so bold and italic text still stand out.
+fn styled_font(font_mgr: &FontMgr, regular: &Typeface, style: FontStyle, size: f32) -> Font {
+    let family = regular.family_name();
+    // Unknown families match some other default face; only accept our own.
+    let typeface = font_mgr
+        .match_family_style(&family, style)
+        .filter(|face| face.family_name() == family)
+        .unwrap_or_else(|| regular.clone());
+    let actual = typeface.font_style();
+
+    let mut font = Font::from_typeface(typeface, size);
+    if *style.weight() >= *Weight::SEMI_BOLD && *actual.weight() < *Weight::SEMI_BOLD {
+        font.set_embolden(true);
+    }
+    if style.slant() != Slant::Upright && actual.slant() == Slant::Upright {
+        font.set_skew_x(FAUX_ITALIC_SKEW);
+    }
+    font
+}
+
+/// Load a user-supplied font, falling back (None) on anything suspicious.
+fn load_font_file(font_mgr: &FontMgr, path: &Path) -> Option<Typeface> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    if !matches!(ext.as_deref(), Some("ttf" | "otf" | "ttc")) {
+        log::warn!("Font {:?} is not a .ttf/.otf/.ttc file, ignoring", path);
+        return None;
+    }
+
+    let metadata = match fs::metadata(path) {
+        Ok(m) => m,
+        Err(e) => {
+            log::warn!("Font {:?} unavailable: {:?}", path, e);
+            return None;
         }
+    };
+    if !metadata.is_file() || metadata.len() > MAX_FONT_FILE_BYTES {
+        log::warn!("Font {:?} is not a regular file or is too large", path);
+        return None;
+    }
+
+    let bytes = fs::read(path)
+        .map_err(|e| log::warn!("Failed to read font {:?}: {:?}", path, e))
+        .ok()?;
+    let Some(typeface) = font_mgr.new_from_data(&Data::new_copy(&bytes), None) else {
+        log::warn!("Font {:?} could not be parsed, using built-in font", path);
+        return None;
+    };
+    if !typeface.is_fixed_pitch() {
+        log::warn!("Font {:?} is not monospaced; glyphs may not align", path);
     }
+    log::info!("Loaded user font {:?}", path);
+    Some(typeface)
 }
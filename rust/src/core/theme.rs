@@ -0,0 +1,61 @@
+use crate::core::glyph::DEFAULT_COLORS;
+
+/// Light counterpart of [`DEFAULT_COLORS`].
+pub const LIGHT_COLORS: [u32; 16] = [
+    0x383a42, // 0: black
+    0xe45649, // 1: red
+    0x50a14f, // 2: green
+    0xc18401, // 3: yellow
+    0x4078f2, // 4: blue
+    0xa626a4, // 5: magenta
+    0x0184bc, // 6: cyan
+    0xa0a1a7, // 7: white
+    0x696c77, // 8: bright black
+    0xca1243, // 9: bright red
+    0x50a14f, // 10: bright green
+    0x986801, // 11: bright yellow
+    0x4078f2, // 12: bright blue
+    0xa626a4, // 13: bright magenta
+    0x0184bc, // 14: bright cyan
+    0xfafafa, // 15: bright white
+];
+
+/// Colours cells are resolved against: the 16-colour palette plus the
+/// default foreground and background used until SGR picks another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Theme {
+    pub palette: [u32; 16],
+    pub foreground: u32,
+    pub background: u32,
+}
+
+impl Theme {
+    /// The palette's white on its black, as before there were themes.
+    pub const DARK: Theme = Theme::on_black(DEFAULT_COLORS);
+
+    /// `palette` with white (7) on black (0) as the defaults.
+    pub const fn on_black(palette: [u32; 16]) -> Theme {
+        Theme {
+            palette,
+            foreground: palette[7],
+            background: palette[0],
+        }
+    }
+
+    pub const LIGHT: Theme = Theme {
+        palette: LIGHT_COLORS,
+        foreground: 0x383a42,
+        background: 0xfafafa,
+    };
+}
+
+/// Which theme to show; `System` follows Android's dark mode setting.
+/// Unless one is set it is `Dark`, the look from before there were
+/// themes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ThemeMode {
+    System,
+    #[default]
+    Dark,
+    Light,
+}
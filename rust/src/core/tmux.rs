@@ -0,0 +1,199 @@
+//! Client side of tmux control mode (`tmux -CC`).
+//!
+//! tmux opens control mode with `DCS 1000 p` and then speaks a line-based
+//! protocol until the closing `ST`. The parser hands those lines to
+//! [`TmuxControl`], which keeps one [`Term`] per tmux pane so a UI can
+//! render panes natively instead of dumping the protocol into the grid.
+
+use std::collections::BTreeMap;
+
+use crate::core::parser::Parser;
+use crate::core::types::Term;
+
+/// Notifications from tmux that a pane-embedding UI cares about.
+#[derive(Clone, Debug)]
+pub enum TmuxNotification {
+    WindowAdd(String),
+    WindowClose(String),
+    WindowRenamed {
+        window: String,
+        name: String,
+    },
+    LayoutChange {
+        window: String,
+        layout: String,
+    },
+    SessionChanged {
+        session: String,
+        name: String,
+    },
+    /// Reply to a command: the `%begin`/`%end` (or `%error`) block body.
+    Reply {
+        ok: bool,
+        lines: Vec<String>,
+    },
+    Exit(Option<String>),
+}
+
+pub struct TmuxPane {
+    pub term: Term,
+    parser: Parser,
+}
+
+pub struct TmuxControl {
+    line: Vec<u8>,
+    block: Option<Vec<String>>,
+    cols: usize,
+    rows: usize,
+    pub panes: BTreeMap<u32, TmuxPane>,
+    notifications: Vec<TmuxNotification>,
+}
+
+impl TmuxControl {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        Self {
+            line: Vec::new(),
+            block: None,
+            cols,
+            rows,
+            panes: BTreeMap::new(),
+            notifications: Vec::new(),
+        }
+    }
+
+    pub fn feed(&mut self, byte: u8) {
+        match byte {
+            b'\n' => {
+                let line = std::mem::take(&mut self.line);
+                self.handle_line(&line);
+            }
+            b'\r' => {}
+            _ => self.line.push(byte),
+        }
+    }
+
+    pub fn pane(&self, id: u32) -> Option<&Term> {
+        self.panes.get(&id).map(|p| &p.term)
+    }
+
+    pub fn take_notifications(&mut self) -> Vec<TmuxNotification> {
+        std::mem::take(&mut self.notifications)
+    }
+
+    /// Resize locally tracked panes, keeping what they show until tmux
+    /// redraws them; pair with [`refresh_client_command`].
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        self.cols = cols;
+        self.rows = rows;
+        for pane in self.panes.values_mut() {
+            pane.term.resize(cols, rows);
+        }
+    }
+
+    fn handle_line(&mut self, line: &[u8]) {
+        if let Some(block) = self.block.as_mut() {
+            if line.starts_with(b"%end") || line.starts_with(b"%error") {
+                let ok = line.starts_with(b"%end");
+                let lines = self.block.take().unwrap_or_default();
+                self.notifications
+                    .push(TmuxNotification::Reply { ok, lines });
+            } else {
+                block.push(String::from_utf8_lossy(line).into_owned());
+            }
+            return;
+        }
+
+        let text = String::from_utf8_lossy(line);
+        let mut words = text.splitn(3, ' ');
+        let Some(kind) = words.next() else {
+            return;
+        };
+        let arg = words.next().unwrap_or("").to_string();
+        let rest = words.next().unwrap_or("").to_string();
+
+        match kind {
+            "%begin" => self.block = Some(Vec::new()),
+            "%output" => {
+                // `%output %<pane> <escaped data>`: keep the data as raw bytes.
+                let Some(id) = arg.strip_prefix('%').and_then(|id| id.parse().ok()) else {
+                    return;
+                };
+                let prefix_len = kind.len() + 1 + arg.len() + 1;
+                let data = unescape_output(line.get(prefix_len..).unwrap_or(&[]));
+                self.pane_output(id, &data);
+            }
+            "%window-add" => self.notifications.push(TmuxNotification::WindowAdd(arg)),
+            "%window-close" | "%unlinked-window-close" => {
+                self.notifications.push(TmuxNotification::WindowClose(arg))
+            }
+            "%window-renamed" => self.notifications.push(TmuxNotification::WindowRenamed {
+                window: arg,
+                name: rest,
+            }),
+            "%layout-change" => {
+                let layout = rest.split(' ').next().unwrap_or("").to_string();
+                self.notifications.push(TmuxNotification::LayoutChange {
+                    window: arg,
+                    layout,
+                });
+            }
+            "%session-changed" => self.notifications.push(TmuxNotification::SessionChanged {
+                session: arg,
+                name: rest,
+            }),
+            "%exit" => {
+                let reason = if arg.is_empty() { None } else { Some(arg) };
+                self.notifications.push(TmuxNotification::Exit(reason));
+            }
+            _ => {}
+        }
+    }
+
+    fn pane_output(&mut self, id: u32, data: &[u8]) {
+        let (cols, rows) = (self.cols, self.rows);
+        let pane = self.panes.entry(id).or_insert_with(|| TmuxPane {
+            term: Term::new(cols, rows),
+            parser: Parser::new(),
+        });
+        for &byte in data {
+            pane.parser.process(&mut pane.term, byte);
+        }
+    }
+}
+
+/// Command that types `data` into `pane` (`send-keys -H` takes hex bytes).
+pub fn send_keys_command(pane: u32, data: &[u8]) -> Vec<u8> {
+    let mut cmd = format!("send-keys -t %{} -H", pane);
+    for b in data {
+        cmd.push_str(&format!(" {:02x}", b));
+    }
+    cmd.push('\n');
+    cmd.into_bytes()
+}
+
+/// Command telling tmux the client size so it re-lays out its panes.
+pub fn refresh_client_command(cols: usize, rows: usize) -> Vec<u8> {
+    format!("refresh-client -C {},{}\n", cols, rows).into_bytes()
+}
+
+/// `%output` data escapes bytes below 0x20 and `\` as three-digit octal.
+fn unescape_output(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] == b'\\' {
+            let digits = data.get(i + 1..i + 4).unwrap_or(&[]);
+            if digits.len() == 3 && digits.iter().all(|d| (b'0'..=b'7').contains(d)) {
+                let v = digits
+                    .iter()
+                    .fold(0u32, |acc, d| acc * 8 + (d - b'0') as u32);
+                out.push(v as u8);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(data[i]);
+        i += 1;
+    }
+    out
+}
@@ -1,6 +1,19 @@
-use crate::core::glyph::Glyph;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+use crate::core::glyph::{Glyph, GlyphFlags, Marks, DEFAULT_BG, DEFAULT_FG};
 use bitflags::bitflags;
 
+pub const SCROLLBACK_LINES: usize = 10_000;
+/// xterm limits the XTWINOPS title stack to 10 entries.
+pub const TITLE_STACK_DEPTH: usize = 10;
+
+/// Characters besides whitespace that end a word for word selection,
+/// unless the config says otherwise.
+pub const WORD_SEPARATORS: &str = ",│`|:\"'()[]{}<>";
+/// Schemes that mark the start of a URL for [`Term::url_at`].
+const URL_SCHEMES: [&str; 5] = ["https://", "http://", "ftp://", "file://", "mailto:"];
+
 bitflags! {
     #[derive(Clone, Copy)]
     pub struct TermMode: u32 {
@@ -11,6 +24,15 @@ bitflags! {
         const ECHO      = 1 << 4;
         const PRINT     = 1 << 5;
         const UTF8      = 1 << 6;
+        const FOCUS     = 1 << 7;
+        const BRACKETED_PASTE = 1 << 8;
+        const APPCURSOR = 1 << 9;
+        const MOUSE_BUTTON = 1 << 10;
+        const MOUSE_DRAG = 1 << 11;
+        const MOUSE_MOTION = 1 << 12;
+        const MOUSE_SGR = 1 << 13;
+        const APPKEYPAD = 1 << 14;
+        const MOUSE = Self::MOUSE_BUTTON.bits() | Self::MOUSE_DRAG.bits() | Self::MOUSE_MOTION.bits();
     }
 }
 
@@ -45,6 +67,91 @@ pub enum Charset {
     Fin,
 }
 
+impl Charset {
+    /// Charset selected by the final byte of an `ESC ( ) * +` designation.
+    pub fn from_designator(c: u8) -> Option<Self> {
+        match c {
+            b'0' => Some(Charset::Graphic0),
+            b'2' => Some(Charset::Graphic1),
+            b'A' => Some(Charset::UK),
+            b'B' => Some(Charset::USA),
+            b'<' => Some(Charset::Multi),
+            b'K' => Some(Charset::Ger),
+            b'5' | b'C' => Some(Charset::Fin),
+            _ => None,
+        }
+    }
+
+    /// Translate a GL character through this charset.
+    pub fn map(self, c: char) -> char {
+        match self {
+            Charset::Graphic0 => match c {
+                '_' => '\u{a0}',
+                '`' => '◆',
+                'a' => '▒',
+                'b' => '␉',
+                'c' => '␌',
+                'd' => '␍',
+                'e' => '␊',
+                'f' => '°',
+                'g' => '±',
+                'h' => '␤',
+                'i' => '␋',
+                'j' => '┘',
+                'k' => '┐',
+                'l' => '┌',
+                'm' => '└',
+                'n' => '┼',
+                'o' => '⎺',
+                'p' => '⎻',
+                'q' => '─',
+                'r' => '⎼',
+                's' => '⎽',
+                't' => '├',
+                'u' => '┤',
+                'v' => '┴',
+                'w' => '┬',
+                'x' => '│',
+                'y' => '≤',
+                'z' => '≥',
+                '{' => 'π',
+                '|' => '≠',
+                '}' => '£',
+                '~' => '·',
+                _ => c,
+            },
+            Charset::UK => match c {
+                '#' => '£',
+                _ => c,
+            },
+            Charset::Ger => match c {
+                '@' => '§',
+                '[' => 'Ä',
+                '\\' => 'Ö',
+                ']' => 'Ü',
+                '{' => 'ä',
+                '|' => 'ö',
+                '}' => 'ü',
+                '~' => 'ß',
+                _ => c,
+            },
+            Charset::Fin => match c {
+                '[' => 'Ä',
+                '\\' => 'Ö',
+                ']' => 'Å',
+                '^' => 'Ü',
+                '`' => 'é',
+                '{' => 'ä',
+                '|' => 'ö',
+                '}' => 'å',
+                '~' => 'ü',
+                _ => c,
+            },
+            Charset::Graphic1 | Charset::USA | Charset::Multi => c,
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Cursor {
     pub attr: Glyph,
@@ -64,17 +171,176 @@ impl Default for Cursor {
     }
 }
 
+/// A selection between two (absolute line, column) points, both inclusive.
+/// `anchor` is where it started; `end` follows the pointer and may come
+/// before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: (usize, usize),
+    pub end: (usize, usize),
+}
+
+impl Selection {
+    /// The two points in reading order.
+    pub fn ordered(&self) -> ((usize, usize), (usize, usize)) {
+        if self.anchor <= self.end {
+            (self.anchor, self.end)
+        } else {
+            (self.end, self.anchor)
+        }
+    }
+}
+
+/// Cursor shape selected by DECSCUSR (`CSI Ps SP q`) or the config.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CursorShape {
+    #[default]
+    Block,
+    Underline,
+    Bar,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blinking: bool,
+}
+
+impl Default for CursorStyle {
+    fn default() -> Self {
+        Self {
+            shape: CursorShape::Block,
+            blinking: true,
+        }
+    }
+}
+
+impl CursorStyle {
+    /// DECSCUSR 1-6: odd values blink, even values are steady. 0 (reset to
+    /// the user default) is left to the caller.
+    pub fn from_decscusr(ps: usize) -> Option<Self> {
+        let shape = match ps {
+            1 | 2 => CursorShape::Block,
+            3 | 4 => CursorShape::Underline,
+            5 | 6 => CursorShape::Bar,
+            _ => return None,
+        };
+        Some(Self {
+            shape,
+            blinking: ps % 2 == 1,
+        })
+    }
+}
+
+/// DEC line size attribute set by `ESC # 3/4/5/6`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineSize {
+    #[default]
+    Single,
+    DoubleWidth,
+    DoubleHeightTop,
+    DoubleHeightBottom,
+}
+
+/// OSC 133 (FinalTerm) semantic prompt markers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PromptKind {
+    /// `A`: start of the prompt.
+    PromptStart,
+    /// `B`: end of the prompt, start of the typed command.
+    CommandStart,
+    /// `C`: command submitted, start of its output.
+    OutputStart,
+    /// `D[;exit]`: command finished.
+    CommandEnd(Option<i32>),
+}
+
+/// A prompt marker anchored to an absolute line (scrollback + grid).
+#[derive(Clone, Copy, Debug)]
+pub struct PromptMark {
+    pub kind: PromptKind,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// A cell with nothing in it, as erasing leaves it.
+fn is_blank(glyph: &Glyph) -> bool {
+    glyph.rune == ' ' as u32
+        && glyph.bg == DEFAULT_BG
+        && glyph.attrs == 0
+        && glyph.flags == 0
+        && glyph.marks == 0
+}
+
+/// Break `text` into rows of `cols`, flagging each row but the last as
+/// wrapped. A double-width character that would be split moves to the next
+/// row whole. Returns the rows and the offset into `text` each starts at.
+fn rewrap(text: &[Glyph], cols: usize) -> (Vec<Vec<Glyph>>, Vec<usize>) {
+    let mut rows = Vec::new();
+    let mut starts = vec![0];
+    let mut row = Vec::with_capacity(cols);
+    for (i, glyph) in text.iter().enumerate() {
+        let wide = glyph.flags().contains(GlyphFlags::WIDE) && cols > 1;
+        if row.len() == cols || (wide && row.len() == cols - 1) {
+            row.resize(cols, Glyph::default());
+            row[cols - 1].flags |= GlyphFlags::WRAPLINE.bits();
+            rows.push(std::mem::replace(&mut row, Vec::with_capacity(cols)));
+            starts.push(i);
+        }
+        row.push(*glyph);
+    }
+    row.resize(cols, Glyph::default());
+    rows.push(row);
+    (rows, starts)
+}
+
 pub struct Term {
     pub rows: usize,
     pub cols: usize,
     pub grid: Vec<Glyph>,
     pub alt_grid: Vec<Vec<Glyph>>,
     pub dirty: Vec<bool>,
+    pub line_size: Vec<LineSize>,
     pub cursor: Cursor,
+    pub cursor_style: CursorStyle,
+    /// Style restored by DECSCUSR 0 and RIS, taken from the config.
+    pub default_cursor_style: CursorStyle,
     pub mode: TermMode,
     pub esc: EscapeState,
-    pub charset: Charset,
+    /// G0-G3 designations.
+    pub trantbl: [Charset; 4],
+    /// Index into `trantbl` currently invoked into GL (SI/SO/LS2/LS3).
+    pub charset: usize,
+    /// Pending SS2/SS3: G2 or G3 for the next printed character only.
+    pub single_shift: Option<usize>,
     pub lastc: char,
+
+    /// Lines scrolled off the top of the primary screen, oldest first.
+    pub scrollback: VecDeque<Vec<Glyph>>,
+    /// Absolute line number of `scrollback[0]`.
+    pub history_base: usize,
+    /// How many lines the view is scrolled back into history (0 = live).
+    pub display_offset: usize,
+    pub prompt_marks: Vec<PromptMark>,
+    pub selection: Option<Selection>,
+
+    pub title: String,
+    pub title_stack: Vec<String>,
+    /// Working directory the shell last reported with OSC 7.
+    pub cwd: Option<PathBuf>,
+
+    /// Treat East Asian ambiguous-width characters as double width.
+    pub ambiguous_wide: bool,
+    /// Reply to ENQ (0x05); empty sends nothing.
+    pub answerback: String,
+    /// Characters besides whitespace that end a word for word selection.
+    pub word_separators: String,
+    /// Bytes the terminal wants to send back to the PTY (reports, answerback).
+    pub responses: Vec<u8>,
+    /// BEL was received since this was last cleared.
+    pub bell: bool,
+    /// Zero-width characters the cells' [`Glyph::marks`] refer to.
+    pub marks: Marks,
 }
 
 impl Term {
@@ -88,14 +354,40 @@ impl Term {
             grid,
             alt_grid: Vec::new(),
             dirty,
+            line_size: vec![LineSize::Single; rows],
             cursor: Cursor::default(),
+            cursor_style: CursorStyle::default(),
+            default_cursor_style: CursorStyle::default(),
             mode: TermMode::WRAP | TermMode::UTF8,
             esc: EscapeState::empty(),
-            charset: Charset::USA,
+            trantbl: [Charset::USA; 4],
+            charset: 0,
+            single_shift: None,
             lastc: '\0',
+            scrollback: VecDeque::new(),
+            history_base: 0,
+            display_offset: 0,
+            prompt_marks: Vec::new(),
+            selection: None,
+            title: String::new(),
+            title_stack: Vec::new(),
+            cwd: None,
+            ambiguous_wide: false,
+            answerback: String::new(),
+            word_separators: WORD_SEPARATORS.to_string(),
+            responses: Vec::new(),
+            bell: false,
+            marks: Marks::new(),
         }
     }
 
+    /// Append what cell `g` shows to `out`: its character, then any
+    /// zero-width ones after it.
+    pub fn push_cell_text(&self, out: &mut String, g: &Glyph) {
+        out.push(g.char());
+        out.push_str(self.marks.get(g.marks));
+    }
+
     #[inline]
     pub fn idx(&self, x: usize, y: usize) -> usize {
         y * self.cols + x
@@ -106,9 +398,567 @@ impl Term {
         &self.grid[self.idx(x, y)]
     }
 
+    /// Line size of an absolute line; history lines are always single size.
+    pub fn line_size_at(&self, line: usize) -> LineSize {
+        let top = self.abs_line(0);
+        match line.checked_sub(top) {
+            Some(y) if y < self.rows => self.line_size[y],
+            _ => LineSize::Single,
+        }
+    }
+
+    /// Number of usable columns on grid row `y`.
+    #[inline]
+    pub fn line_cols(&self, y: usize) -> usize {
+        match self.line_size[y] {
+            LineSize::Single => self.cols,
+            _ => (self.cols / 2).max(1),
+        }
+    }
+
+    /// Absolute line number of grid row `y`.
+    #[inline]
+    pub fn abs_line(&self, y: usize) -> usize {
+        self.history_base + self.scrollback.len() + y
+    }
+
+    /// Glyphs of an absolute line, if it is still retained.
+    pub fn line_at(&self, line: usize) -> Option<&[Glyph]> {
+        let rel = line.checked_sub(self.history_base)?;
+        if rel < self.scrollback.len() {
+            return Some(&self.scrollback[rel]);
+        }
+        let y = rel - self.scrollback.len();
+        if y < self.rows {
+            let start = y * self.cols;
+            return Some(&self.grid[start..start + self.cols]);
+        }
+        None
+    }
+
+    /// Push grid row `y` into scrollback. Lines leaving the alternate
+    /// screen are discarded, as in xterm.
+    pub fn save_to_history(&mut self, y: usize) {
+        if self.mode.contains(TermMode::ALTSCREEN) {
+            return;
+        }
+        let start = y * self.cols;
+        self.push_history(self.grid[start..start + self.cols].to_vec());
+    }
+
+    fn push_history(&mut self, line: Vec<Glyph>) {
+        self.scrollback.push_back(line);
+        if self.scrollback.len() > SCROLLBACK_LINES {
+            self.scrollback.pop_front();
+            self.history_base += 1;
+            let base = self.history_base;
+            self.prompt_marks.retain(|m| m.line >= base);
+        }
+        if self.display_offset > 0 {
+            // Keep the viewport pinned to the same history content.
+            self.display_offset = (self.display_offset + 1).min(self.scrollback.len());
+        }
+    }
+
+    /// Change the grid to `cols`x`rows`, keeping what is on it. Rows below
+    /// the cursor go first when it shrinks, then rows off the top into
+    /// scrollback, which gives them back when it grows again. The primary
+    /// screen's lines are rewrapped to a new width; the alternate screen's
+    /// are cut or padded, its program redrawing them anyway.
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        if cols == self.cols && rows == self.rows {
+            return;
+        }
+        if cols != self.cols && !self.mode.contains(TermMode::ALTSCREEN) {
+            self.reflow(cols);
+        }
+        let mut lines: VecDeque<Vec<Glyph>> =
+            self.grid.chunks(self.cols).map(<[Glyph]>::to_vec).collect();
+        let mut line_size: VecDeque<LineSize> = self.line_size.iter().copied().collect();
+        while lines.len() > rows && lines.len() > self.cursor.y + 1 {
+            lines.pop_back();
+            line_size.pop_back();
+        }
+        while lines.len() > rows {
+            let line = lines.pop_front().unwrap_or_default();
+            line_size.pop_front();
+            if !self.mode.contains(TermMode::ALTSCREEN) {
+                self.push_history(line);
+            }
+            self.cursor.y -= 1;
+        }
+        while lines.len() < rows && !self.mode.contains(TermMode::ALTSCREEN) {
+            let Some(line) = self.scrollback.pop_back() else {
+                break;
+            };
+            lines.push_front(line);
+            line_size.push_front(LineSize::Single);
+            self.cursor.y += 1;
+        }
+        lines.resize(rows, Vec::new());
+        line_size.resize(rows, LineSize::Single);
+
+        self.grid = lines
+            .into_iter()
+            .flat_map(|mut line| {
+                line.resize(cols, Glyph::default());
+                line
+            })
+            .collect();
+        self.line_size = line_size.into();
+        self.dirty = vec![true; rows];
+        self.cols = cols;
+        self.rows = rows;
+        self.cursor.x = self.cursor.x.min(cols - 1);
+        self.cursor.y = self.cursor.y.min(rows - 1);
+        self.display_offset = self.display_offset.min(self.scrollback.len());
+    }
+
+    /// Rewrap the primary screen and its scrollback to `cols` columns:
+    /// lines that autowrapped are joined and broken again at the new
+    /// width, and the cursor and prompt marks move with their text. The
+    /// grid keeps its row count, for [`Self::resize`] to change.
+    fn reflow(&mut self, cols: usize) {
+        let old_cols = self.cols;
+        // Blank rows below the cursor hold nothing to rewrap.
+        let used = (0..self.rows)
+            .rposition(|y| {
+                !self.grid[y * old_cols..(y + 1) * old_cols]
+                    .iter()
+                    .all(is_blank)
+            })
+            .map_or(0, |y| y + 1)
+            .max(self.cursor.y + 1);
+        let mut old: Vec<(Vec<Glyph>, LineSize)> = self
+            .scrollback
+            .drain(..)
+            .map(|line| (line, LineSize::Single))
+            .collect();
+        let history = old.len();
+        old.extend((0..used).map(|y| {
+            let line = self.grid[y * old_cols..(y + 1) * old_cols].to_vec();
+            (line, self.line_size[y])
+        }));
+
+        // The cursor, the grid's top, then each prompt mark, as (row in
+        // `old`, column). A pending wrap puts the cursor's text one cell on.
+        let cursor_x = match self.cursor.state {
+            CursorState::WrapNext => self.cursor.x + 1,
+            _ => self.cursor.x,
+        };
+        let mut points = vec![(history + self.cursor.y, cursor_x), (history, 0)];
+        points.extend(
+            self.prompt_marks
+                .iter()
+                .map(|m| (m.line - self.history_base, m.col)),
+        );
+        let mut moved = vec![None; points.len()];
+
+        let mut lines: Vec<(Vec<Glyph>, LineSize)> = Vec::new();
+        let mut i = 0;
+        while i < old.len() {
+            let first = i;
+            if old[i].1 != LineSize::Single {
+                // Half as many columns; rewrapping would change its look.
+                let (mut line, size) = std::mem::take(&mut old[i]);
+                line.resize(cols, Glyph::default());
+                for (point, new) in points.iter().zip(&mut moved) {
+                    if point.0 == i {
+                        *new = Some((lines.len(), point.1.min(cols - 1)));
+                    }
+                }
+                lines.push((line, size));
+                i += 1;
+                continue;
+            }
+            // Join the rows of one logical line, noting where each starts.
+            let mut text = Vec::new();
+            let mut starts = Vec::new();
+            loop {
+                let mut row = std::mem::take(&mut old[i].0);
+                let wrapped = row.iter().any(|g| g.flags().contains(GlyphFlags::WRAPLINE));
+                // A double-width character that didn't fit left a blank.
+                let wide_next = old
+                    .get(i + 1)
+                    .and_then(|(next, _)| next.first())
+                    .is_some_and(|g| g.flags().contains(GlyphFlags::WIDE));
+                if wrapped && wide_next && row.last().is_some_and(|g| g.rune == ' ' as u32) {
+                    row.pop();
+                }
+                starts.push(text.len());
+                text.extend(row);
+                i += 1;
+                if !wrapped || i == old.len() || old[i].1 != LineSize::Single {
+                    break;
+                }
+            }
+            for glyph in &mut text {
+                glyph.flags &= !GlyphFlags::WRAPLINE.bits();
+            }
+            while text.last().is_some_and(is_blank) {
+                text.pop();
+            }
+            let offsets: Vec<(usize, usize)> = points
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| (first..i).contains(&p.0))
+                .map(|(k, p)| (k, starts[p.0 - first] + p.1))
+                .collect();
+            // Room for the cursor, which may sit past the text.
+            if let Some(&(_, offset)) = offsets.iter().find(|(k, _)| *k == 0) {
+                if text.len() <= offset {
+                    text.resize(offset + 1, Glyph::default());
+                }
+            }
+            let (rows, row_starts) = rewrap(&text, cols);
+            for (k, offset) in offsets {
+                let row = row_starts.iter().rposition(|&s| s <= offset).unwrap_or(0);
+                let col = (offset - row_starts[row]).min(cols - 1);
+                moved[k] = Some((lines.len() + row, col));
+            }
+            lines.extend(rows.into_iter().map(|row| (row, LineSize::Single)));
+        }
+
+        // What was in scrollback stays there, unless the cursor would end
+        // up below the screen.
+        let (cursor_row, cursor_col) = moved[0].unwrap_or((0, 0));
+        let grid_top = moved[1].map_or(0, |(row, _)| row);
+        let top = grid_top.max(cursor_row.saturating_sub(self.rows - 1));
+        let dropped = top.saturating_sub(SCROLLBACK_LINES);
+        lines.truncate(top + self.rows);
+        let mut lines = lines.into_iter().skip(dropped);
+        self.history_base += dropped;
+        self.scrollback = lines
+            .by_ref()
+            .take(top - dropped)
+            .map(|(line, _)| line)
+            .collect();
+        let (grid, line_size): (Vec<_>, Vec<_>) = lines.unzip();
+        self.grid = grid.into_iter().flatten().collect();
+        self.line_size = line_size;
+        self.grid.resize(cols * self.rows, Glyph::default());
+        self.line_size.resize(self.rows, LineSize::Single);
+
+        let base = self.history_base;
+        self.prompt_marks = self
+            .prompt_marks
+            .iter()
+            .zip(&moved[2..])
+            .filter_map(|(mark, new)| {
+                let (row, col) = (*new)?;
+                (dropped..top + self.rows)
+                    .contains(&row)
+                    .then_some(PromptMark {
+                        line: base + row - dropped,
+                        col,
+                        ..*mark
+                    })
+            })
+            .collect();
+        self.cols = cols;
+        self.cursor.x = cursor_col;
+        self.cursor.y = cursor_row - top;
+        self.cursor.state = CursorState::Default;
+        self.display_offset = 0;
+        self.selection = None;
+    }
+
+    /// Drop all scrollback (ED 3). Absolute line numbers keep counting up so
+    /// prompt marks on the visible screen stay valid.
+    pub fn clear_history(&mut self) {
+        self.history_base += self.scrollback.len();
+        self.scrollback.clear();
+        let base = self.history_base;
+        self.prompt_marks.retain(|m| m.line >= base);
+        self.reset_display();
+    }
+
+    pub fn scroll_display(&mut self, delta: isize) {
+        let max = self.scrollback.len() as isize;
+        let offset = (self.display_offset as isize + delta).clamp(0, max) as usize;
+        if offset != self.display_offset {
+            self.display_offset = offset;
+            self.mark_dirty();
+        }
+    }
+
+    pub fn reset_display(&mut self) {
+        if self.display_offset != 0 {
+            self.display_offset = 0;
+            self.mark_dirty();
+        }
+    }
+
+    /// Absolute line shown on viewport row `y`.
+    #[inline]
+    pub fn view_line(&self, y: usize) -> usize {
+        self.abs_line(y) - self.display_offset.min(self.scrollback.len())
+    }
+
+    /// Begin a selection at viewport cell (`x`, `y`).
+    pub fn start_selection(&mut self, x: usize, y: usize) {
+        let point = (self.view_line(y), x);
+        self.selection = Some(Selection {
+            anchor: point,
+            end: point,
+        });
+        self.mark_dirty();
+    }
+
+    /// Move the free end of the selection to viewport cell (`x`, `y`).
+    pub fn update_selection(&mut self, x: usize, y: usize) {
+        let point = (self.view_line(y), x);
+        if let Some(selection) = self.selection.as_mut() {
+            if selection.end != point {
+                selection.end = point;
+                self.mark_dirty();
+            }
+        }
+    }
+
+    /// Select the word under viewport cell (`x`, `y`), or just that cell
+    /// if it is a separator.
+    pub fn select_word(&mut self, x: usize, y: usize) {
+        let line = self.view_line(y);
+        let Some(glyphs) = self.line_at(line) else {
+            return;
+        };
+        let separators = &self.word_separators;
+        let is_word = |g: &Glyph| !g.char().is_whitespace() && !separators.contains(g.char());
+        let x = x.min(glyphs.len().saturating_sub(1));
+        let (mut from, mut to) = (x, x);
+        if glyphs.get(x).is_some_and(is_word) {
+            while from > 0 && is_word(&glyphs[from - 1]) {
+                from -= 1;
+            }
+            while to + 1 < glyphs.len() && is_word(&glyphs[to + 1]) {
+                to += 1;
+            }
+        }
+        self.selection = Some(Selection {
+            anchor: (line, from),
+            end: (line, to),
+        });
+        self.mark_dirty();
+    }
+
+    /// Select everything from the oldest scrollback line to the last line
+    /// with text on it.
+    pub fn select_all(&mut self) {
+        let first = self.history_base;
+        let Some(last) = (first..self.abs_line(self.rows)).rev().find(|&line| {
+            self.line_at(line)
+                .is_some_and(|glyphs| glyphs.iter().any(|g| g.char() != ' '))
+        }) else {
+            return;
+        };
+        self.selection = Some(Selection {
+            anchor: (first, 0),
+            end: (last, self.cols - 1),
+        });
+        self.mark_dirty();
+    }
+
+    /// The URL under viewport cell (`x`, `y`), if there is one.
+    pub fn url_at(&self, x: usize, y: usize) -> Option<String> {
+        let glyphs = self.line_at(self.view_line(y))?;
+        let is_url = |g: &Glyph| is_url_char(g.char());
+        if !glyphs.get(x).is_some_and(is_url) {
+            return None;
+        }
+        let (mut from, mut to) = (x, x);
+        while from > 0 && is_url(&glyphs[from - 1]) {
+            from -= 1;
+        }
+        while to + 1 < glyphs.len() && is_url(&glyphs[to + 1]) {
+            to += 1;
+        }
+        // URL characters are all ASCII, so bytes and cells line up.
+        let token: String = glyphs[from..=to].iter().map(|g| g.char()).collect();
+        let start = URL_SCHEMES
+            .iter()
+            .filter_map(|scheme| token.find(scheme))
+            .min()
+            .filter(|&start| start <= x - from)?;
+        let mut url = &token[start..];
+        // Punctuation ending a sentence, or a closing bracket around the
+        // link, isn't part of it.
+        loop {
+            let trimmed = url.trim_end_matches(['.', ',', ':', ';', '!', '?', '\'', '"']);
+            url = match trimmed.strip_suffix(')') {
+                Some(inner) if trimmed.matches('(').count() < trimmed.matches(')').count() => inner,
+                _ => trimmed,
+            };
+            if url.len() == trimmed.len() {
+                break;
+            }
+        }
+        (start + url.len() > x - from && !URL_SCHEMES.contains(&url)).then(|| url.to_string())
+    }
+
+    /// Start dragging one end of the selection: the other end becomes the
+    /// anchor so [`Term::update_selection`] moves the dragged one.
+    pub fn grab_selection_end(&mut self, start: bool) {
+        if let Some(selection) = self.selection.as_mut() {
+            let (first, last) = selection.ordered();
+            let (anchor, end) = if start { (last, first) } else { (first, last) };
+            *selection = Selection { anchor, end };
+        }
+    }
+
+    pub fn clear_selection(&mut self) {
+        if self.selection.take().is_some() {
+            self.mark_dirty();
+        }
+    }
+
+    /// Selected columns `[from, to)` on absolute line `line`, if any.
+    pub fn selected_cols(&self, line: usize) -> Option<(usize, usize)> {
+        let (start, end) = self.selection?.ordered();
+        if line < start.0 || line > end.0 {
+            return None;
+        }
+        let from = if line == start.0 { start.1 } else { 0 };
+        let to = if line == end.0 {
+            (end.1 + 1).min(self.cols)
+        } else {
+            self.cols
+        };
+        (from < to).then_some((from, to))
+    }
+
+    pub fn selection_text(&self) -> Option<String> {
+        let (start, end) = self.selection?.ordered();
+        Some(self.text_between(start, (end.0, end.1 + 1)))
+    }
+
+    pub fn add_prompt_mark(&mut self, kind: PromptKind) {
+        let line = self.abs_line(self.cursor.y);
+        self.prompt_marks.push(PromptMark {
+            kind,
+            line,
+            col: self.cursor.x,
+        });
+    }
+
+    /// Scroll the view so the previous prompt above the top of the viewport
+    /// becomes the first visible row. Returns false if there is none.
+    pub fn jump_to_prev_prompt(&mut self) -> bool {
+        let top = self.abs_line(0) - self.display_offset.min(self.scrollback.len());
+        let target = self
+            .prompt_marks
+            .iter()
+            .rev()
+            .filter(|m| m.kind == PromptKind::PromptStart)
+            .find(|m| m.line < top)
+            .map(|m| m.line);
+        self.jump_to_line(target)
+    }
+
+    /// Scroll the view forward to the next prompt below the top of the viewport.
+    pub fn jump_to_next_prompt(&mut self) -> bool {
+        let top = self.abs_line(0) - self.display_offset.min(self.scrollback.len());
+        let target = self
+            .prompt_marks
+            .iter()
+            .filter(|m| m.kind == PromptKind::PromptStart)
+            .find(|m| m.line > top)
+            .map(|m| m.line);
+        if target.is_none() && self.display_offset > 0 {
+            self.reset_display();
+            return true;
+        }
+        self.jump_to_line(target)
+    }
+
+    fn jump_to_line(&mut self, line: Option<usize>) -> bool {
+        let Some(line) = line else {
+            return false;
+        };
+        let live_top = self.abs_line(0);
+        let offset = live_top.saturating_sub(line);
+        self.scroll_display(offset as isize - self.display_offset as isize);
+        true
+    }
+
+    /// Text between `start` and `end` (absolute line, column), exclusive of
+    /// `end`. Trailing blanks on each line are trimmed.
+    pub fn text_between(&self, start: (usize, usize), end: (usize, usize)) -> String {
+        let mut out = String::new();
+        for line in start.0..=end.0 {
+            let Some(glyphs) = self.line_at(line) else {
+                continue;
+            };
+            let from = if line == start.0 { start.1 } else { 0 };
+            let to = if line == end.0 {
+                end.1.min(glyphs.len())
+            } else {
+                glyphs.len()
+            };
+            let wrapped = glyphs
+                .iter()
+                .any(|g| g.flags().contains(GlyphFlags::WRAPLINE));
+            if from < to {
+                let mut text = String::new();
+                for g in glyphs[from..to]
+                    .iter()
+                    .filter(|g| !g.flags().contains(GlyphFlags::WIDE_SPACER))
+                {
+                    self.push_cell_text(&mut text, g);
+                }
+                // Trailing blanks of a wrapped line are real spaces.
+                out.push_str(if wrapped { &text } else { text.trim_end() });
+            }
+            if line != end.0 && !wrapped {
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// Output of the most recent command delimited by OSC 133 C/D marks.
+    /// A still-running command yields its output up to the cursor.
+    pub fn last_command_output(&self) -> Option<String> {
+        let start_idx = self
+            .prompt_marks
+            .iter()
+            .rposition(|m| m.kind == PromptKind::OutputStart)?;
+        let start = &self.prompt_marks[start_idx];
+        let end = self.prompt_marks[start_idx + 1..]
+            .iter()
+            .find(|m| matches!(m.kind, PromptKind::CommandEnd(_) | PromptKind::PromptStart))
+            .map(|m| (m.line, m.col))
+            .unwrap_or((self.abs_line(self.cursor.y), self.cursor.x));
+        let text = self.text_between((start.line, start.col), end);
+        Some(text.trim_end_matches('\n').to_string())
+    }
+
+    pub fn respond(&mut self, bytes: &[u8]) {
+        self.responses.extend_from_slice(bytes);
+    }
+
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.responses)
+    }
+
+    /// XTWINOPS 22: save the current title.
+    pub fn push_title(&mut self) {
+        if self.title_stack.len() >= TITLE_STACK_DEPTH {
+            self.title_stack.remove(0);
+        }
+        self.title_stack.push(self.title.clone());
+    }
+
+    /// XTWINOPS 23: restore the most recently saved title.
+    pub fn pop_title(&mut self) {
+        if let Some(title) = self.title_stack.pop() {
+            self.title = title;
+        }
+    }
+
     pub fn put_char(&mut self, c: char) {
         let idx = self.idx(self.cursor.x, self.cursor.y);
-        self.grid[idx] = Glyph::new(c, 7, 0); // white on black
+        self.grid[idx] = Glyph::new(c, DEFAULT_FG, DEFAULT_BG);
         self.dirty[self.cursor.y] = true;
         self.lastc = c;
 
@@ -147,6 +997,7 @@ impl Term {
     }
 
     fn scroll_up(&mut self) {
+        self.save_to_history(0);
         for y in 1..self.rows {
             let src_start = y * self.cols;
             let dst_start = (y - 1) * self.cols;
@@ -160,6 +1011,8 @@ impl Term {
         for x in 0..self.cols {
             self.grid[bottom_start + x] = Glyph::default();
         }
+        self.line_size.rotate_left(1);
+        self.line_size[self.rows - 1] = LineSize::Single;
         self.dirty[self.rows - 1] = true;
     }
 
@@ -173,11 +1026,24 @@ impl Term {
         for g in self.grid.iter_mut() {
             *g = Glyph::default();
         }
+        self.line_size.fill(LineSize::Single);
         self.cursor = Cursor::default();
+        self.cursor_style = self.default_cursor_style;
         self.mode = TermMode::WRAP | TermMode::UTF8;
         self.esc = EscapeState::empty();
-        self.charset = Charset::USA;
+        self.trantbl = [Charset::USA; 4];
+        self.charset = 0;
+        self.single_shift = None;
         self.lastc = '\0';
+        self.display_offset = 0;
+        self.selection = None;
+        self.title.clear();
+        self.title_stack.clear();
         self.mark_dirty();
     }
 }
+
+/// Characters allowed in a URL (RFC 3986 unreserved and reserved, plus `%`).
+fn is_url_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "-._~:/?#[]@!$&'()*+,;=%".contains(c)
+}
@@ -0,0 +1,450 @@
+//! The file service behind `com.mynk8.gui_engine.HomeDocumentsProvider`,
+//! through which other apps' file pickers and the system Files app browse
+//! and edit $HOME and the prefix. The Java side turns what it gets back
+//! into cursors; this side maps document ids to paths and does the file
+//! work.
+//!
+//! A document id is a root's id, [`HOME_ROOT`] or [`PREFIX_ROOT`], then the
+//! path under it: `home/notes/todo.txt`. A named environment's roots carry
+//! its name after [`ENVIRONMENT_SEPARATOR`], as in `home@work/notes`.
+//! Every call takes `base`, the app's files dir, which the default
+//! environment is installed under.
+
+use jni::objects::{JClass, JObject, JString};
+use jni::sys::{jboolean, jint, jobject, jobjectArray, jstring, JNI_FALSE};
+use jni::JNIEnv;
+use std::error::Error;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io;
+use std::os::fd::IntoRawFd;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::bootstrap;
+
+/// Root ids, which are also the roots' document ids.
+const HOME_ROOT: &str = "home";
+const PREFIX_ROOT: &str = "prefix";
+/// Between a root's id and the environment it belongs to.
+const ENVIRONMENT_SEPARATOR: char = '@';
+/// What documents come back to Java as.
+const ENTRY_CLASS: &str = "com/mynk8/gui_engine/HomeDocumentsProvider$Entry";
+const ENTRY_INIT: &str = "(Ljava/lang/String;Ljava/lang/String;ZZJJ)V";
+/// Most " (n)" suffixes tried for a free name before giving up.
+const MAX_NAME_SUFFIX: u32 = 100;
+
+/// A document, as the provider describes it.
+struct Entry {
+    id: String,
+    name: String,
+    directory: bool,
+    writable: bool,
+    size: u64,
+    /// Milliseconds since the epoch.
+    modified: i64,
+}
+
+impl Entry {
+    fn new(id: String, metadata: &Metadata) -> Self {
+        let name = id.rsplit('/').next().unwrap_or_default().to_string();
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_millis() as i64);
+        Self {
+            id,
+            name,
+            directory: metadata.is_dir(),
+            writable: !metadata.permissions().readonly(),
+            size: metadata.len(),
+            modified,
+        }
+    }
+
+    fn to_java<'local>(&self, env: &mut JNIEnv<'local>) -> jni::errors::Result<JObject<'local>> {
+        let id = env.new_string(&self.id)?;
+        let name = env.new_string(&self.name)?;
+        env.new_object(
+            ENTRY_CLASS,
+            ENTRY_INIT,
+            &[
+                (&id).into(),
+                (&name).into(),
+                self.directory.into(),
+                self.writable.into(),
+                (self.size as i64).into(),
+                self.modified.into(),
+            ],
+        )
+    }
+}
+
+fn not_found(id: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no document {}", id))
+}
+
+/// The directory of root `root`, in the default environment or the one
+/// named after [`ENVIRONMENT_SEPARATOR`].
+fn root_dir(base: &Path, root: &str) -> Option<PathBuf> {
+    let (kind, base) = match root.split_once(ENVIRONMENT_SEPARATOR) {
+        Some((kind, name)) if bootstrap::is_environment_name(name) => {
+            (kind, bootstrap::environment_base(base, name))
+        }
+        Some(_) => return None,
+        None => (root, base.to_path_buf()),
+    };
+    match kind {
+        HOME_ROOT => Some(bootstrap::home_dir(&base)),
+        PREFIX_ROOT => Some(bootstrap::prefix_dir(&base)),
+        _ => None,
+    }
+}
+
+/// The path document `id` names, refusing ids that would climb out of
+/// their root.
+fn resolve(base: &Path, id: &str) -> io::Result<PathBuf> {
+    let mut parts = id.split('/');
+    let root = parts.next().unwrap_or_default();
+    let Some(mut path) = root_dir(base, root) else {
+        return Err(not_found(id));
+    };
+    for part in parts {
+        check_name(part).map_err(|_| not_found(id))?;
+        path.push(part);
+    }
+    Ok(path)
+}
+
+/// Refuse a name that isn't a single path component.
+fn check_name(name: &str) -> io::Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("bad file name {:?}", name),
+        ));
+    }
+    Ok(())
+}
+
+/// What a link points at, or the link itself if it dangles.
+fn metadata(path: &Path) -> io::Result<Metadata> {
+    fs::metadata(path).or_else(|_| fs::symlink_metadata(path))
+}
+
+fn query_document(base: &Path, id: &str) -> io::Result<Entry> {
+    let path = resolve(base, id)?;
+    Ok(Entry::new(id.to_string(), &metadata(&path)?))
+}
+
+/// The documents in directory `parent`. Names Java can't hold, not being
+/// UTF-8, are left out.
+fn query_children(base: &Path, parent: &str) -> io::Result<Vec<Entry>> {
+    let dir = resolve(base, parent)?;
+    let mut entries = Vec::new();
+    for dir_entry in fs::read_dir(&dir)? {
+        let dir_entry = dir_entry?;
+        let Ok(name) = dir_entry.file_name().into_string() else {
+            continue;
+        };
+        match metadata(&dir_entry.path()) {
+            Ok(metadata) => entries.push(Entry::new(format!("{}/{}", parent, name), &metadata)),
+            Err(e) => log::warn!("Failed to stat {:?}: {:?}", dir_entry.path(), e),
+        }
+    }
+    Ok(entries)
+}
+
+/// `name` in `dir`, or if taken the first free "name (n).ext", as the
+/// system's own providers do.
+fn free_name(dir: &Path, name: &str) -> io::Result<String> {
+    if fs::symlink_metadata(dir.join(name)).is_err() {
+        return Ok(name.to_string());
+    }
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+        _ => (name, None),
+    };
+    for n in 1..=MAX_NAME_SUFFIX {
+        let candidate = match extension {
+            Some(extension) => format!("{} ({}).{}", stem, n, extension),
+            None => format!("{} ({})", stem, n),
+        };
+        if fs::symlink_metadata(dir.join(&candidate)).is_err() {
+            return Ok(candidate);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} already exists", name),
+    ))
+}
+
+/// Make a file or directory `name` in `parent`. Returns its id.
+fn create_document(base: &Path, parent: &str, name: &str, directory: bool) -> io::Result<String> {
+    check_name(name)?;
+    let dir = resolve(base, parent)?;
+    let name = free_name(&dir, name)?;
+    let path = dir.join(&name);
+    if directory {
+        fs::create_dir(&path)?;
+    } else {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+    }
+    Ok(format!("{}/{}", parent, name))
+}
+
+/// Delete document `id`, a directory with everything in it. A link goes
+/// alone, not what it points at, so ~/storage's links don't take shared
+/// storage with them.
+fn delete_document(base: &Path, id: &str) -> io::Result<()> {
+    if !id.contains('/') {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "a root can't be deleted",
+        ));
+    }
+    let path = resolve(base, id)?;
+    if fs::symlink_metadata(&path)?.is_dir() {
+        fs::remove_dir_all(&path)
+    } else {
+        fs::remove_file(&path)
+    }
+}
+
+/// Rename document `id` to `name`, in the same directory. Returns its new
+/// id.
+fn rename_document(base: &Path, id: &str, name: &str) -> io::Result<String> {
+    check_name(name)?;
+    let Some((parent, _)) = id.rsplit_once('/') else {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "a root can't be renamed",
+        ));
+    };
+    let path = resolve(base, id)?;
+    let dir = resolve(base, parent)?;
+    let name = free_name(&dir, name)?;
+    fs::rename(&path, dir.join(&name))?;
+    Ok(format!("{}/{}", parent, name))
+}
+
+/// Open document `id` in `mode`, which is ParcelFileDescriptor's: "r",
+/// "w", "wt", "wa", "rw" or "rwt".
+fn open_document(base: &Path, id: &str, mode: &str) -> io::Result<File> {
+    let path = resolve(base, id)?;
+    if path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is a directory", id),
+        ));
+    }
+    let mut options = OpenOptions::new();
+    match mode {
+        "r" => options.read(true),
+        "w" | "wt" => options.write(true).create(true).truncate(true),
+        "wa" => options.append(true).create(true),
+        "rw" => options.read(true).write(true).create(true),
+        "rwt" => options.read(true).write(true).create(true).truncate(true),
+        mode => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("bad mode {:?}", mode),
+            ))
+        }
+    };
+    options.open(&path)
+}
+
+/// Run `f` on the Java strings `args`, throwing `FileNotFoundException`
+/// (which the provider's methods declare) if it fails and returning
+/// `failed` instead.
+fn serve<'local, T, const N: usize>(
+    env: &mut JNIEnv<'local>,
+    what: &str,
+    args: [&JString; N],
+    failed: T,
+    f: impl FnOnce(&mut JNIEnv<'local>, [String; N]) -> Result<T, Box<dyn Error>>,
+) -> T {
+    let result = (|| {
+        let mut strings = Vec::with_capacity(N);
+        for arg in args {
+            strings.push(String::from(env.get_string(arg)?));
+        }
+        let strings: [String; N] = strings.try_into().unwrap();
+        f(env, strings)
+    })();
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            log::warn!("{} failed: {}", what, e);
+            if !env.exception_check().unwrap_or(true) {
+                let _ = env.throw_new("java/io/FileNotFoundException", e.to_string());
+            }
+            failed
+        }
+    }
+}
+
+/// The named environments, whose roots are listed after the default's.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_HomeDocumentsProvider_nativeEnvironments<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    base: JString<'local>,
+) -> jobjectArray {
+    serve(
+        &mut env,
+        "Listing environments",
+        [&base],
+        std::ptr::null_mut(),
+        |env, [base]| {
+            let names = bootstrap::environments(Path::new(&base));
+            let array =
+                env.new_object_array(names.len() as i32, "java/lang/String", JObject::null())?;
+            for (i, name) in names.iter().enumerate() {
+                let name = env.new_string(name)?;
+                env.set_object_array_element(&array, i as i32, &name)?;
+                env.delete_local_ref(name)?;
+            }
+            Ok(array.into_raw())
+        },
+    )
+}
+
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_HomeDocumentsProvider_nativeQueryDocument<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    base: JString<'local>,
+    id: JString<'local>,
+) -> jobject {
+    serve(
+        &mut env,
+        "Querying document",
+        [&base, &id],
+        std::ptr::null_mut(),
+        |env, [base, id]| {
+            let entry = query_document(Path::new(&base), &id)?;
+            Ok(entry.to_java(env)?.into_raw())
+        },
+    )
+}
+
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_HomeDocumentsProvider_nativeQueryChildren<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    base: JString<'local>,
+    parent: JString<'local>,
+) -> jobjectArray {
+    serve(
+        &mut env,
+        "Listing documents",
+        [&base, &parent],
+        std::ptr::null_mut(),
+        |env, [base, parent]| {
+            let entries = query_children(Path::new(&base), &parent)?;
+            let array = env.new_object_array(entries.len() as i32, ENTRY_CLASS, JObject::null())?;
+            for (i, entry) in entries.iter().enumerate() {
+                let entry = entry.to_java(env)?;
+                env.set_object_array_element(&array, i as i32, &entry)?;
+                env.delete_local_ref(entry)?;
+            }
+            Ok(array.into_raw())
+        },
+    )
+}
+
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_HomeDocumentsProvider_nativeCreateDocument<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    base: JString<'local>,
+    parent: JString<'local>,
+    name: JString<'local>,
+    directory: jboolean,
+) -> jstring {
+    serve(
+        &mut env,
+        "Creating document",
+        [&base, &parent, &name],
+        std::ptr::null_mut(),
+        |env, [base, parent, name]| {
+            let directory = directory != JNI_FALSE;
+            let id = create_document(Path::new(&base), &parent, &name, directory)?;
+            Ok(env.new_string(id)?.into_raw())
+        },
+    )
+}
+
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_HomeDocumentsProvider_nativeDeleteDocument<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    base: JString<'local>,
+    id: JString<'local>,
+) {
+    serve(
+        &mut env,
+        "Deleting document",
+        [&base, &id],
+        (),
+        |_, [base, id]| Ok(delete_document(Path::new(&base), &id)?),
+    )
+}
+
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_HomeDocumentsProvider_nativeRenameDocument<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    base: JString<'local>,
+    id: JString<'local>,
+    name: JString<'local>,
+) -> jstring {
+    serve(
+        &mut env,
+        "Renaming document",
+        [&base, &id, &name],
+        std::ptr::null_mut(),
+        |env, [base, id, name]| {
+            let id = rename_document(Path::new(&base), &id, &name)?;
+            Ok(env.new_string(id)?.into_raw())
+        },
+    )
+}
+
+/// Returns a file descriptor for the caller to adopt, or -1.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_HomeDocumentsProvider_nativeOpenDocument<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    base: JString<'local>,
+    id: JString<'local>,
+    mode: JString<'local>,
+) -> jint {
+    serve(
+        &mut env,
+        "Opening document",
+        [&base, &id, &mode],
+        -1,
+        |_, [base, id, mode]| Ok(open_document(Path::new(&base), &id, &mode)?.into_raw_fd()),
+    )
+}
@@ -0,0 +1,355 @@
+//! JNI entry points for apps that draw the terminal in their own views
+//! instead of the winit window; `com.mynk8.gui_engine.TerminalSession` is
+//! the Java side. A session is a [`Term`] with its [`Parser`] and the
+//! [`Pty`] feeding them, read on a thread of its own that tells the Java
+//! listener whenever the screen changed.
+
+use jni::objects::{JByteArray, JClass, JIntArray, JObject, JObjectArray, JString, JValue};
+use jni::sys::{jint, jlong, jstring};
+use jni::{JNIEnv, JavaVM};
+use nix::fcntl::OFlag;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::pipe2;
+use std::os::fd::OwnedFd;
+use std::sync::{Arc, Mutex};
+
+use crate::core::children;
+use crate::core::types::Term;
+use crate::core::{Parser, Pty, PtyEnv, ShellCommand};
+use crate::read_pty;
+
+/// Ints per cell in [`nativeReadRow`]'s output: rune, fg, bg, attrs.
+const CELL_INTS: usize = 4;
+
+struct Screen {
+    term: Term,
+    parser: Parser,
+}
+
+struct Session {
+    screen: Mutex<Screen>,
+    pty: Pty,
+    /// Dropping it stops the reader thread.
+    reader_wakeup: Mutex<Option<OwnedFd>>,
+}
+
+/// The session behind a handle from [`nativeCreate`].
+///
+/// # Safety
+///
+/// `handle` must come from `nativeCreate` and not have been destroyed.
+/// TerminalSession makes each call holding the lock `nativeDestroy` is
+/// called with, so it can't be destroyed before the call returns.
+unsafe fn session<'a>(handle: jlong) -> &'a Arc<Session> {
+    unsafe { &*(handle as *const Arc<Session>) }
+}
+
+/// Throw `IOException` with `message`, unless an exception is already
+/// pending.
+fn throw(env: &mut JNIEnv, message: &str) {
+    if !env.exception_check().unwrap_or(true) {
+        let _ = env.throw_new("java/io/IOException", message);
+    }
+}
+
+fn strings(env: &mut JNIEnv, array: &JObjectArray) -> jni::errors::Result<Vec<String>> {
+    if array.is_null() {
+        return Ok(Vec::new());
+    }
+    let len = env.get_array_length(array)?;
+    let mut out = Vec::with_capacity(len as usize);
+    for i in 0..len {
+        let value = JString::from(env.get_object_array_element(array, i)?);
+        out.push(env.get_string(&value)?.into());
+    }
+    Ok(out)
+}
+
+/// Call `void method(args)` on the listener, logging whatever it throws.
+fn notify(env: &mut JNIEnv, listener: &JObject, method: &str, sig: &str, args: &[JValue]) {
+    if let Err(e) = env.call_method(listener, method, sig, args) {
+        log::warn!("TerminalSession.Listener.{} failed: {:?}", method, e);
+        if env.exception_check().unwrap_or(false) {
+            let _ = env.exception_describe();
+            let _ = env.exception_clear();
+        }
+    }
+}
+
+/// Start `program` with `args` and `NAME=value` pairs `vars` on a
+/// `cols`x`rows` terminal. Returns the session's handle, or 0 with an
+/// `IOException` thrown.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_TerminalSession_nativeCreate<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    program: JString<'local>,
+    args: JObjectArray<'local>,
+    vars: JObjectArray<'local>,
+    cwd: JString<'local>,
+    cols: jint,
+    rows: jint,
+    listener: JObject<'local>,
+) -> jlong {
+    match create(&mut env, program, args, vars, cwd, cols, rows, listener) {
+        Ok(handle) => handle,
+        Err(e) => {
+            log::error!("Failed to create terminal session: {:?}", e);
+            throw(
+                &mut env,
+                &format!("Failed to create terminal session: {}", e),
+            );
+            0
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create(
+    env: &mut JNIEnv,
+    program: JString,
+    args: JObjectArray,
+    vars: JObjectArray,
+    cwd: JString,
+    cols: jint,
+    rows: jint,
+    listener: JObject,
+) -> Result<jlong, Box<dyn std::error::Error>> {
+    let program: String = env.get_string(&program)?.into();
+    let args = strings(env, &args)?;
+    let vars = strings(env, &vars)?;
+    let mut builder = PtyEnv::builder().vars(vars.iter().filter_map(|var| {
+        let (name, value) = var.split_once('=')?;
+        Some((name.to_string(), value.to_string()))
+    }));
+    if !cwd.is_null() {
+        let cwd: String = env.get_string(&cwd)?.into();
+        builder = builder.cwd(cwd);
+    }
+    let (cols, rows) = (cols.max(1), rows.max(1));
+    let command = ShellCommand {
+        program,
+        args,
+        login: false,
+    };
+    // Waited for by its reader thread, not the app's reaper.
+    let pty = children::claim(
+        || Pty::spawn(&command, rows as u16, cols as u16, &builder.build()),
+        Pty::child_pid,
+    )?;
+    let (wakeup, wakeup_tx) = pipe2(OFlag::O_CLOEXEC)?;
+
+    let session = Arc::new(Session {
+        screen: Mutex::new(Screen {
+            term: Term::new(cols as usize, rows as usize),
+            parser: Parser::new(),
+        }),
+        pty,
+        reader_wakeup: Mutex::new(Some(wakeup_tx)),
+    });
+    let vm = env.get_java_vm()?;
+    let listener = env.new_global_ref(listener)?;
+    let reader = session.clone();
+    std::thread::spawn(move || read_session(vm, &reader, wakeup, listener.as_obj()));
+
+    Ok(Box::into_raw(Box::new(session)) as jlong)
+}
+
+/// Feed the PTY's output to the screen until the program exits or the
+/// session is destroyed, telling `listener` about each change.
+fn read_session(vm: JavaVM, session: &Session, wakeup: OwnedFd, listener: &JObject) {
+    let mut env = match vm.attach_current_thread_as_daemon() {
+        Ok(env) => env,
+        Err(e) => {
+            log::error!("Terminal session reader failed to attach: {:?}", e);
+            return;
+        }
+    };
+    read_pty(&session.pty, wakeup, |data| {
+        let responses = {
+            let mut screen = session.screen.lock().unwrap();
+            let Screen { term, parser } = &mut *screen;
+            for &byte in data {
+                parser.process(term, byte);
+            }
+            term.take_responses()
+        };
+        if !responses.is_empty() {
+            let _ = session.pty.write(&responses);
+        }
+        notify(&mut env, listener, "onScreenUpdate", "()V", &[]);
+    });
+
+    // Nothing else reaps the program here, as the app's own session does.
+    let pid = session.pty.child_pid();
+    let destroyed = session.reader_wakeup.lock().unwrap().is_none();
+    if destroyed {
+        let _ = kill(pid, Signal::SIGHUP);
+    }
+    let status = match waitpid(pid, None) {
+        Ok(WaitStatus::Exited(_, code)) => code,
+        Ok(WaitStatus::Signaled(_, signal, _)) => 128 + signal as i32,
+        _ => -1,
+    };
+    children::release(pid);
+    log::info!("Embedded session exited with status {}", status);
+    if !destroyed {
+        notify(&mut env, listener, "onExit", "(I)V", &[status.into()]);
+    }
+}
+
+/// Send `data` to the program as if typed.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_TerminalSession_nativeWrite<'local>(
+    env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    data: JByteArray<'local>,
+) {
+    let session = unsafe { session(handle) };
+    match env.convert_byte_array(&data) {
+        Ok(bytes) => {
+            if let Err(e) = session.pty.write(&bytes) {
+                log::warn!("Embedded session write failed: {:?}", e);
+            }
+        }
+        Err(e) => log::warn!("Embedded session write failed: {:?}", e),
+    }
+}
+
+/// Resize the screen and the PTY to `cols`x`rows`.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_TerminalSession_nativeResize<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    cols: jint,
+    rows: jint,
+) {
+    let session = unsafe { session(handle) };
+    let (cols, rows) = (cols.max(1), rows.max(1));
+    session
+        .screen
+        .lock()
+        .unwrap()
+        .term
+        .resize(cols as usize, rows as usize);
+    session.pty.resize(rows as u16, cols as u16);
+}
+
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_TerminalSession_nativeColumns<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jint {
+    let session = unsafe { session(handle) };
+    session.screen.lock().unwrap().term.cols as jint
+}
+
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_TerminalSession_nativeRows<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jint {
+    let session = unsafe { session(handle) };
+    session.screen.lock().unwrap().term.rows as jint
+}
+
+/// Cursor position as `row << 16 | col`.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_TerminalSession_nativeCursor<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jint {
+    let session = unsafe { session(handle) };
+    let screen = session.screen.lock().unwrap();
+    let cursor = &screen.term.cursor;
+    ((cursor.y as jint) << 16) | cursor.x as jint
+}
+
+/// Fill `cells` with screen row `row`, four ints a cell: the character,
+/// the foreground and background colours, and the attribute bits. Returns
+/// how many cells were written.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_TerminalSession_nativeReadRow<'local>(
+    env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    row: jint,
+    cells: JIntArray<'local>,
+) -> jint {
+    let session = unsafe { session(handle) };
+    let out = {
+        let screen = session.screen.lock().unwrap();
+        let term = &screen.term;
+        let glyphs = usize::try_from(row)
+            .ok()
+            .filter(|&row| row < term.rows)
+            .and_then(|row| term.line_at(term.view_line(row)))
+            .unwrap_or_default();
+        let capacity = env.get_array_length(&cells).unwrap_or(0) as usize / CELL_INTS;
+        glyphs
+            .iter()
+            .take(capacity)
+            .flat_map(|g| [g.rune, g.fg, g.bg, g.attrs as u32 | (g.flags as u32) << 8])
+            .map(|v| v as jint)
+            .collect::<Vec<_>>()
+    };
+    if let Err(e) = env.set_int_array_region(&cells, 0, &out) {
+        log::warn!("Reading embedded session row failed: {:?}", e);
+        return 0;
+    }
+    (out.len() / CELL_INTS) as jint
+}
+
+/// Text of screen row `row`, without trailing blanks.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_TerminalSession_nativeRowText<'local>(
+    env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    row: jint,
+) -> jstring {
+    let session = unsafe { session(handle) };
+    let text = {
+        let screen = session.screen.lock().unwrap();
+        let term = &screen.term;
+        let line = term.view_line(row.clamp(0, term.rows as jint - 1) as usize);
+        term.text_between((line, 0), (line, term.cols))
+    };
+    env.new_string(text)
+        .map(|s| s.into_raw())
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// The title the program last set.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_TerminalSession_nativeTitle<'local>(
+    env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jstring {
+    let session = unsafe { session(handle) };
+    let title = session.screen.lock().unwrap().term.title.clone();
+    env.new_string(title)
+        .map(|s| s.into_raw())
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// Stop reading, hang up the program and free the session. The handle is
+/// invalid afterwards.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mynk8_gui_1engine_TerminalSession_nativeDestroy<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) {
+    let session = unsafe { Box::from_raw(handle as *mut Arc<Session>) };
+    // The reader thread drops the last reference, and with it the PTY.
+    session.reader_wakeup.lock().unwrap().take();
+}
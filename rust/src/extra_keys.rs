@@ -0,0 +1,127 @@
+use winit::keyboard::KeyCode;
+
+/// Keys shown when the config doesn't list any.
+pub const DEFAULT_EXTRA_KEYS: &str = "ESC TAB CTRL ALT - / | LEFT DOWN UP RIGHT";
+
+/// Names accepted in `[extra_keys] keys`, with the key each one presses
+/// and its label on screen.
+const NAMED_KEYS: [(&str, KeyCode, &str); 26] = [
+    ("ESC", KeyCode::Escape, "ESC"),
+    ("TAB", KeyCode::Tab, "TAB"),
+    ("ENTER", KeyCode::Enter, "⏎"),
+    ("BKSP", KeyCode::Backspace, "⌫"),
+    ("DEL", KeyCode::Delete, "DEL"),
+    ("INS", KeyCode::Insert, "INS"),
+    ("UP", KeyCode::ArrowUp, "↑"),
+    ("DOWN", KeyCode::ArrowDown, "↓"),
+    ("LEFT", KeyCode::ArrowLeft, "←"),
+    ("RIGHT", KeyCode::ArrowRight, "→"),
+    ("HOME", KeyCode::Home, "HOME"),
+    ("END", KeyCode::End, "END"),
+    ("PGUP", KeyCode::PageUp, "PGUP"),
+    ("PGDN", KeyCode::PageDown, "PGDN"),
+    ("F1", KeyCode::F1, "F1"),
+    ("F2", KeyCode::F2, "F2"),
+    ("F3", KeyCode::F3, "F3"),
+    ("F4", KeyCode::F4, "F4"),
+    ("F5", KeyCode::F5, "F5"),
+    ("F6", KeyCode::F6, "F6"),
+    ("F7", KeyCode::F7, "F7"),
+    ("F8", KeyCode::F8, "F8"),
+    ("F9", KeyCode::F9, "F9"),
+    ("F10", KeyCode::F10, "F10"),
+    ("F11", KeyCode::F11, "F11"),
+    ("F12", KeyCode::F12, "F12"),
+];
+
+/// One key on the extra keys row.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExtraKey {
+    /// Sticky modifiers: they latch until the next key, from any keyboard.
+    Ctrl,
+    Alt,
+    /// Pressed as if on a hardware keyboard.
+    Key(KeyCode),
+    /// Typed as is; for symbols that are buried on soft keyboards.
+    Text(String),
+}
+
+impl ExtraKey {
+    /// A key name from the config, like `ESC` or `PGUP`; anything else
+    /// types itself.
+    pub fn parse(name: &str) -> Self {
+        match name.to_ascii_uppercase().as_str() {
+            "CTRL" => ExtraKey::Ctrl,
+            "ALT" => ExtraKey::Alt,
+            upper => NAMED_KEYS
+                .iter()
+                .find(|(n, _, _)| *n == upper)
+                .map(|&(_, code, _)| ExtraKey::Key(code))
+                .unwrap_or_else(|| ExtraKey::Text(name.to_string())),
+        }
+    }
+
+    /// The config name, as accepted by [`ExtraKey::parse`].
+    pub fn name(&self) -> &str {
+        match self {
+            ExtraKey::Ctrl => "CTRL",
+            ExtraKey::Alt => "ALT",
+            ExtraKey::Key(code) => Self::named(*code).map_or("", |(name, _, _)| name),
+            ExtraKey::Text(text) => text,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            ExtraKey::Key(code) => Self::named(*code).map_or("", |(_, _, label)| label),
+            _ => self.name(),
+        }
+    }
+
+    fn named(code: KeyCode) -> Option<&'static (&'static str, KeyCode, &'static str)> {
+        NAMED_KEYS.iter().find(|(_, c, _)| *c == code)
+    }
+}
+
+/// Parse a space-separated key list.
+pub fn parse_extra_keys(value: &str) -> Vec<ExtraKey> {
+    value.split_whitespace().map(ExtraKey::parse).collect()
+}
+
+/// CTRL and ALT as latched from the extra keys row.
+#[derive(Default)]
+pub struct StickyModifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+impl StickyModifiers {
+    /// Apply the latched modifiers to the bytes of one key and release
+    /// them. Ctrl turns a character into its control code; Alt prefixes
+    /// ESC, as xterm does with `metaSendsEscape`.
+    pub fn apply(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(bytes.len() + 1);
+        if std::mem::take(&mut self.alt) {
+            out.push(0x1b);
+        }
+        let ctrl = std::mem::take(&mut self.ctrl);
+        match bytes {
+            [b] if ctrl => out.push(ctrl_byte(*b)),
+            _ => out.extend_from_slice(bytes),
+        }
+        out
+    }
+}
+
+/// The control code Ctrl+`b` types, or `b` itself if it has none.
+pub fn ctrl_byte(b: u8) -> u8 {
+    match b {
+        b'a'..=b'z' => b - 0x60,
+        b'@'..=b'_' => b - 0x40,
+        b' ' | b'2' => 0x00,
+        b'6' => 0x1e,
+        b'-' | b'/' => 0x1f,
+        b'?' => 0x7f,
+        _ => b,
+    }
+}
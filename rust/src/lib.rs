@@ -1,57 +1,137 @@
+mod android;
+mod backend;
 mod bootstrap;
+mod compose;
 mod config;
+mod control;
 mod core;
+mod documents;
+mod embed;
+mod extra_keys;
+mod menu;
+mod mouse;
+mod scroll;
+mod touch;
+mod transcript;
 
 use android_activity::AndroidApp;
-use glutin::config::Config;
-use glutin::{
-    config::ConfigTemplateBuilder,
-    context::{
-        ContextApi, ContextAttributesBuilder, NotCurrentGlContext, PossiblyCurrentContext, Version,
-    },
-    display::{GetGlDisplay, GlDisplay},
-    prelude::GlSurface,
-    surface::{Surface as GlutinSurface, SurfaceAttributesBuilder, WindowSurface},
-};
-use glutin_winit::DisplayBuilder;
-use raw_window_handle::HasWindowHandle;
-use skia_safe::{
-    gpu::{
-        backend_render_targets, direct_contexts, gl::FramebufferInfo, surfaces, Protected,
-        SurfaceOrigin,
-    },
-    ColorType, Surface,
-};
-use std::sync::atomic::{AtomicBool, Ordering};
+use ndk::configuration::UiModeNight;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags, EpollTimeout};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use nix::unistd::pipe2;
+use skia_safe::{Point, Rect};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::os::fd::OwnedFd;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::{
-    ffi::CString,
-    num::NonZeroU32,
-    path::PathBuf,
-    time::{Duration, Instant},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
 };
 use winit::{
     application::ApplicationHandler,
-    event::{ElementState, WindowEvent},
-    event_loop::{ActiveEventLoop, EventLoop, EventLoopProxy},
-    keyboard::{KeyCode, PhysicalKey},
+    dpi::PhysicalPosition,
+    event::{
+        ElementState, KeyEvent, MouseButton, MouseScrollDelta, Touch, TouchPhase, WindowEvent,
+    },
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop, EventLoopProxy},
+    keyboard::{Key, KeyCode, NamedKey, PhysicalKey},
     window::{Window, WindowId},
 };
 
-use crate::bootstrap::setup_bootstrap_if_needed;
-use crate::config::{config_path, AppConfig};
-use crate::core::types::Term;
-use crate::core::{Parser, Pty, PtyEnv, Renderer};
+use crate::backend::Backend;
+use crate::bootstrap::{
+    setup_bootstrap_if_needed, BootstrapPaths, BootstrapSource, DiskUsage, Progress,
+};
+use crate::config::{config_path, last_cwd_path, AppConfig, BackAction, ShellProgram};
+use crate::core::types::{Term, TermMode};
+use crate::core::{DebugStats, Parser, Pty, PtyEnv, Renderer, ShellCommand};
+use crate::extra_keys::{ctrl_byte, ExtraKey, StickyModifiers};
+use crate::menu::{ContextMenu, MenuAction, SIGNALS};
+use crate::mouse::{MouseMods, MouseReport};
+use crate::scroll::SmoothScroll;
+use crate::touch::{Gesture, TouchTracker};
+use crate::transcript::Transcript;
 
 #[derive(Debug, Clone)]
 enum AppEvent {
     CursorBlink,
     PtyOutput(Vec<u8>),
     PtyExit,
+    /// Whether something other than the shell is in the foreground.
+    JobRunning(bool),
+    BootstrapProgress(Progress),
+    /// The bootstrap is installed, with the shell's environment, or failed
+    /// to install and the system's will have to do.
+    BootstrapDone(Option<PtyEnv>),
+    RepairDone,
+    DiskUsage(DiskUsage),
+    /// The bootstrap was installed just now; greet the user.
+    Welcome,
+    /// The config file was written.
+    ConfigChanged,
 }
 
 const CURSOR_BLINK_MS: u64 = 500;
 const DEFAULT_SHELL: &str = "/system/bin/sh";
+/// Printed before the first session in a new install, unless
+/// ~/.hushlogin exists, as login(1) would the message of the day.
+const WELCOME: &str = "\
+Welcome! This is a Termux environment.
+
+  pkg install <name>   install a package; pkg search finds them
+  open <file or URL>   view it in another app
+  long press           select text and open the menu
+  Ctrl+Shift+C / V     copy / paste
+  Ctrl+Shift+R         repair the environment
+  Ctrl+Shift+U         disk usage
+  Ctrl+Shift+S         link shared storage under ~/storage
+  Ctrl+Shift+B         erase and reinstall the environment
+  Ctrl+Shift+M         move to the next display
+
+Settings are in ~/../gui-engine.ini.
+
+";
+/// Where shared files are copied, under $HOME.
+const SHARED_DIR: &str = "shared";
+/// Where a bare `[shell] program` name is found without a bootstrap.
+const SYSTEM_BIN: &str = "/system/bin";
+// Font sizes are in density-independent points, like the config.
+const FONT_SIZE_STEP: f32 = 1.0;
+const MIN_FONT_SIZE: f32 = 4.0;
+const MAX_FONT_SIZE: f32 = 48.0;
+/// Soft keyboard toggle: side length and distance from the corner, in points.
+const KEYBOARD_BUTTON_SIZE: f32 = 40.0;
+const KEYBOARD_BUTTON_INSET: f32 = 8.0;
+/// Height of the extra keys row, in points.
+const EXTRA_KEYS_HEIGHT: f32 = 36.0;
+/// How far a finger may drift and still tap, in points.
+const TOUCH_SLOP: f64 = 8.0;
+/// Slowest finger release that still flings, in points per second.
+const MIN_FLING_SPEED: f64 = 50.0;
+const SELECTION_HANDLE_RADIUS: f32 = 10.0;
+/// Space between a long press and the context menu above it, in points.
+const MENU_GAP: f32 = 48.0;
+/// Notification ids; a new one replaces the last of its kind.
+const BELL_NOTIFICATION: i32 = 1;
+const DONE_NOTIFICATION: i32 = 2;
+/// How often to check whether a job is running.
+const JOB_POLL: Duration = Duration::from_secs(1);
+/// How often to check whether the DNS servers changed.
+const NETWORK_POLL: Duration = Duration::from_secs(10);
+/// How long the window has to keep its size before the grid follows.
+const RESIZE_SETTLE: Duration = Duration::from_millis(150);
+/// Assumed of a display that doesn't say its refresh rate.
+const DEFAULT_REFRESH_RATE: f32 = 60.0;
+/// Length of the haptic buzz.
+const HAPTIC_DURATION: Duration = Duration::from_millis(15);
+/// Lines scrolled back per mouse wheel notch.
+const WHEEL_LINES: f32 = 3.0;
 
 #[unsafe(no_mangle)]
 fn android_main(app: AndroidApp) {
@@ -59,48 +139,67 @@ fn android_main(app: AndroidApp) {
         android_logger::Config::default().with_max_level(log::LevelFilter::Info),
     );
 
+    // Loaded before the event loop, which needs to know whether to take
+    // the volume keys from the system.
+    let base = app.internal_data_path();
+    let config = base.as_ref().map(|base| {
+        let path = config_path(base);
+        let mut config = AppConfig::load_or_create(&path);
+        config.resolve_paths(base);
+        log::info!("Loaded config: {:?}", path);
+        (config, path)
+    });
+
+    // Before the first window comes, so it is made with the right format.
+    if config
+        .as_ref()
+        .is_some_and(|(config, _)| config.background_opacity < 1.0)
+    {
+        android::show_wallpaper(&app);
+    }
+
     use winit::platform::android::EventLoopBuilderExtAndroid;
-    let app_for_loop = app.clone();
-    let event_loop: EventLoop<AppEvent> = EventLoop::with_user_event()
-        .with_android_app(app_for_loop)
-        .build()
-        .expect("Failed to create event loop");
+    let mut builder = EventLoop::with_user_event();
+    builder.with_android_app(app.clone());
+    let volume_keys = config
+        .as_ref()
+        .map_or(AppConfig::default().volume_keys, |(config, _)| {
+            config.volume_keys
+        });
+    if volume_keys {
+        builder.handle_volume_keys();
+    }
+    let event_loop: EventLoop<AppEvent> = builder.build().expect("Failed to create event loop");
 
-    let proxy = event_loop.create_proxy();
-    let mut application = App::new(proxy);
-    if let Some(base) = app.internal_data_path() {
-        let path = config_path(&base);
-        application.config = Some(AppConfig::load_or_create(&path));
-        log::info!("Loaded config: {:?}", path);
+    if let Err(e) = core::pty::adopt_orphans() {
+        log::warn!("Failed to become child subreaper: {:?}", e);
+    }
 
-        let assets = app.asset_manager();
-        match setup_bootstrap_if_needed(&base, &assets) {
-            Ok(paths) => {
-                let prefix = paths.prefix.to_string_lossy().to_string();
-                let mut env = PtyEnv::system_default();
-                env.term = "xterm-256color".to_string();
-                env.home = paths.home.clone();
-                env.cwd = Some(paths.home);
-                env.tmp = Some(paths.tmp);
-                env.prefix = Some(paths.prefix);
-                env.path = format!("{}/bin:/system/bin", prefix);
-                env.ld_library_path = Some(format!("{}/lib", prefix));
-                let termux_exec = format!("{}/lib/libtermux-exec.so", prefix);
-                if PathBuf::from(&termux_exec).is_file() {
-                    env.ld_preload = Some(termux_exec);
-                } else {
-                    log::warn!("libtermux-exec.so not found, using linker-only execution path");
-                }
-                log::info!("Bootstrapped prefix at {}", prefix);
-                application.pty_env = Some(env);
-            }
-            Err(e) => {
-                log::error!("Bootstrap setup failed: {:?}", e);
-            }
-        }
-    } else {
+    let proxy = event_loop.create_proxy();
+    let mut application = App::new(proxy, app.clone());
+    application.run_command = external_command(&app, config.as_ref().map(|(config, _)| config));
+    if let Some(run) = &application.run_command {
+        log::info!("Launched to run {:?}", run);
+    }
+    if let Some((config, path)) = config {
+        application.config = Some(config);
+        application.config_path = Some(path);
+    }
+    if base.is_none() {
         log::warn!("No internal data path available; using defaults");
     }
+    application.data_dir = base;
+    let environment = match &application.run_command {
+        Some(android::RunCommand {
+            environment: Some(name),
+            ..
+        }) if bootstrap::is_environment_name(name) => Some(name.clone()),
+        _ => application
+            .config
+            .as_ref()
+            .map(|c| c.bootstrap_environment.clone()),
+    };
+    application.environment = environment.unwrap_or_default();
 
     log::info!("Starting terminal emulator...");
     let _ = event_loop.run_app(&mut application);
@@ -109,140 +208,180 @@ fn android_main(app: AndroidApp) {
 struct App {
     state: Option<AppState>,
     event_proxy: EventLoopProxy<AppEvent>,
-    threads_running: Arc<AtomicBool>,
+    /// Cleared to stop the running cursor blink timer.
+    blink_timer: Option<Arc<AtomicBool>>,
+    /// Write end of the pipe the PTY reader thread also waits on; dropping
+    /// it stops the thread.
+    reader_wakeup: Option<OwnedFd>,
     pty: Option<Arc<Pty>>,
+    /// Process id of the current shell, for the thread reaping children.
+    shell_pid: Arc<AtomicI32>,
+    /// Bytes the reader thread has queued that the UI hasn't parsed yet.
+    pty_backlog: Arc<AtomicUsize>,
     config: Option<AppConfig>,
+    config_path: Option<PathBuf>,
+    /// The app's internal storage, where the bootstrap goes.
+    data_dir: Option<PathBuf>,
+    /// Named environment the session runs in; empty for the default one.
+    environment: String,
+    /// While the bootstrap is being installed or repaired.
+    installing: bool,
     pty_env: Option<PtyEnv>,
+    /// Command from the launch intent, run instead of the shell.
+    run_command: Option<android::RunCommand>,
+    /// Text or file from the share sheet, for the shell once it starts.
+    shared: Option<android::Shared>,
+    /// Held while a job runs, if `[shell] wake_lock` allows.
+    wake_lock: Option<android::WakeLock>,
+    /// Whether the keep-alive service is running for a job, as `[shell]
+    /// keep_alive` asks.
+    keep_alive: bool,
+    /// Name of the job in the foreground, while there is one.
+    job: Option<String>,
+    /// Between resumed and suspended; otherwise events worth knowing
+    /// about are posted as notifications.
+    visible: bool,
+    android_app: AndroidApp,
 }
 
 impl App {
-    fn new(proxy: EventLoopProxy<AppEvent>) -> Self {
+    fn new(proxy: EventLoopProxy<AppEvent>, android_app: AndroidApp) -> Self {
         Self {
             state: None,
             event_proxy: proxy,
-            threads_running: Arc::new(AtomicBool::new(false)),
+            blink_timer: None,
+            reader_wakeup: None,
             pty: None,
+            shell_pid: Arc::new(AtomicI32::new(0)),
+            pty_backlog: Arc::new(AtomicUsize::new(0)),
             config: None,
+            config_path: None,
+            data_dir: None,
+            environment: String::new(),
+            installing: false,
             pty_env: None,
+            run_command: None,
+            shared: None,
+            wake_lock: None,
+            keep_alive: false,
+            job: None,
+            visible: false,
+            android_app,
         }
     }
 
-    fn start_background_threads(&mut self, rows: u16, cols: u16) {
-        if self.threads_running.swap(true, Ordering::SeqCst) {
+    fn system_dark(&self) -> bool {
+        self.android_app.config().ui_mode_night() == UiModeNight::Yes
+    }
+
+    /// Start the shell and the threads that pump its output and wait for
+    /// it, once. They outlive the window: the session keeps running while
+    /// the activity is in the background.
+    fn start_session(&mut self, rows: u16, cols: u16) {
+        if self.pty.is_some() || self.installing {
             return;
         }
 
-        let env = self.pty_env.clone().unwrap_or_else(PtyEnv::system_default);
-        let shell = env
-            .prefix
-            .as_ref()
-            .and_then(|p| {
-                let bash = p.join("bin/bash");
-                if bash.is_file() {
-                    return Some(bash);
-                }
-                let sh = p.join("bin/sh");
-                if sh.is_file() {
-                    return Some(sh);
-                }
-                None
-            })
-            .unwrap_or_else(|| PathBuf::from(DEFAULT_SHELL));
-        let shell = shell.to_string_lossy().to_string();
-        log::info!("Launching PTY shell: {}", shell);
+        let mut env = self.session_env();
+        let default_config = AppConfig::default();
+        let config = self.config.as_ref().unwrap_or(&default_config);
+        if config.restore_cwd {
+            if let Some(cwd) = self.last_cwd_path().as_deref().and_then(read_last_cwd) {
+                log::info!("Starting in last directory {:?}", cwd);
+                env.cwd = Some(cwd);
+            }
+        }
+        let run = self.run_command.take();
+        let mut shell = match &run {
+            Some(run) => external_shell_command(run, &mut env),
+            None => shell_command(config, &env),
+        };
+        shell.program = bootstrap::resolve_staged(Path::new(&shell.program))
+            .to_string_lossy()
+            .into_owned();
+        log::info!("Launching PTY shell: {:?}", shell);
 
         match Pty::spawn(&shell, rows, cols, &env) {
             Ok(pty) => {
                 log::info!("PTY spawned successfully");
+                if run.is_some_and(|run| run.background) {
+                    android::move_to_background(&self.android_app);
+                }
+                if let Some(shared) = self.shared.take() {
+                    let _ = pty.write(&shared_input(&shared, &config.share_command));
+                }
                 let pty = Arc::new(pty);
                 self.pty = Some(pty.clone());
 
+                // The reader blocks until there is output; closing the other
+                // end of this pipe wakes it up to stop.
+                let wakeup = match pipe2(OFlag::O_CLOEXEC) {
+                    Ok((rx, tx)) => {
+                        self.reader_wakeup = Some(tx);
+                        rx
+                    }
+                    Err(e) => {
+                        log::error!("Failed to create reader wakeup pipe: {:?}", e);
+                        return;
+                    }
+                };
                 let proxy = self.event_proxy.clone();
-                let running = self.threads_running.clone();
                 let pty_reader = pty.clone();
+                let backlog = self.pty_backlog.clone();
                 std::thread::spawn(move || {
-                    use nix::sys::epoll::{
-                        epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent,
-                        EpollFlags, EpollOp,
-                    };
-
                     log::info!("PTY reader thread started");
+                    read_pty(&pty_reader, wakeup, |data| {
+                        backlog.fetch_add(data.len(), Ordering::Relaxed);
+                        let _ = proxy.send_event(AppEvent::PtyOutput(data.to_vec()));
+                    });
+                    log::info!("PTY reader thread stopped");
+                });
 
-                    let epoll_fd = match epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC) {
-                        Ok(fd) => fd,
-                        Err(e) => {
-                            log::error!("Failed to create epoll: {:?}", e);
-                            return;
-                        }
-                    };
-
-                    let epoll_fd = epoll_fd;
-
-                    let mut event = EpollEvent::new(
-                        EpollFlags::EPOLLIN | EpollFlags::EPOLLET | EpollFlags::EPOLLERR,
-                        pty_reader.master_fd() as u64,
-                    );
-
-                    if let Err(e) = epoll_ctl(
-                        epoll_fd,
-                        EpollOp::EpollCtlAdd,
-                        pty_reader.master_fd(),
-                        &mut event,
-                    ) {
-                        log::error!("Failed to register epoll: {:?}", e);
-                        let _ = nix::unistd::close(epoll_fd);
-                        return;
-                    }
-
-                    let mut buf = [0u8; 4096];
-                    let mut events = [EpollEvent::empty(); 8];
-                    while running.load(Ordering::SeqCst) {
-                        let ready = match epoll_wait(epoll_fd, &mut events, -1) {
-                            Ok(n) => n,
-                            Err(e) => {
-                                log::error!("Epoll wait error: {:?}", e);
-                                let _ = nix::unistd::close(epoll_fd);
-                                break;
-                            }
-                        };
-
-                        for _ in events.iter().take(ready) {
-                            loop {
-                                match pty_reader.read(&mut buf) {
-                                    Ok(0) => break,
-                                    Ok(n) => {
-                                        let data = buf[..n].to_vec();
-                                        let _ = proxy.send_event(AppEvent::PtyOutput(data));
-                                    }
-                                    Err(e) => {
-                                        if e.kind() == std::io::ErrorKind::WouldBlock {
-                                            break;
-                                        }
-                                        log::error!("PTY read error: {:?}", e);
-                                        let _ = nix::unistd::close(epoll_fd);
-                                        return;
-                                    }
-                                }
-                            }
+                let proxy = self.event_proxy.clone();
+                let watched = Arc::downgrade(&pty);
+                std::thread::spawn(move || {
+                    let mut running = false;
+                    while let Some(pty) = watched.upgrade() {
+                        let job = pty
+                            .foreground_pgrp()
+                            .is_some_and(|pgrp| pgrp != pty.child_pid());
+                        drop(pty);
+                        if job != running {
+                            running = job;
+                            let _ = proxy.send_event(AppEvent::JobRunning(job));
                         }
+                        std::thread::sleep(JOB_POLL);
                     }
-                    let _ = nix::unistd::close(epoll_fd);
-                    log::info!("PTY reader thread stopped");
                 });
 
-                // Exit only when the spawned shell process actually terminates.
+                // Reap the shell and the jobs it orphans, leaving children
+                // others wait for, and exit only when there are none left. A
+                // shell from before a reset may still be running this loop,
+                // so the current one is looked up on each exit.
                 let proxy = self.event_proxy.clone();
-                let child = pty.child_pid();
+                self.shell_pid
+                    .store(pty.child_pid().as_raw(), Ordering::SeqCst);
+                let shell_pid = self.shell_pid.clone();
                 std::thread::spawn(move || {
-                    use nix::sys::wait::waitpid;
-
-                    match waitpid(child, None) {
-                        Ok(status) => {
-                            log::info!("PTY child {} exited: {:?}", child, status);
-                            let _ = proxy.send_event(AppEvent::PtyExit);
-                        }
-                        Err(e) => {
-                            log::warn!("waitpid({}) failed: {:?}", child, e);
+                    use nix::unistd::Pid;
+
+                    loop {
+                        let result = core::children::wait_unclaimed();
+                        let shell = Pid::from_raw(shell_pid.load(Ordering::SeqCst));
+                        match result {
+                            Ok(status) if status.pid() == Some(shell) => {
+                                log::info!("PTY child {} exited: {:?}", shell, status);
+                                let _ = proxy.send_event(AppEvent::PtyExit);
+                            }
+                            Ok(status) => {
+                                log::info!("Reaped orphaned child: {:?}", status);
+                            }
+                            Err(Errno::EINTR) => {}
+                            Err(Errno::ECHILD) => break,
+                            Err(e) => {
+                                log::warn!("waitpid failed: {:?}", e);
+                                break;
+                            }
                         }
                     }
                 });
@@ -251,9 +390,72 @@ impl App {
                 log::error!("Failed to spawn PTY: {:?}", e);
             }
         }
+    }
+
+    /// What programs the session starts are run with: the bootstrap's
+    /// environment, the control socket and `[env]`.
+    fn session_env(&self) -> PtyEnv {
+        let mut env = self.pty_env.clone().unwrap_or_else(PtyEnv::system_default);
+        let default_config = AppConfig::default();
+        let config = self.config.as_ref().unwrap_or(&default_config);
+        env.flow_control = config.flow_control;
+        if let Some(data_dir) = &self.data_dir {
+            let socket = control::socket_path(data_dir);
+            env.vars.push((
+                control::SOCKET_ENV.to_string(),
+                socket.to_string_lossy().into_owned(),
+            ));
+        }
+        env.vars.extend(config.env.iter().cloned());
+        env
+    }
 
+    /// Run `run` in a PTY of its own beside the session, which carries on
+    /// undisturbed. Nobody sees its output; it is drained until the
+    /// command exits.
+    fn run_detached(&self, run: &android::RunCommand, rows: u16, cols: u16) {
+        let mut env = self.session_env();
+        let mut command = external_shell_command(run, &mut env);
+        command.program = bootstrap::resolve_staged(Path::new(&command.program))
+            .to_string_lossy()
+            .into_owned();
+        // Waited for below, not by the session's reaper.
+        let spawned = pipe2(OFlag::O_CLOEXEC)
+            .map_err(std::io::Error::from)
+            .and_then(|wakeup| {
+                let pty = core::children::claim(
+                    || Pty::spawn(&command, rows, cols, &env),
+                    Pty::child_pid,
+                )?;
+                Ok((pty, wakeup))
+            });
+        let (pty, (wakeup, wakeup_tx)) = match spawned {
+            Ok(spawned) => spawned,
+            Err(e) => {
+                log::error!("Failed to run {:?}: {:?}", run, e);
+                return;
+            }
+        };
+        std::thread::spawn(move || {
+            read_pty(&pty, wakeup, |_| {});
+            drop(wakeup_tx);
+            // Hang up before reaping, while the pid is still its own.
+            let pid = pty.child_pid();
+            drop(pty);
+            let status = nix::sys::wait::waitpid(pid, None);
+            core::children::release(pid);
+            log::info!("Detached command {} exited: {:?}", pid, status);
+        });
+    }
+
+    /// Tick the cursor blink while the activity is visible.
+    fn start_blink_timer(&mut self) {
+        if self.blink_timer.is_some() {
+            return;
+        }
+        let running = Arc::new(AtomicBool::new(true));
+        self.blink_timer = Some(running.clone());
         let proxy = self.event_proxy.clone();
-        let running = self.threads_running.clone();
         std::thread::spawn(move || {
             log::info!("Cursor blink timer started");
             while running.load(Ordering::SeqCst) {
@@ -266,204 +468,1622 @@ impl App {
         });
     }
 
+    fn stop_blink_timer(&mut self) {
+        if let Some(running) = self.blink_timer.take() {
+            running.store(false, Ordering::SeqCst);
+        }
+    }
+
+    /// Tell the event loop whenever the config file is written. The
+    /// directory is watched rather than the file, since editors save by
+    /// renaming a new file over it.
+    fn watch_config(&self) {
+        let Some(path) = self.config_path.clone() else {
+            return;
+        };
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return;
+        };
+        let inotify = match Inotify::init(InitFlags::IN_CLOEXEC).and_then(|inotify| {
+            inotify.add_watch(
+                dir,
+                AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO,
+            )?;
+            Ok(inotify)
+        }) {
+            Ok(inotify) => inotify,
+            Err(e) => {
+                log::warn!("Failed to watch {:?}: {:?}", path, e);
+                return;
+            }
+        };
+        let name = name.to_os_string();
+        let proxy = self.event_proxy.clone();
+        std::thread::spawn(move || loop {
+            match inotify.read_events() {
+                Ok(events) => {
+                    if events.iter().any(|e| e.name.as_ref() == Some(&name)) {
+                        let _ = proxy.send_event(AppEvent::ConfigChanged);
+                    }
+                }
+                Err(Errno::EINTR) => {}
+                Err(e) => {
+                    log::warn!("Stopped watching {:?}: {:?}", path, e);
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Keep each environment's resolv.conf following the system's DNS
+    /// servers as the device moves between networks.
+    fn watch_network(&self) {
+        let Some(base) = self.data_dir.clone() else {
+            return;
+        };
+        let app = self.android_app.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(NETWORK_POLL);
+            let dns = android::dns_servers(&app);
+            let named = bootstrap::environments(&base);
+            let bases = std::iter::once(base.clone()).chain(
+                named
+                    .iter()
+                    .map(|name| bootstrap::environment_base(&base, name)),
+            );
+            for base in bases {
+                if let Err(e) = bootstrap::update_network_config(&base, &dns) {
+                    log::warn!("Failed to update network configuration: {:?}", e);
+                }
+            }
+        });
+    }
+
+    /// Act on an intent handed to the running activity: run a
+    /// RUN_COMMAND's command in a PTY of its own if it asks to stay in the
+    /// background, or else type it at the session's prompt, as a share
+    /// is. A command is refused rather than typed into a running job.
+    fn handle_new_intent(&mut self) {
+        let run = external_command(&self.android_app, self.config.as_ref());
+        let shared = if run.is_none() {
+            let shared_dir = self.home().join(SHARED_DIR);
+            android::shared_content(&self.android_app, &shared_dir)
+        } else {
+            None
+        };
+        let Some(pty) = &self.pty else {
+            // The session hasn't started; the command runs in place of
+            // the shell, and the share is typed once it is up.
+            if run.is_some() {
+                self.run_command = run;
+            }
+            if shared.is_some() {
+                self.shared = shared;
+            }
+            return;
+        };
+        if let Some(run) = run {
+            log::info!("Asked to run {:?}", run);
+            let environment = run
+                .environment
+                .as_deref()
+                .filter(|name| bootstrap::is_environment_name(name));
+            if environment.is_some_and(|name| name != self.environment) {
+                log::warn!(
+                    "Ignoring {:?}: the session runs in environment {:?}",
+                    run,
+                    self.environment
+                );
+            } else if run.background {
+                let (rows, cols) = self
+                    .state
+                    .as_ref()
+                    .map_or((24, 80), |state| (state.rows(), state.cols()));
+                self.run_detached(&run, rows, cols);
+                android::move_to_background(&self.android_app);
+            } else if pty.foreground_pgrp() != Some(pty.child_pid()) {
+                log::warn!("Ignoring {:?}: a job is in the foreground", run);
+            } else if let Some(input) = run_command_input(&run) {
+                let _ = pty.write(&input);
+            } else {
+                log::warn!("Ignoring {:?}: it has control characters", run);
+            }
+        } else if let Some(shared) = shared {
+            let share_command = self
+                .config
+                .as_ref()
+                .map_or("", |config| config.share_command.as_str());
+            let _ = pty.write(&shared_input(&shared, share_command));
+        }
+    }
+
+    /// Listen for the prefix's helpers, which ask for what takes an app,
+    /// like opening a file in another one.
+    fn serve_control(&self) {
+        let Some(data_dir) = &self.data_dir else {
+            return;
+        };
+        let socket = control::socket_path(data_dir);
+        if let Err(e) = control::serve(self.android_app.clone(), &socket) {
+            log::warn!("Failed to start control socket: {:?}", e);
+        }
+    }
+
+    /// Install the bootstrap if it isn't already, first erasing it if
+    /// `reset`, on a thread of its own so the window can show progress
+    /// meanwhile. The session starts once it is done.
+    fn install_bootstrap(&mut self, reset: bool) {
+        let Some(base) = self.base() else {
+            self.bootstrap_done(None);
+            return;
+        };
+        self.installing = true;
+        let config = self.config.clone().unwrap_or_default();
+        let app = self.android_app.clone();
+        let proxy = self.event_proxy.clone();
+        std::thread::spawn(move || {
+            let mut progress = |progress| {
+                let _ = proxy.send_event(AppEvent::BootstrapProgress(progress));
+            };
+            if reset {
+                if let Err(e) = bootstrap::reset_prefix(&base) {
+                    log::error!("Bootstrap reset failed: {:?}", e);
+                }
+            }
+            let source = bootstrap_source(&config);
+            let env = match setup_bootstrap_if_needed(&base, &app, &source, &mut progress) {
+                Ok(paths) => {
+                    if paths.installed && !paths.home.join(".hushlogin").exists() {
+                        let _ = proxy.send_event(AppEvent::Welcome);
+                    }
+                    Some(bootstrap_env(paths))
+                }
+                Err(e) => {
+                    log::error!("Bootstrap setup failed: {:?}", e);
+                    None
+                }
+            };
+            let installed = env.is_some();
+            let _ = proxy.send_event(AppEvent::BootstrapDone(env));
+            // Meanwhile the session starts with the library it has.
+            if installed && !config.termux_exec_url.is_empty() {
+                if let Err(e) = bootstrap::update_termux_exec(
+                    &app,
+                    &base,
+                    &config.termux_exec_url,
+                    config.termux_exec_version,
+                    &config.termux_exec_sha256,
+                ) {
+                    log::warn!("Failed to update termux-exec: {:?}", e);
+                }
+            }
+        });
+    }
+
+    /// Start the session, in the bootstrap's environment if it installed.
+    fn bootstrap_done(&mut self, env: Option<PtyEnv>) {
+        self.installing = false;
+        self.pty_env = env;
+        self.apply_mirror();
+        let home = self.home();
+        let Some(state) = &mut self.state else {
+            return;
+        };
+        state.installing = None;
+        state.home = home;
+        state.environment = self.environment.clone();
+        state.window.request_redraw();
+        let (rows, cols) = (state.rows(), state.cols());
+        self.start_session(rows, cols);
+    }
+
+    /// Put back what has gone missing from the bootstrap (Ctrl+Shift+R),
+    /// in the background like installing it.
+    fn repair_bootstrap(&mut self) {
+        let Some(base) = self.base() else {
+            return;
+        };
+        if self.installing {
+            return;
+        }
+        self.installing = true;
+        let config = self.config.clone().unwrap_or_default();
+        let app = self.android_app.clone();
+        let proxy = self.event_proxy.clone();
+        std::thread::spawn(move || {
+            let mut progress = |progress| {
+                let _ = proxy.send_event(AppEvent::BootstrapProgress(progress));
+            };
+            let source = bootstrap_source(&config);
+            if let Err(e) = bootstrap::repair_prefix(&base, &app, &source, &mut progress) {
+                log::error!("Bootstrap repair failed: {:?}", e);
+            }
+            let _ = proxy.send_event(AppEvent::RepairDone);
+        });
+    }
+
+    /// Erase the bootstrap and install it afresh, once confirmed from the
+    /// menu Ctrl+Shift+B opens. The shell is hung up first, since its
+    /// programs are about to go, and a new one starts in the new prefix.
+    fn reset_bootstrap(&mut self) {
+        if self.data_dir.is_none() || self.installing {
+            return;
+        }
+        log::warn!("Resetting the bootstrap");
+        self.end_session();
+        self.install_bootstrap(true);
+    }
+
+    /// Hang up the shell, remembering its directory, so another can start
+    /// once the bootstrap is ready.
+    fn end_session(&mut self) {
+        self.save_cwd();
+        // The reader thread holds the last other reference to the PTY, and
+        // hangs up the shell when it lets go. Its exit is expected then.
+        self.reader_wakeup = None;
+        self.pty = None;
+        self.job = None;
+        if let Some(lock) = self.wake_lock.take() {
+            lock.release(&self.android_app);
+        }
+        self.stop_keep_alive();
+    }
+
+    /// Move the session to environment `name`, installing it first if it
+    /// is new.
+    fn switch_environment(&mut self, name: &str) {
+        if name == self.environment || self.data_dir.is_none() {
+            return;
+        }
+        if self.installing {
+            log::warn!("Not switching to environment {:?} during an install", name);
+            return;
+        }
+        log::info!("Switching to environment {:?}", name);
+        self.end_session();
+        self.environment = name.to_string();
+        self.install_bootstrap(false);
+    }
+
+    /// Delete the named environment the session runs in, once confirmed
+    /// from the menu Ctrl+Shift+B opens, and carry on in the default one.
+    fn remove_environment(&mut self) {
+        let Some(base) = self.data_dir.clone() else {
+            return;
+        };
+        if self.environment.is_empty() || self.installing {
+            return;
+        }
+        self.end_session();
+        let name = std::mem::take(&mut self.environment);
+        if let Err(e) = bootstrap::remove_environment(&base, &name) {
+            log::error!("Failed to remove environment {:?}: {:?}", name, e);
+        }
+        if self
+            .config
+            .as_ref()
+            .is_some_and(|config| config.bootstrap_environment == name)
+        {
+            log::warn!(
+                "[bootstrap] environment still names {:?}; it is reinstalled next launch",
+                name
+            );
+        }
+        self.install_bootstrap(false);
+    }
+
+    /// Measure the bootstrap's disk usage in the background (Ctrl+Shift+U)
+    /// and show it, after cleaning apt's cache if `clean_apt`.
+    fn measure_disk_usage(&mut self, clean_apt: bool) {
+        let Some(base) = self.base() else {
+            return;
+        };
+        let proxy = self.event_proxy.clone();
+        std::thread::spawn(move || {
+            if clean_apt {
+                match bootstrap::clean_apt_cache(&base) {
+                    Ok(freed) => log::info!("Cleaned apt cache, {} bytes freed", freed),
+                    Err(e) => log::warn!("Failed to clean apt cache: {:?}", e),
+                }
+            }
+            let _ = proxy.send_event(AppEvent::DiskUsage(DiskUsage::measure(&base)));
+        });
+    }
+
+    /// Link shared storage under ~/storage (Ctrl+Shift+S), asking for
+    /// access first if we lack it. The links are made either way, and
+    /// work once the user grants it.
+    fn setup_storage(&self) {
+        android::request_storage_access(&self.android_app);
+        let dirs = android::shared_storage_dirs(&self.android_app);
+        if let Err(e) = bootstrap::link_storage(&self.home(), &dirs) {
+            log::warn!("Failed to link shared storage: {:?}", e);
+        }
+    }
+
+    /// Follow the settings the session just re-read from the config file
+    /// that aren't its own.
+    fn apply_reloaded_config(&mut self) {
+        let Some(state) = &self.state else {
+            return;
+        };
+        let mirror = state.config.bootstrap_mirror.clone();
+        let environment = state.config.bootstrap_environment.clone();
+        self.update_mirror(&mirror);
+        self.update_environment(&environment);
+    }
+
+    /// Follow a change to `[bootstrap] mirror` in the config file.
+    fn update_mirror(&mut self, mirror: &str) {
+        let config = self.config.get_or_insert_with(AppConfig::default);
+        if config.bootstrap_mirror == mirror {
+            return;
+        }
+        config.bootstrap_mirror = mirror.to_string();
+        // An install or repair under way sets it once done.
+        if !self.installing {
+            self.apply_mirror();
+        }
+    }
+
+    /// Follow a change to `[bootstrap] environment` in the config file.
+    fn update_environment(&mut self, environment: &str) {
+        let config = self.config.get_or_insert_with(AppConfig::default);
+        if config.bootstrap_environment == environment {
+            return;
+        }
+        config.bootstrap_environment = environment.to_string();
+        self.switch_environment(environment);
+    }
+
+    /// Point apt at `[bootstrap] mirror`, if there is a bootstrap.
+    fn apply_mirror(&self) {
+        let (Some(base), Some(config)) = (self.base(), &self.config) else {
+            return;
+        };
+        if let Err(e) = bootstrap::set_mirror(&base, &config.bootstrap_mirror) {
+            log::warn!("Failed to set package mirror: {:?}", e);
+        }
+    }
+
+    /// Where the session's environment is installed, if there is
+    /// internal storage for it.
+    fn base(&self) -> Option<PathBuf> {
+        let base = self.data_dir.as_deref()?;
+        Some(bootstrap::environment_base(base, &self.environment))
+    }
+
+    /// $HOME for the shell, once the bootstrap is installed.
+    fn home(&self) -> PathBuf {
+        match (&self.pty_env, self.base()) {
+            (Some(env), _) => env.home.clone(),
+            (None, Some(base)) => bootstrap::home_dir(&base),
+            (None, None) => PtyEnv::system_default().home,
+        }
+    }
+
+    /// Where the shell's directory is remembered between sessions, one per
+    /// environment.
+    fn last_cwd_path(&self) -> Option<PathBuf> {
+        self.base().map(|base| last_cwd_path(&base))
+    }
+
+    /// Remember the shell's directory for the next session, if
+    /// `[shell] restore_cwd` is set.
+    fn save_cwd(&self) {
+        let (Some(state), Some(path)) = (&self.state, self.last_cwd_path()) else {
+            return;
+        };
+        if !state.config.restore_cwd {
+            return;
+        }
+        let cwd = state.term.cwd.clone().or_else(|| self.pty.as_ref()?.cwd());
+        if let Some(cwd) = cwd {
+            if let Err(e) = fs::write(&path, cwd.as_os_str().as_bytes()) {
+                log::warn!("Failed to save last directory to {:?}: {:?}", path, e);
+            }
+        }
+    }
+
+    /// Stop everything, on the way out.
     fn stop_background_threads(&mut self) {
-        self.threads_running.store(false, Ordering::SeqCst);
+        self.stop_blink_timer();
+        self.reader_wakeup = None;
+        if let Some(lock) = self.wake_lock.take() {
+            lock.release(&self.android_app);
+        }
+        self.stop_keep_alive();
+    }
+
+    /// Stop the keep-alive service, if a job started it.
+    fn stop_keep_alive(&mut self) {
+        if std::mem::take(&mut self.keep_alive) {
+            android::stop_keep_alive(&self.android_app);
+        }
     }
 }
 
+/// Everything on screen. The terminal and its scrollback outlive the
+/// window's surface: only `backend` (and the renderer's cached grid) go
+/// with it.
 struct AppState {
     window: Window,
-    #[allow(dead_code)]
-    gl_config: Config,
-    gl_context: PossiblyCurrentContext,
-    gl_surface: GlutinSurface<WindowSurface>,
-    gr_context: skia_safe::gpu::DirectContext,
-    skia_surface: Surface,
+    /// None while the activity has no native window to draw on.
+    backend: Option<Backend>,
+    android_app: AndroidApp,
 
     term: Term,
     renderer: Renderer,
     parser: Parser,
     config: AppConfig,
+    /// Watched for theme changes while running.
+    config_path: Option<PathBuf>,
+    config_mtime: Option<SystemTime>,
+    system_dark: bool,
+    /// The session's $HOME, which relative transcript paths are under.
+    home: PathBuf,
+    /// Named environment the session runs in; empty for the default one.
+    environment: String,
+    /// Shown in place of the terminal while the bootstrap installs.
+    installing: Option<Progress>,
+    /// Present while the session is being recorded.
+    transcript: Option<Transcript>,
+
+    /// Device pixels per density-independent point.
+    scale_factor: f64,
+    /// Current font size in points; differs from the config after zooming.
+    font_size: f32,
+    /// Display the window is on, as of [`AppState::follow_display`].
+    display: Option<android::Display>,
+    /// Font size last used on each display left this session, by id, so
+    /// zooming on the monitor and then undocking doesn't carry over.
+    display_font_sizes: HashMap<i32, f32>,
+    /// Time between frames on the display, to pace animations by.
+    frame_interval: Duration,
+    /// When the next frame of an animation is due.
+    next_frame: Option<Instant>,
 
     cursor_visible: bool,
     last_input: Instant,
+    focused: bool,
+    /// Shown in the recent apps list; see [`AppState::update_title`].
+    title: String,
+    /// Foreground command and its directory, as last read from /proc.
+    job: Option<(String, Option<PathBuf>)>,
+    /// Present while the debug overlay is shown.
+    debug: Option<DebugStats>,
+    scroll: SmoothScroll,
+    touch: TouchTracker,
+    /// Whether we last asked for the soft keyboard to be shown.
+    keyboard_visible: bool,
+    /// Touch id dragging a selection handle.
+    handle_drag: Option<u64>,
+    /// CTRL/ALT latched on the extra keys row.
+    sticky: StickyModifiers,
+    /// Last seen bottom of the area the soft keyboard leaves uncovered.
+    last_visible_bottom: f32,
+    /// When the window, last resized, counts as settled and the grid is
+    /// fitted to it. Split-screen and freeform windows resize many times
+    /// in a row, and each regrid reflows the text and tells the program.
+    regrid_at: Option<Instant>,
+    /// How far in from each edge the system bars and display cutout
+    /// reach, as of the last [`AppState::regrid`].
+    safe_area: android::Insets,
+    /// Part of a drag or wheel scroll on the alternate screen not yet sent
+    /// as an arrow key, in lines.
+    drag_lines: f32,
+    menu: Option<ContextMenu>,
+    /// A menu action that needs more than the window state, left for the
+    /// app to carry out.
+    app_action: Option<MenuAction>,
+    /// Disk usage report shown along with the menu, once measured.
+    disk_usage: Option<Vec<String>>,
+    /// Where the mouse pointer is, while one is over the window.
+    pointer: Option<Point>,
+    /// Mouse button held down on the grid, if any.
+    mouse_button: Option<u8>,
+    /// Cell of the last mouse report, or where a click-drag selection
+    /// would start.
+    mouse_cell: (usize, usize),
+    /// Wheel notches not yet reported to the application.
+    wheel_notches: f32,
 
     ctrl_pressed: bool,
     shift_pressed: bool,
+    /// Left Alt only; Right Alt is AltGr on most non-US layouts and
+    /// already shows in the text the layout produces.
+    alt_pressed: bool,
+    /// Caps Lock as toggled by key presses since we started.
+    caps_lock: bool,
+    /// Accent from a dead key, waiting for the character it goes on.
+    dead_key: Option<char>,
+    /// Volume keys held, when `[keyboard] volume_keys` claims them.
+    volume_down_pressed: bool,
+    volume_up_pressed: bool,
 }
 
 impl AppState {
-    fn init(event_loop: &ActiveEventLoop, config: AppConfig) -> Self {
-        let template = ConfigTemplateBuilder::new()
-            .with_alpha_size(8)
-            .with_depth_size(0)
-            .with_stencil_size(8);
+    fn init(
+        event_loop: &ActiveEventLoop,
+        android_app: AndroidApp,
+        mut config: AppConfig,
+        config_path: Option<PathBuf>,
+        system_dark: bool,
+        home: PathBuf,
+    ) -> Self {
+        let (window, backend) = Backend::create(event_loop, &config);
+        let debug = config.debug_overlay.then(DebugStats::default);
+        let size = window.inner_size();
 
-        let display_builder =
-            DisplayBuilder::new().with_window_attributes(Some(Window::default_attributes()));
+        let scale_factor = window.scale_factor();
+        let scale = scale_factor as f32;
+        config.resolve_font_size(scale);
+        let display = android::current_display(&android_app);
+        let font_size = display_font_size(&config, display.as_ref());
+        let mut renderer = Renderer::new(
+            font_size * scale,
+            config.active_theme(system_dark),
+            config.font_path.as_deref(),
+            config.ligatures,
+        );
+        renderer.background_opacity = config.background_opacity;
+        renderer.cursor_color = config.cursor_color;
+        renderer.bidi = config.bidi;
+        renderer.set_padding(
+            scaled(config.cell_padding, scale),
+            scaled(config.margin, scale),
+        );
+        let bottom = extra_keys_height(&config, scale);
+        let (cols, rows) = Self::fit_grid(&config, &renderer, size.width, size.height, bottom);
 
-        let (window, gl_config) = display_builder
-            .build(event_loop, template, |mut configs| configs.next().unwrap())
-            .unwrap();
+        log::info!("Terminal size: {}x{} cells", cols, rows);
 
-        let window = window.expect("Failed to create window");
-        let raw_window_handle = window.window_handle().unwrap().as_raw();
+        let term = Self::new_term(&config, cols, rows);
+        let parser = Parser::new();
 
-        let context_attrs = ContextAttributesBuilder::new()
-            .with_context_api(ContextApi::Gles(Some(Version::new(2, 0))))
-            .build(Some(raw_window_handle));
+        let mut state = Self {
+            window,
+            backend,
+            android_app,
+            term,
+            renderer,
+            parser,
+            font_size,
+            frame_interval: frame_interval(display.as_ref()),
+            display,
+            display_font_sizes: HashMap::new(),
+            next_frame: None,
+            config,
+            config_mtime: config_path.as_deref().and_then(modified),
+            config_path,
+            system_dark,
+            home,
+            environment: String::new(),
+            installing: None,
+            transcript: None,
+            scale_factor,
+            cursor_visible: true,
+            last_input: Instant::now(),
+            focused: true,
+            title: String::new(),
+            job: None,
+            debug,
+            scroll: SmoothScroll::default(),
+            touch: TouchTracker::default(),
+            keyboard_visible: false,
+            handle_drag: None,
+            sticky: StickyModifiers::default(),
+            last_visible_bottom: size.height as f32,
+            regrid_at: None,
+            safe_area: android::Insets::default(),
+            drag_lines: 0.0,
+            menu: None,
+            app_action: None,
+            disk_usage: None,
+            pointer: None,
+            mouse_button: None,
+            mouse_cell: (0, 0),
+            wheel_notches: 0.0,
+            ctrl_pressed: false,
+            shift_pressed: false,
+            alt_pressed: false,
+            caps_lock: false,
+            dead_key: None,
+            volume_down_pressed: false,
+            volume_up_pressed: false,
+        };
+        if state.config.transcript {
+            state.toggle_transcript();
+        }
+        // Bars and cutout only show up once the window is laid out.
+        state.regrid(size.width, size.height);
+        state
+    }
 
-        let gl_display = gl_config.display();
+    /// Grid size for a `width`x`height` surface, less the outer margins
+    /// and `bottom` pixels kept for the extra keys, unless the config pins it.
+    fn fit_grid(
+        config: &AppConfig,
+        renderer: &Renderer,
+        width: u32,
+        height: u32,
+        bottom: f32,
+    ) -> (usize, usize) {
+        let width = (width as f32 - 2.0 * renderer.margin.0).max(0.0);
+        let height = (height as f32 - 2.0 * renderer.margin.1 - bottom).max(0.0);
+        let cols = config
+            .grid_cols
+            .unwrap_or((width / renderer.cell_w).floor() as usize)
+            .max(1);
+        let rows = config
+            .grid_rows
+            .unwrap_or((height / renderer.cell_h).floor() as usize)
+            .max(1);
+        (cols, rows)
+    }
+
+    /// Create a Term with the config-driven terminal settings applied.
+    fn new_term(config: &AppConfig, cols: usize, rows: usize) -> Term {
+        let mut term = Term::new(cols, rows);
+        term.ambiguous_wide = config.ambiguous_wide;
+        term.answerback = config.answerback.clone();
+        term.word_separators = config.word_separators.clone();
+        term.cursor_style = config.cursor_style;
+        term.default_cursor_style = config.cursor_style;
+        term
+    }
+
+    fn cols(&self) -> u16 {
+        self.term.cols as u16
+    }
+
+    fn rows(&self) -> u16 {
+        self.term.rows as u16
+    }
 
-        let not_current = unsafe {
-            gl_display
-                .create_context(&gl_config, &context_attrs)
-                .unwrap()
+    /// Create a window and backend for the activity's new native window
+    /// after a resume.
+    fn recreate_surface(&mut self, event_loop: &ActiveEventLoop) {
+        // The cached grid belongs to the old backend's GPU context.
+        self.renderer.drop_cache();
+        let (window, backend) = Backend::create(event_loop, &self.config);
+        self.window = window;
+        self.backend = backend;
+        // A new window comes at its final size; no need to wait it out.
+        let size = self.window.inner_size();
+        self.menu = None;
+        if let Some(backend) = &mut self.backend {
+            backend.resize(size.width, size.height);
+        }
+        self.regrid_at = None;
+        self.regrid(size.width, size.height);
+    }
+
+    /// Let go of the GPU context and the surface before the native window
+    /// they were made for is destroyed. The terminal is kept, and drawn in
+    /// full on the next window.
+    fn release_surface(&mut self) {
+        self.renderer.drop_cache();
+        self.backend = None;
+    }
+
+    /// Follow the window to `width`x`height`: the surface at once, the
+    /// grid once the size settles (see [`Self::regrid_if_settled`]).
+    fn resize(&mut self, width: u32, height: u32) {
+        // Laid out for the old size.
+        self.menu = None;
+        if let Some(backend) = &mut self.backend {
+            backend.resize(width, height);
+        }
+        self.regrid_at = Some(Instant::now() + RESIZE_SETTLE);
+    }
+
+    /// Fit the grid to the window if it has kept its size since the last
+    /// resize for [`RESIZE_SETTLE`]. Returns true if the terminal changed
+    /// size.
+    fn regrid_if_settled(&mut self, now: Instant) -> bool {
+        if self.regrid_at.is_none_or(|at| now < at) {
+            return false;
+        }
+        self.regrid_at = None;
+        self.window.request_redraw();
+        // Moving to another display comes as a resize.
+        if self.follow_display() {
+            self.renderer
+                .set_font_size(self.font_size * self.scale_factor as f32);
+            self.term.mark_dirty();
+        }
+        let size = self.window.inner_size();
+        self.regrid(size.width, size.height)
+    }
+
+    /// Catch up with the window having moved to another display: pace
+    /// frames to its refresh rate and take up the font size it had, or its
+    /// configured one. Returns true if the font size changed; the renderer
+    /// is left to the caller.
+    fn follow_display(&mut self) -> bool {
+        let Some(display) = android::current_display(&self.android_app) else {
+            return false;
+        };
+        if self.display.as_ref() == Some(&display) {
+            return false;
+        }
+        log::info!(
+            "On display {} ({:?}, {} Hz)",
+            display.id,
+            display.name,
+            display.refresh_rate
+        );
+        let id = display.id;
+        self.frame_interval = frame_interval(Some(&display));
+        match self.display.replace(display) {
+            // Only the refresh rate changed.
+            Some(old) if old.id == id => return false,
+            Some(old) => {
+                self.display_font_sizes.insert(old.id, self.font_size);
+            }
+            None => {}
+        }
+        let font_size = match self.display_font_sizes.get(&id) {
+            Some(&size) => size,
+            None => display_font_size(&self.config, self.display.as_ref()),
         };
+        let changed = font_size != self.font_size;
+        self.font_size = font_size;
+        changed
+    }
 
-        let size = window.inner_size();
+    /// Move the window to the next display there is (Ctrl+Shift+M), as
+    /// when docked to a monitor.
+    fn move_to_next_display(&self) {
+        let displays = android::displays(&self.android_app);
+        let current = self
+            .display
+            .as_ref()
+            .map_or(android::DEFAULT_DISPLAY, |d| d.id);
+        let next = displays
+            .iter()
+            .skip_while(|d| d.id != current)
+            .nth(1)
+            .or(displays.first());
+        match next {
+            Some(next) if next.id != current => {
+                log::info!("Moving to display {} ({:?})", next.id, next.name);
+                android::move_to_display(&self.android_app, next.id);
+            }
+            _ => log::info!("No other display to move to"),
+        }
+    }
 
-        let surface_attrs = SurfaceAttributesBuilder::<WindowSurface>::new().build(
-            raw_window_handle,
-            NonZeroU32::new(size.width.max(1)).unwrap(),
-            NonZeroU32::new(size.height.max(1)).unwrap(),
+    /// Fit the grid to `width`x`height` pixels at the current cell size,
+    /// above the soft keyboard if it is up and clear of the system bars
+    /// and display cutout. Returns true if the terminal changed size.
+    fn regrid(&mut self, width: u32, height: u32) -> bool {
+        let safe = android::safe_insets(&self.android_app);
+        self.safe_area = safe;
+        self.renderer
+            .set_insets((safe.left as f32, safe.top as f32));
+        let width = width.saturating_sub((safe.left + safe.right) as u32);
+        let height = height
+            .min(self.visible_bottom() as u32)
+            .saturating_sub(safe.top as u32);
+        let bottom = extra_keys_height(&self.config, self.scale_factor as f32);
+        let (new_cols, new_rows) =
+            Self::fit_grid(&self.config, &self.renderer, width, height, bottom);
+
+        if new_cols == self.term.cols && new_rows == self.term.rows {
+            return false;
+        }
+        log::info!(
+            "Terminal resized: {}x{} -> {}x{}",
+            self.term.cols,
+            self.term.rows,
+            new_cols,
+            new_rows
         );
+        self.term.resize(new_cols, new_rows);
+        true
+    }
+
+    /// Change the font size (in points) live and re-grid the terminal to
+    /// match. Returns true if the grid changed, in which case the PTY needs
+    /// a TIOCSWINSZ.
+    fn set_font_size(&mut self, font_size: f32) -> bool {
+        let font_size = font_size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+        if font_size == self.font_size {
+            return false;
+        }
+        self.font_size = font_size;
+        self.renderer
+            .set_font_size(font_size * self.scale_factor as f32);
+        let size = self.window.inner_size();
+        let changed = self.regrid(size.width, size.height);
+        self.term.mark_dirty();
+        changed
+    }
 
-        let gl_surface = unsafe {
-            gl_display
-                .create_window_surface(&gl_config, &surface_attrs)
-                .unwrap()
+    /// Re-derive pixel metrics after a density change (e.g. moving to an
+    /// external display). Returns true if the grid changed.
+    fn set_scale_factor(&mut self, scale_factor: f64) -> bool {
+        log::info!("Scale factor {} -> {}", self.scale_factor, scale_factor);
+        self.scale_factor = scale_factor;
+        self.follow_display();
+        let scale = scale_factor as f32;
+        self.renderer.set_padding(
+            scaled(self.config.cell_padding, scale),
+            scaled(self.config.margin, scale),
+        );
+        self.renderer.set_font_size(self.font_size * scale);
+        let size = self.window.inner_size();
+        let changed = self.regrid(size.width, size.height);
+        self.term.mark_dirty();
+        changed
+    }
+
+    /// Ctrl+Shift+Plus/Minus zoom the font, Ctrl+Shift+0 restores the
+    /// configured size. Returns Some(grid changed) if the key was consumed.
+    fn handle_zoom_key(&mut self, key: &PhysicalKey) -> Option<bool> {
+        if !(self.ctrl_pressed && self.shift_pressed) {
+            return None;
+        }
+        let size = self.font_size;
+        let new_size = match key {
+            PhysicalKey::Code(KeyCode::Equal) | PhysicalKey::Code(KeyCode::NumpadAdd) => {
+                size + FONT_SIZE_STEP
+            }
+            PhysicalKey::Code(KeyCode::Minus) | PhysicalKey::Code(KeyCode::NumpadSubtract) => {
+                size - FONT_SIZE_STEP
+            }
+            PhysicalKey::Code(KeyCode::Digit0) => {
+                display_font_size(&self.config, self.display.as_ref())
+            }
+            _ => return None,
         };
+        Some(self.set_font_size(new_size))
+    }
 
-        let gl_context = not_current.make_current(&gl_surface).unwrap();
+    fn render(&mut self) {
+        if let Some(progress) = self.installing {
+            self.draw_progress(&progress);
+            return;
+        }
+        let start = Instant::now();
+        let delta = self.scroll.step(start);
+        if delta != 0.0 {
+            self.scroll_lines(delta);
+        }
+        // Laid out before the frame is taken, which borrows the backend.
+        let button = self.keyboard_button();
+        let extra_keys = self.extra_keys_rect().map(|rect| {
+            let keys: Vec<_> = self
+                .extra_key_rects()
+                .map(|(key, rect)| {
+                    let active = match key {
+                        ExtraKey::Ctrl => self.sticky.ctrl,
+                        ExtraKey::Alt => self.sticky.alt,
+                        _ => false,
+                    };
+                    (rect, active)
+                })
+                .collect();
+            (rect, keys)
+        });
+        let Some(backend) = &mut self.backend else {
+            return;
+        };
+        let Some(surface) = backend.frame() else {
+            return;
+        };
+        let repainted = self.renderer.render(
+            surface.canvas(),
+            &mut self.term,
+            self.cursor_visible,
+            self.focused,
+            self.scroll.fraction * self.renderer.cell_h,
+        );
+        self.renderer
+            .draw_overscroll(surface.canvas(), self.scroll.overscroll());
+        let radius = SELECTION_HANDLE_RADIUS * self.scale_factor as f32;
+        self.renderer
+            .draw_selection_handles(surface.canvas(), &self.term, radius);
+        self.renderer
+            .draw_keyboard_button(surface.canvas(), button, self.keyboard_visible);
+        if let Some((rect, keys)) = extra_keys {
+            let labels = self.config.extra_keys.iter().map(ExtraKey::label);
+            let keys = labels
+                .zip(keys)
+                .map(|(label, (rect, active))| (label, rect, active));
+            self.renderer.draw_extra_keys(surface.canvas(), rect, keys);
+        }
+        if let (Some(lines), Some(_)) = (&self.disk_usage, &self.menu) {
+            self.renderer.draw_report(surface.canvas(), lines);
+        }
+        if let Some(menu) = &self.menu {
+            let items = menu
+                .items
+                .iter()
+                .map(|&(action, rect)| (action.label(), rect));
+            self.renderer.draw_menu(surface.canvas(), menu.rect, items);
+        }
+        if let Some(pos) = self.pointer {
+            self.renderer.draw_pointer(surface.canvas(), pos);
+        }
+        if let Some(stats) = self.debug.as_mut() {
+            stats.record_frame(start.elapsed(), repainted);
+            self.renderer.draw_overlay(surface.canvas(), stats);
+        }
+        let damage = self.renderer.take_damage();
+        backend.present(damage.as_deref());
+    }
 
-        gl_surface
-            .set_swap_interval(&gl_context, glutin::surface::SwapInterval::DontWait)
-            .unwrap_or_else(|e| log::warn!("Failed to disable VSync: {:?}", e));
+    /// Show the bootstrap installer's progress in place of the terminal.
+    fn draw_progress(&mut self, progress: &Progress) {
+        let Some(backend) = &mut self.backend else {
+            return;
+        };
+        let Some(surface) = backend.frame() else {
+            return;
+        };
+        self.renderer
+            .draw_progress(surface.canvas(), &progress.status(), progress.fraction());
+        let damage = self.renderer.take_damage();
+        backend.present(damage.as_deref());
+    }
+
+    /// Where the soft keyboard toggle sits: the bottom-right corner, above
+    /// the extra keys.
+    fn keyboard_button(&self) -> Rect {
+        let scale = self.scale_factor as f32;
+        let size = self.window.inner_size();
+        let side = KEYBOARD_BUTTON_SIZE * scale;
+        let inset = KEYBOARD_BUTTON_INSET * scale;
+        let bottom = self
+            .extra_keys_rect()
+            .map_or_else(|| self.visible_bottom(), |rect| rect.top);
+        Rect::from_xywh(
+            (size.width as i32 - self.safe_area.right) as f32 - inset - side,
+            bottom - inset - side,
+            side,
+            side,
+        )
+    }
 
-        gl::load_with(|s| gl_display.get_proc_address(&CString::new(s).unwrap()));
+    /// Bottom of the part of the window the soft keyboard and the
+    /// navigation bar leave uncovered.
+    fn visible_bottom(&self) -> f32 {
+        let height = (self.window.inner_size().height as i32 - self.safe_area.bottom) as f32;
+        let bottom = self.android_app.content_rect().bottom as f32;
+        if bottom > 0.0 {
+            bottom.min(height)
+        } else {
+            height
+        }
+    }
 
-        let interface = skia_safe::gpu::gl::Interface::new_load_with(|s| {
-            gl_display.get_proc_address(&CString::new(s).unwrap())
+    /// The extra keys row, just above the soft keyboard when it is up.
+    fn extra_keys_rect(&self) -> Option<Rect> {
+        let height = extra_keys_height(&self.config, self.scale_factor as f32);
+        if height == 0.0 {
+            return None;
+        }
+        let left = self.safe_area.left as f32;
+        let right = (self.window.inner_size().width as i32 - self.safe_area.right) as f32;
+        let bottom = self.visible_bottom();
+        Some(Rect::from_ltrb(left, bottom - height, right, bottom))
+    }
+
+    /// Each extra key with its share of the row.
+    fn extra_key_rects(&self) -> impl Iterator<Item = (&ExtraKey, Rect)> {
+        let row = self.extra_keys_rect().unwrap_or_default();
+        let keys = &self.config.extra_keys;
+        let key_w = row.width() / keys.len().max(1) as f32;
+        keys.iter().enumerate().map(move |(i, key)| {
+            let left = row.left + i as f32 * key_w;
+            (
+                key,
+                Rect::from_ltrb(left, row.top, left + key_w, row.bottom),
+            )
         })
-        .expect("Failed to create Skia GL interface");
+    }
 
-        let mut gr_context =
-            direct_contexts::make_gl(interface, None).expect("Failed to create Skia DirectContext");
+    fn extra_key_at(&self, pos: Point) -> Option<ExtraKey> {
+        if !self.extra_keys_rect()?.contains(pos) {
+            return None;
+        }
+        self.extra_key_rects()
+            .find(|(_, rect)| rect.contains(pos))
+            .map(|(key, _)| key.clone())
+    }
 
-        let fb_info = FramebufferInfo {
-            fboid: 0,
-            format: skia_safe::gpu::gl::Format::RGBA8.into(),
-            protected: Protected::No,
+    /// Latch a modifier, or send a key with whatever is latched.
+    fn press_extra_key(&mut self, key: ExtraKey, pty: Option<&Pty>) {
+        let bytes = match key {
+            ExtraKey::Ctrl => {
+                self.sticky.ctrl = !self.sticky.ctrl;
+                return;
+            }
+            ExtraKey::Alt => {
+                self.sticky.alt = !self.sticky.alt;
+                return;
+            }
+            ExtraKey::Key(code) => {
+                let key = PhysicalKey::Code(code);
+                let Some(bytes) = Self::keycode_to_bytes(&key, self.term.mode) else {
+                    return;
+                };
+                bytes
+            }
+            ExtraKey::Text(text) => text.into_bytes(),
         };
+        let bytes = self.sticky.apply(&bytes);
+        if let Some(pty) = pty {
+            self.send_input(pty, &bytes);
+        }
+        self.reset_display();
+        self.reset_cursor();
+    }
 
-        let backend_rt =
-            backend_render_targets::make_gl((size.width as i32, size.height as i32), 0, 8, fb_info);
+    /// A short buzz confirming a soft key or long press, unless
+    /// `[keyboard] haptics` is off.
+    fn haptic(&self) {
+        if self.config.haptics > 0 {
+            android::vibrate(&self.android_app, HAPTIC_DURATION, self.config.haptics);
+        }
+    }
 
-        let skia_surface = surfaces::wrap_backend_render_target(
-            &mut gr_context,
-            &backend_rt,
-            SurfaceOrigin::BottomLeft,
-            ColorType::RGBA8888,
-            None,
-            None,
-        )
-        .expect("Failed to create Skia surface");
+    fn set_keyboard_visible(&mut self, visible: bool) {
+        if visible {
+            self.android_app.show_soft_input(true);
+        } else {
+            self.android_app.hide_soft_input(false);
+        }
+        self.keyboard_visible = visible;
+    }
 
-        let renderer = Renderer::new(config.font_size, config.palette);
-        let cols = config
-            .grid_cols
-            .unwrap_or((size.width as f32 / renderer.cell_w).floor() as usize)
-            .max(1);
-        let rows = config
-            .grid_rows
-            .unwrap_or((size.height as f32 / renderer.cell_h).floor() as usize)
-            .max(1);
+    /// The toggle flips the soft keyboard; a tap anywhere else brings it up,
+    /// since the user may have dismissed it with the back gesture. Returns
+    /// true if a redraw is needed.
+    fn handle_touch(&mut self, touch: &Touch, pty: Option<&Pty>) -> bool {
+        let pos = Point::new(touch.location.x as f32, touch.location.y as f32);
+        // A finger on a selection handle drags it instead of gesturing.
+        match (touch.phase, self.handle_drag) {
+            (TouchPhase::Started, None) => {
+                if let Some(start) = self.selection_handle_at(pos) {
+                    self.term.grab_selection_end(start);
+                    self.handle_drag = Some(touch.id);
+                    return self.menu.take().is_some();
+                }
+            }
+            (TouchPhase::Moved, Some(id)) if id == touch.id => {
+                self.extend_selection(pos);
+                return true;
+            }
+            (TouchPhase::Ended | TouchPhase::Cancelled, Some(id)) if id == touch.id => {
+                self.handle_drag = None;
+                self.open_menu(pos);
+                return true;
+            }
+            _ => {}
+        }
 
-        log::info!("Terminal size: {}x{} cells", cols, rows);
+        if touch.phase == TouchPhase::Started {
+            // Catch a fling in progress.
+            self.scroll.halt();
+        }
+        let slop = TOUCH_SLOP * self.scale_factor;
+        match self.touch.handle(touch, slop) {
+            Some(gesture) => self.handle_gesture(gesture, pty),
+            None => false,
+        }
+    }
 
-        let term = Term::new(cols, rows);
-        let parser = Parser::new();
+    /// Returns true if a redraw is needed.
+    fn handle_gesture(&mut self, gesture: Gesture, pty: Option<&Pty>) -> bool {
+        let point = |pos: PhysicalPosition<f64>| Point::new(pos.x as f32, pos.y as f32);
+        match gesture {
+            Gesture::Tap(pos) => {
+                // Any tap closes the menu; one on an entry also runs it.
+                if let Some(menu) = self.menu.take() {
+                    if let Some(action) = menu.action_at(point(pos)) {
+                        self.run_menu_action(action, menu, pty);
+                        return true;
+                    }
+                }
+                if let Some(key) = self.extra_key_at(point(pos)) {
+                    self.haptic();
+                    self.press_extra_key(key, pty);
+                    return true;
+                }
+                if self.keyboard_button().contains(point(pos)) {
+                    self.set_keyboard_visible(!self.keyboard_visible);
+                } else if self.term.selection.is_some() {
+                    self.term.clear_selection();
+                } else {
+                    self.set_keyboard_visible(true);
+                }
+                true
+            }
+            Gesture::LongPress(pos) => {
+                self.haptic();
+                let (x, y) = self.renderer.cell_at(&self.term, point(pos));
+                self.term.select_word(x, y);
+                self.menu = None;
+                true
+            }
+            Gesture::LongPressDrag(pos) => {
+                self.extend_selection(point(pos));
+                true
+            }
+            Gesture::LongPressEnd(pos) => {
+                self.open_menu(point(pos));
+                true
+            }
+            Gesture::Scroll(dy) => self.drag_scroll(dy as f32, pty),
+            Gesture::Fling(velocity) => {
+                let min = MIN_FLING_SPEED * self.scale_factor;
+                // The alternate screen scrolls by arrow keys, which have
+                // no momentum.
+                if velocity.abs() < min || self.term.mode.contains(TermMode::ALTSCREEN) {
+                    return false;
+                }
+                self.scroll.fling(velocity as f32 / self.renderer.cell_h);
+                true
+            }
+        }
+    }
 
-        Self {
-            window,
-            gl_config,
-            gl_context,
-            gl_surface,
-            gr_context,
-            skia_surface,
-            term,
-            renderer,
-            parser,
-            config,
-            cursor_visible: true,
-            last_input: Instant::now(),
-            ctrl_pressed: false,
-            shift_pressed: false,
+    /// Follow a finger dragging the view down by `dy` pixels: back through
+    /// scrollback on the main screen, or as Up/Down arrows on the alternate
+    /// screen, which has no history of its own but usually runs a pager or
+    /// editor that scrolls on arrows. An application there that asked for
+    /// mouse events gets the wheel instead. Returns true if a redraw is
+    /// needed.
+    fn drag_scroll(&mut self, dy: f32, pty: Option<&Pty>) -> bool {
+        let lines = dy / self.renderer.cell_h;
+        if !self.term.mode.contains(TermMode::ALTSCREEN) {
+            self.scroll.halt();
+            self.scroll_lines(lines);
+            return true;
+        }
+        if self.mouse_reporting() {
+            let pos = self.pointer.unwrap_or_default();
+            self.report_wheel(lines / WHEEL_LINES, pos, pty);
+        } else {
+            self.arrow_scroll(lines, pty);
         }
+        false
     }
 
-    fn cols(&self) -> u16 {
-        self.term.cols as u16
+    /// Scroll the alternate screen by sending one Up (positive `lines`) or
+    /// Down arrow per line, keeping the fraction for next time.
+    fn arrow_scroll(&mut self, lines: f32, pty: Option<&Pty>) {
+        self.drag_lines += lines;
+        let whole = self.drag_lines.trunc();
+        self.drag_lines -= whole;
+        if whole != 0.0 {
+            if let Some(pty) = pty {
+                let app_cursor = self.term.mode.contains(TermMode::APPCURSOR);
+                let key = cursor_key(if whole > 0.0 { b'A' } else { b'B' }, app_cursor);
+                let _ = pty.write(&key.repeat(whole.abs() as usize));
+            }
+        }
+    }
+
+    /// Show the context menu for a long press released at `pos`.
+    fn open_menu(&mut self, pos: Point) {
+        let (x, y) = self.renderer.cell_at(&self.term, pos);
+        let url = self.term.url_at(x, y);
+        self.open_menu_with(pos, url);
+    }
+
+    fn open_menu_with(&mut self, anchor: Point, url: Option<String>) {
+        let has_selection = self
+            .term
+            .selection_text()
+            .is_some_and(|text| !text.is_empty());
+        let actions = [
+            (MenuAction::Copy, has_selection),
+            (MenuAction::Paste, true),
+            (MenuAction::SelectAll, true),
+            (MenuAction::OpenUrl, url.is_some()),
+            (
+                MenuAction::Share,
+                has_selection || self.share_text().is_some(),
+            ),
+            (MenuAction::StartTranscript, self.transcript.is_none()),
+            (MenuAction::StopTranscript, self.transcript.is_some()),
+            (MenuAction::Signals, true),
+        ];
+        let actions = actions
+            .into_iter()
+            .filter(|&(_, shown)| shown)
+            .map(|(action, _)| action);
+        self.show_menu(actions, anchor, url);
+    }
+
+    fn show_menu(
+        &mut self,
+        actions: impl IntoIterator<Item = MenuAction>,
+        anchor: Point,
+        url: Option<String>,
+    ) {
+        let items = actions
+            .into_iter()
+            .map(|action| (action, self.renderer.menu_item_size(action.label())))
+            .collect();
+        let size = self.window.inner_size();
+        let bounds = Rect::from_wh(size.width as f32, size.height as f32);
+        let gap = MENU_GAP * self.scale_factor as f32;
+        self.menu = Some(ContextMenu::new(items, url, anchor, gap, bounds));
+        self.disk_usage = None;
+    }
+
+    /// Show how much space the bootstrap takes, with the menu to clean
+    /// apt's cache; a tap anywhere else closes both.
+    fn show_disk_usage(&mut self, usage: &DiskUsage) {
+        let size = self.window.inner_size();
+        let center = Point::new(size.width as f32 / 2.0, size.height as f32 / 2.0);
+        self.show_menu([MenuAction::CleanAptCache], center, None);
+        self.disk_usage = Some(usage.report());
+    }
+
+    fn run_menu_action(&mut self, action: MenuAction, menu: ContextMenu, pty: Option<&Pty>) {
+        match action {
+            MenuAction::Copy => {
+                self.copy_selection();
+                self.term.clear_selection();
+            }
+            MenuAction::Paste => {
+                if let (Some(bytes), Some(pty)) = (self.paste_bytes(), pty) {
+                    self.send_input(pty, &bytes);
+                }
+                self.term.clear_selection();
+            }
+            MenuAction::SelectAll => {
+                self.term.select_all();
+                // Selecting is rarely the last step; offer Copy next.
+                self.open_menu_with(menu.anchor, menu.url);
+            }
+            MenuAction::OpenUrl => {
+                if let Some(url) = &menu.url {
+                    android::open_url(&self.android_app, url);
+                }
+                self.term.clear_selection();
+            }
+            MenuAction::Share => {
+                if let Some(text) = self.share_text() {
+                    android::share_text(&self.android_app, &text);
+                }
+                self.term.clear_selection();
+            }
+            MenuAction::StartTranscript | MenuAction::StopTranscript => {
+                self.toggle_transcript();
+            }
+            MenuAction::Signals => {
+                let signals = SIGNALS.into_iter().map(MenuAction::Signal);
+                self.show_menu(signals, menu.anchor, None);
+            }
+            MenuAction::Signal(signal) => {
+                if let Some(Err(e)) = pty.map(|pty| pty.signal_foreground(signal)) {
+                    log::warn!("Failed to send {}: {:?}", signal, e);
+                }
+            }
+            MenuAction::ResetBootstrap
+            | MenuAction::RemoveEnvironment
+            | MenuAction::CleanAptCache => {
+                self.app_action = Some(action);
+            }
+        }
+    }
+
+    /// The back button or gesture, as `[keyboard] back` says. None of the
+    /// choices end the session. A shown soft keyboard takes the back press
+    /// itself, so this usually only sees it once the keyboard is down.
+    fn handle_back(&mut self, pty: Option<&Pty>) {
+        // Popups close first, as everywhere on Android.
+        if self.menu.take().is_some() {
+            return;
+        }
+        match self.config.back_key {
+            BackAction::Escape => {
+                if let Some(pty) = pty {
+                    self.send_input(pty, b"\x1b");
+                }
+                self.reset_display();
+                self.reset_cursor();
+            }
+            BackAction::Keyboard => {
+                if self.keyboard_visible {
+                    self.set_keyboard_visible(false);
+                }
+            }
+            BackAction::Background => {
+                android::move_to_background(&self.android_app);
+            }
+        }
+    }
+
+    /// Whether mouse events go to the application rather than selecting.
+    /// Holding Shift keeps them here, as in xterm.
+    fn mouse_reporting(&self) -> bool {
+        self.term.mode.intersects(TermMode::MOUSE) && !self.shift_pressed
+    }
+
+    fn report_mouse(&self, report: MouseReport, (x, y): (usize, usize), pty: Option<&Pty>) {
+        let mods = MouseMods {
+            shift: self.shift_pressed,
+            alt: self.alt_pressed,
+            ctrl: self.ctrl_pressed,
+        };
+        if let (Some(bytes), Some(pty)) = (mouse::encode(self.term.mode, report, x, y, mods), pty) {
+            let _ = pty.write(&bytes);
+        }
+    }
+
+    /// Move the pointer, reporting the motion or extending a click-drag
+    /// selection. Returns true if a redraw is needed.
+    fn mouse_moved(&mut self, pos: Point, pty: Option<&Pty>) -> bool {
+        self.pointer = Some(pos);
+        let cell = self.renderer.cell_at(&self.term, pos);
+        if self.mouse_reporting() {
+            if cell != self.mouse_cell {
+                self.mouse_cell = cell;
+                self.report_mouse(MouseReport::Motion(self.mouse_button), cell, pty);
+            }
+        } else if self.mouse_button == Some(mouse::LEFT) {
+            // Only a drag selects; a plain click leaves nothing behind.
+            if self.term.selection.is_none() && cell != self.mouse_cell {
+                self.term
+                    .start_selection(self.mouse_cell.0, self.mouse_cell.1);
+            }
+            self.term.update_selection(cell.0, cell.1);
+        }
+        true
+    }
+
+    /// A mouse button went down or up at the pointer. The overlays take
+    /// clicks first; on the grid they go to the application if it asked
+    /// for them, and otherwise Left selects, Middle pastes and Right opens
+    /// the context menu. Returns true if a redraw is needed.
+    fn mouse_input(&mut self, button: MouseButton, pressed: bool, pty: Option<&Pty>) -> bool {
+        let Some(pos) = self.pointer else {
+            return false;
+        };
+        let code = match button {
+            MouseButton::Left => mouse::LEFT,
+            MouseButton::Middle => mouse::MIDDLE,
+            MouseButton::Right => mouse::RIGHT,
+            _ => return false,
+        };
+        let cell = self.renderer.cell_at(&self.term, pos);
+        if !pressed {
+            if self.mouse_button != Some(code) {
+                return false;
+            }
+            self.mouse_button = None;
+            if self.mouse_reporting() {
+                self.report_mouse(MouseReport::Release(code), cell, pty);
+            }
+            return false;
+        }
+
+        if let Some(menu) = self.menu.take() {
+            if let Some(action) = menu.action_at(pos) {
+                self.run_menu_action(action, menu, pty);
+            }
+            return true;
+        }
+        if code == mouse::LEFT {
+            if let Some(key) = self.extra_key_at(pos) {
+                self.press_extra_key(key, pty);
+                return true;
+            }
+            if self.keyboard_button().contains(pos) {
+                self.set_keyboard_visible(!self.keyboard_visible);
+                return true;
+            }
+        }
+
+        self.mouse_button = Some(code);
+        self.mouse_cell = cell;
+        if self.mouse_reporting() {
+            self.report_mouse(MouseReport::Press(code), cell, pty);
+            return false;
+        }
+        match code {
+            mouse::LEFT => self.term.clear_selection(),
+            mouse::MIDDLE => {
+                if let (Some(bytes), Some(pty)) = (self.paste_bytes(), pty) {
+                    self.send_input(pty, &bytes);
+                }
+            }
+            _ => self.open_menu(pos),
+        }
+        true
+    }
+
+    /// Scroll back through history, or report the wheel to an application
+    /// that asked for it. Without a mouse mode, the alternate screen gets
+    /// arrow keys instead, as for a drag. Returns true if a redraw is needed.
+    fn mouse_wheel(&mut self, delta: MouseScrollDelta, pty: Option<&Pty>) -> bool {
+        // Positive goes back into history, as for `SmoothScroll`.
+        let (notches, lines) = match delta {
+            MouseScrollDelta::LineDelta(_, y) => (y, y * WHEEL_LINES),
+            MouseScrollDelta::PixelDelta(pos) => {
+                let lines = pos.y as f32 / self.renderer.cell_h;
+                (lines / WHEEL_LINES, lines)
+            }
+        };
+        if !self.mouse_reporting() {
+            if self.term.mode.contains(TermMode::ALTSCREEN) {
+                self.arrow_scroll(lines, pty);
+                return false;
+            }
+            self.scroll.scroll(lines);
+            return true;
+        }
+        let pos = self.pointer.unwrap_or_default();
+        self.report_wheel(notches, pos, pty);
+        false
+    }
+
+    /// Report wheel `notches` at `pos`, positive rolling up, keeping the
+    /// fraction of a notch for next time.
+    fn report_wheel(&mut self, notches: f32, pos: Point, pty: Option<&Pty>) {
+        self.wheel_notches += notches;
+        let whole = self.wheel_notches.trunc();
+        self.wheel_notches -= whole;
+        let cell = self.renderer.cell_at(&self.term, pos);
+        let report = MouseReport::Wheel { up: whole > 0.0 };
+        for _ in 0..whole.abs() as usize {
+            self.report_mouse(report, cell, pty);
+        }
+    }
+
+    fn extend_selection(&mut self, pos: Point) {
+        let (x, y) = self.renderer.cell_at(&self.term, pos);
+        self.term.update_selection(x, y);
+    }
+
+    /// Which selection handle, if any, is under `pos`: Some(true) for the
+    /// start, Some(false) for the end.
+    fn selection_handle_at(&self, pos: Point) -> Option<bool> {
+        let radius = SELECTION_HANDLE_RADIUS * self.scale_factor as f32;
+        let [start, end] = self.renderer.selection_handles(&self.term);
+        [(start, -1.0, true), (end, 1.0, false)]
+            .into_iter()
+            .find_map(|(tip, dir, is_start)| {
+                let tip = tip?;
+                let center = Point::new(tip.x + dir * radius, tip.y + radius);
+                // Generous, since handles are small under a finger.
+                ((pos - center).length() < radius * 2.0).then_some(is_start)
+            })
+    }
+
+    /// Scroll the view by a possibly fractional number of lines, positive
+    /// going back into history.
+    fn scroll_lines(&mut self, lines: f32) {
+        let offset = self.term.display_offset;
+        let target = self.scroll.apply(lines, offset, self.term.scrollback.len());
+        self.term.scroll_display(target as isize - offset as isize);
+    }
+
+    /// Jump back to the live screen, cancelling any scroll animation.
+    fn reset_display(&mut self) {
+        self.scroll.stop();
+        self.term.reset_display();
+    }
+
+    /// Follow the system dark mode setting. Returns true if the theme
+    /// may have changed.
+    fn set_system_dark(&mut self, dark: bool) -> bool {
+        if self.system_dark == dark {
+            return false;
+        }
+        self.system_dark = dark;
+        self.apply_theme();
+        true
+    }
+
+    fn apply_theme(&mut self) {
+        self.renderer
+            .set_theme(self.config.active_theme(self.system_dark));
+    }
+
+    /// Re-read the config file if it changed and apply its theme settings
+    /// and package mirror; everything else still takes effect on the next
+    /// start.
+    fn reload_theme_if_changed(&mut self) -> bool {
+        let Some(path) = self.config_path.as_deref() else {
+            return false;
+        };
+        let mtime = modified(path);
+        if mtime == self.config_mtime {
+            return false;
+        }
+        self.config_mtime = mtime;
+        let Some(config) = AppConfig::load(path) else {
+            return false;
+        };
+        log::info!("Config changed, reloading theme");
+        self.config.theme = config.theme;
+        self.config.dark_theme = config.dark_theme;
+        self.config.light_theme = config.light_theme;
+        self.config.bootstrap_mirror = config.bootstrap_mirror;
+        self.config.bootstrap_environment = config.bootstrap_environment;
+        self.apply_theme();
+        true
+    }
+
+    /// Copy the selection to the system clipboard.
+    fn copy_selection(&self) {
+        if let Some(text) = self.term.selection_text().filter(|t| !t.is_empty()) {
+            android::set_clipboard_text(&self.android_app, &text);
+        }
     }
 
-    fn rows(&self) -> u16 {
-        self.term.rows as u16
+    /// What Share sends: the selection, or without one the output of the
+    /// last command, if the shell marks it (OSC 133).
+    fn share_text(&self) -> Option<String> {
+        self.term
+            .selection_text()
+            .filter(|t| !t.is_empty())
+            .or_else(|| self.term.last_command_output())
+            .filter(|t| !t.is_empty())
     }
 
-    fn resize(&mut self, width: u32, height: u32) {
-        let fb_info = FramebufferInfo {
-            fboid: 0,
-            format: skia_safe::gpu::gl::Format::RGBA8.into(),
-            protected: Protected::No,
-        };
-
-        let backend_rt =
-            backend_render_targets::make_gl((width as i32, height as i32), 0, 0, fb_info);
-
-        self.skia_surface = surfaces::wrap_backend_render_target(
-            &mut self.gr_context,
-            &backend_rt,
-            SurfaceOrigin::BottomLeft,
-            ColorType::RGBA8888,
-            None,
-            None,
-        )
-        .unwrap();
+    /// Bytes to send the PTY to paste the clipboard, if it holds any text.
+    fn paste_bytes(&mut self) -> Option<Vec<u8>> {
+        let text = android::clipboard_text(&self.android_app).filter(|t| !t.is_empty())?;
+        self.reset_display();
+        self.reset_cursor();
+        Some(paste_bytes(
+            &text,
+            self.term.mode.contains(TermMode::BRACKETED_PASTE),
+        ))
+    }
 
-        let new_cols = self
-            .config
-            .grid_cols
-            .unwrap_or((width as f32 / self.renderer.cell_w).floor() as usize)
-            .max(1);
-        let new_rows = self
-            .config
-            .grid_rows
-            .unwrap_or((height as f32 / self.renderer.cell_h).floor() as usize)
-            .max(1);
+    /// Start recording the session as `[transcript]` says, or stop.
+    fn toggle_transcript(&mut self) {
+        if self.transcript.take().is_some() {
+            log::info!("Transcript stopped");
+            return;
+        }
+        let dir = self.home.join(&self.config.transcript_dir);
+        match Transcript::start(
+            &dir,
+            self.config.transcript_max_size,
+            self.config.transcript_keep,
+            self.config.transcript_input,
+        ) {
+            Ok(transcript) => self.transcript = Some(transcript),
+            Err(e) => log::error!("Failed to start transcript in {:?}: {:?}", dir, e),
+        }
+    }
 
-        if new_cols != self.term.cols || new_rows != self.term.rows {
-            log::info!(
-                "Terminal resized: {}x{} -> {}x{}",
-                self.term.cols,
-                self.term.rows,
-                new_cols,
-                new_rows
-            );
-            self.term = Term::new(new_cols, new_rows);
+    /// Send what the user typed or pasted to the shell, recording it if
+    /// the transcript takes input.
+    fn send_input(&mut self, pty: &Pty, bytes: &[u8]) {
+        if let Some(transcript) = &mut self.transcript {
+            transcript.input(bytes);
         }
+        let _ = pty.write(bytes);
     }
 
-    fn render(&mut self) {
-        let canvas = self.skia_surface.canvas();
-        self.renderer
-            .render(canvas, &self.term, self.cursor_visible);
-        self.gr_context.flush_and_submit();
-        self.gl_surface.swap_buffers(&self.gl_context).unwrap();
+    fn toggle_debug_overlay(&mut self) {
+        self.debug = match self.debug {
+            Some(_) => None,
+            None => Some(DebugStats::default()),
+        };
     }
 
     /// Toggle cursor blink state
     fn toggle_cursor_blink(&mut self) {
+        // The hollow unfocused cursor stays put.
+        if !self.term.cursor_style.blinking || !self.focused {
+            self.cursor_visible = true;
+            return;
+        }
         if self.last_input.elapsed() > Duration::from_millis(CURSOR_BLINK_MS) {
             self.cursor_visible = !self.cursor_visible;
             self.term.dirty[self.term.cursor.y] = true;
@@ -478,121 +2098,164 @@ impl AppState {
 
     /// Process PTY output data through the parser
     fn process_pty_output(&mut self, data: &[u8]) {
+        if let Some(transcript) = &mut self.transcript {
+            transcript.output(data);
+        }
         for &byte in data {
             self.parser.process(&mut self.term, byte);
         }
+        if let Some(stats) = self.debug.as_mut() {
+            stats.record_parse(data.len());
+        }
+        self.update_title();
+        // No native pane UI yet; drain so notifications don't pile up.
+        if let Some(tmux) = self.parser.tmux.as_mut() {
+            for notification in tmux.take_notifications() {
+                log::info!("tmux: {:?}", notification);
+            }
+        }
     }
 
-    /// Convert physical keycode to bytes for PTY, considering modifiers
-    fn keycode_to_bytes(key: &PhysicalKey, ctrl: bool, shift: bool) -> Option<Vec<u8>> {
-        // Ctrl + letter = ASCII control character (1-26)
-        if ctrl {
-            return match key {
-                PhysicalKey::Code(KeyCode::KeyA) => Some(vec![0x01]), // SOH
-                PhysicalKey::Code(KeyCode::KeyB) => Some(vec![0x02]), // STX
-                PhysicalKey::Code(KeyCode::KeyC) => Some(vec![0x03]), // ETX - SIGINT
-                PhysicalKey::Code(KeyCode::KeyD) => Some(vec![0x04]), // EOT - EOF
-                PhysicalKey::Code(KeyCode::KeyE) => Some(vec![0x05]), // ENQ
-                PhysicalKey::Code(KeyCode::KeyF) => Some(vec![0x06]), // ACK
-                PhysicalKey::Code(KeyCode::KeyG) => Some(vec![0x07]), // BEL
-                PhysicalKey::Code(KeyCode::KeyH) => Some(vec![0x08]), // BS
-                PhysicalKey::Code(KeyCode::KeyI) => Some(vec![0x09]), // HT (tab)
-                PhysicalKey::Code(KeyCode::KeyJ) => Some(vec![0x0a]), // LF
-                PhysicalKey::Code(KeyCode::KeyK) => Some(vec![0x0b]), // VT
-                PhysicalKey::Code(KeyCode::KeyL) => Some(vec![0x0c]), // FF - clear
-                PhysicalKey::Code(KeyCode::KeyM) => Some(vec![0x0d]), // CR
-                PhysicalKey::Code(KeyCode::KeyN) => Some(vec![0x0e]), // SO
-                PhysicalKey::Code(KeyCode::KeyO) => Some(vec![0x0f]), // SI
-                PhysicalKey::Code(KeyCode::KeyP) => Some(vec![0x10]), // DLE
-                PhysicalKey::Code(KeyCode::KeyQ) => Some(vec![0x11]), // DC1
-                PhysicalKey::Code(KeyCode::KeyR) => Some(vec![0x12]), // DC2
-                PhysicalKey::Code(KeyCode::KeyS) => Some(vec![0x13]), // DC3
-                PhysicalKey::Code(KeyCode::KeyT) => Some(vec![0x14]), // DC4
-                PhysicalKey::Code(KeyCode::KeyU) => Some(vec![0x15]), // NAK
-                PhysicalKey::Code(KeyCode::KeyV) => Some(vec![0x16]), // SYN
-                PhysicalKey::Code(KeyCode::KeyW) => Some(vec![0x17]), // ETB
-                PhysicalKey::Code(KeyCode::KeyX) => Some(vec![0x18]), // CAN
-                PhysicalKey::Code(KeyCode::KeyY) => Some(vec![0x19]), // EM
-                PhysicalKey::Code(KeyCode::KeyZ) => Some(vec![0x1a]), // SUB - SIGTSTP
-                PhysicalKey::Code(KeyCode::BracketLeft) => Some(vec![0x1b]), // ESC
-                PhysicalKey::Code(KeyCode::Backslash) => Some(vec![0x1c]), // FS
-                PhysicalKey::Code(KeyCode::BracketRight) => Some(vec![0x1d]), // GS
-                PhysicalKey::Code(KeyCode::Digit6) => Some(vec![0x1e]), // RS (Ctrl+^)
-                PhysicalKey::Code(KeyCode::Minus) => Some(vec![0x1f]), // US (Ctrl+_)
-                _ => None,
-            };
+    /// Look up what the terminal is running, for the title.
+    fn poll_job(&mut self, pty: Option<&Pty>) {
+        self.job = pty.and_then(|pty| Some((pty.foreground_command()?, pty.foreground_cwd())));
+        self.update_title();
+    }
+
+    /// The title an application set, or else the running command and the
+    /// directory the shell last reported with OSC 7, or the command's own.
+    fn update_title(&mut self) {
+        let title = if !self.term.title.is_empty() {
+            self.term.title.clone()
+        } else if let Some((command, cwd)) = &self.job {
+            match self.term.cwd.as_ref().or(cwd.as_ref()) {
+                Some(cwd) => format!("{}: {}", command, tilde_path(cwd, &self.home)),
+                None => command.clone(),
+            }
+        } else {
+            String::new()
+        };
+        if title != self.title {
+            self.title = title;
+            self.window.set_title(&self.title);
+            android::set_task_label(&self.android_app, &self.title);
+        }
+    }
+
+    /// Scrollback navigation: Shift+PageUp/PageDown scroll by a page,
+    /// Ctrl+Shift+Z/X jump to the previous/next shell prompt (OSC 133).
+    /// Returns true if the key was consumed.
+    fn handle_scroll_key(&mut self, key: &PhysicalKey) -> bool {
+        if !self.shift_pressed {
+            return false;
+        }
+        let page = self.term.rows as f32;
+        match key {
+            PhysicalKey::Code(KeyCode::PageUp) if !self.ctrl_pressed => {
+                self.scroll.scroll(page);
+            }
+            PhysicalKey::Code(KeyCode::PageDown) if !self.ctrl_pressed => {
+                self.scroll.scroll(-page);
+            }
+            PhysicalKey::Code(KeyCode::KeyZ) if self.ctrl_pressed => {
+                self.scroll.stop();
+                self.term.jump_to_prev_prompt();
+            }
+            PhysicalKey::Code(KeyCode::KeyX) if self.ctrl_pressed => {
+                self.scroll.stop();
+                self.term.jump_to_next_prompt();
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Terminal actions on Ctrl+Shift+letter. The whole layer is reserved:
+    /// unbound letters are swallowed as well, since they would otherwise
+    /// reach the PTY as the same control code as Ctrl alone, and a copy
+    /// that misses must not turn into SIGINT. Returns true if the key was
+    /// taken.
+    fn handle_shortcut(&mut self, key: &PhysicalKey, pty: Option<&Pty>) -> bool {
+        if !(self.ctrl_pressed && self.shift_pressed) {
+            return false;
+        }
+        match key {
+            PhysicalKey::Code(KeyCode::KeyC) => self.copy_selection(),
+            PhysicalKey::Code(KeyCode::KeyV) => {
+                if let (Some(bytes), Some(pty)) = (self.paste_bytes(), pty) {
+                    self.send_input(pty, &bytes);
+                }
+            }
+            PhysicalKey::Code(KeyCode::KeyD) => self.toggle_debug_overlay(),
+            PhysicalKey::Code(KeyCode::KeyL) => self.toggle_transcript(),
+            PhysicalKey::Code(KeyCode::KeyM) => self.move_to_next_display(),
+            PhysicalKey::Code(KeyCode::KeyB) => {
+                // Erasing the bootstrap is too drastic for a chord alone;
+                // it takes a tap on the menu this opens to confirm.
+                let size = self.window.inner_size();
+                let center = Point::new(size.width as f32 / 2.0, size.height as f32 / 2.0);
+                let mut actions = vec![MenuAction::ResetBootstrap];
+                if !self.environment.is_empty() {
+                    actions.push(MenuAction::RemoveEnvironment);
+                }
+                self.show_menu(actions, center, None);
+            }
+            _ => return us_letter(key).is_some(),
+        }
+        true
+    }
+
+    /// Bytes for a key press. Keys that don't type text come from the
+    /// table in [`AppState::keycode_to_bytes`]; everything else is the text
+    /// the keyboard layout produced, so AZERTY or Dvorak type what is printed
+    /// on their keys.
+    fn key_event_bytes(event: &KeyEvent, ctrl: bool, mode: TermMode) -> Option<Vec<u8>> {
+        if let Some(bytes) = Self::keycode_to_bytes(&event.physical_key, mode) {
+            return Some(bytes);
         }
+        let text = match (&event.text, &event.logical_key) {
+            (Some(text), _) => text.as_str(),
+            (None, Key::Character(c)) => c.as_str(),
+            _ => return None,
+        };
+        if !ctrl {
+            return Some(text.as_bytes().to_vec());
+        }
+        // Ctrl goes by the character too, falling back to the key's
+        // position for layouts without Latin letters.
+        let byte = match text.as_bytes() {
+            &[b] if b.is_ascii() => b.to_ascii_lowercase(),
+            _ => us_letter(&event.physical_key)?,
+        };
+        let control = ctrl_byte(byte);
+        (control != byte).then(|| vec![control])
+    }
 
+    /// Convert keys that don't type text to bytes for the PTY, in cursor
+    /// key mode (DECCKM) and application keypad mode (DECKPAM) if the
+    /// application asked for them.
+    fn keycode_to_bytes(key: &PhysicalKey, mode: TermMode) -> Option<Vec<u8>> {
+        let app_cursor = mode.contains(TermMode::APPCURSOR);
+        if let PhysicalKey::Code(code) = key {
+            if let Some(bytes) = numpad_key(*code, mode.contains(TermMode::APPKEYPAD)) {
+                return Some(bytes);
+            }
+        }
         match key {
-            // Letters a-z (handle shift for uppercase)
-            PhysicalKey::Code(KeyCode::KeyA) => Some(vec![if shift { b'A' } else { b'a' }]),
-            PhysicalKey::Code(KeyCode::KeyB) => Some(vec![if shift { b'B' } else { b'b' }]),
-            PhysicalKey::Code(KeyCode::KeyC) => Some(vec![if shift { b'C' } else { b'c' }]),
-            PhysicalKey::Code(KeyCode::KeyD) => Some(vec![if shift { b'D' } else { b'd' }]),
-            PhysicalKey::Code(KeyCode::KeyE) => Some(vec![if shift { b'E' } else { b'e' }]),
-            PhysicalKey::Code(KeyCode::KeyF) => Some(vec![if shift { b'F' } else { b'f' }]),
-            PhysicalKey::Code(KeyCode::KeyG) => Some(vec![if shift { b'G' } else { b'g' }]),
-            PhysicalKey::Code(KeyCode::KeyH) => Some(vec![if shift { b'H' } else { b'h' }]),
-            PhysicalKey::Code(KeyCode::KeyI) => Some(vec![if shift { b'I' } else { b'i' }]),
-            PhysicalKey::Code(KeyCode::KeyJ) => Some(vec![if shift { b'J' } else { b'j' }]),
-            PhysicalKey::Code(KeyCode::KeyK) => Some(vec![if shift { b'K' } else { b'k' }]),
-            PhysicalKey::Code(KeyCode::KeyL) => Some(vec![if shift { b'L' } else { b'l' }]),
-            PhysicalKey::Code(KeyCode::KeyM) => Some(vec![if shift { b'M' } else { b'm' }]),
-            PhysicalKey::Code(KeyCode::KeyN) => Some(vec![if shift { b'N' } else { b'n' }]),
-            PhysicalKey::Code(KeyCode::KeyO) => Some(vec![if shift { b'O' } else { b'o' }]),
-            PhysicalKey::Code(KeyCode::KeyP) => Some(vec![if shift { b'P' } else { b'p' }]),
-            PhysicalKey::Code(KeyCode::KeyQ) => Some(vec![if shift { b'Q' } else { b'q' }]),
-            PhysicalKey::Code(KeyCode::KeyR) => Some(vec![if shift { b'R' } else { b'r' }]),
-            PhysicalKey::Code(KeyCode::KeyS) => Some(vec![if shift { b'S' } else { b's' }]),
-            PhysicalKey::Code(KeyCode::KeyT) => Some(vec![if shift { b'T' } else { b't' }]),
-            PhysicalKey::Code(KeyCode::KeyU) => Some(vec![if shift { b'U' } else { b'u' }]),
-            PhysicalKey::Code(KeyCode::KeyV) => Some(vec![if shift { b'V' } else { b'v' }]),
-            PhysicalKey::Code(KeyCode::KeyW) => Some(vec![if shift { b'W' } else { b'w' }]),
-            PhysicalKey::Code(KeyCode::KeyX) => Some(vec![if shift { b'X' } else { b'x' }]),
-            PhysicalKey::Code(KeyCode::KeyY) => Some(vec![if shift { b'Y' } else { b'y' }]),
-            PhysicalKey::Code(KeyCode::KeyZ) => Some(vec![if shift { b'Z' } else { b'z' }]),
-
-            // Numbers and shift symbols
-            PhysicalKey::Code(KeyCode::Digit1) => Some(vec![if shift { b'!' } else { b'1' }]),
-            PhysicalKey::Code(KeyCode::Digit2) => Some(vec![if shift { b'@' } else { b'2' }]),
-            PhysicalKey::Code(KeyCode::Digit3) => Some(vec![if shift { b'#' } else { b'3' }]),
-            PhysicalKey::Code(KeyCode::Digit4) => Some(vec![if shift { b'$' } else { b'4' }]),
-            PhysicalKey::Code(KeyCode::Digit5) => Some(vec![if shift { b'%' } else { b'5' }]),
-            PhysicalKey::Code(KeyCode::Digit6) => Some(vec![if shift { b'^' } else { b'6' }]),
-            PhysicalKey::Code(KeyCode::Digit7) => Some(vec![if shift { b'&' } else { b'7' }]),
-            PhysicalKey::Code(KeyCode::Digit8) => Some(vec![if shift { b'*' } else { b'8' }]),
-            PhysicalKey::Code(KeyCode::Digit9) => Some(vec![if shift { b'(' } else { b'9' }]),
-            PhysicalKey::Code(KeyCode::Digit0) => Some(vec![if shift { b')' } else { b'0' }]),
-
-            // Special keys
-            PhysicalKey::Code(KeyCode::Space) => Some(vec![b' ']),
             PhysicalKey::Code(KeyCode::Enter) => Some(vec![b'\n']),
             PhysicalKey::Code(KeyCode::Backspace) => Some(vec![0x7f]), // DEL
             PhysicalKey::Code(KeyCode::Tab) => Some(vec![b'\t']),
             PhysicalKey::Code(KeyCode::Escape) => Some(vec![0x1b]),
 
-            // Punctuation with shift variants
-            PhysicalKey::Code(KeyCode::Period) => Some(vec![if shift { b'>' } else { b'.' }]),
-            PhysicalKey::Code(KeyCode::Comma) => Some(vec![if shift { b'<' } else { b',' }]),
-            PhysicalKey::Code(KeyCode::Semicolon) => Some(vec![if shift { b':' } else { b';' }]),
-            PhysicalKey::Code(KeyCode::Quote) => Some(vec![if shift { b'"' } else { b'\'' }]),
-            PhysicalKey::Code(KeyCode::Slash) => Some(vec![if shift { b'?' } else { b'/' }]),
-            PhysicalKey::Code(KeyCode::Backslash) => Some(vec![if shift { b'|' } else { b'\\' }]),
-            PhysicalKey::Code(KeyCode::Minus) => Some(vec![if shift { b'_' } else { b'-' }]),
-            PhysicalKey::Code(KeyCode::Equal) => Some(vec![if shift { b'+' } else { b'=' }]),
-            PhysicalKey::Code(KeyCode::BracketLeft) => Some(vec![if shift { b'{' } else { b'[' }]),
-            PhysicalKey::Code(KeyCode::BracketRight) => Some(vec![if shift { b'}' } else { b']' }]),
-            PhysicalKey::Code(KeyCode::Backquote) => Some(vec![if shift { b'~' } else { b'`' }]),
-
             // Arrow keys (ANSI escape sequences)
-            PhysicalKey::Code(KeyCode::ArrowUp) => Some(vec![0x1b, b'[', b'A']),
-            PhysicalKey::Code(KeyCode::ArrowDown) => Some(vec![0x1b, b'[', b'B']),
-            PhysicalKey::Code(KeyCode::ArrowRight) => Some(vec![0x1b, b'[', b'C']),
-            PhysicalKey::Code(KeyCode::ArrowLeft) => Some(vec![0x1b, b'[', b'D']),
+            PhysicalKey::Code(KeyCode::ArrowUp) => Some(cursor_key(b'A', app_cursor)),
+            PhysicalKey::Code(KeyCode::ArrowDown) => Some(cursor_key(b'B', app_cursor)),
+            PhysicalKey::Code(KeyCode::ArrowRight) => Some(cursor_key(b'C', app_cursor)),
+            PhysicalKey::Code(KeyCode::ArrowLeft) => Some(cursor_key(b'D', app_cursor)),
 
             // Home/End/Page keys
-            PhysicalKey::Code(KeyCode::Home) => Some(vec![0x1b, b'[', b'H']),
-            PhysicalKey::Code(KeyCode::End) => Some(vec![0x1b, b'[', b'F']),
+            PhysicalKey::Code(KeyCode::Home) => Some(cursor_key(b'H', app_cursor)),
+            PhysicalKey::Code(KeyCode::End) => Some(cursor_key(b'F', app_cursor)),
             PhysicalKey::Code(KeyCode::PageUp) => Some(vec![0x1b, b'[', b'5', b'~']),
             PhysicalKey::Code(KeyCode::PageDown) => Some(vec![0x1b, b'[', b'6', b'~']),
             PhysicalKey::Code(KeyCode::Delete) => Some(vec![0x1b, b'[', b'3', b'~']),
@@ -620,19 +2283,115 @@ impl AppState {
 impl ApplicationHandler<AppEvent> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         log::info!("App resumed, initializing...");
-        if self.state.is_none() {
+        self.visible = true;
+        android::cancel_notifications(&self.android_app);
+        if let Some(state) = &mut self.state {
+            // The old window's surface went away while we were suspended.
+            state.recreate_surface(event_loop);
+            if let Some(pty) = &self.pty {
+                pty.resize(state.rows(), state.cols());
+            }
+        } else {
             let config = self.config.clone().unwrap_or_else(AppConfig::default);
-            self.state = Some(AppState::init(event_loop, config));
+            let dark = self.system_dark();
+            if config.notify_bell || config.notify_done || config.keep_alive {
+                android::request_notification_permission(&self.android_app);
+            }
+            if config.edge_to_edge {
+                android::set_edge_to_edge(&self.android_app, true);
+            }
+            self.state = Some(AppState::init(
+                event_loop,
+                self.android_app.clone(),
+                config,
+                self.config_path.clone(),
+                dark,
+                self.home(),
+            ));
+            let shared_dir = self.home().join(SHARED_DIR);
+            self.shared = android::shared_content(&self.android_app, &shared_dir);
+            self.install_bootstrap(false);
+            self.watch_network();
+            self.watch_config();
+            self.serve_control();
         }
         if let Some(state) = &self.state {
             state.window.request_redraw();
-            self.start_background_threads(state.rows(), state.cols());
+            self.start_session(state.rows(), state.cols());
+            self.start_blink_timer();
+        }
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if android::take_new_intent() {
+            self.handle_new_intent();
+        }
+        match self
+            .state
+            .as_mut()
+            .and_then(|state| state.app_action.take())
+        {
+            Some(MenuAction::ResetBootstrap) => self.reset_bootstrap(),
+            Some(MenuAction::RemoveEnvironment) => self.remove_environment(),
+            Some(MenuAction::CleanAptCache) => self.measure_disk_usage(true),
+            _ => {}
+        }
+        // winit has no theme events on Android; poll the configuration,
+        // which changes in place since the manifest handles uiMode.
+        let dark = self.system_dark();
+        let Some(state) = &mut self.state else {
+            return;
+        };
+        if state.set_system_dark(dark) {
+            state.window.request_redraw();
+        }
+
+        // The extra keys follow the soft keyboard up and down, and the
+        // grid shrinks to keep the prompt above it once the keyboard stops
+        // moving.
+        let now = Instant::now();
+        let bottom = state.visible_bottom();
+        if bottom != state.last_visible_bottom {
+            state.last_visible_bottom = bottom;
+            state.regrid_at = Some(now + RESIZE_SETTLE);
+            state.window.request_redraw();
+        }
+        if state.regrid_if_settled(now) {
+            if let Some(pty) = &self.pty {
+                pty.resize(state.rows(), state.cols());
+            }
+        }
+        if state.next_frame.is_some_and(|at| now >= at) {
+            state.next_frame = None;
+            state.window.request_redraw();
+        }
+
+        // Long presses happen while nothing else does; wake up for them.
+        if let Some(gesture) = state.touch.poll(Instant::now()) {
+            if state.handle_gesture(gesture, self.pty.as_deref()) {
+                state.window.request_redraw();
+            }
         }
+        let deadline = [state.touch.deadline(), state.regrid_at, state.next_frame]
+            .into_iter()
+            .flatten()
+            .min();
+        event_loop.set_control_flow(match deadline {
+            Some(deadline) => ControlFlow::WaitUntil(deadline),
+            None => ControlFlow::Wait,
+        });
     }
 
+    /// Only drawing stops; the shell keeps running and its output is
+    /// still parsed, so the screen is current on return.
     fn suspended(&mut self, _event_loop: &ActiveEventLoop) {
         log::info!("App suspended");
-        self.stop_background_threads();
+        self.visible = false;
+        self.save_cwd();
+        self.stop_blink_timer();
+        if let Some(state) = &mut self.state {
+            state.release_surface();
+        }
     }
 
     fn window_event(
@@ -653,15 +2412,74 @@ impl ApplicationHandler<AppEvent> for App {
             }
             WindowEvent::Resized(size) => {
                 log::info!("Resized to {:?}", size);
+                // The PTY hears of it once the size settles.
                 state.resize(size.width, size.height);
-                // Notify PTY of resize
-                if let Some(pty) = &self.pty {
-                    pty.resize(state.rows(), state.cols());
+                state.window.request_redraw();
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                if state.set_scale_factor(scale_factor) {
+                    if let Some(pty) = &self.pty {
+                        pty.resize(state.rows(), state.cols());
+                    }
+                }
+                state.window.request_redraw();
+            }
+            WindowEvent::Focused(focused) => {
+                state.focused = focused;
+                // An edit made while another app had the screen.
+                if focused && state.reload_theme_if_changed() {
+                    self.apply_reloaded_config();
+                }
+                let Some(state) = &mut self.state else {
+                    return;
+                };
+                state.window.request_redraw();
+                if state.term.mode.contains(TermMode::FOCUS) {
+                    if let Some(pty) = &self.pty {
+                        let _ = pty.write(if focused { b"\x1b[I" } else { b"\x1b[O" });
+                    }
+                }
+            }
+            WindowEvent::Touch(touch) => {
+                if state.handle_touch(&touch, self.pty.as_deref()) {
+                    state.window.request_redraw();
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let pos = Point::new(position.x as f32, position.y as f32);
+                if state.mouse_moved(pos, self.pty.as_deref()) {
+                    state.window.request_redraw();
                 }
+            }
+            WindowEvent::CursorLeft { .. } => {
+                state.pointer = None;
+                state.mouse_button = None;
                 state.window.request_redraw();
             }
+            WindowEvent::MouseInput {
+                state: button_state,
+                button,
+                ..
+            } => {
+                let pressed = button_state == ElementState::Pressed;
+                if state.mouse_input(button, pressed, self.pty.as_deref()) {
+                    state.window.request_redraw();
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                if state.mouse_wheel(delta, self.pty.as_deref()) {
+                    state.window.request_redraw();
+                }
+            }
             WindowEvent::RedrawRequested => {
+                let start = Instant::now();
                 state.render();
+                // Keep drawing frames until the scroll animation settles,
+                // as often as the display shows them.
+                state.next_frame = state
+                    .scroll
+                    .is_animating()
+                    .then(|| start + state.frame_interval);
             }
             WindowEvent::KeyboardInput { event, .. } => {
                 match event.physical_key {
@@ -673,19 +2491,109 @@ impl ApplicationHandler<AppEvent> for App {
                     | PhysicalKey::Code(KeyCode::ShiftRight) => {
                         state.shift_pressed = event.state == ElementState::Pressed;
                     }
+                    PhysicalKey::Code(KeyCode::CapsLock) => {
+                        if event.state == ElementState::Pressed && !event.repeat {
+                            state.caps_lock = !state.caps_lock;
+                        }
+                    }
+                    PhysicalKey::Code(KeyCode::AltLeft) => {
+                        state.alt_pressed = event.state == ElementState::Pressed;
+                    }
+                    PhysicalKey::Code(KeyCode::AudioVolumeDown) => {
+                        state.volume_down_pressed = event.state == ElementState::Pressed;
+                    }
+                    PhysicalKey::Code(KeyCode::AudioVolumeUp) => {
+                        state.volume_up_pressed = event.state == ElementState::Pressed;
+                    }
                     _ => {}
                 }
 
+                if event.logical_key == Key::Named(NamedKey::BrowserBack) {
+                    if event.state == ElementState::Pressed && !event.repeat {
+                        state.handle_back(self.pty.as_deref());
+                        state.window.request_redraw();
+                    }
+                    return;
+                }
+
                 if event.state == ElementState::Pressed {
-                    if let Some(bytes) = AppState::keycode_to_bytes(
-                        &event.physical_key,
-                        state.ctrl_pressed,
-                        state.shift_pressed,
-                    ) {
+                    if state.handle_scroll_key(&event.physical_key) {
+                        state.window.request_redraw();
+                        return;
+                    }
+                    if let Some(resized) = state.handle_zoom_key(&event.physical_key) {
+                        if resized {
+                            if let Some(pty) = &self.pty {
+                                pty.resize(state.rows(), state.cols());
+                            }
+                        }
+                        state.window.request_redraw();
+                        return;
+                    }
+                    // Shortcuts that need more than the window state.
+                    if state.ctrl_pressed && state.shift_pressed {
+                        match event.physical_key {
+                            PhysicalKey::Code(KeyCode::KeyR) => {
+                                self.repair_bootstrap();
+                                return;
+                            }
+                            PhysicalKey::Code(KeyCode::KeyU) => {
+                                self.measure_disk_usage(false);
+                                return;
+                            }
+                            PhysicalKey::Code(KeyCode::KeyS) => {
+                                self.setup_storage();
+                                return;
+                            }
+                            _ => {}
+                        }
+                    }
+                    if state.handle_shortcut(&event.physical_key, self.pty.as_deref()) {
+                        state.window.request_redraw();
+                        return;
+                    }
+                    if let Key::Dead(Some(accent)) = event.logical_key {
+                        state.dead_key = Some(accent);
+                        return;
+                    }
+                    let caps_text = event
+                        .text
+                        .as_deref()
+                        .filter(|_| state.caps_lock)
+                        .and_then(|text| caps_locked(text, state.shift_pressed));
+                    let text = caps_text.as_deref().or(event.text.as_deref());
+                    // A pending dead key goes on the next key with text;
+                    // modifiers in between (Shift for capitals) don't count.
+                    let dead_key = text.and_then(|_| state.dead_key.take());
+                    let composed = dead_key
+                        .zip(text)
+                        .filter(|(_, text)| !text.chars().any(char::is_control))
+                        .map(|(accent, text)| compose::combine(accent, text).into_bytes());
+
+                    let mode = state.term.mode;
+                    // Volume Down is Ctrl; Volume Up turns the key into
+                    // another one.
+                    let ctrl = state.ctrl_pressed || state.volume_down_pressed;
+                    let volume_up = state.volume_up_pressed;
+                    let bytes = composed
+                        .or_else(|| {
+                            let text = event.text.as_deref().filter(|_| volume_up)?;
+                            volume_up_key(text, mode)
+                        })
+                        .or_else(|| caps_text.filter(|_| !ctrl).map(String::into_bytes))
+                        .or_else(|| AppState::key_event_bytes(&event, ctrl, mode));
+                    if let Some(bytes) = bytes {
+                        // Alt is Meta: ESC before the key, like a latched ALT.
+                        if state.alt_pressed {
+                            state.sticky.alt = true;
+                        }
+                        let bytes = state.sticky.apply(&bytes);
                         if let Some(pty) = &self.pty {
-                            let _ = pty.write(&bytes);
+                            state.send_input(pty, &bytes);
                         }
+                        state.reset_display();
                         state.reset_cursor();
+                        state.window.request_redraw();
                     }
                 }
             }
@@ -695,25 +2603,550 @@ impl ApplicationHandler<AppEvent> for App {
 
     fn user_event(&mut self, event_loop: &ActiveEventLoop, event: AppEvent) {
         match event {
+            AppEvent::BootstrapProgress(progress) => {
+                if let Some(state) = &mut self.state {
+                    state.installing = Some(progress);
+                    state.window.request_redraw();
+                }
+            }
+            AppEvent::BootstrapDone(env) => self.bootstrap_done(env),
+            AppEvent::Welcome => {
+                if let Some(state) = &mut self.state {
+                    // The terminal wants carriage returns too.
+                    state.process_pty_output(WELCOME.replace('\n', "\r\n").as_bytes());
+                }
+            }
+            AppEvent::DiskUsage(usage) => {
+                if let Some(state) = &mut self.state {
+                    state.show_disk_usage(&usage);
+                    state.window.request_redraw();
+                }
+            }
+            AppEvent::RepairDone => {
+                self.installing = false;
+                // Also puts the mirror back in a sources.list repair restored.
+                self.apply_mirror();
+                if let Some(state) = &mut self.state {
+                    state.installing = None;
+                    state.window.request_redraw();
+                }
+            }
             AppEvent::PtyExit => {
+                if self.pty.is_none() {
+                    log::info!("Previous shell exited");
+                    return;
+                }
                 log::info!("Shell exited, closing app");
+                self.save_cwd();
                 self.stop_background_threads();
                 event_loop.exit();
             }
+            AppEvent::JobRunning(true) => {
+                self.job = self.pty.as_ref().and_then(|pty| pty.foreground_command());
+                let Some(state) = &self.state else {
+                    return;
+                };
+                if state.config.wake_lock && self.wake_lock.is_none() {
+                    log::info!("Job started, holding a wake lock");
+                    self.wake_lock = android::WakeLock::acquire(&self.android_app);
+                }
+                if state.config.keep_alive && !self.keep_alive {
+                    let job = self.job.as_deref().unwrap_or("A command");
+                    self.keep_alive = android::start_keep_alive(
+                        &self.android_app,
+                        &format!("{} is running", job),
+                    );
+                }
+            }
+            AppEvent::JobRunning(false) => {
+                if let Some(lock) = self.wake_lock.take() {
+                    log::info!("Job finished, releasing the wake lock");
+                    lock.release(&self.android_app);
+                }
+                self.stop_keep_alive();
+                let job = self.job.take();
+                let notify = self.state.as_ref().is_some_and(|s| s.config.notify_done);
+                if notify && !self.visible {
+                    let job = job.as_deref().unwrap_or("The command");
+                    android::notify(
+                        &self.android_app,
+                        DONE_NOTIFICATION,
+                        "Command finished",
+                        &format!("{} has finished", job),
+                    );
+                }
+            }
             AppEvent::CursorBlink => {
                 let Some(state) = &mut self.state else {
                     return;
                 };
+                state.poll_job(self.pty.as_deref());
                 state.toggle_cursor_blink();
                 state.window.request_redraw();
             }
+            AppEvent::ConfigChanged => {
+                let Some(state) = &mut self.state else {
+                    return;
+                };
+                if state.reload_theme_if_changed() {
+                    state.window.request_redraw();
+                    self.apply_reloaded_config();
+                }
+            }
             AppEvent::PtyOutput(data) => {
+                self.pty_backlog.fetch_sub(data.len(), Ordering::Relaxed);
                 let Some(state) = &mut self.state else {
                     return;
                 };
+                if let Some(stats) = state.debug.as_mut() {
+                    stats.pty_backlog = self.pty_backlog.load(Ordering::Relaxed);
+                }
                 state.process_pty_output(&data);
+                if std::mem::take(&mut state.term.bell) && state.config.notify_bell && !self.visible
+                {
+                    let text = match state.title.as_str() {
+                        "" => "The terminal rang its bell",
+                        title => title,
+                    };
+                    android::notify(&self.android_app, BELL_NOTIFICATION, "Bell", text);
+                }
+                let responses = state.term.take_responses();
+                if !responses.is_empty() {
+                    if let Some(pty) = &self.pty {
+                        let _ = pty.write(&responses);
+                    }
+                }
                 state.window.request_redraw();
             }
         }
     }
 }
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Pixels kept at the bottom of the window for the extra keys row.
+fn extra_keys_height(config: &AppConfig, scale: f32) -> f32 {
+    if config.extra_keys.is_empty() {
+        0.0
+    } else {
+        EXTRA_KEYS_HEIGHT * scale
+    }
+}
+
+/// `path` with `home` shortened to ~, as shells show it.
+fn tilde_path(path: &Path, home: &Path) -> String {
+    match path.strip_prefix(home) {
+        Ok(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Ok(rest) => format!("~/{}", rest.display()),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+/// Where `name` runs from: a bare program name is looked up in the
+/// bootstrap's bin directory, or the system's before it is installed.
+fn find_program(name: &str, env: &PtyEnv) -> PathBuf {
+    if name.contains('/') {
+        return PathBuf::from(name);
+    }
+    match &env.prefix {
+        Some(prefix) => prefix.join("bin").join(name),
+        None => Path::new(SYSTEM_BIN).join(name),
+    }
+}
+
+/// The directory saved by the last session, if it is still there.
+fn read_last_cwd(path: &Path) -> Option<PathBuf> {
+    let cwd = PathBuf::from(OsString::from_vec(fs::read(path).ok()?));
+    cwd.is_dir().then_some(cwd)
+}
+
+/// The shell's environment in an installed bootstrap.
+fn bootstrap_env(paths: BootstrapPaths) -> PtyEnv {
+    let prefix = paths.prefix.to_string_lossy().to_string();
+    let mut env = PtyEnv::builder()
+        .term("xterm-256color")
+        .home(paths.home)
+        .tmp(paths.tmp)
+        .prefix(paths.prefix)
+        .path(format!("{}/bin:/system/bin", prefix))
+        .ld_library_path(format!("{}/lib", prefix));
+    let termux_exec = format!("{}/lib/libtermux-exec.so", prefix);
+    if PathBuf::from(&termux_exec).is_file() {
+        env = env.ld_preload(termux_exec);
+    } else {
+        log::warn!("libtermux-exec.so not found, using linker-only execution path");
+    }
+    log::info!("Bootstrapped prefix at {}", prefix);
+    env.build()
+}
+
+/// Where `[bootstrap]` says to install from.
+fn bootstrap_source(config: &AppConfig) -> BootstrapSource<'_> {
+    match config.bootstrap_url.as_str() {
+        "" => BootstrapSource::Asset,
+        url => BootstrapSource::Url {
+            url,
+            sha256: &config.bootstrap_sha256,
+        },
+    }
+}
+
+/// The command `[shell]` asks for.
+fn shell_command(config: &AppConfig, env: &PtyEnv) -> ShellCommand {
+    let bin = env.prefix.as_ref().map(|prefix| prefix.join("bin"));
+    let program = match &config.shell {
+        ShellProgram::Auto => bin
+            .iter()
+            .flat_map(|bin| [bin.join("bash"), bin.join("sh")])
+            .find(|shell| shell.is_file())
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SHELL)),
+        ShellProgram::System => PathBuf::from(DEFAULT_SHELL),
+        ShellProgram::Path(name) => find_program(name, env),
+    };
+    ShellCommand {
+        program: program.to_string_lossy().into_owned(),
+        args: config.shell_args.clone(),
+        login: config.login_shell,
+    }
+}
+
+/// The Volume Up layer, as in Termux: Volume Up plus a letter or digit
+/// types a key phones lack. `text` is what the key would type alone.
+fn volume_up_key(text: &str, mode: TermMode) -> Option<Vec<u8>> {
+    let mut chars = text.chars();
+    let c = chars.next()?.to_ascii_lowercase();
+    if chars.next().is_some() {
+        return None;
+    }
+    let key = match c {
+        'w' => KeyCode::ArrowUp,
+        'a' => KeyCode::ArrowLeft,
+        's' => KeyCode::ArrowDown,
+        'd' => KeyCode::ArrowRight,
+        'e' => KeyCode::Escape,
+        't' => KeyCode::Tab,
+        'p' => KeyCode::PageUp,
+        'n' => KeyCode::PageDown,
+        '1' => KeyCode::F1,
+        '2' => KeyCode::F2,
+        '3' => KeyCode::F3,
+        '4' => KeyCode::F4,
+        '5' => KeyCode::F5,
+        '6' => KeyCode::F6,
+        '7' => KeyCode::F7,
+        '8' => KeyCode::F8,
+        '9' => KeyCode::F9,
+        '0' => KeyCode::F10,
+        'l' => return Some(b"|".to_vec()),
+        'h' => return Some(b"~".to_vec()),
+        'u' => return Some(b"_".to_vec()),
+        // Alt+B/F/X for readline and emacs.
+        'b' | 'f' | 'x' => return Some(vec![0x1b, c as u8]),
+        _ => return None,
+    };
+    AppState::keycode_to_bytes(&PhysicalKey::Code(key), mode)
+}
+
+/// A numpad key: what it types, or in application keypad mode the `SS3`
+/// sequence that tells it apart from the same key on the main block.
+fn numpad_key(code: KeyCode, app_keypad: bool) -> Option<Vec<u8>> {
+    let (normal, app) = match code {
+        KeyCode::Numpad0 => (b'0', b'p'),
+        KeyCode::Numpad1 => (b'1', b'q'),
+        KeyCode::Numpad2 => (b'2', b'r'),
+        KeyCode::Numpad3 => (b'3', b's'),
+        KeyCode::Numpad4 => (b'4', b't'),
+        KeyCode::Numpad5 => (b'5', b'u'),
+        KeyCode::Numpad6 => (b'6', b'v'),
+        KeyCode::Numpad7 => (b'7', b'w'),
+        KeyCode::Numpad8 => (b'8', b'x'),
+        KeyCode::Numpad9 => (b'9', b'y'),
+        KeyCode::NumpadDecimal => (b'.', b'n'),
+        KeyCode::NumpadComma => (b',', b'l'),
+        KeyCode::NumpadAdd => (b'+', b'k'),
+        KeyCode::NumpadSubtract => (b'-', b'm'),
+        KeyCode::NumpadMultiply => (b'*', b'j'),
+        KeyCode::NumpadDivide => (b'/', b'o'),
+        KeyCode::NumpadEqual => (b'=', b'X'),
+        // Like the main Enter key.
+        KeyCode::NumpadEnter => (b'\n', b'M'),
+        _ => return None,
+    };
+    Some(if app_keypad {
+        vec![0x1b, b'O', app]
+    } else {
+        vec![normal]
+    })
+}
+
+/// What a key typing `text` types with Caps Lock on: a letter is upper
+/// case unless Shift is held too. None for anything but a single letter,
+/// which Caps Lock leaves alone.
+fn caps_locked(text: &str, shift: bool) -> Option<String> {
+    let mut chars = text.chars();
+    let c = chars.next().filter(|c| c.is_alphabetic())?;
+    if chars.next().is_some() {
+        return None;
+    }
+    Some(if shift {
+        c.to_lowercase().collect()
+    } else {
+        c.to_uppercase().collect()
+    })
+}
+
+/// The letter a key types on a US layout, for Ctrl chords on layouts
+/// without Latin letters.
+fn us_letter(key: &PhysicalKey) -> Option<u8> {
+    let PhysicalKey::Code(code) = key else {
+        return None;
+    };
+    let letter = match code {
+        KeyCode::KeyA => b'a',
+        KeyCode::KeyB => b'b',
+        KeyCode::KeyC => b'c',
+        KeyCode::KeyD => b'd',
+        KeyCode::KeyE => b'e',
+        KeyCode::KeyF => b'f',
+        KeyCode::KeyG => b'g',
+        KeyCode::KeyH => b'h',
+        KeyCode::KeyI => b'i',
+        KeyCode::KeyJ => b'j',
+        KeyCode::KeyK => b'k',
+        KeyCode::KeyL => b'l',
+        KeyCode::KeyM => b'm',
+        KeyCode::KeyN => b'n',
+        KeyCode::KeyO => b'o',
+        KeyCode::KeyP => b'p',
+        KeyCode::KeyQ => b'q',
+        KeyCode::KeyR => b'r',
+        KeyCode::KeyS => b's',
+        KeyCode::KeyT => b't',
+        KeyCode::KeyU => b'u',
+        KeyCode::KeyV => b'v',
+        KeyCode::KeyW => b'w',
+        KeyCode::KeyX => b'x',
+        KeyCode::KeyY => b'y',
+        KeyCode::KeyZ => b'z',
+        _ => return None,
+    };
+    Some(letter)
+}
+
+/// An arrow, Home or End key: `CSI final`, or `SS3 final` in cursor key
+/// mode.
+fn cursor_key(final_byte: u8, app_cursor: bool) -> Vec<u8> {
+    vec![0x1b, if app_cursor { b'O' } else { b'[' }, final_byte]
+}
+
+/// What to type for something shared with us: `[share] command` run on
+/// it, or else the text or quoted path at the prompt, on one line so
+/// nothing runs by itself.
+fn shared_input(shared: &android::Shared, command: &str) -> Vec<u8> {
+    let arg = match shared {
+        android::Shared::Text(text) if command.is_empty() => {
+            sanitize_paste(text).replace('\n', " ")
+        }
+        android::Shared::Text(text) => shell_quote(&sanitize_paste(text)),
+        android::Shared::File(path) => shell_quote(&path.to_string_lossy()),
+    };
+    if command.is_empty() {
+        arg.into_bytes()
+    } else {
+        format!("{} {}\n", command, arg).into_bytes()
+    }
+}
+
+/// The intent's RUN_COMMAND, unless `[shell] allow_external_apps` is off.
+fn external_command(app: &AndroidApp, config: Option<&AppConfig>) -> Option<android::RunCommand> {
+    let run = android::run_command(app)?;
+    if !config.is_some_and(|config| config.allow_external_apps) {
+        log::warn!("Ignoring {:?}: [shell] allow_external_apps is off", run);
+        return None;
+    }
+    Some(run)
+}
+
+/// What runs `run`, in its directory if it has one.
+fn external_shell_command(run: &android::RunCommand, env: &mut PtyEnv) -> ShellCommand {
+    if run.cwd.is_some() {
+        env.cwd = run.cwd.clone();
+    }
+    ShellCommand {
+        program: find_program(&run.command, env)
+            .to_string_lossy()
+            .into_owned(),
+        args: run.args.clone(),
+        login: false,
+    }
+}
+
+/// `run` typed at a shell prompt, in a subshell when it has a directory
+/// so the session's own stays put. None if it has control characters,
+/// which the line discipline would act on even inside quotes.
+fn run_command_input(run: &android::RunCommand) -> Option<Vec<u8>> {
+    let cwd = run.cwd.as_ref().map(|cwd| cwd.to_string_lossy());
+    let fields = std::iter::once(run.command.as_str())
+        .chain(run.args.iter().map(String::as_str))
+        .chain(cwd.as_deref());
+    if fields.flat_map(str::chars).any(char::is_control) {
+        return None;
+    }
+    let mut line = shell_quote(&run.command);
+    for arg in &run.args {
+        line.push(' ');
+        line.push_str(&shell_quote(arg));
+    }
+    if let Some(cwd) = &run.cwd {
+        line = format!("(cd {} && {})", shell_quote(&cwd.to_string_lossy()), line);
+    }
+    line.push('\n');
+    Some(line.into_bytes())
+}
+
+/// `arg` in single quotes, safe to type at a POSIX shell prompt.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Wrap pasted text in bracketed-paste markers when the application asked
+/// for them (DECSET 2004), so shells can tell it from typed input.
+fn paste_bytes(text: &str, bracketed: bool) -> Vec<u8> {
+    let text = sanitize_paste(text);
+    if !bracketed {
+        return text.into_bytes();
+    }
+    let mut bytes = Vec::with_capacity(text.len() + 12);
+    bytes.extend_from_slice(b"\x1b[200~");
+    bytes.extend_from_slice(text.as_bytes());
+    bytes.extend_from_slice(b"\x1b[201~");
+    bytes
+}
+
+/// Clipboard text made safe to type: line breaks become `\n`, like the
+/// Enter key, and every other control character but tab is dropped. That
+/// includes ESC, so a copied web page can't end a bracketed paste early
+/// or smuggle in escape sequences that run commands.
+fn sanitize_paste(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .chars()
+        .filter_map(|c| match c {
+            '\r' => Some('\n'),
+            '\n' | '\t' => Some(c),
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+/// Pass everything the shell writes to `on_data` until `wakeup` becomes
+/// readable or hangs up, or the PTY closes, and feed it input that
+/// [`Pty::write`] had to queue as it makes room. Blocks in epoll in
+/// between, so an idle session costs nothing.
+fn read_pty(pty: &Pty, wakeup: OwnedFd, mut on_data: impl FnMut(&[u8])) {
+    const OUTPUT: u64 = 0;
+    const WAKEUP: u64 = 1;
+    const QUEUED: u64 = 2;
+
+    let epoll = match Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC) {
+        Ok(epoll) => epoll,
+        Err(e) => {
+            log::error!("Failed to create epoll: {:?}", e);
+            return;
+        }
+    };
+    let registered = epoll
+        .add(pty, EpollEvent::new(EpollFlags::EPOLLIN, OUTPUT))
+        .and_then(|()| epoll.add(&wakeup, EpollEvent::new(EpollFlags::EPOLLIN, WAKEUP)))
+        .and_then(|()| {
+            epoll.add(
+                pty.queue_event(),
+                EpollEvent::new(EpollFlags::EPOLLIN, QUEUED),
+            )
+        });
+    if let Err(e) = registered {
+        log::error!("Failed to register epoll: {:?}", e);
+        return;
+    }
+
+    let mut buf = [0u8; 4096];
+    let mut events = [EpollEvent::empty(); 3];
+    // Whether we're waiting for room to write queued input.
+    let mut writing = false;
+    loop {
+        let ready = match epoll.wait(&mut events, EpollTimeout::NONE) {
+            Ok(n) => n,
+            Err(Errno::EINTR) => continue,
+            Err(e) => {
+                log::error!("Epoll wait error: {:?}", e);
+                return;
+            }
+        };
+        let mut flush = false;
+        for event in &events[..ready] {
+            match event.data() {
+                WAKEUP => return,
+                QUEUED => flush = true,
+                _ => {
+                    flush |= event.events().contains(EpollFlags::EPOLLOUT);
+                    loop {
+                        match pty.read(&mut buf) {
+                            Ok(0) => break,
+                            Ok(n) => on_data(&buf[..n]),
+                            Err(e) => {
+                                // EIO once the shell and everything it
+                                // started have closed the terminal.
+                                log::info!("PTY read ended: {:?}", e);
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if !flush {
+            continue;
+        }
+
+        let flushed = pty.flush().unwrap_or_else(|e| {
+            log::warn!("Dropped queued PTY input: {:?}", e);
+            true
+        });
+        if flushed == writing {
+            writing = !flushed;
+            let flags = if writing {
+                EpollFlags::EPOLLIN | EpollFlags::EPOLLOUT
+            } else {
+                EpollFlags::EPOLLIN
+            };
+            if let Err(e) = epoll.modify(pty, &mut EpollEvent::new(flags, OUTPUT)) {
+                log::error!("Failed to update epoll: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Convert a (x, y) pair of points to device pixels.
+/// Font size `config` asks for on `display`.
+fn display_font_size(config: &AppConfig, display: Option<&android::Display>) -> f32 {
+    match (display, config.external_font_size) {
+        (Some(display), Some(size)) if display.id != android::DEFAULT_DISPLAY => size,
+        _ => config.font_size,
+    }
+}
+
+/// Time between frames on `display`.
+fn frame_interval(display: Option<&android::Display>) -> Duration {
+    let rate = display
+        .map(|d| d.refresh_rate)
+        .filter(|&rate| rate >= 1.0)
+        .unwrap_or(DEFAULT_REFRESH_RATE);
+    Duration::from_secs_f32(1.0 / rate)
+}
+
+fn scaled((x, y): (f32, f32), scale: f32) -> (f32, f32) {
+    (x * scale, y * scale)
+}
@@ -0,0 +1,111 @@
+use nix::sys::signal::Signal;
+use skia_safe::{Point, Rect};
+
+/// Offered by [`MenuAction::Signals`], gentlest first.
+pub const SIGNALS: [Signal; 4] = [
+    Signal::SIGINT,
+    Signal::SIGHUP,
+    Signal::SIGTERM,
+    Signal::SIGKILL,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuAction {
+    Copy,
+    Paste,
+    SelectAll,
+    OpenUrl,
+    /// Shares the selection, or else the last command's output.
+    Share,
+    StartTranscript,
+    StopTranscript,
+    /// Opens a menu of [`SIGNALS`] for the foreground job.
+    Signals,
+    Signal(Signal),
+    /// Confirms erasing the bootstrap and installing it again.
+    ResetBootstrap,
+    /// Confirms deleting the named environment the session runs in.
+    RemoveEnvironment,
+    /// Offered with the disk usage report.
+    CleanAptCache,
+}
+
+impl MenuAction {
+    pub fn label(self) -> &'static str {
+        match self {
+            MenuAction::Copy => "Copy",
+            MenuAction::Paste => "Paste",
+            MenuAction::SelectAll => "Select all",
+            MenuAction::OpenUrl => "Open link",
+            MenuAction::Share => "Share",
+            MenuAction::StartTranscript => "Record",
+            MenuAction::StopTranscript => "Stop recording",
+            MenuAction::Signals => "Signal",
+            MenuAction::Signal(signal) => signal.as_str(),
+            MenuAction::ResetBootstrap => "Erase and reinstall",
+            MenuAction::RemoveEnvironment => "Remove environment",
+            MenuAction::CleanAptCache => "Clean apt cache",
+        }
+    }
+}
+
+/// The long-press menu: a row of actions floating over the grid. There
+/// are no Android views to borrow one from, so it is drawn and hit-tested
+/// here, in device pixels.
+pub struct ContextMenu {
+    /// Each action with the rect it occupies.
+    pub items: Vec<(MenuAction, Rect)>,
+    pub rect: Rect,
+    /// Target of [`MenuAction::OpenUrl`].
+    pub url: Option<String>,
+    /// Where it was opened, for reopening after an action.
+    pub anchor: Point,
+}
+
+impl ContextMenu {
+    /// Lay out `items` (action and size) side by side, centred over
+    /// `anchor` and `gap` above it so the finger doesn't hide it, or below
+    /// when there's no room above. The menu is kept inside `bounds`.
+    pub fn new(
+        items: Vec<(MenuAction, (f32, f32))>,
+        url: Option<String>,
+        anchor: Point,
+        gap: f32,
+        bounds: Rect,
+    ) -> Self {
+        let width: f32 = items.iter().map(|(_, (w, _))| w).sum();
+        let height = items.iter().map(|(_, (_, h))| *h).fold(0.0, f32::max);
+        let top = if anchor.y - gap - height >= bounds.top {
+            anchor.y - gap - height
+        } else {
+            anchor.y + gap
+        };
+        let left = (anchor.x - width / 2.0)
+            .min(bounds.right - width)
+            .max(bounds.left);
+        let top = top.min(bounds.bottom - height).max(bounds.top);
+
+        let mut x = left;
+        let items = items
+            .into_iter()
+            .map(|(action, (w, _))| {
+                let rect = Rect::from_xywh(x, top, w, height);
+                x += w;
+                (action, rect)
+            })
+            .collect();
+        Self {
+            items,
+            rect: Rect::from_xywh(left, top, width, height),
+            url,
+            anchor,
+        }
+    }
+
+    pub fn action_at(&self, pos: Point) -> Option<MenuAction> {
+        self.items
+            .iter()
+            .find(|(_, rect)| rect.contains(pos))
+            .map(|&(action, _)| action)
+    }
+}
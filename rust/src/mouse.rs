@@ -0,0 +1,87 @@
+use crate::core::types::TermMode;
+
+/// Button numbers as the mouse protocols count them.
+pub const LEFT: u8 = 0;
+pub const MIDDLE: u8 = 1;
+pub const RIGHT: u8 = 2;
+const WHEEL_UP: u8 = 64;
+const WHEEL_DOWN: u8 = 65;
+/// Legacy encoding has no per-button release, only this.
+const RELEASE: u8 = 3;
+/// Added to the button for motion reports.
+const MOTION: u8 = 32;
+
+/// What happened, to be reported to an application that asked for mouse
+/// events (DECSET 1000/1002/1003).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseReport {
+    Press(u8),
+    Release(u8),
+    /// The pointer moved to another cell, with this button held, if any.
+    Motion(Option<u8>),
+    Wheel {
+        up: bool,
+    },
+}
+
+/// Modifier keys held during the event.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MouseMods {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl MouseMods {
+    fn bits(self) -> u8 {
+        (self.shift as u8) << 2 | (self.alt as u8) << 3 | (self.ctrl as u8) << 4
+    }
+}
+
+/// Bytes reporting `report` at cell (`x`, `y`), or None if the current
+/// mouse mode doesn't ask for it. Uses the SGR encoding (DECSET 1006) when
+/// enabled and the legacy X10-style one otherwise.
+pub fn encode(
+    mode: TermMode,
+    report: MouseReport,
+    x: usize,
+    y: usize,
+    mods: MouseMods,
+) -> Option<Vec<u8>> {
+    let wanted = match report {
+        MouseReport::Press(_) | MouseReport::Release(_) | MouseReport::Wheel { .. } => {
+            mode.intersects(TermMode::MOUSE)
+        }
+        MouseReport::Motion(Some(_)) => {
+            mode.intersects(TermMode::MOUSE_DRAG | TermMode::MOUSE_MOTION)
+        }
+        MouseReport::Motion(None) => mode.contains(TermMode::MOUSE_MOTION),
+    };
+    if !wanted {
+        return None;
+    }
+
+    let sgr = mode.contains(TermMode::MOUSE_SGR);
+    let button = match report {
+        MouseReport::Press(button) => button,
+        MouseReport::Release(button) if sgr => button,
+        MouseReport::Release(_) => RELEASE,
+        MouseReport::Motion(button) => button.unwrap_or(RELEASE) + MOTION,
+        MouseReport::Wheel { up: true } => WHEEL_UP,
+        MouseReport::Wheel { up: false } => WHEEL_DOWN,
+    } + mods.bits();
+
+    if sgr {
+        let end = if matches!(report, MouseReport::Release(_)) {
+            'm'
+        } else {
+            'M'
+        };
+        return Some(format!("\x1b[<{};{};{}{}", button, x + 1, y + 1, end).into_bytes());
+    }
+    // Each value travels as one byte offset by 32, so cells past 223 can't
+    // be reported at all.
+    let x = u8::try_from(x + 1 + 32).ok()?;
+    let y = u8::try_from(y + 1 + 32).ok()?;
+    Some(vec![0x1b, b'[', b'M', button + 32, x, y])
+}
@@ -0,0 +1,138 @@
+use std::time::{Duration, Instant};
+
+/// Time constant of the ease-out: each frame covers the share of the
+/// remaining distance that this much time would.
+const EASE_TAU: Duration = Duration::from_millis(60);
+/// Remaining distance (in lines) below which the animation snaps home.
+const SNAP_LINES: f32 = 0.02;
+/// Time constant of a fling's exponential slowdown.
+const FLING_TAU: Duration = Duration::from_millis(325);
+/// A fling slower than this, in lines per second, has stopped.
+const FLING_STOP_SPEED: f32 = 0.5;
+/// How far past either end of history, in lines, the view can be pulled.
+const MAX_OVERSCROLL: f32 = 3.0;
+/// A fling hitting an end pulls as far as it would have gone in this long.
+const FLING_ABSORB: Duration = Duration::from_millis(50);
+/// Time constant of overscroll springing back.
+const OVERSCROLL_TAU: Duration = Duration::from_millis(120);
+
+/// Pixel-smooth scrollback position.
+///
+/// The terminal itself only scrolls by whole lines (`display_offset`); this
+/// keeps the fraction of a line in between, which the renderer shows by
+/// shifting the grid down, plus any distance still left to animate, the
+/// momentum of a fling and how far the view has been pulled past an end.
+#[derive(Default)]
+pub struct SmoothScroll {
+    /// Part of the line above the viewport that is scrolled into view,
+    /// 0.0 (none) up to but excluding 1.0.
+    pub fraction: f32,
+    /// Lines still to travel; positive scrolls back into history.
+    pending: f32,
+    /// Fling speed in lines per second; positive scrolls back.
+    velocity: f32,
+    /// Lines pulled past the oldest (positive) or newest (negative) line.
+    overscroll: f32,
+    last_frame: Option<Instant>,
+}
+
+impl SmoothScroll {
+    /// Queue an animated scroll by `lines`.
+    pub fn scroll(&mut self, lines: f32) {
+        if !self.is_animating() {
+            self.last_frame = None;
+        }
+        self.pending += lines;
+    }
+
+    /// Keep scrolling at `velocity` lines per second, slowing down.
+    pub fn fling(&mut self, velocity: f32) {
+        if !self.is_animating() {
+            self.last_frame = None;
+        }
+        self.velocity = velocity;
+    }
+
+    /// Drop any animation and line fraction, e.g. when snapping to the bottom.
+    pub fn stop(&mut self) {
+        self.fraction = 0.0;
+        self.overscroll = 0.0;
+        self.halt();
+    }
+
+    /// Stop animating but stay where we are, e.g. when a finger takes over.
+    pub fn halt(&mut self) {
+        self.pending = 0.0;
+        self.velocity = 0.0;
+        self.last_frame = None;
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.pending != 0.0 || self.velocity != 0.0 || self.overscroll != 0.0
+    }
+
+    /// How far the view is pulled past an end, from -1.0 (fully, below the
+    /// newest line) to 1.0 (fully, above the oldest).
+    pub fn overscroll(&self) -> f32 {
+        self.overscroll / MAX_OVERSCROLL
+    }
+
+    /// Advance the animation to `now` and return how many lines to move by.
+    pub fn step(&mut self, now: Instant) -> f32 {
+        if !self.is_animating() {
+            return 0.0;
+        }
+        // The first frame only starts the clock.
+        let Some(last) = self.last_frame.replace(now) else {
+            return 0.0;
+        };
+        let dt = now.duration_since(last).as_secs_f32();
+
+        let mut delta = 0.0;
+        if self.pending != 0.0 {
+            let share = 1.0 - (-dt / EASE_TAU.as_secs_f32()).exp();
+            let mut eased = self.pending * share;
+            if (self.pending - eased).abs() < SNAP_LINES {
+                eased = self.pending;
+            }
+            self.pending -= eased;
+            delta += eased;
+        }
+        if self.velocity != 0.0 {
+            // Distance covered while the speed decays over `dt`.
+            let tau = FLING_TAU.as_secs_f32();
+            let decay = (-dt / tau).exp();
+            delta += self.velocity * tau * (1.0 - decay);
+            self.velocity *= decay;
+            if self.velocity.abs() < FLING_STOP_SPEED {
+                self.velocity = 0.0;
+            }
+        }
+        if self.overscroll != 0.0 {
+            self.overscroll *= (-dt / OVERSCROLL_TAU.as_secs_f32()).exp();
+            if self.overscroll.abs() < SNAP_LINES {
+                self.overscroll = 0.0;
+            }
+        }
+        delta
+    }
+
+    /// Move to `lines` past the current position and split the result into
+    /// a whole-line offset (for `Term::display_offset`) and a fraction.
+    /// `offset` is the current display offset and `max` the history length.
+    /// Running into either end stops any motion and turns the rest of it
+    /// into overscroll.
+    pub fn apply(&mut self, lines: f32, offset: usize, max: usize) -> usize {
+        let target = offset as f32 + self.fraction + lines;
+        let pos = target.clamp(0.0, max as f32);
+        if pos != target {
+            let pull = target - pos + self.velocity * FLING_ABSORB.as_secs_f32();
+            self.overscroll = (self.overscroll + pull).clamp(-MAX_OVERSCROLL, MAX_OVERSCROLL);
+            self.pending = 0.0;
+            self.velocity = 0.0;
+        }
+        let whole = pos.floor();
+        self.fraction = pos - whole;
+        whole as usize
+    }
+}
@@ -0,0 +1,163 @@
+//! Touch gestures. A stylus is taken for a finger: winit's Android backend
+//! passes on neither the pointer's tool type nor its button state, and
+//! drops hover events, so pen hover, pen drag selection and the pen
+//! button can't be told apart from touch here.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use winit::dpi::PhysicalPosition;
+use winit::event::{Touch, TouchPhase};
+
+/// A press held longer than this is not a tap.
+const TAP_TIMEOUT: Duration = Duration::from_millis(300);
+/// Holding still this long is a long press.
+const LONG_PRESS_TIMEOUT: Duration = Duration::from_millis(500);
+/// Only movement this recent counts towards a fling's speed.
+const VELOCITY_WINDOW: Duration = Duration::from_millis(100);
+
+/// What a finger did, once it is clear.
+#[derive(Clone, Copy, Debug)]
+pub enum Gesture {
+    Tap(PhysicalPosition<f64>),
+    /// Held still past the long-press timeout; reported while still down.
+    LongPress(PhysicalPosition<f64>),
+    /// The finger moved after a long press.
+    LongPressDrag(PhysicalPosition<f64>),
+    /// The finger lifted after a long press, here.
+    LongPressEnd(PhysicalPosition<f64>),
+    /// The finger dragged this many pixels vertically since the last
+    /// report; positive is downwards.
+    Scroll(f64),
+    /// The finger lifted after scrolling, moving this fast vertically in
+    /// pixels per second; positive is downwards.
+    Fling(f64),
+}
+
+struct ActiveTouch {
+    id: u64,
+    start: PhysicalPosition<f64>,
+    started: Instant,
+    /// Moved further than the touch slop at some point.
+    moved: bool,
+    /// Where the last scroll was reported from.
+    last: PhysicalPosition<f64>,
+    /// Recent (time, y) positions while scrolling, oldest first.
+    samples: VecDeque<(Instant, f64)>,
+    long_pressed: bool,
+}
+
+impl ActiveTouch {
+    fn track(&mut self, y: f64) {
+        let now = Instant::now();
+        while self
+            .samples
+            .front()
+            .is_some_and(|&(t, _)| now.duration_since(t) > VELOCITY_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, y));
+    }
+
+    /// Vertical speed over the last few samples, in pixels per second.
+    /// A finger that stopped before lifting has none.
+    fn velocity(&self) -> f64 {
+        let (Some(&(t0, y0)), Some(&(t1, y1))) = (self.samples.front(), self.samples.back()) else {
+            return 0.0;
+        };
+        let dt = t1.duration_since(t0).as_secs_f64();
+        if dt == 0.0 || t1.elapsed() > VELOCITY_WINDOW {
+            return 0.0;
+        }
+        (y1 - y0) / dt
+    }
+}
+
+/// Turns the raw winit touch stream into gestures. Only the first finger
+/// down is followed; others are ignored until it lifts.
+#[derive(Default)]
+pub struct TouchTracker {
+    active: Option<ActiveTouch>,
+}
+
+impl TouchTracker {
+    /// Feed a touch event. `slop` is how far, in pixels, a finger may
+    /// wander before it counts as moving.
+    pub fn handle(&mut self, touch: &Touch, slop: f64) -> Option<Gesture> {
+        match touch.phase {
+            TouchPhase::Started => {
+                if self.active.is_none() {
+                    self.active = Some(ActiveTouch {
+                        id: touch.id,
+                        start: touch.location,
+                        started: Instant::now(),
+                        moved: false,
+                        last: touch.location,
+                        samples: VecDeque::new(),
+                        long_pressed: false,
+                    });
+                }
+                None
+            }
+            TouchPhase::Moved => {
+                let active = self.active.as_mut().filter(|a| a.id == touch.id)?;
+                if active.long_pressed {
+                    return Some(Gesture::LongPressDrag(touch.location));
+                }
+                if active.moved {
+                    let dy = touch.location.y - active.last.y;
+                    active.last = touch.location;
+                    active.track(touch.location.y);
+                    return Some(Gesture::Scroll(dy));
+                }
+                let (dx, dy) = (
+                    touch.location.x - active.start.x,
+                    touch.location.y - active.start.y,
+                );
+                if dx.hypot(dy) > slop {
+                    // Scroll from here on, so the view doesn't jump by the slop.
+                    active.moved = true;
+                    active.last = touch.location;
+                    active.track(touch.location.y);
+                }
+                None
+            }
+            TouchPhase::Ended => {
+                let active = self.active.take_if(|a| a.id == touch.id)?;
+                if active.long_pressed {
+                    return Some(Gesture::LongPressEnd(touch.location));
+                }
+                if active.moved {
+                    return Some(Gesture::Fling(active.velocity()));
+                }
+                (!active.moved && active.started.elapsed() < TAP_TIMEOUT)
+                    .then_some(Gesture::Tap(active.start))
+            }
+            TouchPhase::Cancelled => {
+                let active = self.active.take_if(|a| a.id == touch.id)?;
+                active
+                    .long_pressed
+                    .then_some(Gesture::LongPressEnd(touch.location))
+            }
+        }
+    }
+
+    /// When [`TouchTracker::poll`] should next be called, if a long press
+    /// may be pending.
+    pub fn deadline(&self) -> Option<Instant> {
+        let active = self.active.as_ref()?;
+        (!active.moved && !active.long_pressed).then(|| active.started + LONG_PRESS_TIMEOUT)
+    }
+
+    /// Report a long press once a finger has been held still long enough.
+    pub fn poll(&mut self, now: Instant) -> Option<Gesture> {
+        let deadline = self.deadline()?;
+        if now < deadline {
+            return None;
+        }
+        let active = self.active.as_mut()?;
+        active.long_pressed = true;
+        Some(Gesture::LongPress(active.start))
+    }
+}
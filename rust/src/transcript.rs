@@ -0,0 +1,180 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A copy of the session on disk: everything the shell printed and,
+/// optionally, everything typed, each in its own series of files.
+pub struct Transcript {
+    output: LogFile,
+    input: Option<LogFile>,
+}
+
+impl Transcript {
+    /// Start new files in `dir`, named after the current time. Each file is
+    /// rotated at `max_size` bytes, keeping the newest `keep` of each kind.
+    pub fn start(dir: &Path, max_size: u64, keep: usize, input: bool) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let stamp = timestamp(SystemTime::now());
+        let output = LogFile::open(dir, &stamp, "output", max_size, keep)?;
+        let input = match input {
+            true => Some(LogFile::open(dir, &stamp, "input", max_size, keep)?),
+            false => None,
+        };
+        Ok(Self { output, input })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.output.path
+    }
+
+    /// Record shell output. Errors are logged once and then the file is
+    /// left alone rather than failing the session over it.
+    pub fn output(&mut self, data: &[u8]) {
+        self.output.write(data);
+    }
+
+    pub fn input(&mut self, data: &[u8]) {
+        if let Some(input) = &mut self.input {
+            input.write(data);
+        }
+    }
+}
+
+/// One series of files: session-20261016-143005-output.log, then
+/// session-20261016-143005.001-output.log and so on as each fills up.
+struct LogFile {
+    dir: PathBuf,
+    /// When recording started.
+    stamp: String,
+    /// `output` or `input`.
+    kind: &'static str,
+    /// How many times the series has rotated.
+    part: u32,
+    path: PathBuf,
+    file: Option<File>,
+    written: u64,
+    max_size: u64,
+    keep: usize,
+}
+
+impl LogFile {
+    fn open(
+        dir: &Path,
+        stamp: &str,
+        kind: &'static str,
+        max_size: u64,
+        keep: usize,
+    ) -> io::Result<Self> {
+        let mut log = Self {
+            dir: dir.to_path_buf(),
+            stamp: stamp.to_string(),
+            kind,
+            part: 0,
+            path: PathBuf::new(),
+            file: None,
+            written: 0,
+            max_size,
+            keep,
+        };
+        log.open_part()?;
+        Ok(log)
+    }
+
+    /// Open file number `part` of the series and prune old ones.
+    fn open_part(&mut self) -> io::Result<()> {
+        let name = match self.part {
+            0 => format!("session-{}-{}.log", self.stamp, self.kind),
+            n => format!("session-{}.{:03}-{}.log", self.stamp, n, self.kind),
+        };
+        let path = self.dir.join(name);
+        // Appends if recording restarted within the same second.
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        log::info!("Transcript file: {:?}", path);
+        self.path = path;
+        self.file = Some(file);
+        self.written = 0;
+        self.prune();
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        if let Err(e) = file.write_all(data) {
+            log::warn!("Transcript write to {:?} failed: {:?}", self.path, e);
+            self.file = None;
+            return;
+        }
+        self.written += data.len() as u64;
+        if self.max_size > 0 && self.written >= self.max_size {
+            self.rotate();
+        }
+    }
+
+    /// Carry on in a new file, dropping the oldest past `keep`.
+    fn rotate(&mut self) {
+        self.part += 1;
+        if let Err(e) = self.open_part() {
+            log::warn!("Transcript rotation in {:?} failed: {:?}", self.dir, e);
+            self.file = None;
+        }
+    }
+
+    fn prune(&self) {
+        if self.keep == 0 {
+            return;
+        }
+        let suffix = format!("-{}.log", self.kind);
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        // Names sort in the order they were written.
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                name.starts_with("session-") && name.ends_with(&suffix)
+            })
+            .map(|entry| entry.path())
+            .collect();
+        if files.len() <= self.keep {
+            return;
+        }
+        files.sort();
+        for path in &files[..files.len() - self.keep] {
+            if *path != self.path {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// `time` in UTC as 20261016-143005, which sorts in time order.
+fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Days since the epoch to a civil date, after Howard Hinnant.
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}